@@ -0,0 +1,63 @@
+//! What adding a line to the waterfall costs now, against colouring every pixel through the
+//! gradient into an image handle of its own like it used to. Run with
+//! `cargo bench --bench waterfall`.
+
+use std::collections::VecDeque;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use iced::widget::image::Handle;
+use rustic_sdr::waterfall_ring::{self, LineRing, Upload, RGBA};
+use rustic_sdr::FFT_AMMOUNT;
+
+//About what a full screen window shows
+const HEIGHT: usize = 1000;
+const MAX: f32 = 60.0;
+const MIN: f32 = -20.0;
+
+//A spectrum with some shape to it so the colours aren't all the same
+fn sample() -> Vec<f32> {
+    (0..FFT_AMMOUNT)
+        .map(|bin| MIN + (MAX - MIN) * (bin as f32 / 97.0).sin().abs())
+        .collect()
+}
+
+fn waterfall(c: &mut Criterion) {
+    let sample = sample();
+    let mut group = c.benchmark_group("add a line");
+
+    //The gradient built and sampled for every pixel, each line its own handle
+    let mut handles: VecDeque<Handle> = VecDeque::new();
+    group.bench_function("per pixel gradient and a handle per line", |b| {
+        b.iter(|| {
+            let mut line = Vec::with_capacity(FFT_AMMOUNT * RGBA);
+            for val in black_box(&sample) {
+                let adj_val = val.clamp(MIN, MAX) / MAX;
+                let color = colorgrad::turbo().at(adj_val.into()).to_rgba8();
+                line.extend(color);
+            }
+            handles.truncate(HEIGHT - 1);
+            handles.push_front(Handle::from_pixels(FFT_AMMOUNT as u32, 1, line));
+        })
+    });
+
+    //Table lookups into the ring, then the one new line handed to the GPU
+    let lut = waterfall_ring::lut(&colorgrad::turbo());
+    let mut ring = LineRing::new(FFT_AMMOUNT);
+    ring.resize(HEIGHT);
+    let generation = ring.generation();
+    let _ = ring.take_upload(None);
+    group.bench_function("colour table into the ring and upload", |b| {
+        b.iter(|| {
+            waterfall_ring::color_line(ring.next_line(), black_box(&sample), &lut, MAX, MIN);
+            match ring.take_upload(Some(generation)) {
+                Upload::Lines(lines) => black_box(lines.len()),
+                Upload::Full { .. } => unreachable!("the ring was not resized"),
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, waterfall);
+criterion_main!(benches);
@@ -0,0 +1,573 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::handshake::server::{ErrorResponse, Request as WsRequest, Response as WsResponse};
+
+//How long a request waits for the GUI to pick up its command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+//How long an idle spectrum socket goes without sending before it pings the client
+const FRAME_WAIT: Duration = Duration::from_secs(1);
+//How often the spectrum socket stops waiting for frames to look at what the client sent
+const SOCKET_POLL: Duration = Duration::from_millis(100);
+//Threads answering requests and streaming spectrum sockets, more clients wait their turn
+const REQUEST_WORKERS: usize = 4;
+const SPECTRUM_WORKERS: usize = 4;
+
+//Browser UI for running without the iced window
+const WEB_UI: &str = include_str!("web/index.html");
+
+/// Commands the API hands to the app, they go through the same paths as the GUI controls
+#[derive(Debug, Clone)]
+pub enum ApiCommand {
+    Start { device: Option<String> },
+    Stop,
+    SetFreq(f64),
+    SetGain(f64),
+    SetSampleRate(f64),
+    StartRecording,
+    StopRecording,
+}
+
+#[derive(Debug)]
+pub struct ApiRequest {
+    pub command: ApiCommand,
+    reply: Sender<ApiStatus>,
+}
+
+impl ApiRequest {
+    pub fn reply(self, status: ApiStatus) {
+        //The client may have timed out already
+        let _ = self.reply.send(status);
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ApiStatus {
+    pub running: bool,
+    pub recording: bool,
+    pub record_duration: f32,
+    pub device: String,
+    pub devices: Vec<String>,
+    pub center_freq: f64,
+    pub gain: f64,
+    pub sample_rate: f64,
+    pub sample_rates: Vec<f64>,
+    pub fft_min: f32,
+    pub fft_max: f32,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StartBody {
+    device: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HzBody {
+    hz: f64,
+}
+
+#[derive(Deserialize)]
+struct GainBody {
+    db: f64,
+}
+
+struct SpectrumFrame {
+    seq: u64,
+    data: Arc<Vec<f32>>,
+}
+
+struct Shared {
+    status: Mutex<ApiStatus>,
+    spectrum: Mutex<SpectrumFrame>,
+    new_frame: Condvar,
+    commands: Sender<ApiRequest>,
+    //Required on every API call when set, as a bearer token or a token query parameter
+    token: Option<String>,
+}
+
+/// The app side of the API, owned by whoever runs the receiver
+pub struct ApiHandle {
+    shared: Arc<Shared>,
+    requests: Receiver<ApiRequest>,
+    addr: SocketAddr,
+    spectrum_addr: SocketAddr,
+}
+
+impl ApiHandle {
+    /// Starts the HTTP server on `addr` and the spectrum WebSocket on the port after it, or any
+    /// free port for port 0. Anything other than a loopback address needs a `token` clients
+    /// have to send.
+    pub fn start(
+        addr: SocketAddr,
+        token: Option<String>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if token.is_none() && !addr.ip().is_loopback() {
+            return Err(format!("Listening on {} needs an API token", addr.ip()).into());
+        }
+        let server = Arc::new(Server::http(addr)?);
+        let spectrum_port = match addr.port() {
+            0 => 0,
+            port => port.wrapping_add(1),
+        };
+        let spectrum_listener = TcpListener::bind(SocketAddr::new(addr.ip(), spectrum_port))?;
+        let spectrum_addr = spectrum_listener.local_addr()?;
+        let addr = server.server_addr().to_ip().unwrap_or(addr);
+        let (sender, requests) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            status: Mutex::new(ApiStatus::default()),
+            spectrum: Mutex::new(SpectrumFrame {
+                seq: 0,
+                data: Arc::new(Vec::new()),
+            }),
+            new_frame: Condvar::new(),
+            commands: sender,
+            token,
+        });
+
+        //A request waiting on the GUI or a slow client must not hold up the others, but a
+        //client opening connection after connection mustn't get a thread for each either
+        for _ in 0..REQUEST_WORKERS {
+            let server = server.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(&shared, request);
+                }
+            });
+        }
+        for _ in 0..SPECTRUM_WORKERS {
+            let listener = spectrum_listener.try_clone()?;
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    stream_spectrum(shared.clone(), stream);
+                }
+            });
+        }
+
+        Ok(Self {
+            shared,
+            requests,
+            addr,
+            spectrum_addr,
+        })
+    }
+
+    /// Address the HTTP server is listening on
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Address the spectrum WebSocket is listening on
+    #[inline]
+    pub fn spectrum_addr(&self) -> SocketAddr {
+        self.spectrum_addr
+    }
+
+    /// Commands received since the last call
+    pub fn pending(&self) -> Vec<ApiRequest> {
+        self.requests.try_iter().collect()
+    }
+
+    pub fn publish_status(&self, status: ApiStatus) {
+        if let Ok(mut current) = self.shared.status.lock() {
+            *current = status;
+        }
+    }
+
+    pub fn publish_spectrum(&self, data: &[f32]) {
+        if let Ok(mut frame) = self.shared.spectrum.lock() {
+            frame.seq += 1;
+            frame.data = Arc::new(data.to_vec());
+        }
+        self.shared.new_frame.notify_all();
+    }
+}
+
+fn handle_request(shared: &Arc<Shared>, mut request: Request) {
+    let (url, query) = match request.url().split_once('?') {
+        Some((url, query)) => (url.to_owned(), Some(query.to_owned())),
+        None => (request.url().to_owned(), None),
+    };
+    //The page itself holds nothing, it passes on the token from its own URL
+    let auth = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str().to_owned());
+    if url != "/" && !authorized(shared, query.as_deref(), auth.as_deref()) {
+        return respond_error(request, 401, "Missing or wrong API token");
+    }
+    //Only commands carry a body, reading one on an upgrade request would block
+    let mut body = String::new();
+    if *request.method() == Method::Post && request.as_reader().read_to_string(&mut body).is_err() {
+        respond_error(request, 400, "Unreadable request body");
+        return;
+    }
+
+    let command = match (request.method(), url.as_str()) {
+        (Method::Get, "/") => {
+            let _ = request.respond(
+                Response::from_string(WEB_UI)
+                    .with_header(header("Content-Type", "text/html; charset=utf-8")),
+            );
+            return;
+        }
+        (Method::Get, "/api/status") => {
+            let status = shared.status.lock().map(|s| s.clone()).unwrap_or_default();
+            respond_json(request, 200, &status);
+            return;
+        }
+        (Method::Get, "/api/devices") => {
+            let status = shared.status.lock().map(|s| s.clone()).unwrap_or_default();
+            respond_json(request, 200, &status.devices);
+            return;
+        }
+        (Method::Post, "/api/sdr/start") => {
+            let device = serde_json::from_str::<StartBody>(&body)
+                .ok()
+                .and_then(|b| b.device);
+            ApiCommand::Start { device }
+        }
+        (Method::Post, "/api/sdr/stop") => ApiCommand::Stop,
+        (Method::Post, "/api/freq") => match serde_json::from_str::<HzBody>(&body) {
+            Ok(b) => ApiCommand::SetFreq(b.hz),
+            Err(e) => return respond_error(request, 400, &e.to_string()),
+        },
+        (Method::Post, "/api/gain") => match serde_json::from_str::<GainBody>(&body) {
+            Ok(b) => ApiCommand::SetGain(b.db),
+            Err(e) => return respond_error(request, 400, &e.to_string()),
+        },
+        (Method::Post, "/api/sample_rate") => match serde_json::from_str::<HzBody>(&body) {
+            Ok(b) => ApiCommand::SetSampleRate(b.hz),
+            Err(e) => return respond_error(request, 400, &e.to_string()),
+        },
+        (Method::Post, "/api/recording/start") => ApiCommand::StartRecording,
+        (Method::Post, "/api/recording/stop") => ApiCommand::StopRecording,
+        _ => return respond_error(request, 404, "Not found"),
+    };
+
+    let (reply, reply_recv) = mpsc::channel();
+    if shared.commands.send(ApiRequest { command, reply }).is_err() {
+        return respond_error(request, 503, "Receiver is not running");
+    }
+
+    match reply_recv.recv_timeout(REPLY_TIMEOUT) {
+        Ok(status) => respond_json(request, 200, &status),
+        Err(_) => respond_error(request, 503, "Timed out waiting for the receiver"),
+    }
+}
+
+/// Accepts a WebSocket on `/api/spectrum` that gets every published spectrum frame
+/// as a binary message of little endian f32 dB values
+fn stream_spectrum(shared: Arc<Shared>, stream: TcpStream) {
+    //The rejection is tungstenite's own type
+    #[allow(clippy::result_large_err)]
+    let check = |request: &WsRequest, response: WsResponse| {
+        let auth = request
+            .headers()
+            .get("Authorization")
+            .and_then(|val| val.to_str().ok());
+        if request.uri().path() != "/api/spectrum" {
+            Err(error_response(404, "Not found"))
+        } else if !authorized(&shared, request.uri().query(), auth) {
+            Err(error_response(401, "Missing or wrong API token"))
+        } else {
+            Ok(response)
+        }
+    };
+    let Ok(mut socket) = tungstenite::accept_hdr(stream, check) else {
+        return;
+    };
+    //Reads give up quickly so frames keep going out while nothing comes in
+    if socket
+        .get_ref()
+        .set_read_timeout(Some(SOCKET_POLL))
+        .is_err()
+    {
+        return;
+    }
+
+    let mut last_seq = 0;
+    let mut last_send = Instant::now();
+    loop {
+        //Pongs and the reply to a Close are queued by read and sent by flush
+        match socket.read() {
+            Ok(tungstenite::Message::Close(_)) => {
+                let _ = socket.flush();
+                return;
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return,
+        }
+
+        let frame = {
+            let Ok(frame) = shared.spectrum.lock() else {
+                return;
+            };
+            let Ok((frame, _)) = shared
+                .new_frame
+                .wait_timeout_while(frame, SOCKET_POLL, |f| f.seq == last_seq)
+            else {
+                return;
+            };
+            if frame.seq == last_seq {
+                None
+            } else {
+                last_seq = frame.seq;
+                Some(frame.data.clone())
+            }
+        };
+
+        let msg = match frame {
+            Some(data) => tungstenite::Message::Binary(
+                data.iter().flat_map(|val| val.to_le_bytes()).collect(),
+            ),
+            //Keeps proxies from dropping the connection and finds clients that went away
+            None if last_send.elapsed() >= FRAME_WAIT => tungstenite::Message::Ping(Vec::new()),
+            None => {
+                if socket_failed(socket.flush()) {
+                    return;
+                }
+                continue;
+            }
+        };
+        last_send = Instant::now();
+        if socket_failed(socket.send(msg)) {
+            return;
+        }
+    }
+}
+
+//A write that couldn't finish in time is retried by the next flush
+fn socket_failed(res: tungstenite::Result<()>) -> bool {
+    match res {
+        Ok(()) => false,
+        Err(tungstenite::Error::Io(e)) => {
+            !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+        }
+        Err(_) => true,
+    }
+}
+
+fn authorized(shared: &Shared, query: Option<&str>, auth: Option<&str>) -> bool {
+    let Some(token) = shared.token.as_deref() else {
+        return true;
+    };
+    let from_query = query
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .any(|(key, val)| key == "token" && same_token(&percent_decode(val), token.as_bytes()));
+    let from_header = auth
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .is_some_and(|given| same_token(given.as_bytes(), token.as_bytes()));
+
+    from_query || from_header
+}
+
+//Looks at every byte whatever the first difference, so timing doesn't give the token away
+fn same_token(given: &[u8], token: &[u8]) -> bool {
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (given, token)| diff | (given ^ token))
+            == 0
+}
+
+//A query value with the %XX escapes and + for space a browser sends undone
+fn percent_decode(val: &str) -> Vec<u8> {
+    let bytes = val.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = bytes
+            .get(idx + 1..idx + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[idx], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                idx += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+    decoded
+}
+
+fn error_response(code: u16, msg: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(msg.to_owned()));
+    *response.status_mut() = tungstenite::http::StatusCode::from_u16(code)
+        .unwrap_or(tungstenite::http::StatusCode::BAD_REQUEST);
+    response
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("Invalid header")
+}
+
+fn respond_json<T: Serialize>(request: Request, code: u16, body: &T) {
+    let body = serde_json::to_string(body).unwrap_or_default();
+    let _ = request.respond(
+        Response::from_string(body)
+            .with_status_code(code)
+            .with_header(header("Content-Type", "application/json")),
+    );
+}
+
+fn respond_error(request: Request, code: u16, msg: &str) {
+    respond_json(request, code, &serde_json::json!({ "error": msg }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    //Status code and body of one request, the connection is closed after it
+    fn http(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            addr,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let code = response[9..12].parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_owned())
+            .unwrap_or_default();
+        (code, body)
+    }
+
+    //Answers commands the way the app does until the request on `client` is done
+    fn answer<T: Send + 'static>(api: &ApiHandle, client: std::thread::JoinHandle<T>) -> T {
+        while !client.is_finished() {
+            for request in api.pending() {
+                let status = match request.command {
+                    ApiCommand::SetFreq(hz) => ApiStatus {
+                        center_freq: hz,
+                        ..Default::default()
+                    },
+                    _ => ApiStatus::default(),
+                };
+                request.reply(status);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn serves_status_and_commands() {
+        let api = ApiHandle::start("127.0.0.1:0".parse().unwrap(), None).unwrap();
+        api.publish_status(ApiStatus {
+            device: "0 | rtlsdr 0".into(),
+            devices: vec!["0 | rtlsdr 0".into()],
+            ..Default::default()
+        });
+        let addr = api.addr();
+
+        let (code, body) = http(addr, "GET", "/api/status", "");
+        assert_eq!(code, 200);
+        let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status["device"], "0 | rtlsdr 0");
+        assert_eq!(
+            http(addr, "GET", "/api/devices", "").1,
+            r#"["0 | rtlsdr 0"]"#
+        );
+        assert_eq!(http(addr, "GET", "/nothing", "").0, 404);
+        assert_eq!(http(addr, "POST", "/api/freq", "{}").0, 400);
+        assert!(http(addr, "GET", "/", "").1.contains("<html"));
+
+        let client =
+            std::thread::spawn(move || http(addr, "POST", "/api/freq", r#"{"hz": 145800000}"#));
+        let (code, body) = answer(&api, client);
+        assert_eq!(code, 200);
+        let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status["center_freq"], 145_800_000.0);
+    }
+
+    #[test]
+    fn needs_the_token_off_loopback() {
+        assert!(ApiHandle::start("0.0.0.0:0".parse().unwrap(), None).is_err());
+
+        let api = ApiHandle::start("127.0.0.1:0".parse().unwrap(), Some("a b/c".into())).unwrap();
+        let addr = api.addr();
+        assert_eq!(http(addr, "GET", "/api/status", "").0, 401);
+        assert_eq!(http(addr, "GET", "/api/status?token=a+b", "").0, 401);
+        assert_eq!(http(addr, "GET", "/api/status?token=a+b%2Fc", "").0, 200);
+        assert_eq!(http(addr, "GET", "/api/status?token=a%20b/c", "").0, 200);
+        //The page itself is served without it and passes it on
+        assert_eq!(http(addr, "GET", "/", "").0, 200);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /api/status HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\
+             Authorization: Bearer a b/c\r\n\r\n",
+            addr
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let url = format!("ws://{}/api/spectrum", api.spectrum_addr());
+        assert!(tungstenite::connect(url).is_err());
+    }
+
+    #[test]
+    fn streams_spectrum_frames() {
+        let api = ApiHandle::start("127.0.0.1:0".parse().unwrap(), None).unwrap();
+        api.publish_spectrum(&[-100.0, -50.5, 0.0]);
+
+        let url = format!("ws://{}/api/spectrum", api.spectrum_addr());
+        let (mut socket, _) = tungstenite::connect(url).unwrap();
+        let frame = loop {
+            match socket.read().unwrap() {
+                tungstenite::Message::Binary(frame) => break frame,
+                _ => continue,
+            }
+        };
+        let values: Vec<f32> = frame
+            .chunks_exact(4)
+            .map(|val| f32::from_le_bytes(val.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, [-100.0, -50.5, 0.0]);
+    }
+
+    #[test]
+    fn decodes_query_values() {
+        assert_eq!(percent_decode("a%2Fb+c%zz%4"), b"a/b c%zz%4");
+        assert!(same_token(b"secret", b"secret"));
+        assert!(!same_token(b"secreT", b"secret"));
+        assert!(!same_token(b"secret!", b"secret"));
+    }
+}
@@ -21,15 +21,21 @@ pub enum BaseBandFormat {
     i8,
 }
 
+/// Format of the next recording, set before starting one
 #[derive(Default, Clone)]
 pub struct BaseBandSpec {
     pub format: BaseBandFormat,
     pub sample_rate: u32,
 }
 
+type BaseBandWriter = hound::WavWriter<std::io::BufWriter<std::fs::File>>;
+
+/// Records the incoming samples to a stereo I/Q WAV file while toggled on
 pub struct BaseBandSink {
     spec: BaseBandSpec,
-    writer: Option<hound::WavWriter<std::io::BufWriter<std::fs::File>>>,
+    writer: Option<BaseBandWriter>,
+    //Set when the writer fails mid recording and reported on the next duration request
+    error: Option<String>,
 }
 
 impl BaseBandSink {
@@ -47,6 +53,7 @@ impl BaseBandSink {
             BaseBandSink {
                 writer: None,
                 spec: Default::default(),
+                error: None,
             },
         )
     }
@@ -59,8 +66,10 @@ impl BaseBandSink {
         _meta: &mut BlockMeta,
         p: Pmt,
     ) -> Result<Pmt> {
-        if self.writer.is_some() {
-            self.writer = None;
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.finalize() {
+                return Ok(Pmt::String(e.to_string()));
+            }
             return Ok(Pmt::Bool(false));
         } else {
             let time_stamp = Utc::now();
@@ -93,8 +102,12 @@ impl BaseBandSink {
                 bits_per_sample: bit_per_sample,
                 sample_format,
             };
-            let writer = hound::WavWriter::create(file_name, wav_spec).unwrap();
+            let writer = match hound::WavWriter::create(file_name, wav_spec) {
+                core::result::Result::Ok(writer) => writer,
+                Err(e) => return Ok(Pmt::String(e.to_string())),
+            };
 
+            self.error = None;
             self.writer = Some(writer);
             return Ok(Pmt::Bool(true));
         };
@@ -109,7 +122,10 @@ impl BaseBandSink {
         p: Pmt,
     ) -> Result<Pmt> {
         let spec: BaseBandSpec = match p {
-            Pmt::Any(b) => b.downcast_ref::<BaseBandSpec>().unwrap().clone(),
+            Pmt::Any(b) => match b.downcast_ref::<BaseBandSpec>() {
+                Some(spec) => spec.clone(),
+                None => return Ok(Pmt::InvalidValue),
+            },
             _ => Default::default(),
         };
         self.spec = spec;
@@ -124,7 +140,9 @@ impl BaseBandSink {
         _meta: &mut BlockMeta,
         _p: Pmt,
     ) -> Result<Pmt> {
-        if let Some(writer) = self.writer.as_ref() {
+        if let Some(err) = self.error.take() {
+            return Ok(Pmt::String(err));
+        } else if let Some(writer) = self.writer.as_ref() {
             let duration_secs = writer.duration() / self.spec.sample_rate;
             return Ok(Pmt::F32(duration_secs as f32));
         } else {
@@ -146,25 +164,9 @@ impl Kernel for BaseBandSink {
         let items = i.len();
         if items > 0 {
             if let Some(writer) = self.writer.as_mut() {
-                for t in i {
-                    match self.spec.format {
-                        BaseBandFormat::f32 => {
-                            writer.write_sample(t.re).unwrap();
-                            writer.write_sample(t.im).unwrap();
-                        }
-                        BaseBandFormat::i16 => {
-                            writer
-                                .write_sample((t.re * i16::MAX as f32) as i16)
-                                .unwrap();
-                            writer
-                                .write_sample((t.im * i16::MAX as f32) as i16)
-                                .unwrap();
-                        }
-                        BaseBandFormat::i8 => {
-                            writer.write_sample((t.re * i8::MAX as f32) as i8).unwrap();
-                            writer.write_sample((t.im * i8::MAX as f32) as i8).unwrap();
-                        }
-                    }
+                if let Err(e) = write_samples(writer, &self.spec.format, i) {
+                    self.error = Some(e.to_string());
+                    self.writer = None;
                 }
             }
         }
@@ -176,4 +178,38 @@ impl Kernel for BaseBandSink {
         sio.input(0).consume(items);
         Ok(())
     }
+
+    async fn deinit(&mut self, _mio: &mut MessageIo<Self>, _meta: &mut BlockMeta) -> Result<()> {
+        //Make sure the header is valid even if the flowgraph stops mid recording
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_samples(
+    writer: &mut BaseBandWriter,
+    format: &BaseBandFormat,
+    samples: &[Complex32],
+) -> core::result::Result<(), hound::Error> {
+    for t in samples {
+        match format {
+            BaseBandFormat::f32 => {
+                writer.write_sample(t.re)?;
+                writer.write_sample(t.im)?;
+            }
+            BaseBandFormat::i16 => {
+                writer.write_sample((t.re * i16::MAX as f32) as i16)?;
+                writer.write_sample((t.im * i16::MAX as f32) as i16)?;
+            }
+            BaseBandFormat::i8 => {
+                writer.write_sample((t.re * i8::MAX as f32) as i8)?;
+                writer.write_sample((t.im * i8::MAX as f32) as i8)?;
+            }
+        }
+    }
+
+    core::result::Result::Ok(())
 }
@@ -0,0 +1,796 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use chrono::{DateTime, Utc};
+
+use iced::widget::canvas::{self, event};
+use iced::widget::{
+    button, column, container, image, pick_list, row, scrollable, text, text_input, toggler, Column,
+};
+use iced::{mouse, Color, Element, Length, Rectangle};
+use plotters::{coord::Shift, prelude::*};
+use plotters_backend::DrawingBackend;
+use plotters_iced::{plotters_backend, Chart, ChartBuilder, ChartWidget, DrawingArea};
+
+use rustic_sdr::decoders::adsb::{AircraftTable, AIRCRAFT_TIMEOUT, BEAST_PORT, SBS_PORT};
+use rustic_sdr::decoders::ais::{AisState, VESSEL_TIMEOUT};
+use rustic_sdr::decoders::aprs::{AprsLog, KISS_PORT};
+use rustic_sdr::decoders::apt::{AptImage, LINE_WORDS, WORD_RATE};
+use rustic_sdr::decoders::cw::CwText;
+use rustic_sdr::decoders::digi::{
+    DigiMode, DigiSettings, DigiState, SPECTRUM_BINS, SPECTRUM_MAX_HZ,
+};
+use rustic_sdr::decoders::ism::IsmLog;
+use rustic_sdr::decoders::pocsag::PagerLog;
+use rustic_sdr::decoders::rtty::{BAUDS, SHIFTS};
+use rustic_sdr::decoders::selcall::SelcallLog;
+use rustic_sdr::decoders::DecoderState;
+
+use crate::Message;
+
+//Height of each decoder's panel
+const PANEL_HEIGHT: f32 = 180.0;
+//Newest APT lines shown live, every other word of each
+const APT_PREVIEW_LINES: usize = 360;
+//Width of the text panes beside the waterfall
+const SIDE_WIDTH: f32 = 320.0;
+//Newest characters shown in a text pane
+const SIDE_TEXT_LEN: usize = 1_000;
+//Height of the audio spectrum RTTY and PSK31 are tuned on
+const TUNE_HEIGHT: f32 = 100.0;
+//Gap between the frequency lines on it
+const TUNE_GRID_HZ: f64 = 500.0;
+const KEY_COLOR: Color = Color {
+    r: 0.33,
+    g: 1.0,
+    b: 0.33,
+    a: 1.0,
+};
+const ERROR_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.33,
+    b: 0.33,
+    a: 1.0,
+};
+
+/// host:port typed into a panel and what it resolved to once applied
+#[derive(Debug, Default)]
+pub struct ForwardTarget {
+    pub input: String,
+    /// The applied host:port and its address, None if it didn't resolve
+    pub applied: Option<(String, Option<SocketAddr>)>,
+}
+
+impl ForwardTarget {
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.applied.as_ref().and_then(|(_, addr)| *addr)
+    }
+
+    //Error to show under the input, if the applied address didn't resolve
+    fn error(&self) -> Option<String> {
+        match self.applied.as_ref()? {
+            (input, None) if !input.is_empty() => Some(format!("Can't resolve {}", input)),
+            _ => None,
+        }
+    }
+}
+
+/// Decoder options set from the GUI, kept so they survive the SDR restarting
+#[derive(Debug, Default)]
+pub struct DecoderSettings {
+    /// Where AIS sentences are forwarded to, empty for none
+    pub ais_forward: ForwardTarget,
+    /// JSON-lines file pages are written to
+    pub pager_json: Option<String>,
+    /// Where ISM events are sent to as JSON, empty for none
+    pub ism_forward: ForwardTarget,
+    /// JSON-lines file ISM events are written to
+    pub ism_json: Option<String>,
+    /// RTTY/PSK31 mode and tuning
+    pub digi: DigiSettings,
+}
+
+impl DecoderSettings {
+    /// Hands the settings to a freshly created decoder
+    pub fn apply(&self, state: &DecoderState) {
+        match state {
+            DecoderState::Ais(ais) => {
+                if let Ok(mut ais) = ais.lock() {
+                    ais.forward = self.ais_forward.addr();
+                }
+            }
+            DecoderState::Pocsag(log) => {
+                if let Ok(mut log) = log.lock() {
+                    log.json_path = self.pager_json.as_ref().map(|path| path.into());
+                    if log.json_path.is_some() {
+                        log.error = None;
+                    }
+                }
+            }
+            DecoderState::Ism(log) => {
+                if let Ok(mut log) = log.lock() {
+                    log.forward = self.ism_forward.addr();
+                    log.json_path = self.ism_json.as_ref().map(|path| path.into());
+                    log.error = None;
+                }
+            }
+            DecoderState::Digi(digi) => {
+                if let Ok(mut digi) = digi.lock() {
+                    digi.settings = self.digi.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// First address `addr` resolves to, looked up on its own thread as it can block for seconds.
+/// None if it is empty or doesn't resolve.
+pub fn resolve(addr: String) -> impl std::future::Future<Output = Option<SocketAddr>> {
+    let (tx, rx) = iced::futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(
+            addr.to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next()),
+        );
+    });
+    async move { rx.await.ok().flatten() }
+}
+
+/// Saves a copy of the image on its own thread as encoding a whole pass takes a while.
+/// Gives the file name it was saved as.
+pub fn save_apt_png(image: AptImage) -> impl std::future::Future<Output = Result<String, String>> {
+    let (tx, rx) = iced::futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let file_name = image.file_name();
+        let saved = image.save_png(std::path::Path::new(&file_name));
+        let _ = tx.send(saved.map(|()| file_name));
+    });
+    async move {
+        rx.await
+            .unwrap_or_else(|_| Err("Saving the APT image failed".into()))
+    }
+}
+
+/// Scaled down picture of the newest APT lines, only rebuilt when lines arrive
+#[derive(Debug, Default)]
+pub struct AptPreview {
+    //Line count and pass start the handle was made from
+    made_from: (usize, Option<DateTime<Utc>>),
+    handle: Option<image::Handle>,
+}
+
+impl AptPreview {
+    pub fn update(&mut self, apt: &AptImage) {
+        let made_from = (apt.lines.len(), apt.start);
+        if made_from == self.made_from {
+            return;
+        }
+        self.made_from = made_from;
+
+        let shown = &apt.lines[apt.lines.len().saturating_sub(APT_PREVIEW_LINES)..];
+        let width = LINE_WORDS / 2;
+        let mut pixels = Vec::with_capacity(width * shown.len() * 4);
+        for line in shown {
+            for val in line.iter().step_by(2) {
+                pixels.extend([*val, *val, *val, 255]);
+            }
+        }
+        self.handle = (!shown.is_empty())
+            .then(|| image::Handle::from_pixels(width as u32, shown.len() as u32, pixels));
+    }
+}
+
+/// Panel showing what a decoder has found, None for decoders shown elsewhere
+pub fn view<'a>(
+    state: &DecoderState,
+    settings: &'a DecoderSettings,
+    apt_preview: &AptPreview,
+) -> Option<Element<'a, Message>> {
+    match state {
+        DecoderState::Rds(_) | DecoderState::Cw(_) => None,
+        DecoderState::Adsb(table) => table.lock().ok().map(|table| aircraft_view(&table)),
+        DecoderState::Ais(ais) => ais.lock().ok().map(|ais| vessel_view(&ais, settings)),
+        DecoderState::Pocsag(log) => log.lock().ok().map(|log| pager_view(&log)),
+        DecoderState::Aprs(log) => log.lock().ok().map(|log| packet_view(&log)),
+        DecoderState::Ism(log) => log.lock().ok().map(|log| event_view(&log, settings)),
+        DecoderState::Apt(image) => image.lock().ok().map(|image| apt_view(&image, apt_preview)),
+        DecoderState::Digi(digi) => digi.lock().ok().map(|digi| digi_view(&digi)),
+        DecoderState::Selcall(log) => log.lock().ok().map(|log| selcall_view(&log)),
+    }
+}
+
+/// Text panes of the decoders that are shown beside the waterfall, None if none are running
+pub fn side_view<'a>(
+    states: impl Iterator<Item = &'a DecoderState>,
+) -> Option<Element<'a, Message>> {
+    let panes: Vec<Element<'a, Message>> = states
+        .filter_map(|state| match state {
+            DecoderState::Cw(text) => text.lock().ok().map(|text| cw_view(&text)),
+            DecoderState::Digi(digi) => digi.lock().ok().map(|digi| digi_text_view(&digi)),
+            _ => None,
+        })
+        .collect();
+    (!panes.is_empty()).then(|| {
+        Column::with_children(panes)
+            .spacing(5)
+            .width(Length::Fixed(SIDE_WIDTH))
+            .into()
+    })
+}
+
+//The newest part of a decoded text
+fn text_tail(val: &str) -> String {
+    let mut start = val.len().saturating_sub(SIDE_TEXT_LEN);
+    while !val.is_char_boundary(start) {
+        start += 1;
+    }
+    val[start..].to_string()
+}
+
+fn cell(val: String, width: f32) -> Element<'static, Message> {
+    text(val).size(14).width(Length::Fixed(width)).into()
+}
+
+fn aircraft_view(table: &AircraftTable) -> Element<'static, Message> {
+    const WIDTHS: [f32; 9] = [70.0, 90.0, 80.0, 70.0, 60.0, 80.0, 170.0, 60.0, 50.0];
+    let header = [
+        "ICAO", "Callsign", "Alt (ft)", "GS (kt)", "Track", "V/S", "Position", "Msgs", "Seen",
+    ];
+
+    let mut rows = vec![row(header
+        .iter()
+        .zip(WIDTHS)
+        .map(|(name, width)| cell(name.to_string(), width)))
+    .into()];
+    for aircraft in table
+        .aircraft
+        .values()
+        .filter(|aircraft| aircraft.last_seen.elapsed() < AIRCRAFT_TIMEOUT)
+    {
+        let opt = |val: Option<String>| val.unwrap_or_default();
+        let vals = [
+            format!("{:06X}", aircraft.icao),
+            opt(aircraft.callsign.clone()),
+            opt(aircraft.altitude_ft.map(|alt| alt.to_string())),
+            opt(aircraft.ground_speed_kt.map(|gs| format!("{:.0}", gs))),
+            opt(aircraft.track_deg.map(|track| format!("{:.0}°", track))),
+            opt(aircraft.vertical_rate_fpm.map(|rate| rate.to_string())),
+            opt(aircraft
+                .position
+                .map(|(lat, lon)| format!("{:.4}, {:.4}", lat, lon))),
+            aircraft.messages.to_string(),
+            format!("{}s", aircraft.last_seen.elapsed().as_secs()),
+        ];
+        rows.push(
+            row(vals
+                .into_iter()
+                .zip(WIDTHS)
+                .map(|(val, width)| cell(val, width)))
+            .into(),
+        );
+    }
+
+    let mut summary = column![text(format!(
+        "ADS-B: {} aircraft, {} messages, {}",
+        rows.len() - 1,
+        table.messages,
+        match table.feed_host {
+            Some(host) => format!(
+                "{} feed clients (SBS-1 {}, Beast {})",
+                table.clients,
+                SocketAddr::from((host, SBS_PORT)),
+                SocketAddr::from((host, BEAST_PORT))
+            ),
+            None => "feeds off".into(),
+        }
+    ))];
+    if let Some(error) = table.error.as_ref() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+
+    container(column![
+        summary,
+        scrollable(Column::with_children(rows).spacing(2)).height(Length::Fixed(PANEL_HEIGHT))
+    ])
+    .padding(5)
+    .into()
+}
+
+fn vessel_view<'a>(state: &AisState, settings: &'a DecoderSettings) -> Element<'a, Message> {
+    const WIDTHS: [f32; 8] = [100.0, 180.0, 80.0, 170.0, 70.0, 70.0, 140.0, 60.0];
+    let header = [
+        "MMSI",
+        "Name",
+        "Callsign",
+        "Position",
+        "SOG (kn)",
+        "COG",
+        "Destination",
+        "Seen",
+    ];
+
+    let mut rows = vec![row(header
+        .iter()
+        .zip(WIDTHS)
+        .map(|(name, width)| cell(name.to_string(), width)))
+    .into()];
+    for vessel in state
+        .vessels
+        .values()
+        .filter(|vessel| vessel.last_seen.elapsed() < VESSEL_TIMEOUT)
+    {
+        let opt = |val: Option<String>| val.unwrap_or_default();
+        let vals = [
+            format!("{:09}", vessel.mmsi),
+            opt(vessel.name.clone()),
+            opt(vessel.callsign.clone()),
+            opt(vessel
+                .position
+                .map(|(lat, lon)| format!("{:.5}, {:.5}", lat, lon))),
+            opt(vessel.speed_kn.map(|sog| format!("{:.1}", sog))),
+            opt(vessel.course_deg.map(|cog| format!("{:.0}°", cog))),
+            opt(vessel.destination.clone()),
+            format!("{}s", vessel.last_seen.elapsed().as_secs()),
+        ];
+        rows.push(
+            row(vals
+                .into_iter()
+                .zip(WIDTHS)
+                .map(|(val, width)| cell(val, width)))
+            .into(),
+        );
+    }
+
+    let mut summary = column![row!(
+        text(format!(
+            "AIS: {} vessels, {} messages, NMEA over UDP to ",
+            rows.len() - 1,
+            state.messages
+        )),
+        text_input("127.0.0.1:10110", &settings.ais_forward.input)
+            .on_input(Message::AisForward)
+            .on_submit(Message::ApplyAisForward)
+            .width(200),
+        button("Apply").on_press(Message::ApplyAisForward),
+    )
+    .spacing(5)
+    .align_items(iced::Alignment::Center)];
+    if let Some(error) = settings.ais_forward.error() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+    if let Some(error) = state.error.as_ref() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+
+    container(column![
+        summary,
+        scrollable(Column::with_children(rows).spacing(2)).height(Length::Fixed(PANEL_HEIGHT))
+    ])
+    .padding(5)
+    .into()
+}
+
+fn pager_view(log: &PagerLog) -> Element<'static, Message> {
+    const WIDTHS: [f32; 6] = [80.0, 100.0, 50.0, 80.0, 60.0, 600.0];
+    let header = ["Time", "Freq (MHz)", "Baud", "Address", "Func", "Message"];
+
+    let mut rows = vec![row(header
+        .iter()
+        .zip(WIDTHS)
+        .map(|(name, width)| cell(name.to_string(), width)))
+    .into()];
+    for message in log.messages.iter() {
+        let mut page = message.text.clone();
+        if message.errors > 0 {
+            page += &format!(" [{} bad codewords]", message.errors);
+        }
+        let vals = [
+            message.time.format("%H:%M:%S").to_string(),
+            message
+                .freq_hz
+                .map(|hz| format!("{:.4}", hz / 1_000_000.0))
+                .unwrap_or_default(),
+            message.baud.to_string(),
+            message.address.to_string(),
+            format!("{} {}", message.function, message.content),
+            page,
+        ];
+        rows.push(
+            row(vals
+                .into_iter()
+                .zip(WIDTHS)
+                .map(|(val, width)| cell(val, width)))
+            .into(),
+        );
+    }
+
+    let mut summary = column![row!(
+        text(format!("POCSAG: {} messages ", log.total)),
+        toggler(
+            Some("JSON-lines log".into()),
+            log.json_path.is_some(),
+            Message::PagerJsonLog
+        )
+        .width(Length::Shrink),
+        text(
+            log.json_path
+                .as_ref()
+                .map(|path| format!(" {}", path.display()))
+                .unwrap_or_default()
+        )
+        .size(12),
+    )
+    .align_items(iced::Alignment::Center)];
+    if let Some(error) = log.error.as_ref() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+
+    container(column![
+        summary,
+        scrollable(Column::with_children(rows).spacing(2)).height(Length::Fixed(PANEL_HEIGHT))
+    ])
+    .padding(5)
+    .into()
+}
+
+fn packet_view(log: &AprsLog) -> Element<'static, Message> {
+    const WIDTHS: [f32; 6] = [80.0, 100.0, 200.0, 80.0, 170.0, 500.0];
+    let header = ["Time", "From", "Path", "Type", "Position", "Text"];
+
+    let mut rows = vec![row(header
+        .iter()
+        .zip(WIDTHS)
+        .map(|(name, width)| cell(name.to_string(), width)))
+    .into()];
+    for packet in log.packets.iter() {
+        let mut text = packet.text.clone();
+        if let Some(telemetry) = packet.telemetry.as_ref() {
+            let analog: Vec<String> = telemetry.analog.iter().map(|val| val.to_string()).collect();
+            text = format!(
+                "#{} {} {} {}",
+                telemetry.sequence,
+                analog.join(" "),
+                telemetry.digital,
+                text
+            );
+        }
+        let vals = [
+            packet.time.format("%H:%M:%S").to_string(),
+            packet.source.clone(),
+            std::iter::once(packet.dest.clone())
+                .chain(packet.path.iter().cloned())
+                .collect::<Vec<_>>()
+                .join(","),
+            packet.kind.to_string(),
+            packet
+                .position
+                .map(|(lat, lon)| format!("{:.4}, {:.4}", lat, lon))
+                .unwrap_or_default(),
+            text,
+        ];
+        rows.push(
+            row(vals
+                .into_iter()
+                .zip(WIDTHS)
+                .map(|(val, width)| cell(val, width)))
+            .into(),
+        );
+    }
+
+    let mut summary = column![text(format!(
+        "APRS: {} packets, {}",
+        log.total,
+        match log.kiss_host {
+            Some(host) => format!(
+                "{} KISS clients on {}",
+                log.clients,
+                SocketAddr::from((host, KISS_PORT))
+            ),
+            None => "KISS off".into(),
+        }
+    ))];
+    if let Some(error) = log.error.as_ref() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+
+    container(column![
+        summary,
+        scrollable(Column::with_children(rows).spacing(2)).height(Length::Fixed(PANEL_HEIGHT))
+    ])
+    .padding(5)
+    .into()
+}
+
+fn event_view<'a>(log: &IsmLog, settings: &'a DecoderSettings) -> Element<'a, Message> {
+    const WIDTHS: [f32; 5] = [80.0, 120.0, 50.0, 60.0, 600.0];
+    let header = ["Time", "Model", "Mod", "SNR", "Data"];
+
+    let mut rows = vec![row(header
+        .iter()
+        .zip(WIDTHS)
+        .map(|(name, width)| cell(name.to_string(), width)))
+    .into()];
+    for event in log.events.iter() {
+        let data = event
+            .fields
+            .as_object()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|(key, val)| format!("{}: {}", key, val))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        let vals = [
+            event.time.format("%H:%M:%S").to_string(),
+            event.model.to_string(),
+            event.modulation.to_string(),
+            format!("{:.1} dB", event.snr_db),
+            data,
+        ];
+        rows.push(
+            row(vals
+                .into_iter()
+                .zip(WIDTHS)
+                .map(|(val, width)| cell(val, width)))
+            .into(),
+        );
+    }
+
+    let mut summary = column![row!(
+        text(format!(
+            "ISM: {} events from {} bursts, JSON over UDP to ",
+            log.total, log.bursts
+        )),
+        text_input("127.0.0.1:1433", &settings.ism_forward.input)
+            .on_input(Message::IsmForward)
+            .on_submit(Message::ApplyIsmForward)
+            .width(200),
+        button("Apply").on_press(Message::ApplyIsmForward),
+        toggler(
+            Some(" JSON-lines log".into()),
+            log.json_path.is_some(),
+            Message::IsmJsonLog
+        )
+        .width(Length::Shrink),
+        text(
+            log.json_path
+                .as_ref()
+                .map(|path| format!(" {}", path.display()))
+                .unwrap_or_default()
+        )
+        .size(12),
+    )
+    .spacing(5)
+    .align_items(iced::Alignment::Center)];
+    if let Some(error) = settings.ism_forward.error() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+    if let Some(error) = log.error.as_ref() {
+        summary = summary.push(text(error).style(ERROR_COLOR));
+    }
+
+    container(column![
+        summary,
+        scrollable(Column::with_children(rows).spacing(2)).height(Length::Fixed(PANEL_HEIGHT))
+    ])
+    .padding(5)
+    .into()
+}
+
+fn apt_view(apt: &AptImage, preview: &AptPreview) -> Element<'static, Message> {
+    let mut summary = row!(
+        text(format!(
+            "NOAA APT: {} lines, {:.0} s{} ",
+            apt.lines.len(),
+            apt.lines.len() as f64 * LINE_WORDS as f64 / WORD_RATE,
+            if apt.synced { ", synced" } else { "" }
+        )),
+        button("Save PNG").on_press(Message::SaveAptImage),
+        button("New image").on_press(Message::ClearAptImage),
+    )
+    .spacing(5)
+    .align_items(iced::Alignment::Center);
+    if let Some(start) = apt.start {
+        summary = summary.push(text(format!(
+            "Pass started {}",
+            start.format("%H:%M:%S UTC")
+        )));
+    }
+
+    let preview: Element<'static, Message> = match preview.handle.clone() {
+        Some(handle) => image(handle).height(Length::Fixed(PANEL_HEIGHT)).into(),
+        None => text("Waiting for sync").into(),
+    };
+
+    container(column![summary, preview].spacing(5))
+        .padding(5)
+        .into()
+}
+
+fn cw_view(cw: &CwText) -> Element<'static, Message> {
+    let key = if cw.key_down {
+        text("●").style(KEY_COLOR)
+    } else {
+        text("○")
+    };
+    container(column![
+        row!(
+            text(format!("CW: {:.0} WPM at {:.0} Hz ", cw.wpm, cw.pitch_hz)),
+            key
+        ),
+        scrollable(text(text_tail(&cw.text)).size(16)).height(Length::Fill),
+    ])
+    .padding(5)
+    .height(Length::Fill)
+    .into()
+}
+
+fn selcall_view(log: &SelcallLog) -> Element<'static, Message> {
+    const WIDTHS: [f32; 4] = [80.0, 100.0, 60.0, 600.0];
+    let header = ["Time", "Freq (MHz)", "Type", "Digits"];
+
+    let mut rows = vec![row(header
+        .iter()
+        .zip(WIDTHS)
+        .map(|(name, width)| cell(name.to_string(), width)))
+    .into()];
+    for event in log.events.iter() {
+        let vals = [
+            event.time.format("%H:%M:%S").to_string(),
+            event
+                .freq_hz
+                .map(|hz| format!("{:.4}", hz / 1_000_000.0))
+                .unwrap_or_default(),
+            event.kind.to_string(),
+            event.digits.clone(),
+        ];
+        rows.push(
+            row(vals
+                .into_iter()
+                .zip(WIDTHS)
+                .map(|(val, width)| cell(val, width)))
+            .into(),
+        );
+    }
+
+    container(column![
+        text(format!("DTMF/Selcall: {} sequences", log.total)),
+        scrollable(Column::with_children(rows).spacing(2)).height(Length::Fixed(PANEL_HEIGHT))
+    ])
+    .padding(5)
+    .into()
+}
+
+fn digi_view(digi: &DigiState) -> Element<'static, Message> {
+    let settings = &digi.settings;
+    let mut summary = row!(
+        text("Mode"),
+        pick_list(&DigiMode::ALL[..], Some(settings.mode), Message::DigiMode),
+        text(format!("at {:.0} Hz", settings.tone_hz)),
+    )
+    .spacing(5)
+    .align_items(iced::Alignment::Center);
+    if settings.mode == DigiMode::Rtty {
+        summary = summary
+            .push(pick_list(
+                &BAUDS[..],
+                Some(settings.baud),
+                Message::DigiBaud,
+            ))
+            .push(text("baud"))
+            .push(pick_list(
+                SHIFTS,
+                Some(settings.shift_hz),
+                Message::DigiShift,
+            ))
+            .push(text("Hz shift"));
+    }
+    summary = summary.push(
+        toggler(
+            Some("Reverse".into()),
+            settings.reverse,
+            Message::DigiReverse,
+        )
+        .width(Length::Shrink),
+    );
+    if let Some(quality) = digi.quality {
+        summary = summary.push(text(format!("Quality {:.0}%", quality.max(0.0) * 100.0)));
+    }
+    summary = summary.push(button("Clear").on_press(Message::ClearDigiText));
+
+    let tune = ChartWidget::new(TuneChart {
+        spectrum: digi.spectrum.clone(),
+        tones: settings.tones(),
+    })
+    .width(Length::Fill)
+    .height(Length::Fixed(TUNE_HEIGHT));
+
+    container(column![summary, tune].spacing(5))
+        .padding(5)
+        .into()
+}
+
+fn digi_text_view(digi: &DigiState) -> Element<'static, Message> {
+    container(column![
+        text(format!(
+            "{} at {:.0} Hz",
+            digi.settings.mode, digi.settings.tone_hz
+        )),
+        scrollable(text(text_tail(&digi.text)).size(16)).height(Length::Fill),
+    ])
+    .padding(5)
+    .height(Length::Fill)
+    .into()
+}
+
+//Audio spectrum from 0 to SPECTRUM_MAX_HZ across the whole width, clicking it tunes there
+struct TuneChart {
+    spectrum: Vec<f32>,
+    tones: Vec<f64>,
+}
+
+impl Chart<Message> for TuneChart {
+    type State = ();
+    // leave it empty
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, _builder: ChartBuilder<DB>) {}
+
+    fn draw_chart<DB: DrawingBackend>(&self, _state: &Self::State, root: DrawingArea<DB, Shift>) {
+        if self.spectrum.is_empty() {
+            return;
+        }
+        let max = self.spectrum.iter().copied().fold(f32::MIN, f32::max);
+        //Noise floor near the bottom, 40 dB up to the strongest signal
+        let min = max - 40.0;
+        let Ok(mut chart) =
+            ChartBuilder::on(&root).build_cartesian_2d(0.0..SPECTRUM_MAX_HZ, min..max)
+        else {
+            return;
+        };
+
+        let bin_hz = SPECTRUM_MAX_HZ / (SPECTRUM_BINS - 1) as f64;
+        let grid =
+            (1..(SPECTRUM_MAX_HZ / TUNE_GRID_HZ) as usize).map(|idx| idx as f64 * TUNE_GRID_HZ);
+        let _ = chart.draw_series(
+            grid.clone()
+                .map(|hz| PathElement::new(vec![(hz, min), (hz, max)], WHITE.mix(0.2))),
+        );
+        let _ = chart.draw_series(grid.map(|hz| {
+            Text::new(
+                format!("{:.0}", hz),
+                (hz, max),
+                ("sans-serif", 11).into_font().color(&WHITE.mix(0.5)),
+            )
+        }));
+        let _ = chart.draw_series(LineSeries::new(
+            self.spectrum
+                .iter()
+                .enumerate()
+                .map(|(idx, val)| (idx as f64 * bin_hz, val.max(min))),
+            &full_palette::ORANGE,
+        ));
+        let _ = chart.draw_series(
+            self.tones
+                .iter()
+                .map(|hz| PathElement::new(vec![(*hz, min), (*hz, max)], RED)),
+        );
+    }
+
+    fn update(
+        &self,
+        _state: &mut Self::State,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> (event::Status, Option<Message>) {
+        if let canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) = event {
+            if let Some(pos) = cursor.position_in(bounds) {
+                let hz = pos.x as f64 / bounds.width as f64 * SPECTRUM_MAX_HZ;
+                return (event::Status::Captured, Some(Message::DigiTune(hz)));
+            }
+        }
+        (event::Status::Ignored, None)
+    }
+}
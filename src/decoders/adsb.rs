@@ -0,0 +1,683 @@
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futuresdr::num_complex::Complex32;
+
+use super::net::TcpFeed;
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::Resampler;
+
+/// Rate the demodulator works at, two samples per Mode S bit
+pub const ADSB_RATE: f64 = 2_000_000.0;
+/// Port the SBS-1 (BaseStation) text feed is served on
+pub const SBS_PORT: u16 = 30003;
+/// Port the Beast binary feed is served on
+pub const BEAST_PORT: u16 = 30005;
+/// Aircraft not heard from for this long are dropped from the table
+pub const AIRCRAFT_TIMEOUT: Duration = Duration::from_secs(60);
+
+//8 µs preamble followed by up to 112 bits of 1 µs each
+const PREAMBLE: usize = 16;
+const LONG_BITS: usize = 112;
+const WINDOW: usize = PREAMBLE + LONG_BITS * 2;
+//Generator of the Mode S parity, x^24 left out
+const CRC_POLY: u32 = 0xFFF409;
+//Beast timestamps count a 12 MHz clock
+const BEAST_TICKS: u64 = 6;
+//Even and odd CPR frames further apart than this aren't combined
+const CPR_MAX_AGE: Duration = Duration::from_secs(10);
+//Rate below which the 0.5 µs pulses can't be told apart
+const MIN_RATE: f64 = 1_900_000.0;
+const CALLSIGN_CHARS: &[u8; 64] =
+    b"#ABCDEFGHIJKLMNOPQRSTUVWXYZ##### ###############0123456789######";
+
+#[derive(Debug, Clone, Copy)]
+struct CprFrame {
+    lat: u32,
+    lon: u32,
+    time: Instant,
+}
+
+/// One row of the aircraft table
+#[derive(Debug, Clone)]
+pub struct Aircraft {
+    pub icao: u32,
+    pub callsign: Option<String>,
+    /// Barometric altitude in feet
+    pub altitude_ft: Option<i32>,
+    pub ground_speed_kt: Option<f64>,
+    /// Track over ground in degrees from true north
+    pub track_deg: Option<f64>,
+    pub vertical_rate_fpm: Option<i32>,
+    /// Latitude and longitude in degrees
+    pub position: Option<(f64, f64)>,
+    pub messages: u64,
+    pub last_seen: Instant,
+    //Latest even and odd CPR position
+    cpr: [Option<CprFrame>; 2],
+}
+
+impl Aircraft {
+    fn new(icao: u32, now: Instant) -> Self {
+        Self {
+            icao,
+            callsign: None,
+            altitude_ft: None,
+            ground_speed_kt: None,
+            track_deg: None,
+            vertical_rate_fpm: None,
+            position: None,
+            messages: 0,
+            last_seen: now,
+            cpr: [None; 2],
+        }
+    }
+}
+
+/// Aircraft heard recently, keyed by ICAO address
+#[derive(Debug, Default)]
+pub struct AircraftTable {
+    pub aircraft: BTreeMap<u32, Aircraft>,
+    /// DF17/18 messages that passed the CRC
+    pub messages: u64,
+    /// Address the SBS-1 and Beast ports listen on, None when they aren't served
+    pub feed_host: Option<IpAddr>,
+    /// Clients connected to the SBS-1 and Beast ports
+    pub clients: usize,
+    /// Why the decoder or its network feeds aren't working
+    pub error: Option<String>,
+}
+
+//What an SBS-1 line reports
+#[derive(Debug, Clone, Copy)]
+enum SbsType {
+    Identification = 1,
+    Position = 3,
+    Velocity = 4,
+}
+
+/// Mode S extended squitter (DF17/18) decoder on the raw 1090 MHz samples
+pub struct AdsbDecoder {
+    table: Arc<Mutex<AircraftTable>>,
+    //Only used when the device isn't at exactly `ADSB_RATE`
+    resampler: Option<(f64, Resampler<f32>)>,
+    magnitude: Vec<f32>,
+    //Magnitude waiting to be searched, the tail of one block is kept for the next
+    mag: Vec<f32>,
+    //Samples dropped from the front of `mag` so far, for the Beast timestamps
+    samples: u64,
+    //Single bit errors by syndrome
+    corrections: HashMap<u32, usize>,
+    //SBS-1 and Beast
+    feeds: Option<(TcpFeed, TcpFeed)>,
+}
+
+impl AdsbDecoder {
+    /// Serves the SBS-1 and Beast feeds on `feed_host`, or not at all for None
+    pub fn new(feed_host: Option<IpAddr>) -> Self {
+        let mut corrections = HashMap::new();
+        //The downlink format bits are left alone so a corrupt short message can't pass as DF17
+        for bit in 5..LONG_BITS {
+            let mut msg = [0u8; LONG_BITS / 8];
+            msg[bit / 8] = 0x80 >> (bit % 8);
+            corrections.insert(crc(&msg), bit);
+        }
+
+        Self {
+            table: Arc::new(Mutex::new(AircraftTable {
+                feed_host,
+                ..Default::default()
+            })),
+            resampler: None,
+            magnitude: Vec::new(),
+            mag: Vec::new(),
+            samples: 0,
+            corrections,
+            feeds: feed_host.map(|host| {
+                (
+                    TcpFeed::new(SocketAddr::from((host, SBS_PORT))),
+                    TcpFeed::new(SocketAddr::from((host, BEAST_PORT))),
+                )
+            }),
+        }
+    }
+
+    pub fn table(&self) -> Arc<Mutex<AircraftTable>> {
+        self.table.clone()
+    }
+
+    //Message length in samples after the preamble if one starts at `pos`
+    fn detect(&mut self, pos: usize) -> Option<usize> {
+        let m = &self.mag[pos..pos + WINDOW];
+        //Pulses at 0, 1, 3.5 and 4.5 µs with quiet in between
+        let shape = m[0] > m[1]
+            && m[1] < m[2]
+            && m[2] > m[3]
+            && m[3] < m[0]
+            && m[4] < m[0]
+            && m[5] < m[0]
+            && m[6] < m[0]
+            && m[7] > m[8]
+            && m[8] < m[9]
+            && m[9] > m[6];
+        if !shape {
+            return None;
+        }
+        let high = (m[0] + m[2] + m[7] + m[9]) / 6.0;
+        if m[4] >= high || m[5] >= high || m[11..PREAMBLE].iter().any(|x| *x >= high) {
+            return None;
+        }
+
+        //Pulse position: a one is high in the first half of the bit
+        let mut msg = [0u8; LONG_BITS / 8];
+        for bit in 0..LONG_BITS {
+            let idx = PREAMBLE + bit * 2;
+            if m[idx] > m[idx + 1] {
+                msg[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+        let signal = (m[0] + m[2] + m[7] + m[9]) / 4.0;
+
+        let df = msg[0] >> 3;
+        if df != 17 && df != 18 {
+            return None;
+        }
+        let syndrome = crc(&msg);
+        if syndrome != 0 {
+            let bit = *self.corrections.get(&syndrome)?;
+            msg[bit / 8] ^= 0x80 >> (bit % 8);
+        }
+
+        self.message(&msg, signal, self.samples + pos as u64);
+        Some(LONG_BITS * 2)
+    }
+
+    fn message(&mut self, msg: &[u8; LONG_BITS / 8], signal: f32, sample: u64) {
+        if let Some((_, beast)) = self.feeds.as_mut() {
+            beast.send(&beast_frame(msg, sample * BEAST_TICKS, signal));
+        }
+
+        let now = Instant::now();
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+        table.messages += 1;
+        table
+            .aircraft
+            .retain(|_, aircraft| now.duration_since(aircraft.last_seen) < AIRCRAFT_TIMEOUT);
+
+        let icao = bits(msg, 8, 24);
+        let aircraft = table
+            .aircraft
+            .entry(icao)
+            .or_insert_with(|| Aircraft::new(icao, now));
+        aircraft.messages += 1;
+        aircraft.last_seen = now;
+
+        //Type code of the extended squitter payload, which starts at bit 32
+        let sbs = match bits(msg, 32, 5) {
+            1..=4 => {
+                let callsign: String = (0..8)
+                    .map(|idx| CALLSIGN_CHARS[bits(msg, 40 + idx * 6, 6) as usize] as char)
+                    .collect();
+                aircraft.callsign = Some(callsign.trim_end().to_string());
+                Some(SbsType::Identification)
+            }
+            tc @ (9..=18 | 20..=22) => {
+                //20 to 22 carry GNSS height, only the barometric altitude is shown
+                if tc <= 18 {
+                    aircraft.altitude_ft = altitude(bits(msg, 40, 12));
+                }
+                let frame = CprFrame {
+                    lat: bits(msg, 54, 17),
+                    lon: bits(msg, 71, 17),
+                    time: now,
+                };
+                let odd = bits(msg, 53, 1) as usize;
+                aircraft.cpr[odd] = Some(frame);
+                if let [Some(even), Some(odd)] = aircraft.cpr {
+                    let age = if even.time > odd.time {
+                        even.time - odd.time
+                    } else {
+                        odd.time - even.time
+                    };
+                    if age < CPR_MAX_AGE {
+                        if let Some(position) = cpr_global(even, odd) {
+                            aircraft.position = Some(position);
+                        }
+                    }
+                }
+                Some(SbsType::Position)
+            }
+            19 => {
+                velocity(msg, aircraft);
+                Some(SbsType::Velocity)
+            }
+            _ => None,
+        };
+
+        let Some((sbs_feed, beast)) = self.feeds.as_mut() else {
+            return;
+        };
+        if let Some(kind) = sbs {
+            sbs_feed.send(sbs_line(kind, aircraft, Utc::now()).as_bytes());
+        }
+        table.clients = sbs_feed.clients() + beast.clients();
+        table.error = sbs_feed
+            .error()
+            .or(beast.error())
+            .map(|e| format!("Feed port unavailable, {}", e));
+    }
+}
+
+impl Default for AdsbDecoder {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Decoder for AdsbDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Adsb
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Raw
+    }
+
+    fn process_complex(&mut self, samples: &[Complex32], rate: f64) {
+        if rate < MIN_RATE {
+            if let Ok(mut table) = self.table.lock() {
+                table.error = Some(format!(
+                    "ADS-B needs at least {:.1} Msps",
+                    ADSB_RATE / 1_000_000.0
+                ));
+            }
+            return;
+        }
+
+        if (rate - ADSB_RATE).abs() < 1.0 {
+            self.mag.extend(samples.iter().map(|x| x.norm()));
+        } else {
+            if self.resampler.as_ref().map(|(r, _)| *r) != Some(rate) {
+                self.resampler = Some((rate, Resampler::new(rate, ADSB_RATE)));
+            }
+            self.magnitude.clear();
+            self.magnitude.extend(samples.iter().map(|x| x.norm()));
+            if let Some((_, resampler)) = self.resampler.as_mut() {
+                resampler.process(&self.magnitude, &mut self.mag);
+            }
+        }
+
+        let mut pos = 0;
+        while pos + WINDOW <= self.mag.len() {
+            pos += match self.detect(pos) {
+                Some(len) => PREAMBLE + len,
+                None => 1,
+            };
+        }
+        self.mag.drain(..pos);
+        self.samples += pos as u64;
+    }
+
+    fn report(&self) -> String {
+        let Ok(table) = self.table.lock() else {
+            return String::new();
+        };
+        let mut report = format!(
+            "{} messages, {} aircraft",
+            table.messages,
+            table.aircraft.len()
+        );
+        for aircraft in table.aircraft.values() {
+            report += &format!(
+                "\n{:06X} {:<8} {:>6} ft {:>4} kt {:>3}° {}",
+                aircraft.icao,
+                aircraft.callsign.as_deref().unwrap_or(""),
+                opt(aircraft.altitude_ft),
+                opt(aircraft.ground_speed_kt.map(|gs| gs.round())),
+                opt(aircraft.track_deg.map(|track| track.round())),
+                aircraft
+                    .position
+                    .map(|(lat, lon)| format!("{:.4}, {:.4}", lat, lon))
+                    .unwrap_or_default()
+            );
+        }
+        report
+    }
+}
+
+//`len` bits of `msg` starting `start` bits from the front
+fn bits(msg: &[u8], start: usize, len: usize) -> u32 {
+    (start..start + len).fold(0, |acc, bit| {
+        (acc << 1) | ((msg[bit / 8] >> (7 - bit % 8)) & 1) as u32
+    })
+}
+
+//Parity of the data bits XORed with the parity field, zero for a good message
+fn crc(msg: &[u8]) -> u32 {
+    let data_bits = msg.len() * 8 - 24;
+    let mut rem: u32 = 0;
+    for bit in 0..data_bits {
+        let top = ((rem >> 23) & 1) ^ bits(msg, bit, 1);
+        rem = (rem << 1) & 0xFF_FFFF;
+        if top == 1 {
+            rem ^= CRC_POLY;
+        }
+    }
+    rem ^ bits(msg, data_bits, 24)
+}
+
+//12 bit altitude field, only the 25 ft encoding, Gray coded altitudes are left out
+fn altitude(ac: u32) -> Option<i32> {
+    if ac & 0x10 == 0 {
+        return None;
+    }
+    let n = ((ac & 0xFE0) >> 1) | (ac & 0xF);
+    Some(n as i32 * 25 - 1000)
+}
+
+fn velocity(msg: &[u8], aircraft: &mut Aircraft) {
+    let subtype = bits(msg, 37, 3);
+    if subtype != 1 && subtype != 2 {
+        return;
+    }
+    //Supersonic aircraft report in units of 4 kt
+    let scale = if subtype == 2 { 4.0 } else { 1.0 };
+    let sign = |bit| if bits(msg, bit, 1) == 1 { -1.0 } else { 1.0 };
+
+    let ew = bits(msg, 46, 10);
+    let ns = bits(msg, 57, 10);
+    if ew != 0 && ns != 0 {
+        let ew = sign(45) * (ew - 1) as f64 * scale;
+        let ns = sign(56) * (ns - 1) as f64 * scale;
+        aircraft.ground_speed_kt = Some(ew.hypot(ns));
+        aircraft.track_deg = Some(ew.atan2(ns).to_degrees().rem_euclid(360.0));
+    }
+
+    let rate = bits(msg, 69, 9);
+    if rate != 0 {
+        aircraft.vertical_rate_fpm = Some((sign(68) * (rate - 1) as f64 * 64.0) as i32);
+    }
+}
+
+//Number of longitude zones at `lat`
+fn cpr_nl(lat: f64) -> f64 {
+    let lat = lat.abs();
+    if lat < 1e-9 {
+        return 59.0;
+    }
+    if lat >= 87.0 {
+        return if lat > 87.0 { 1.0 } else { 2.0 };
+    }
+    let a = 1.0 - (PI / 30.0).cos();
+    let b = (PI / 180.0 * lat).cos().powi(2);
+    (2.0 * PI / (1.0 - a / b).acos()).floor()
+}
+
+//Position from an even and odd frame pair, using whichever is newer
+fn cpr_global(even: CprFrame, odd: CprFrame) -> Option<(f64, f64)> {
+    const SCALE: f64 = 131_072.0;
+    let (lat_e, lon_e) = (even.lat as f64 / SCALE, even.lon as f64 / SCALE);
+    let (lat_o, lon_o) = (odd.lat as f64 / SCALE, odd.lon as f64 / SCALE);
+
+    let j = (59.0 * lat_e - 60.0 * lat_o + 0.5).floor();
+    let wrap = |lat: f64| if lat >= 270.0 { lat - 360.0 } else { lat };
+    let rlat_e = wrap(360.0 / 60.0 * (j.rem_euclid(60.0) + lat_e));
+    let rlat_o = wrap(360.0 / 59.0 * (j.rem_euclid(59.0) + lat_o));
+    if rlat_e.abs() > 90.0 || rlat_o.abs() > 90.0 || cpr_nl(rlat_e) != cpr_nl(rlat_o) {
+        return None;
+    }
+
+    let odd_newer = odd.time > even.time;
+    let (lat, nl, lon_frac) = if odd_newer {
+        (rlat_o, cpr_nl(rlat_o), lon_o)
+    } else {
+        (rlat_e, cpr_nl(rlat_e), lon_e)
+    };
+    let ni = if odd_newer { nl - 1.0 } else { nl }.max(1.0);
+    let m = (lon_e * (nl - 1.0) - lon_o * nl + 0.5).floor();
+    let lon = 360.0 / ni * (m.rem_euclid(ni) + lon_frac);
+    let lon = if lon >= 180.0 { lon - 360.0 } else { lon };
+
+    Some((lat, lon))
+}
+
+fn opt<T: std::fmt::Display>(val: Option<T>) -> String {
+    val.map(|val| val.to_string()).unwrap_or_default()
+}
+
+//BaseStation line, only the fields the message type carries are filled in
+fn sbs_line(kind: SbsType, aircraft: &Aircraft, now: DateTime<Utc>) -> String {
+    let mut fields: [String; 7] = Default::default();
+    match kind {
+        SbsType::Identification => fields[0] = opt(aircraft.callsign.clone()),
+        SbsType::Position => {
+            fields[1] = opt(aircraft.altitude_ft);
+            if let Some((lat, lon)) = aircraft.position {
+                fields[4] = format!("{:.5}", lat);
+                fields[5] = format!("{:.5}", lon);
+            }
+        }
+        SbsType::Velocity => {
+            fields[2] = opt(aircraft.ground_speed_kt.map(|gs| gs.round()));
+            fields[3] = opt(aircraft.track_deg.map(|track| track.round()));
+            fields[6] = opt(aircraft.vertical_rate_fpm);
+        }
+    }
+    let date = now.format("%Y/%m/%d");
+    let time = now.format("%H:%M:%S%.3f");
+
+    format!(
+        "MSG,{},1,1,{:06X},1,{},{},{},{},{},,,,,\r\n",
+        kind as u8,
+        aircraft.icao,
+        date,
+        time,
+        date,
+        time,
+        fields.join(",")
+    )
+}
+
+//Beast binary frame, 0x1a bytes in the body are doubled
+fn beast_frame(msg: &[u8], timestamp: u64, signal: f32) -> Vec<u8> {
+    let kind = if msg.len() == LONG_BITS / 8 {
+        b'3'
+    } else {
+        b'2'
+    };
+    let level = (signal.sqrt().min(1.0) * 255.0) as u8;
+    let timestamp = timestamp.to_be_bytes();
+    let body = timestamp[2..]
+        .iter()
+        .chain(std::iter::once(&level))
+        .chain(msg.iter());
+
+    let mut frame = vec![0x1a, kind];
+    for byte in body {
+        frame.push(*byte);
+        if *byte == 0x1a {
+            frame.push(0x1a);
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Messages from "The 1090 Megahertz Riddle"
+    const IDENTIFICATION: &str = "8D4840D6202CC371C32CE0576098";
+    const EVEN: &str = "8D40621D58C382D690C8AC2863A7";
+    const ODD: &str = "8D40621D58C386435CC412692AD6";
+    const VELOCITY: &str = "8D485020994409940838175B284F";
+
+    fn hex(msg: &str) -> [u8; LONG_BITS / 8] {
+        let mut bytes = [0u8; LONG_BITS / 8];
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&msg[idx * 2..idx * 2 + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    fn frame(msg: &str, time: Instant) -> CprFrame {
+        let msg = hex(msg);
+        CprFrame {
+            lat: bits(&msg, 54, 17),
+            lon: bits(&msg, 71, 17),
+            time,
+        }
+    }
+
+    //Pulse position magnitude at `ADSB_RATE` for each message, with quiet around them
+    fn samples(msgs: &[[u8; LONG_BITS / 8]]) -> Vec<Complex32> {
+        let mut mag = vec![0.0f32; 100];
+        for msg in msgs {
+            let mut preamble = [0.0; PREAMBLE];
+            for idx in [0, 2, 7, 9] {
+                preamble[idx] = 1.0;
+            }
+            mag.extend(preamble);
+            for bit in 0..LONG_BITS {
+                mag.extend(if bits(msg, bit, 1) == 1 {
+                    [1.0, 0.0]
+                } else {
+                    [0.0, 1.0]
+                });
+            }
+            mag.extend([0.0; 100]);
+        }
+        mag.iter().map(|x| Complex32::new(*x, 0.0)).collect()
+    }
+
+    #[test]
+    fn parity_of_good_messages_is_zero() {
+        for msg in [IDENTIFICATION, EVEN, ODD, VELOCITY] {
+            assert_eq!(crc(&hex(msg)), 0, "{}", msg);
+        }
+        let mut msg = hex(EVEN);
+        msg[13] ^= 1;
+        assert_eq!(crc(&msg), 1);
+    }
+
+    #[test]
+    fn single_bit_errors_have_their_own_syndrome() {
+        let decoder = AdsbDecoder::new(None);
+        assert_eq!(decoder.corrections.len(), LONG_BITS - 5);
+        for bit in [5, 40, 87, 111] {
+            let mut msg = hex(IDENTIFICATION);
+            msg[bit / 8] ^= 0x80 >> (bit % 8);
+            assert_eq!(decoder.corrections.get(&crc(&msg)), Some(&bit));
+        }
+    }
+
+    #[test]
+    fn altitude_needs_the_q_bit() {
+        assert_eq!(altitude(bits(&hex(EVEN), 40, 12)), Some(38_000));
+        assert_eq!(altitude(0xC28), None);
+    }
+
+    #[test]
+    fn longitude_zones() {
+        assert_eq!(cpr_nl(0.0), 59.0);
+        assert_eq!(cpr_nl(-10.0), 59.0);
+        assert_eq!(cpr_nl(52.2572), 36.0);
+        assert_eq!(cpr_nl(87.0), 2.0);
+        assert_eq!(cpr_nl(89.0), 1.0);
+    }
+
+    #[test]
+    fn global_position_from_the_newer_frame() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+
+        let (lat, lon) = cpr_global(frame(EVEN, later), frame(ODD, now)).unwrap();
+        assert!((lat - 52.2572).abs() < 1e-4, "{}", lat);
+        assert!((lon - 3.9194).abs() < 1e-4, "{}", lon);
+
+        let (lat, lon) = cpr_global(frame(EVEN, now), frame(ODD, later)).unwrap();
+        assert!((lat - 52.2658).abs() < 1e-4, "{}", lat);
+        assert!((lon - 3.9389).abs() < 1e-4, "{}", lon);
+    }
+
+    #[test]
+    fn airborne_velocity() {
+        let mut aircraft = Aircraft::new(0x485020, Instant::now());
+        velocity(&hex(VELOCITY), &mut aircraft);
+        assert!((aircraft.ground_speed_kt.unwrap() - 159.2).abs() < 0.1);
+        assert!((aircraft.track_deg.unwrap() - 182.88).abs() < 0.01);
+        assert_eq!(aircraft.vertical_rate_fpm, Some(-832));
+    }
+
+    #[test]
+    fn decodes_messages_from_samples() {
+        let mut corrupted = hex(IDENTIFICATION);
+        corrupted[6] ^= 0x10;
+        let mut decoder = AdsbDecoder::new(None);
+        decoder.process_complex(&samples(&[corrupted, hex(EVEN), hex(ODD)]), ADSB_RATE);
+
+        let table = decoder.table();
+        let table = table.lock().unwrap();
+        assert_eq!(table.messages, 3);
+        let klm = &table.aircraft[&0x4840D6];
+        assert_eq!(klm.callsign.as_deref(), Some("KLM1023"));
+        let aircraft = &table.aircraft[&0x40621D];
+        assert_eq!(aircraft.altitude_ft, Some(38_000));
+        let (lat, lon) = aircraft.position.unwrap();
+        assert!((lat - 52.2658).abs() < 1e-4 && (lon - 3.9389).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sbs_position_line() {
+        let mut aircraft = Aircraft::new(0x40621D, Instant::now());
+        aircraft.altitude_ft = Some(38_000);
+        aircraft.position = Some((52.2572, 3.91937));
+        let now = DateTime::parse_from_rfc3339("2024-05-01T13:37:00.250Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let line = sbs_line(SbsType::Position, &aircraft, now);
+        assert!(line.ends_with("\r\n"));
+        let fields: Vec<&str> = line.trim_end().split(',').collect();
+        assert_eq!(fields.len(), 22);
+        assert_eq!(&fields[..5], ["MSG", "3", "1", "1", "40621D"]);
+        assert_eq!(
+            &fields[6..10],
+            ["2024/05/01", "13:37:00.250", "2024/05/01", "13:37:00.250"]
+        );
+        assert_eq!(
+            &fields[10..17],
+            ["", "38000", "", "", "52.25720", "3.91937", ""]
+        );
+    }
+
+    #[test]
+    fn beast_frame_escapes_0x1a() {
+        let mut msg = hex(IDENTIFICATION);
+        msg[3] = 0x1a;
+        let frame = beast_frame(&msg, 0x1a_0000_0001, 1.0);
+        assert_eq!(&frame[..2], [0x1a, b'3']);
+
+        //Undoing the escaping gives the 6 byte timestamp, signal level and message
+        let mut body = Vec::new();
+        let mut escaped = false;
+        for byte in &frame[2..] {
+            if *byte == 0x1a && !escaped {
+                escaped = true;
+                continue;
+            }
+            escaped = false;
+            body.push(*byte);
+        }
+        assert_eq!(frame.len(), 2 + body.len() + 2);
+        assert_eq!(&body[..6], [0x00, 0x1a, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(body[6], 255);
+        assert_eq!(&body[7..], msg);
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futuresdr::num_complex::Complex32;
+
+use super::hdlc::Hdlc;
+use super::net::UdpFeed;
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::{lowpass_taps, num_taps, DcRemover, Fir, FmDiscriminator, Nco, Resampler};
+
+/// Frequency the capture has to be centered on, halfway between the two channels
+pub const AIS_CENTER: f64 = 162_000_000.0;
+/// Vessels not heard from for this long are dropped from the list
+pub const VESSEL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+//Channel 87B (A) and 88B (B) relative to `AIS_CENTER`
+const CHANNELS: [(char, f64); 2] = [('A', -25_000.0), ('B', 25_000.0)];
+const BAUD: f64 = 9_600.0;
+const SAMPLES_PER_BIT: f64 = 5.0;
+const CHANNEL_CUTOFF: f64 = 8_000.0;
+//How far the bit clock is pulled towards each transition
+const CLOCK_GAIN: f64 = 0.3;
+//Longest frame, 5 slots of 256 bits
+const MAX_FRAME_BITS: usize = 5 * 256;
+//Shortest frame worth decoding, 56 bits of message and the FCS
+const MIN_FRAME_BITS: usize = 72;
+//NMEA limits a sentence to 82 characters, which leaves 60 for the payload
+const NMEA_PAYLOAD: usize = 60;
+
+/// A ship or station heard on AIS
+#[derive(Debug, Clone)]
+pub struct Vessel {
+    pub mmsi: u32,
+    pub name: Option<String>,
+    pub callsign: Option<String>,
+    pub destination: Option<String>,
+    /// Latitude and longitude in degrees
+    pub position: Option<(f64, f64)>,
+    /// Speed over ground in knots
+    pub speed_kn: Option<f64>,
+    /// Course over ground in degrees
+    pub course_deg: Option<f64>,
+    pub messages: u64,
+    pub last_seen: Instant,
+}
+
+impl Vessel {
+    fn new(mmsi: u32, now: Instant) -> Self {
+        Self {
+            mmsi,
+            name: None,
+            callsign: None,
+            destination: None,
+            position: None,
+            speed_kn: None,
+            course_deg: None,
+            messages: 0,
+            last_seen: now,
+        }
+    }
+}
+
+/// Vessels heard recently and where the NMEA sentences go
+#[derive(Debug, Default)]
+pub struct AisState {
+    pub vessels: BTreeMap<u32, Vessel>,
+    /// Frames that passed the CRC
+    pub messages: u64,
+    /// Where AIVDM sentences are sent over UDP, e.g. OpenCPN's network input
+    pub forward: Option<SocketAddr>,
+    /// Why forwarding failed
+    pub error: Option<String>,
+}
+
+/// Dual channel AIS decoder on the raw samples of a capture centered on `AIS_CENTER`
+pub struct AisDecoder {
+    state: Arc<Mutex<AisState>>,
+    channels: Vec<AisChannel>,
+    //Channels are rebuilt when the rate changes
+    rate: f64,
+    udp: UdpFeed,
+    //Sequential message id of multi sentence messages
+    sequence: u8,
+}
+
+impl AisDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(AisState::default())),
+            channels: Vec::new(),
+            rate: 0.0,
+            udp: UdpFeed::default(),
+            sequence: 0,
+        }
+    }
+
+    pub fn state(&self) -> Arc<Mutex<AisState>> {
+        self.state.clone()
+    }
+
+    fn frame(&mut self, channel: char, data: &[u8]) {
+        let sentences = nmea_sentences(channel, data, self.sequence);
+        if sentences.len() > 1 {
+            self.sequence = (self.sequence + 1) % 10;
+        }
+
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.messages += 1;
+        if let Some(addr) = state.forward {
+            for sentence in sentences.iter() {
+                self.udp.send(addr, sentence.as_bytes());
+            }
+            state.error = self.udp.error().map(str::to_string);
+        }
+
+        let now = Instant::now();
+        state
+            .vessels
+            .retain(|_, vessel| now.duration_since(vessel.last_seen) < VESSEL_TIMEOUT);
+        update_vessel(&mut state, data, now);
+    }
+}
+
+impl Default for AisDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for AisDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Ais
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Raw
+    }
+
+    fn process_complex(&mut self, samples: &[Complex32], rate: f64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.channels = CHANNELS
+                .iter()
+                .map(|(name, offset)| AisChannel::new(*name, *offset, rate))
+                .collect();
+        }
+
+        let mut frames = Vec::new();
+        for channel in self.channels.iter_mut() {
+            channel.process(samples, &mut frames);
+        }
+        for (channel, data) in frames {
+            self.frame(channel, &data);
+        }
+    }
+
+    fn report(&self) -> String {
+        let Ok(state) = self.state.lock() else {
+            return String::new();
+        };
+        let mut report = format!(
+            "{} messages, {} vessels",
+            state.messages,
+            state.vessels.len()
+        );
+        for vessel in state.vessels.values() {
+            report += &format!(
+                "\n{:09} {:<20} {}",
+                vessel.mmsi,
+                vessel.name.as_deref().unwrap_or(""),
+                vessel
+                    .position
+                    .map(|(lat, lon)| format!("{:.5}, {:.5}", lat, lon))
+                    .unwrap_or_default()
+            );
+        }
+        report
+    }
+}
+
+//One 25 kHz channel from DDC down to HDLC frames
+struct AisChannel {
+    name: char,
+    nco: Nco,
+    filter: Fir<Complex32>,
+    resampler: Resampler<Complex32>,
+    decimated: Vec<Complex32>,
+    iq: Vec<Complex32>,
+    discriminator: FmDiscriminator,
+    dc: DcRemover,
+    //Shapes the discriminator output back into symbols
+    symbol_filter: Fir<f32>,
+    //Position within the bit, a bit is taken each time it wraps
+    clock: f64,
+    last_sample: f32,
+    last_bit: bool,
+    hdlc: Hdlc,
+}
+
+impl AisChannel {
+    fn new(name: char, offset: f64, rate: f64) -> Self {
+        let audio_rate = BAUD * SAMPLES_PER_BIT;
+        let decim = ((rate / audio_rate) as usize).max(1);
+        Self {
+            name,
+            nco: Nco::new(-offset, rate),
+            filter: Fir::new(
+                lowpass_taps(CHANNEL_CUTOFF, rate, num_taps(rate, 2.0 * CHANNEL_CUTOFF)),
+                decim,
+            ),
+            resampler: Resampler::new(rate / decim as f64, audio_rate),
+            decimated: Vec::new(),
+            iq: Vec::new(),
+            discriminator: FmDiscriminator::default(),
+            dc: DcRemover::new(audio_rate),
+            symbol_filter: Fir::new(
+                lowpass_taps(BAUD * 0.6, audio_rate, num_taps(audio_rate, BAUD / 2.0)),
+                1,
+            ),
+            clock: 0.0,
+            last_sample: 0.0,
+            last_bit: false,
+            hdlc: Hdlc::new(MIN_FRAME_BITS, MAX_FRAME_BITS),
+        }
+    }
+
+    fn process(&mut self, samples: &[Complex32], frames: &mut Vec<(char, Vec<u8>)>) {
+        self.decimated.clear();
+        self.iq.clear();
+        for x in samples {
+            if let Some(y) = self.filter.push(self.nco.mix(*x)) {
+                self.decimated.push(y);
+            }
+        }
+        self.resampler.process(&self.decimated, &mut self.iq);
+
+        for idx in 0..self.iq.len() {
+            let freq = self.dc.process(self.discriminator.process(self.iq[idx]));
+            let Some(sample) = self.symbol_filter.push(freq) else {
+                continue;
+            };
+
+            //Transitions belong halfway between the points bits are taken at
+            if (sample > 0.0) != (self.last_sample > 0.0) {
+                self.clock -= CLOCK_GAIN * (self.clock - 0.5);
+            }
+            self.last_sample = sample;
+
+            self.clock += 1.0 / SAMPLES_PER_BIT;
+            if self.clock >= 1.0 {
+                self.clock -= 1.0;
+                let level = sample > 0.0;
+                //NRZI, no change is a one
+                let bit = level == self.last_bit;
+                self.last_bit = level;
+                if let Some(frame) = self.hdlc.push(bit) {
+                    frames.push((self.name, frame));
+                }
+            }
+        }
+    }
+}
+
+//`len` bits of the message starting `start` bits in, message bits run MSB first
+fn bits(data: &[u8], start: usize, len: usize) -> Option<u32> {
+    if start + len > data.len() * 8 {
+        return None;
+    }
+    Some((start..start + len).fold(0, |acc, bit| {
+        (acc << 1) | ((data[bit / 8] >> (7 - bit % 8)) & 1) as u32
+    }))
+}
+
+fn signed_bits(data: &[u8], start: usize, len: usize) -> Option<i32> {
+    let val = bits(data, start, len)?;
+    //Sign extend from `len` bits
+    Some(((val << (32 - len)) as i32) >> (32 - len))
+}
+
+//6 bit ASCII text with the '@' padding and trailing spaces removed
+fn text(data: &[u8], start: usize, chars: usize) -> Option<String> {
+    let text: String = (0..chars)
+        .map(|idx| {
+            bits(data, start + idx * 6, 6).map(|val| {
+                let val = val as u8;
+                (if val < 32 { val + 64 } else { val }) as char
+            })
+        })
+        .collect::<Option<String>>()?;
+    let text = text.trim_end_matches(['@', ' ']).to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+//Position in 1/10000 minutes, None for the "not available" values
+fn position(data: &[u8], lon_start: usize, lat_start: usize) -> Option<(f64, f64)> {
+    let lon = signed_bits(data, lon_start, 28)? as f64 / 600_000.0;
+    let lat = signed_bits(data, lat_start, 27)? as f64 / 600_000.0;
+    (lon.abs() <= 180.0 && lat.abs() <= 90.0).then_some((lat, lon))
+}
+
+fn update_vessel(state: &mut AisState, data: &[u8], now: Instant) {
+    let (Some(msg_type), Some(mmsi)) = (bits(data, 0, 6), bits(data, 8, 30)) else {
+        return;
+    };
+    let vessel = state
+        .vessels
+        .entry(mmsi)
+        .or_insert_with(|| Vessel::new(mmsi, now));
+    vessel.messages += 1;
+    vessel.last_seen = now;
+
+    //Speed and course come in tenths, with the top value meaning not available
+    let tenths =
+        |val: Option<u32>, max: u32| val.filter(|val| *val < max).map(|val| val as f64 / 10.0);
+    match msg_type {
+        //Class A position report
+        1..=3 => {
+            vessel.speed_kn = tenths(bits(data, 50, 10), 1023);
+            vessel.course_deg = tenths(bits(data, 116, 12), 3600);
+            if let Some(position) = position(data, 61, 89) {
+                vessel.position = Some(position);
+            }
+        }
+        //Class A static and voyage data
+        5 => {
+            vessel.callsign = text(data, 70, 7).or(vessel.callsign.take());
+            vessel.name = text(data, 112, 20).or(vessel.name.take());
+            vessel.destination = text(data, 302, 20).or(vessel.destination.take());
+        }
+        //Class B position report
+        18 => {
+            vessel.speed_kn = tenths(bits(data, 46, 10), 1023);
+            vessel.course_deg = tenths(bits(data, 112, 12), 3600);
+            if let Some(position) = position(data, 57, 85) {
+                vessel.position = Some(position);
+            }
+        }
+        //Class B static data, part A has the name and part B the callsign
+        24 => match bits(data, 38, 2) {
+            Some(0) => vessel.name = text(data, 40, 20).or(vessel.name.take()),
+            Some(1) => vessel.callsign = text(data, 90, 7).or(vessel.callsign.take()),
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+//AIVDM sentences carrying `data`, split when it doesn't fit in one
+fn nmea_sentences(channel: char, data: &[u8], sequence: u8) -> Vec<String> {
+    let total_bits = data.len() * 8;
+    let payload: String = (0..total_bits.div_ceil(6))
+        .map(|idx| {
+            //The last character is padded with fill bits
+            let val = (0..6).fold(0u8, |acc, bit| {
+                let pos = idx * 6 + bit;
+                let set = pos < total_bits && (data[pos / 8] >> (7 - pos % 8)) & 1 == 1;
+                (acc << 1) | set as u8
+            });
+            let ch = val + 48;
+            (if ch > 87 { ch + 8 } else { ch }) as char
+        })
+        .collect();
+    let fill = (6 - total_bits % 6) % 6;
+
+    let fragments: Vec<&str> = payload
+        .as_bytes()
+        .chunks(NMEA_PAYLOAD)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or(""))
+        .collect();
+    let count = fragments.len();
+    fragments
+        .iter()
+        .enumerate()
+        .map(|(idx, fragment)| {
+            let sequence = if count > 1 {
+                sequence.to_string()
+            } else {
+                String::new()
+            };
+            let fill = if idx + 1 == count { fill } else { 0 };
+            let body = format!(
+                "AIVDM,{},{},{},{},{},{}",
+                count,
+                idx + 1,
+                sequence,
+                channel,
+                fragment,
+                fill
+            );
+            let checksum = body.bytes().fold(0u8, |acc, byte| acc ^ byte);
+            format!("!{}*{:02X}\r\n", body, checksum)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    use crate::decoders::{decode_wav, fixtures};
+    use crate::demod::Vfo;
+
+    //Sentences from the gpsd AIVDM test set
+    const TYPE_1: &str = "!AIVDM,1,1,,B,15M67FC000G?ufbE`FepT@3n00Sa,0*5C\r\n";
+    const TYPE_5: [&str; 2] = [
+        "!AIVDM,2,1,1,A,55?MbV02;H;s<HtKR20EHE:0@T4@Dn2222222216L961O5Gf0NSQEp6ClRp8,0*1C\r\n",
+        "!AIVDM,2,2,1,A,88888888880,2*25\r\n",
+    ];
+    const TYPE_18: &str = "!AIVDM,1,1,,A,B52K>;h00Fc>jpUlNV@ikwpUoP06,0*4C\r\n";
+    const TYPE_24_A: &str = "!AIVDM,1,1,,A,H42O55i18tMET00000000000000,2*6D\r\n";
+    const TYPE_24_B: &str = "!AIVDM,1,1,,A,H42O55lti4hhhilD3nink000?050,0*40\r\n";
+
+    //Message bytes carried by one or more sentences
+    fn unarmor(sentences: &[&str]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        for sentence in sentences {
+            let fields: Vec<&str> = sentence.split(['*', ',']).collect();
+            for ch in fields[5].bytes() {
+                let val = if ch - 48 > 40 { ch - 56 } else { ch - 48 };
+                bits.extend((0..6).rev().map(|bit| (val >> bit) & 1 == 1));
+            }
+            let fill: usize = fields[6].parse().unwrap();
+            bits.truncate(bits.len() - fill);
+        }
+        bits.chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, bit| (acc << 1) | *bit as u8))
+            .collect()
+    }
+
+    fn vessel(sentences: &[&str]) -> Vessel {
+        let mut state = AisState::default();
+        update_vessel(&mut state, &unarmor(sentences), Instant::now());
+        state.vessels.into_values().next().unwrap()
+    }
+
+    fn assert_near(val: f64, expected: f64) {
+        assert!((val - expected).abs() < 1e-5, "{} isn't {}", val, expected);
+    }
+
+    #[test]
+    fn sentences_round_trip() {
+        for sentence in [TYPE_1, TYPE_18, TYPE_24_A, TYPE_24_B] {
+            let channel = sentence.split(',').nth(4).unwrap().parse().unwrap();
+            assert_eq!(
+                nmea_sentences(channel, &unarmor(&[sentence]), 0),
+                [sentence]
+            );
+        }
+    }
+
+    #[test]
+    fn long_messages_are_split_with_a_sequence_id() {
+        let data = unarmor(&TYPE_5);
+        assert_eq!(data.len(), 53);
+        assert_eq!(nmea_sentences('A', &data, 1), TYPE_5);
+
+        let mut decoder = AisDecoder::new();
+        decoder.frame('A', &data);
+        decoder.frame('A', &data);
+        assert_eq!(decoder.sequence, 2);
+        decoder.frame('A', &unarmor(&[TYPE_1]));
+        assert_eq!(decoder.sequence, 2);
+    }
+
+    #[test]
+    fn class_a_position() {
+        let vessel = vessel(&[TYPE_1]);
+        assert_eq!(vessel.mmsi, 366_053_209);
+        assert_eq!(vessel.speed_kn, Some(0.0));
+        assert_eq!(vessel.course_deg, Some(219.3));
+        let (lat, lon) = vessel.position.unwrap();
+        assert_near(lat, 37.802118);
+        assert_near(lon, -122.341618);
+    }
+
+    #[test]
+    fn class_a_static_data() {
+        let vessel = vessel(&TYPE_5);
+        assert_eq!(vessel.mmsi, 351_759_000);
+        assert_eq!(vessel.callsign.as_deref(), Some("3FOF8"));
+        assert_eq!(vessel.name.as_deref(), Some("EVER DIADEM"));
+        assert_eq!(vessel.destination.as_deref(), Some("NEW YORK"));
+    }
+
+    #[test]
+    fn class_b_position() {
+        let vessel = vessel(&[TYPE_18]);
+        assert_eq!(vessel.mmsi, 338_087_471);
+        assert_eq!(vessel.speed_kn, Some(0.1));
+        assert_eq!(vessel.course_deg, Some(79.6));
+        let (lat, lon) = vessel.position.unwrap();
+        assert_near(lat, 40.68454);
+        assert_near(lon, -74.072132);
+    }
+
+    #[test]
+    fn class_b_static_data_in_two_parts() {
+        let mut state = AisState::default();
+        update_vessel(&mut state, &unarmor(&[TYPE_24_A]), Instant::now());
+        update_vessel(&mut state, &unarmor(&[TYPE_24_B]), Instant::now());
+        let vessel = &state.vessels[&271_041_815];
+        assert_eq!(vessel.name.as_deref(), Some("PROGUY"));
+        assert_eq!(vessel.callsign.as_deref(), Some("TC6163"));
+        assert_eq!(vessel.messages, 2);
+    }
+
+    //GMSK burst of `data` with a BT of 0.4, `offset` Hz from the center
+    fn burst(data: &[u8], offset: f64, rate: f64) -> Vec<f32> {
+        let mut bits: Vec<bool> = (0..24).map(|idx| idx % 2 == 1).collect();
+        bits.extend(fixtures::hdlc_bits(data));
+        bits.extend([false; 8]);
+        let levels = fixtures::nrzi(&bits);
+
+        let samples_per_bit = rate / BAUD;
+        let sigma = 2f64.ln().sqrt() / (2.0 * PI * 0.4) * samples_per_bit;
+        let taps: Vec<f64> = (-(3.0 * samples_per_bit) as i64..=(3.0 * samples_per_bit) as i64)
+            .map(|idx| (-(idx as f64).powi(2) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f64 = taps.iter().sum();
+
+        let square: Vec<f64> = (0..(levels.len() as f64 * samples_per_bit) as usize)
+            .map(|idx| {
+                if levels[(idx as f64 / samples_per_bit) as usize] {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+            .collect();
+        let freq: Vec<f32> = (0..square.len())
+            .map(|idx| {
+                let shaped: f64 = taps
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, weight)| {
+                        let pos = (idx + tap).saturating_sub(taps.len() / 2);
+                        weight * square[pos.min(square.len() - 1)]
+                    })
+                    .sum();
+                (offset + BAUD / 4.0 * shaped / sum) as f32
+            })
+            .collect();
+        fixtures::fm_iq(&freq, 1.0, rate)
+    }
+
+    #[test]
+    fn decodes_bursts_on_both_channels() {
+        const RATE: f64 = 192_000.0;
+        let quiet = vec![0.0; (0.01 * RATE) as usize * 2];
+        let class_a = unarmor(&[TYPE_1]);
+        let class_b = unarmor(&[TYPE_18]);
+        let mut iq = quiet.clone();
+        iq.extend(burst(&class_a, CHANNELS[0].1, RATE));
+        iq.extend(&quiet);
+        iq.extend(burst(&class_b, CHANNELS[1].1, RATE));
+        iq.extend(&quiet);
+        let path = fixtures::write_wav("ais", 2, RATE, &iq);
+
+        //Forwarded sentences show which channel each frame came in on
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let decoder = AisDecoder::new();
+        let state = decoder.state();
+        state.lock().unwrap().forward = Some(socket.local_addr().unwrap());
+        let mut decoders: Vec<Box<dyn Decoder>> = vec![Box::new(decoder)];
+        let decoded = decode_wav(&path, Vfo::default(), &mut decoders);
+        std::fs::remove_file(&path).unwrap();
+        decoded.unwrap();
+
+        let mut buf = [0u8; 128];
+        let mut sentences = Vec::new();
+        for _ in 0..2 {
+            let len = socket.recv(&mut buf).unwrap();
+            sentences.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        assert_eq!(
+            sentences,
+            [
+                nmea_sentences('A', &class_a, 0).remove(0),
+                nmea_sentences('B', &class_b, 0).remove(0)
+            ]
+        );
+
+        let state = state.lock().unwrap();
+        assert_eq!(state.messages, 2);
+        assert!(state.vessels.contains_key(&366_053_209));
+        assert!(state.vessels.contains_key(&338_087_471));
+    }
+}
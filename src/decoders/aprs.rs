@@ -0,0 +1,679 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use super::hdlc::Hdlc;
+use super::net::TcpFeed;
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::ToneCorrelator;
+
+/// Port the KISS TCP server listens on, the usual one for Xastir and YAAC
+pub const KISS_PORT: u16 = 8001;
+/// Packets kept in the list
+pub const PACKET_LOG_LEN: usize = 500;
+
+//Bell 202
+const BAUD: f64 = 1_200.0;
+const MARK: f64 = 1_200.0;
+const SPACE: f64 = 2_200.0;
+//How far the bit clock is pulled towards each transition
+const CLOCK_GAIN: f64 = 0.25;
+//Two addresses, control and FCS
+const MIN_FRAME_BITS: usize = (7 * 2 + 1 + 2) * 8;
+//Ten addresses, control, PID, 256 bytes of info and FCS
+const MAX_FRAME_BITS: usize = (7 * 10 + 2 + 256 + 2) * 8;
+
+const FEND: u8 = 0xC0;
+const FESC: u8 = 0xDB;
+const TFEND: u8 = 0xDC;
+const TFESC: u8 = 0xDD;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AprsKind {
+    Position,
+    Status,
+    Telemetry,
+    Message,
+    Other,
+}
+
+impl std::fmt::Display for AprsKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                AprsKind::Position => "Position",
+                AprsKind::Status => "Status",
+                AprsKind::Telemetry => "Telemetry",
+                AprsKind::Message => "Message",
+                AprsKind::Other => "Other",
+            }
+        )
+    }
+}
+
+/// Analog channels and digital bits of a telemetry report
+#[derive(Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    pub sequence: String,
+    pub analog: Vec<f64>,
+    pub digital: String,
+}
+
+/// One received APRS packet
+#[derive(Debug, Clone)]
+pub struct AprsPacket {
+    pub time: DateTime<Utc>,
+    pub source: String,
+    pub dest: String,
+    /// Digipeaters, with a * after those that have repeated it
+    pub path: Vec<String>,
+    pub kind: AprsKind,
+    /// Latitude and longitude in degrees
+    pub position: Option<(f64, f64)>,
+    /// Symbol table and symbol code
+    pub symbol: Option<(char, char)>,
+    pub telemetry: Option<Telemetry>,
+    /// Comment, status text or message body
+    pub text: String,
+}
+
+impl AprsPacket {
+    /// The packet in the TNC2 monitor format
+    pub fn tnc2(&self, info: &str) -> String {
+        let mut header = format!("{}>{}", self.source, self.dest);
+        for digi in self.path.iter() {
+            header += ",";
+            header += digi;
+        }
+        format!("{}:{}", header, info)
+    }
+}
+
+/// Recent packets, newest first
+#[derive(Debug, Default)]
+pub struct AprsLog {
+    pub packets: VecDeque<AprsPacket>,
+    pub total: u64,
+    /// Address the KISS port listens on, None when it isn't served
+    pub kiss_host: Option<IpAddr>,
+    /// Clients connected to the KISS port
+    pub clients: usize,
+    /// Why the KISS port couldn't be opened
+    pub error: Option<String>,
+}
+
+/// AFSK1200 AX.25 decoder on FM audio
+pub struct AprsDecoder {
+    log: Arc<Mutex<AprsLog>>,
+    rate: f64,
+    afsk: Option<Afsk>,
+    hdlc: Hdlc,
+    kiss: Option<TcpFeed>,
+}
+
+impl AprsDecoder {
+    /// Serves the KISS feed on `kiss_host`, or not at all for None
+    pub fn new(kiss_host: Option<IpAddr>) -> Self {
+        Self {
+            log: Arc::new(Mutex::new(AprsLog {
+                kiss_host,
+                ..Default::default()
+            })),
+            rate: 0.0,
+            afsk: None,
+            hdlc: Hdlc::new(MIN_FRAME_BITS, MAX_FRAME_BITS),
+            kiss: kiss_host.map(|host| TcpFeed::new(SocketAddr::from((host, KISS_PORT)))),
+        }
+    }
+
+    pub fn log(&self) -> Arc<Mutex<AprsLog>> {
+        self.log.clone()
+    }
+
+    fn frame(&mut self, frame: &[u8]) {
+        let Some(packet) = parse_frame(frame) else {
+            return;
+        };
+        if let Some(kiss) = self.kiss.as_mut() {
+            kiss.send(&kiss_frame(frame));
+        }
+
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+        log.total += 1;
+        log.packets.push_front(packet);
+        log.packets.truncate(PACKET_LOG_LEN);
+        if let Some(kiss) = self.kiss.as_ref() {
+            log.clients = kiss.clients();
+            log.error = kiss
+                .error()
+                .map(|e| format!("KISS port unavailable, {}", e));
+        }
+    }
+}
+
+impl Default for AprsDecoder {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Decoder for AprsDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Aprs
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Audio
+    }
+
+    fn process_real(&mut self, samples: &[f32], rate: f64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.afsk = Some(Afsk::new(rate));
+        }
+        let Some(afsk) = self.afsk.as_mut() else {
+            return;
+        };
+
+        let mut frames = Vec::new();
+        for x in samples {
+            if let Some(bit) = afsk.process(*x) {
+                frames.extend(self.hdlc.push(bit));
+            }
+        }
+        for frame in frames {
+            self.frame(&frame);
+        }
+    }
+
+    fn report(&self) -> String {
+        let Ok(log) = self.log.lock() else {
+            return String::new();
+        };
+        let mut report = format!("{} packets", log.total);
+        for packet in log.packets.iter().rev() {
+            report += &format!(
+                "\n{} {} {}",
+                packet.time.format("%Y-%m-%d %H:%M:%S"),
+                packet.kind,
+                packet.tnc2(&packet.text)
+            );
+        }
+        report
+    }
+}
+
+//Tone correlators over one bit period followed by a bit clock
+struct Afsk {
+    mark: ToneCorrelator,
+    space: ToneCorrelator,
+    step: f64,
+    clock: f64,
+    last_level: bool,
+    last_bit: bool,
+}
+
+impl Afsk {
+    fn new(rate: f64) -> Self {
+        let len = (rate / BAUD).round() as usize;
+        Self {
+            mark: ToneCorrelator::new(MARK, rate, len),
+            space: ToneCorrelator::new(SPACE, rate, len),
+            step: BAUD / rate,
+            clock: 0.0,
+            last_level: false,
+            last_bit: false,
+        }
+    }
+
+    //Returns a bit after NRZI decoding once per bit period
+    #[inline]
+    fn process(&mut self, x: f32) -> Option<bool> {
+        let level = self.mark.process(x) > self.space.process(x);
+        //Transitions belong halfway between the points bits are taken at
+        if level != self.last_level {
+            self.clock -= CLOCK_GAIN * (self.clock - 0.5);
+        }
+        self.last_level = level;
+
+        self.clock += self.step;
+        if self.clock < 1.0 {
+            return None;
+        }
+        self.clock -= 1.0;
+        //NRZI, no change is a one
+        let bit = level == self.last_bit;
+        self.last_bit = level;
+        Some(bit)
+    }
+}
+
+//KISS data frame for port 0
+fn kiss_frame(frame: &[u8]) -> Vec<u8> {
+    let mut kiss = vec![FEND, 0x00];
+    for byte in frame {
+        match *byte {
+            FEND => kiss.extend([FESC, TFEND]),
+            FESC => kiss.extend([FESC, TFESC]),
+            byte => kiss.push(byte),
+        }
+    }
+    kiss.push(FEND);
+    kiss
+}
+
+//Callsign and SSID from a 7 byte address field, and whether it is the last address
+fn address(field: &[u8]) -> (String, bool, bool) {
+    let call: String = field[..6]
+        .iter()
+        .map(|byte| (byte >> 1) as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string();
+    let ssid = (field[6] >> 1) & 0x0F;
+    let call = if ssid == 0 {
+        call
+    } else {
+        format!("{}-{}", call, ssid)
+    };
+    //For digipeaters the top bit says it has been repeated
+    (call, field[6] & 0x80 != 0, field[6] & 0x01 != 0)
+}
+
+fn parse_frame(frame: &[u8]) -> Option<AprsPacket> {
+    let mut addresses = Vec::new();
+    let mut pos = 0;
+    loop {
+        let field = frame.get(pos..pos + 7)?;
+        let (call, repeated, last) = address(field);
+        addresses.push((call, repeated));
+        pos += 7;
+        if last {
+            break;
+        }
+        if addresses.len() == 10 {
+            return None;
+        }
+    }
+    if addresses.len() < 2 {
+        return None;
+    }
+
+    //Only UI frames with no layer 3 carry APRS
+    if frame.get(pos..pos + 2)? != [0x03, 0xF0] {
+        return None;
+    }
+    let info = String::from_utf8_lossy(&frame[pos + 2..]).to_string();
+
+    let mut packet = AprsPacket {
+        time: Utc::now(),
+        dest: addresses[0].0.clone(),
+        source: addresses[1].0.clone(),
+        path: addresses[2..]
+            .iter()
+            .map(|(call, repeated)| {
+                if *repeated {
+                    format!("{}*", call)
+                } else {
+                    call.clone()
+                }
+            })
+            .collect(),
+        kind: AprsKind::Other,
+        position: None,
+        symbol: None,
+        telemetry: None,
+        text: info.trim_end().to_string(),
+    };
+    parse_info(&mut packet, &info, &frame[..7]);
+    Some(packet)
+}
+
+fn parse_info(packet: &mut AprsPacket, info: &str, dest: &[u8]) {
+    let Some(data_type) = info.chars().next() else {
+        return;
+    };
+    let body = &info[data_type.len_utf8()..];
+    match data_type {
+        '!' | '=' => position(packet, body),
+        //7 character timestamp before the position
+        '/' | '@' => position(packet, body.get(7..).unwrap_or("")),
+        '>' => {
+            packet.kind = AprsKind::Status;
+            packet.text = body.trim_end().to_string();
+        }
+        'T' => telemetry(packet, body),
+        ':' => {
+            packet.kind = AprsKind::Message;
+            //9 character addressee padded with spaces
+            if let (Some(to), Some(text)) = (body.get(..9), body.get(10..)) {
+                packet.text = format!("To {}: {}", to.trim_end(), text.trim_end());
+            }
+        }
+        '`' | '\'' => mic_e(packet, body.as_bytes(), dest),
+        _ => {}
+    }
+}
+
+fn position(packet: &mut AprsPacket, body: &str) {
+    let bytes = body.as_bytes();
+    let first = bytes.first().copied().unwrap_or(b' ');
+    //Compressed positions start with the symbol table instead of a digit
+    let (lat, lon, table, code, rest) = if first.is_ascii_digit() || first == b' ' {
+        let (Some(lat), Some(lon)) = (body.get(..8), body.get(9..18)) else {
+            return;
+        };
+        let (Some(lat), Some(lon)) = (degrees(lat, 2), degrees(lon, 3)) else {
+            return;
+        };
+        (lat, lon, bytes[8], bytes.get(18).copied(), body.get(19..))
+    } else {
+        if bytes.len() < 10 {
+            return;
+        }
+        let base91 = |chars: &[u8]| {
+            chars
+                .iter()
+                .fold(0.0, |acc, ch| acc * 91.0 + (ch.saturating_sub(33)) as f64)
+        };
+        let lat = 90.0 - base91(&bytes[1..5]) / 380_926.0;
+        let lon = -180.0 + base91(&bytes[5..9]) / 190_463.0;
+        (lat, lon, first, Some(bytes[9]), body.get(13..))
+    };
+
+    packet.kind = AprsKind::Position;
+    packet.position = Some((lat, lon));
+    packet.symbol = code.map(|code| (table as char, code as char));
+    packet.text = rest.unwrap_or("").trim().to_string();
+}
+
+//DDMM.mmN or DDDMM.mmW, spaces for ambiguity count as zero
+fn degrees(val: &str, deg_digits: usize) -> Option<f64> {
+    let hemisphere = val.chars().last()?;
+    let digits = val[..val.len() - hemisphere.len_utf8()].replace(' ', "0");
+    let deg: f64 = digits.get(..deg_digits)?.parse().ok()?;
+    let min: f64 = digits.get(deg_digits..)?.parse().ok()?;
+    let val = deg + min / 60.0;
+    match hemisphere {
+        'N' | 'E' => Some(val),
+        'S' | 'W' => Some(-val),
+        _ => None,
+    }
+}
+
+//T#sss,a1,a2,a3,a4,a5,bbbbbbbb
+fn telemetry(packet: &mut AprsPacket, body: &str) {
+    let Some(body) = body.strip_prefix('#') else {
+        return;
+    };
+    let mut fields = body.split(',');
+    let sequence = fields.next().unwrap_or("").to_string();
+    let fields: Vec<&str> = fields.collect();
+    let analog = fields
+        .iter()
+        .take(5)
+        .filter_map(|val| val.trim().parse().ok())
+        .collect();
+    let digital: String = fields
+        .get(5)
+        .map(|bits| bits.chars().take(8).collect())
+        .unwrap_or_default();
+    let comment = fields
+        .get(5)
+        .and_then(|bits| bits.get(8..))
+        .unwrap_or("")
+        .trim()
+        .to_string();
+
+    packet.kind = AprsKind::Telemetry;
+    packet.telemetry = Some(Telemetry {
+        sequence,
+        analog,
+        digital,
+    });
+    packet.text = comment;
+}
+
+//Mic-E hides the latitude in the destination callsign
+fn mic_e(packet: &mut AprsPacket, body: &[u8], dest: &[u8]) {
+    if body.len() < 8 || dest.len() < 6 {
+        return;
+    }
+    let dest: Vec<u8> = dest[..6].iter().map(|byte| byte >> 1).collect();
+    let digit = |ch: u8| match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'A'..=b'J' => Some(ch - b'A'),
+        b'P'..=b'Y' => Some(ch - b'P'),
+        b'K' | b'L' | b'Z' => Some(0),
+        _ => None,
+    };
+    let Some(digits) = dest
+        .iter()
+        .map(|ch| digit(*ch))
+        .collect::<Option<Vec<u8>>>()
+    else {
+        return;
+    };
+    let custom = |idx: usize| (b'P'..=b'Z').contains(&dest[idx]);
+
+    let lat_deg = (digits[0] * 10 + digits[1]) as f64;
+    let lat_min = (digits[2] * 10 + digits[3]) as f64 + (digits[4] * 10 + digits[5]) as f64 / 100.0;
+    let lat = lat_deg + lat_min / 60.0;
+    let lat = if custom(3) { lat } else { -lat };
+
+    let mut lon_deg = body[0] as i32 - 28;
+    if custom(4) {
+        lon_deg += 100;
+    }
+    if (180..=189).contains(&lon_deg) {
+        lon_deg -= 80;
+    } else if (190..=199).contains(&lon_deg) {
+        lon_deg -= 190;
+    }
+    let mut lon_min = body[1] as i32 - 28;
+    if lon_min >= 60 {
+        lon_min -= 60;
+    }
+    let lon_hundredths = body[2] as i32 - 28;
+    let lon = lon_deg as f64 + (lon_min as f64 + lon_hundredths as f64 / 100.0) / 60.0;
+    let lon = if custom(5) { -lon } else { lon };
+
+    packet.kind = AprsKind::Position;
+    packet.position = Some((lat, lon));
+    packet.symbol = Some((body[7] as char, body[6] as char));
+    packet.text = String::from_utf8_lossy(&body[8..]).trim().to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::{decode_wav, fixtures};
+    use crate::demod::{DemodMode, Vfo};
+
+    //AX.25 address field, a * after a digipeater marks it as repeated
+    fn address_field(call: &str, last: bool) -> Vec<u8> {
+        let repeated = call.ends_with('*');
+        let call = call.trim_end_matches('*');
+        let (call, ssid) = call
+            .split_once('-')
+            .map_or((call, 0), |(call, ssid)| (call, ssid.parse().unwrap()));
+        let mut field: Vec<u8> = format!("{:<6}", call).bytes().map(|ch| ch << 1).collect();
+        field.push(0x60 | (ssid << 1) | ((repeated as u8) << 7) | last as u8);
+        field
+    }
+
+    //UI frame from `source` to `dest` via `path`
+    fn ui_frame(dest: &str, source: &str, path: &[&str], info: &[u8]) -> Vec<u8> {
+        let mut frame = address_field(dest, false);
+        frame.extend(address_field(source, path.is_empty()));
+        for (idx, digi) in path.iter().enumerate() {
+            frame.extend(address_field(digi, idx == path.len() - 1));
+        }
+        frame.extend([0x03, 0xF0]);
+        frame.extend_from_slice(info);
+        frame
+    }
+
+    fn assert_near(position: Option<(f64, f64)>, lat: f64, lon: f64) {
+        let (got_lat, got_lon) = position.expect("no position");
+        assert!(
+            (got_lat - lat).abs() < 1e-4 && (got_lon - lon).abs() < 1e-4,
+            "{:?} isn't near ({}, {})",
+            position,
+            lat,
+            lon
+        );
+    }
+
+    #[test]
+    fn parses_an_uncompressed_position() {
+        let frame = ui_frame(
+            "APRS",
+            "N0CALL-9",
+            &["WIDE1-1*", "WIDE2-1"],
+            b"!4903.50N/07201.75W-Test 001234",
+        );
+        let packet = parse_frame(&frame).unwrap();
+        assert_eq!(packet.source, "N0CALL-9");
+        assert_eq!(packet.dest, "APRS");
+        assert_eq!(packet.path, ["WIDE1-1*", "WIDE2-1"]);
+        assert_eq!(packet.kind, AprsKind::Position);
+        assert_near(packet.position, 49.0 + 3.5 / 60.0, -(72.0 + 1.75 / 60.0));
+        assert_eq!(packet.symbol, Some(('/', '-')));
+        assert_eq!(packet.text, "Test 001234");
+        assert_eq!(
+            packet.tnc2(&packet.text),
+            "N0CALL-9>APRS,WIDE1-1*,WIDE2-1:Test 001234"
+        );
+    }
+
+    #[test]
+    fn parses_a_compressed_position() {
+        //The example from the APRS spec, 49°30' N 72°45' W
+        let frame = ui_frame("APRS", "N0CALL", &[], b"=/5L!!<*e7>7P[Comment");
+        let packet = parse_frame(&frame).unwrap();
+        assert_eq!(packet.kind, AprsKind::Position);
+        assert_near(packet.position, 49.5, -72.75);
+        assert_eq!(packet.symbol, Some(('/', '>')));
+    }
+
+    #[test]
+    fn parses_a_mic_e_position() {
+        //33°25.64' N from the destination, 72°45.00' W from the first info bytes
+        let frame = ui_frame("S32U6T", "N0CALL", &[], b"`dI\x1c!!!>/Hi");
+        let packet = parse_frame(&frame).unwrap();
+        assert_eq!(packet.kind, AprsKind::Position);
+        assert_near(packet.position, 33.0 + 25.64 / 60.0, -72.75);
+        assert_eq!(packet.symbol, Some(('/', '>')));
+        assert_eq!(packet.text, "Hi");
+    }
+
+    #[test]
+    fn parses_status_message_and_telemetry() {
+        let status = parse_frame(&ui_frame("APRS", "N0CALL", &[], b">On the air")).unwrap();
+        assert_eq!(status.kind, AprsKind::Status);
+        assert_eq!(status.text, "On the air");
+
+        let message = parse_frame(&ui_frame("APRS", "N0CALL", &[], b":N1CALL   :Hello{1")).unwrap();
+        assert_eq!(message.kind, AprsKind::Message);
+        assert_eq!(message.text, "To N1CALL: Hello{1");
+
+        let frame = ui_frame(
+            "APRS",
+            "N0CALL",
+            &[],
+            b"T#005,199,000,255,073,123,01101001 ok",
+        );
+        let telemetry = parse_frame(&frame).unwrap();
+        assert_eq!(telemetry.kind, AprsKind::Telemetry);
+        assert_eq!(
+            telemetry.telemetry,
+            Some(Telemetry {
+                sequence: "005".into(),
+                analog: vec![199.0, 0.0, 255.0, 73.0, 123.0],
+                digital: "01101001".into(),
+            })
+        );
+        assert_eq!(telemetry.text, "ok");
+    }
+
+    #[test]
+    fn rejects_frames_that_are_not_aprs() {
+        let mut frame = ui_frame("APRS", "N0CALL", &[], b">status");
+        //An I frame rather than UI
+        frame[14] = 0x00;
+        assert!(parse_frame(&frame).is_none());
+        assert!(parse_frame(&frame[..10]).is_none());
+    }
+
+    #[test]
+    fn kiss_escapes_frame_delimiters() {
+        let frame = [0x01, FEND, 0x02, FESC, 0x03];
+        assert_eq!(
+            kiss_frame(&frame),
+            [FEND, 0x00, 0x01, FESC, TFEND, 0x02, FESC, TFESC, 0x03, FEND]
+        );
+        assert_eq!(kiss_frame(&[]), [FEND, 0x00, FEND]);
+    }
+    //Continuous phase AFSK1200 of the NRZI levels, mark for a high level
+    fn afsk(levels: &[bool], rate: f64) -> Vec<f32> {
+        let per_bit = rate / BAUD;
+        let mut phase = 0.0f64;
+        (0..(levels.len() as f64 * per_bit) as usize)
+            .map(|n| {
+                let hz = if levels[(n as f64 / per_bit) as usize] {
+                    MARK
+                } else {
+                    SPACE
+                };
+                phase += 2.0 * std::f64::consts::PI * hz / rate;
+                0.5 * phase.sin() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_a_packet_from_an_fm_recording() {
+        const RATE: f64 = 48_000.0;
+        let frame = ui_frame(
+            "APRS",
+            "N0CALL-9",
+            &["WIDE1-1"],
+            b"!4903.50N/07201.75W-Test 001234",
+        );
+        //A preamble of flags for the receiver to find the bit clock in
+        let mut bits: Vec<bool> = (0..32)
+            .flat_map(|_| (0..8).map(|bit| (0x7E >> bit) & 1 == 1))
+            .collect();
+        bits.extend(fixtures::hdlc_bits(&frame));
+        let mut audio = vec![0.0; RATE as usize / 10];
+        audio.extend(afsk(&fixtures::nrzi(&bits), RATE));
+        audio.extend(vec![0.0; RATE as usize / 10]);
+        //Deviated 3 kHz like a handheld
+        let path = fixtures::write_wav("aprs", 2, RATE, &fixtures::fm_iq(&audio, 6_000.0, RATE));
+
+        let decoder = AprsDecoder::new(None);
+        let log = decoder.log();
+        let mut decoders: Vec<Box<dyn Decoder>> = vec![Box::new(decoder)];
+        let mut vfo = Vfo::default();
+        vfo.set_mode(DemodMode::Nfm);
+        let decoded = decode_wav(&path, vfo, &mut decoders);
+        std::fs::remove_file(&path).unwrap();
+        decoded.unwrap();
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.total, 1);
+        let packet = &log.packets[0];
+        assert_eq!(packet.source, "N0CALL-9");
+        assert_eq!(packet.path, ["WIDE1-1"]);
+        assert_eq!(packet.kind, AprsKind::Position);
+        assert_near(packet.position, 49.0 + 3.5 / 60.0, -(72.0 + 1.75 / 60.0));
+        assert_eq!(packet.text, "Test 001234");
+    }
+}
@@ -0,0 +1,333 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futuresdr::num_complex::Complex32;
+use plotters::prelude::*;
+
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::{lowpass_taps, num_taps, Fir, Nco, Resampler};
+
+/// Pixels in a line, both channels with their sync, space and telemetry
+pub const LINE_WORDS: usize = 2080;
+/// Pixels per second, two lines a second
+pub const WORD_RATE: f64 = 4_160.0;
+/// FM bandwidth the VFO is set to, the satellites deviate about ±17 kHz
+pub const APT_BANDWIDTH: f64 = 40_000.0;
+
+const SUBCARRIER: f64 = 2_400.0;
+//Sync A is 7 cycles of 1040 Hz, 4 words each, after 4 words of space
+const SYNC_CYCLES: usize = 7;
+const SYNC_LEAD: usize = 4;
+//Words the sync can move by and still count as locked
+const SYNC_SLIP: usize = 4;
+//Share of the darkest and brightest pixels clipped when scaling a line
+const CLIP: f64 = 0.02;
+//How quickly the black and white levels follow the signal, per line
+const LEVEL_ALPHA: f32 = 0.1;
+
+/// The image received so far, lines of [`LINE_WORDS`] grey levels with channel A on the left
+#[derive(Debug, Default, Clone)]
+pub struct AptImage {
+    pub lines: Vec<Vec<u8>>,
+    /// When the first line was received
+    pub start: Option<DateTime<Utc>>,
+    /// RF frequency it was received on, unknown when decoding a recording
+    pub freq_hz: Option<f64>,
+    /// Whether the last line was found where the one before said it would be
+    pub synced: bool,
+}
+
+impl AptImage {
+    /// Starts a new image, e.g. for the next pass
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.start = None;
+        self.synced = false;
+    }
+
+    /// File name made from the pass start, e.g. `apt_137100000Hz_2024-05-01_13-37-00Z.png`
+    pub fn file_name(&self) -> String {
+        let start = self.start.unwrap_or_else(Utc::now);
+        match self.freq_hz {
+            Some(hz) => format!("apt_{}Hz_{}.png", hz, start.format("%Y-%m-%d_%H-%M-%SZ")),
+            None => format!("apt_{}.png", start.format("%Y-%m-%d_%H-%M-%SZ")),
+        }
+    }
+
+    /// Writes the image as a greyscale PNG, one pixel per word
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        if self.lines.is_empty() {
+            return Err("No APT lines have been received".into());
+        }
+        let root = BitMapBackend::new(path, (LINE_WORDS as u32, self.lines.len() as u32))
+            .into_drawing_area();
+        for (y, line) in self.lines.iter().enumerate() {
+            for (x, val) in line.iter().enumerate() {
+                root.draw_pixel((x as i32, y as i32), &RGBColor(*val, *val, *val))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        root.present().map_err(|e| e.to_string())
+    }
+}
+
+/// NOAA APT decoder on FM audio
+pub struct AptDecoder {
+    image: Arc<Mutex<AptImage>>,
+    rate: f64,
+    am: Option<AmDemod>,
+    envelope: Vec<f32>,
+    words: Vec<f32>,
+    sync: Vec<f32>,
+    //Black and white points
+    levels: Option<(f32, f32)>,
+    freq_hz: Option<f64>,
+}
+
+impl AptDecoder {
+    pub fn new() -> Self {
+        //Zero mean so bright or dark areas don't look like sync
+        let mut sync = vec![-1.0; SYNC_LEAD];
+        for _ in 0..SYNC_CYCLES {
+            sync.extend([1.0, 1.0, -1.0, -1.0]);
+        }
+        let mean = sync.iter().sum::<f32>() / sync.len() as f32;
+        for val in sync.iter_mut() {
+            *val -= mean;
+        }
+
+        Self {
+            image: Arc::new(Mutex::new(AptImage::default())),
+            rate: 0.0,
+            am: None,
+            envelope: Vec::new(),
+            words: Vec::new(),
+            sync,
+            levels: None,
+            freq_hz: None,
+        }
+    }
+
+    pub fn image(&self) -> Arc<Mutex<AptImage>> {
+        self.image.clone()
+    }
+
+    //Finds sync A in the buffered words and cuts out the line starting there
+    fn line(&mut self) -> Option<(Vec<f32>, bool)> {
+        if self.words.len() < LINE_WORDS + self.sync.len() {
+            return None;
+        }
+        let offset = (0..LINE_WORDS)
+            .map(|offset| {
+                let corr: f32 = self.words[offset..offset + self.sync.len()]
+                    .iter()
+                    .zip(self.sync.iter())
+                    .map(|(word, sync)| word * sync)
+                    .sum();
+                (offset, corr)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(offset, _)| offset)
+            .unwrap_or(0);
+        if self.words.len() < offset + LINE_WORDS {
+            return None;
+        }
+
+        let line: Vec<f32> = self
+            .words
+            .drain(..offset + LINE_WORDS)
+            .skip(offset)
+            .collect();
+        Some((line, offset <= SYNC_SLIP))
+    }
+
+    //Grey levels with the black and white points following the darkest and brightest pixels
+    fn scale(&mut self, line: &[f32]) -> Vec<u8> {
+        let mut sorted = line.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let low = sorted[(sorted.len() as f64 * CLIP) as usize];
+        let high = sorted[(sorted.len() as f64 * (1.0 - CLIP)) as usize];
+        let (black, white) = match self.levels {
+            Some((black, white)) => (
+                black + LEVEL_ALPHA * (low - black),
+                white + LEVEL_ALPHA * (high - white),
+            ),
+            None => (low, high),
+        };
+        self.levels = Some((black, white));
+
+        let range = (white - black).max(1e-9);
+        line.iter()
+            .map(|val| ((val - black) / range * 255.0).clamp(0.0, 255.0) as u8)
+            .collect()
+    }
+}
+
+impl Default for AptDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for AptDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Apt
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Audio
+    }
+
+    fn process_real(&mut self, samples: &[f32], rate: f64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.am = Some(AmDemod::new(rate));
+            self.words.clear();
+        }
+        let Some(am) = self.am.as_mut() else {
+            return;
+        };
+        am.process(samples, &mut self.envelope, &mut self.words);
+
+        let image = self.image.clone();
+        while let Some((line, synced)) = self.line() {
+            let Ok(mut image) = image.lock() else {
+                continue;
+            };
+            //Cleared from the GUI for a new pass
+            if image.lines.is_empty() {
+                self.levels = None;
+            }
+            let line = self.scale(&line);
+            if image.start.is_none() {
+                image.start = Some(Utc::now());
+                image.freq_hz = self.freq_hz;
+            }
+            image.synced = synced;
+            image.lines.push(line);
+        }
+    }
+
+    fn set_freq(&mut self, rf_hz: f64) {
+        self.freq_hz = Some(rf_hz);
+    }
+
+    fn report(&self) -> String {
+        let Ok(image) = self.image.lock() else {
+            return String::new();
+        };
+        format!(
+            "{} lines, {:.0} s",
+            image.lines.len(),
+            image.lines.len() as f64 * LINE_WORDS as f64 / WORD_RATE
+        )
+    }
+}
+
+//Envelope of the 2400 Hz subcarrier resampled to one sample per word
+struct AmDemod {
+    nco: Nco,
+    filter: Fir<Complex32>,
+    resampler: Resampler<f32>,
+}
+
+impl AmDemod {
+    fn new(rate: f64) -> Self {
+        //The AM sidebands reach half the word rate either side of the subcarrier
+        let cutoff = WORD_RATE / 2.0;
+        Self {
+            nco: Nco::new(-SUBCARRIER, rate),
+            filter: Fir::new(lowpass_taps(cutoff, rate, num_taps(rate, cutoff / 4.0)), 1),
+            resampler: Resampler::new(rate, WORD_RATE),
+        }
+    }
+
+    fn process(&mut self, samples: &[f32], envelope: &mut Vec<f32>, words: &mut Vec<f32>) {
+        envelope.clear();
+        for x in samples {
+            let mixed = self.nco.mix(Complex32::new(*x, 0.0));
+            if let Some(y) = self.filter.push(mixed) {
+                envelope.push(y.norm());
+            }
+        }
+        self.resampler.process(envelope, words);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::{decode_wav, fixtures};
+    use crate::demod::Vfo;
+
+    //The usual rate APT recordings are shared at
+    const RATE: f64 = 11_025.0;
+    const LINES: usize = 10;
+    const IMAGE_A: f32 = 0.3;
+    const IMAGE_B: f32 = 0.7;
+    //Where each channel's image is within the line
+    const A_WORDS: std::ops::Range<usize> = 86..995;
+    const B_WORDS: std::ops::Range<usize> = 1126..2035;
+
+    //One line of grey levels: sync, space, image and telemetry for each channel
+    fn line() -> Vec<f32> {
+        let mut line = vec![0.0; SYNC_LEAD];
+        for _ in 0..SYNC_CYCLES {
+            line.extend([1.0, 1.0, 0.0, 0.0]);
+        }
+        line.resize(39, 0.0);
+        line.resize(39 + 47, 0.0);
+        line.resize(A_WORDS.end, IMAGE_A);
+        line.resize(A_WORDS.end + 45, 0.5);
+        //Sync B is 7 cycles of 832 Hz, five words each
+        for _ in 0..SYNC_CYCLES {
+            line.extend([1.0, 1.0, 1.0, 0.0, 0.0]);
+        }
+        line.resize(line.len() + 4, 0.0);
+        line.resize(B_WORDS.start, 1.0);
+        line.resize(B_WORDS.end, IMAGE_B);
+        line.resize(LINE_WORDS, 0.5);
+        line
+    }
+
+    //The 2400 Hz subcarrier amplitude modulated by the words, as FM audio carries it
+    fn audio(lines: usize) -> Vec<f32> {
+        let line = line();
+        let samples = (lines as f64 * LINE_WORDS as f64 / WORD_RATE * RATE) as usize;
+        (0..samples)
+            .map(|n| {
+                let t = n as f64 / RATE;
+                let word = (t * WORD_RATE) as usize % LINE_WORDS;
+                let carrier = (2.0 * std::f64::consts::PI * SUBCARRIER * t).sin() as f32;
+                (0.1 + 0.8 * line[word]) * carrier
+            })
+            .collect()
+    }
+
+    fn mean(pixels: &[u8]) -> f32 {
+        pixels.iter().map(|val| *val as f32).sum::<f32>() / pixels.len() as f32
+    }
+
+    #[test]
+    fn decodes_both_channels_of_a_recording() {
+        let path = fixtures::write_wav("apt", 1, RATE, &audio(LINES));
+        let decoder = AptDecoder::new();
+        let image = decoder.image();
+        let mut decoders: Vec<Box<dyn Decoder>> = vec![Box::new(decoder)];
+        let decoded = decode_wav(&path, Vfo::default(), &mut decoders);
+        std::fs::remove_file(&path).unwrap();
+        decoded.unwrap();
+
+        let image = image.lock().unwrap();
+        //The last line can't be cut out until sync A of the next one has been seen
+        assert_eq!(image.lines.len(), LINES - 1);
+        assert!(image.synced);
+        for line in image.lines.iter() {
+            assert_eq!(line.len(), LINE_WORDS);
+            let (a, b) = (mean(&line[A_WORDS]), mean(&line[B_WORDS]));
+            assert!((a - IMAGE_A * 255.0).abs() < 10.0, "channel A at {a}");
+            assert!((b - IMAGE_B * 255.0).abs() < 10.0, "channel B at {b}");
+        }
+    }
+}
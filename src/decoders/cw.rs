@@ -0,0 +1,400 @@
+use std::sync::{Arc, Mutex};
+
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::demod::Vfo;
+use crate::dsp::Goertzel;
+
+/// Characters of decoded text kept
+pub const TEXT_LEN: usize = 4_000;
+/// Slowest speed followed
+pub const MIN_WPM: f64 = 5.0;
+/// Fastest speed followed
+pub const MAX_WPM: f64 = 50.0;
+
+//Length of each tone measurement, short enough for a 50 WPM dit
+const BLOCK_SECS: f64 = 0.002;
+//Dit length in ms is 1200 / WPM
+const DIT_MS_PER_WPM: f64 = 1_200.0;
+const START_WPM: f64 = 20.0;
+//How far each mark pulls the dit or dah length towards its own
+const TIMING_GAIN: f64 = 0.2;
+//Marks this much shorter than a dit or longer than a dah mean the speed has changed
+const SPEED_UP: f64 = 0.67;
+const SLOW_DOWN: f64 = 1.5;
+//Marks shorter than this share of a dit are taken as noise
+const GLITCH: f64 = 0.25;
+//Peak has to be this far above the noise floor before anything is keyed, 12 dB
+const MIN_SNR: f32 = 4.0;
+//How quickly the noise floor follows the level between marks, over about 40 ms
+const NOISE_GAIN: f32 = 0.05;
+//Key down above 60% of the way from noise to peak, up below 40%
+const KEY_DOWN: f32 = 0.6;
+const KEY_UP: f32 = 0.4;
+
+const MORSE: [(&str, char); 54] = [
+    (".-", 'A'),
+    ("-...", 'B'),
+    ("-.-.", 'C'),
+    ("-..", 'D'),
+    (".", 'E'),
+    ("..-.", 'F'),
+    ("--.", 'G'),
+    ("....", 'H'),
+    ("..", 'I'),
+    (".---", 'J'),
+    ("-.-", 'K'),
+    (".-..", 'L'),
+    ("--", 'M'),
+    ("-.", 'N'),
+    ("---", 'O'),
+    (".--.", 'P'),
+    ("--.-", 'Q'),
+    (".-.", 'R'),
+    ("...", 'S'),
+    ("-", 'T'),
+    ("..-", 'U'),
+    ("...-", 'V'),
+    (".--", 'W'),
+    ("-..-", 'X'),
+    ("-.--", 'Y'),
+    ("--..", 'Z'),
+    ("-----", '0'),
+    (".----", '1'),
+    ("..---", '2'),
+    ("...--", '3'),
+    ("....-", '4'),
+    (".....", '5'),
+    ("-....", '6'),
+    ("--...", '7'),
+    ("---..", '8'),
+    ("----.", '9'),
+    (".-.-.-", '.'),
+    ("--..--", ','),
+    ("..--..", '?'),
+    (".----.", '\''),
+    ("-.-.--", '!'),
+    ("-..-.", '/'),
+    ("-.--.", '('),
+    ("-.--.-", ')'),
+    (".-...", '&'),
+    ("---...", ':'),
+    ("-.-.-.", ';'),
+    ("-...-", '='),
+    (".-.-.", '+'),
+    ("-....-", '-'),
+    ("..--.-", '_'),
+    (".-..-.", '"'),
+    ("...-..-", '$'),
+    (".--.-.", '@'),
+];
+
+/// Text decoded so far and the sending speed
+#[derive(Debug, Default)]
+pub struct CwText {
+    pub text: String,
+    pub wpm: f64,
+    /// Whether the tone is on right now
+    pub key_down: bool,
+    /// Audio tone listened for in Hz
+    pub pitch_hz: f64,
+}
+
+/// Morse decoder on the audio of the CW or SSB demodulator
+pub struct CwDecoder {
+    text: Arc<Mutex<CwText>>,
+    rate: f64,
+    pitch_hz: f64,
+    goertzel: Option<Goertzel>,
+    peak: f32,
+    noise: f32,
+    key_down: bool,
+    //Time the key has been in its current state
+    run_ms: f64,
+    //Mark lengths seen for dits and dahs, a mark is a dah if it is nearer the second
+    dit_ms: f64,
+    dah_ms: f64,
+    symbols: String,
+    //Whether a space has been written since the last character
+    spaced: bool,
+}
+
+impl CwDecoder {
+    pub fn new() -> Self {
+        Self {
+            text: Arc::new(Mutex::new(CwText::default())),
+            rate: 0.0,
+            pitch_hz: Vfo::default().bfo_hz,
+            goertzel: None,
+            peak: 0.0,
+            noise: 0.0,
+            key_down: false,
+            run_ms: 0.0,
+            dit_ms: DIT_MS_PER_WPM / START_WPM,
+            dah_ms: 3.0 * DIT_MS_PER_WPM / START_WPM,
+            symbols: String::new(),
+            spaced: true,
+        }
+    }
+
+    pub fn text(&self) -> Arc<Mutex<CwText>> {
+        self.text.clone()
+    }
+
+    //Keys the tone from one block's magnitude with thresholds between the noise floor and peak
+    fn block(&mut self, mag: f32) {
+        //The peak jumps up and fades over about a second, the noise floor is the average level
+        //while the key is up so noise alone stays below the SNR needed to key
+        let blocks_per_sec = (1.0 / BLOCK_SECS) as f32;
+        if self.noise == 0.0 {
+            self.noise = mag;
+        }
+        if mag > self.peak {
+            self.peak += 0.3 * (mag - self.peak);
+        } else {
+            self.peak -= self.peak / blocks_per_sec;
+        }
+        if !self.key_down || mag < self.noise {
+            self.noise += NOISE_GAIN * (mag - self.noise);
+        }
+
+        let span = self.peak - self.noise;
+        let key_down = if self.peak < self.noise * MIN_SNR {
+            false
+        } else if self.key_down {
+            mag > self.noise + KEY_UP * span
+        } else {
+            mag > self.noise + KEY_DOWN * span
+        };
+
+        self.run_ms += BLOCK_SECS * 1_000.0;
+        if key_down != self.key_down {
+            if self.key_down {
+                self.mark(self.run_ms);
+            }
+            self.key_down = key_down;
+            self.run_ms = 0.0;
+        } else if !key_down {
+            self.space(self.run_ms);
+        }
+    }
+
+    fn mark(&mut self, ms: f64) {
+        if ms < self.dit_ms * GLITCH {
+            return;
+        }
+        //After a jump in speed the mark is taken as a dit or dah at the new speed, a dah being three dits
+        if ms < SPEED_UP * self.dit_ms {
+            self.dit_ms = ms;
+            self.dah_ms = 3.0 * ms;
+        } else if ms > SLOW_DOWN * self.dah_ms {
+            self.dit_ms = ms / 3.0;
+            self.dah_ms = ms;
+        }
+
+        if ms < (self.dit_ms + self.dah_ms) / 2.0 {
+            self.symbols.push('.');
+            self.dit_ms += TIMING_GAIN * (ms - self.dit_ms);
+        } else {
+            self.symbols.push('-');
+            self.dah_ms += TIMING_GAIN * (ms - self.dah_ms);
+        }
+        self.dit_ms = self
+            .dit_ms
+            .clamp(DIT_MS_PER_WPM / MAX_WPM, DIT_MS_PER_WPM / MIN_WPM);
+        self.dah_ms = self.dah_ms.clamp(
+            2.0 * DIT_MS_PER_WPM / MAX_WPM,
+            4.0 * DIT_MS_PER_WPM / MIN_WPM,
+        );
+    }
+
+    //Characters end after 3 dits of silence and words after 7, split halfway
+    fn space(&mut self, ms: f64) {
+        if !self.symbols.is_empty() && ms > 2.0 * self.dit_ms {
+            let ch = MORSE
+                .iter()
+                .find(|(code, _)| *code == self.symbols)
+                .map(|(_, ch)| *ch)
+                .unwrap_or('*');
+            self.symbols.clear();
+            self.spaced = false;
+            self.write(ch);
+        }
+        if !self.spaced && ms > 5.0 * self.dit_ms {
+            self.spaced = true;
+            self.write(' ');
+        }
+    }
+
+    fn write(&mut self, ch: char) {
+        let Ok(mut text) = self.text.lock() else {
+            return;
+        };
+        text.text.push(ch);
+        if text.text.len() > TEXT_LEN {
+            let cut = text.text.len() - TEXT_LEN;
+            text.text.drain(..cut);
+        }
+    }
+}
+
+impl Default for CwDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for CwDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Cw
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Audio
+    }
+
+    fn process_real(&mut self, samples: &[f32], rate: f64) {
+        if rate != self.rate || self.goertzel.is_none() {
+            self.rate = rate;
+            let block = (BLOCK_SECS * rate).round().max(1.0) as usize;
+            self.goertzel = Some(Goertzel::new(self.pitch_hz, rate, block));
+        }
+
+        let mut mags = Vec::new();
+        if let Some(goertzel) = self.goertzel.as_mut() {
+            mags.extend(
+                samples
+                    .iter()
+                    .filter_map(|x| goertzel.push(*x))
+                    .map(f32::sqrt),
+            );
+        }
+        for mag in mags {
+            self.block(mag);
+        }
+
+        if let Ok(mut text) = self.text.lock() {
+            text.wpm = DIT_MS_PER_WPM / self.dit_ms;
+            text.key_down = self.key_down;
+            text.pitch_hz = self.pitch_hz;
+        }
+    }
+
+    fn set_vfo(&mut self, vfo: &Vfo) {
+        if vfo.bfo_hz != self.pitch_hz {
+            self.pitch_hz = vfo.bfo_hz;
+            self.goertzel = None;
+        }
+    }
+
+    fn report(&self) -> String {
+        let Ok(text) = self.text.lock() else {
+            return String::new();
+        };
+        format!("{:.0} WPM\n{}", text.wpm, text.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 8_000.0;
+
+    //`text` keyed at `wpm` on the default pitch, with a little noise
+    fn keyed(text: &str, wpm: f64) -> Vec<f32> {
+        let dit = (DIT_MS_PER_WPM / wpm / 1_000.0 * RATE) as usize;
+        let pitch = Vfo::default().bfo_hz;
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((seed >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.01
+        };
+
+        let mut keying: Vec<bool> = vec![false; 10 * dit];
+        for ch in text.chars() {
+            if ch == ' ' {
+                keying.extend([false; 4].iter().flat_map(|key| vec![*key; dit]));
+                continue;
+            }
+            let (code, _) = MORSE.iter().find(|(_, morse)| *morse == ch).unwrap();
+            for element in code.chars() {
+                let len = if element == '.' { dit } else { 3 * dit };
+                keying.extend(vec![true; len]);
+                keying.extend(vec![false; dit]);
+            }
+            keying.extend(vec![false; 2 * dit]);
+        }
+        keying.extend(vec![false; 10 * dit]);
+
+        keying
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| {
+                let tone = (2.0 * std::f64::consts::PI * pitch * idx as f64 / RATE).sin() as f32;
+                if *key {
+                    0.5 * tone + noise()
+                } else {
+                    noise()
+                }
+            })
+            .collect()
+    }
+
+    fn decode(audio: &[f32]) -> CwText {
+        let mut decoder = CwDecoder::new();
+        for chunk in audio.chunks(1024) {
+            decoder.process_real(chunk, RATE);
+        }
+        let text = decoder.text();
+        let mut text = text.lock().unwrap();
+        std::mem::take(&mut *text)
+    }
+
+    //The first few characters go on finding the speed
+    fn assert_decodes(wpm: f64) {
+        let decoded = decode(&keyed("VVV PARIS MY GM OK", wpm));
+        assert!(
+            decoded.text.ends_with(" MY GM OK "),
+            "{} WPM decoded as {:?}",
+            wpm,
+            decoded.text
+        );
+        assert!(
+            (decoded.wpm - wpm).abs() < 0.15 * wpm,
+            "{} WPM read as {:.1}",
+            wpm,
+            decoded.wpm
+        );
+    }
+
+    #[test]
+    fn decodes_at_5_wpm() {
+        assert_decodes(5.0);
+    }
+
+    #[test]
+    fn decodes_at_20_wpm() {
+        assert_decodes(20.0);
+    }
+
+    #[test]
+    fn decodes_dah_heavy_text_fast() {
+        for wpm in [35.0, 40.0, 50.0] {
+            assert_decodes(wpm);
+        }
+    }
+
+    #[test]
+    fn follows_a_change_of_speed() {
+        let mut audio = keyed("VVV TEST", 12.0);
+        audio.extend(keyed("VVV MY GM OK", 45.0));
+        let decoded = decode(&audio);
+        assert!(decoded.text.ends_with(" MY GM OK "), "{:?}", decoded.text);
+    }
+
+    #[test]
+    fn dits_alone_keep_their_length() {
+        let decoded = decode(&keyed("EISH EISH", 20.0));
+        assert_eq!(decoded.text, "EISH EISH ");
+    }
+}
@@ -0,0 +1,279 @@
+//! HF keyboard modes on the SSB audio: RTTY, BPSK31 and QPSK31.
+
+use std::sync::{Arc, Mutex};
+
+use super::psk::PskDemod;
+use super::rtty::{self, RttyDemod};
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::tone_power;
+
+/// Characters of decoded text kept
+pub const TEXT_LEN: usize = 8_000;
+/// Top of the audio spectrum used for tuning
+pub const SPECTRUM_MAX_HZ: f64 = 3_000.0;
+/// Points in the audio spectrum
+pub const SPECTRUM_BINS: usize = 256;
+
+//Audio the spectrum is worked out over, about 12 Hz resolution at 48 kHz
+const SPECTRUM_LEN: usize = 4_096;
+const SPECTRUM_EVERY_SECS: f64 = 0.2;
+//PSK text is only written while the phase is this clean, noise alone decodes as garbage
+const PSK_SQUELCH: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DigiMode {
+    #[default]
+    Rtty,
+    Bpsk31,
+    Qpsk31,
+}
+
+impl DigiMode {
+    pub const ALL: [DigiMode; 3] = [DigiMode::Rtty, DigiMode::Bpsk31, DigiMode::Qpsk31];
+}
+
+impl std::fmt::Display for DigiMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DigiMode::Rtty => "RTTY",
+                DigiMode::Bpsk31 => "BPSK31",
+                DigiMode::Qpsk31 => "QPSK31",
+            }
+        )
+    }
+}
+
+/// What to listen for and where in the audio
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigiSettings {
+    pub mode: DigiMode,
+    /// Audio frequency of the signal, for RTTY halfway between mark and space
+    pub tone_hz: f64,
+    /// RTTY speed, one of [`rtty::BAUDS`]
+    pub baud: f64,
+    /// RTTY shift, one of [`rtty::SHIFTS`]
+    pub shift_hz: f64,
+    /// Swap mark and space, or the QPSK phase direction, for signals sent on the other sideband
+    pub reverse: bool,
+}
+
+impl Default for DigiSettings {
+    fn default() -> Self {
+        Self {
+            mode: DigiMode::default(),
+            tone_hz: 1_500.0,
+            baud: rtty::BAUDS[0],
+            shift_hz: rtty::SHIFTS[0],
+            reverse: false,
+        }
+    }
+}
+
+impl DigiSettings {
+    /// Audio frequencies the signal is on, mark and space for RTTY
+    pub fn tones(&self) -> Vec<f64> {
+        match self.mode {
+            DigiMode::Rtty if self.reverse => vec![
+                self.tone_hz - self.shift_hz / 2.0,
+                self.tone_hz + self.shift_hz / 2.0,
+            ],
+            DigiMode::Rtty => vec![
+                self.tone_hz + self.shift_hz / 2.0,
+                self.tone_hz - self.shift_hz / 2.0,
+            ],
+            DigiMode::Bpsk31 | DigiMode::Qpsk31 => vec![self.tone_hz],
+        }
+    }
+}
+
+/// Decoded text and the audio spectrum to tune from
+#[derive(Debug, Default)]
+pub struct DigiState {
+    /// Written by the GUI, the decoder follows it
+    pub settings: DigiSettings,
+    pub text: String,
+    /// dB from 0 Hz to [`SPECTRUM_MAX_HZ`]
+    pub spectrum: Vec<f32>,
+    /// PSK phase quality from 0 to 1, None for RTTY
+    pub quality: Option<f32>,
+}
+
+enum Demod {
+    Rtty(RttyDemod),
+    Psk(Box<PskDemod>),
+}
+
+/// RTTY and PSK31 decoder on SSB audio
+pub struct DigiDecoder {
+    state: Arc<Mutex<DigiState>>,
+    settings: DigiSettings,
+    rate: f64,
+    demod: Option<Demod>,
+    history: Vec<f32>,
+    since_spectrum: usize,
+}
+
+impl DigiDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(DigiState::default())),
+            settings: DigiSettings::default(),
+            rate: 0.0,
+            demod: None,
+            history: Vec::new(),
+            since_spectrum: 0,
+        }
+    }
+
+    pub fn state(&self) -> Arc<Mutex<DigiState>> {
+        self.state.clone()
+    }
+
+    fn spectrum(&self) -> Vec<f32> {
+        (0..SPECTRUM_BINS)
+            .map(|bin| {
+                let freq = bin as f64 * SPECTRUM_MAX_HZ / (SPECTRUM_BINS - 1) as f64;
+                10.0 * (tone_power(&self.history, freq, self.rate) + 1e-12).log10()
+            })
+            .collect()
+    }
+}
+
+impl Default for DigiDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for DigiDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Digi
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Audio
+    }
+
+    fn process_real(&mut self, samples: &[f32], rate: f64) {
+        let settings = self
+            .state
+            .lock()
+            .map(|state| state.settings.clone())
+            .unwrap_or_default();
+        if rate != self.rate || settings != self.settings || self.demod.is_none() {
+            self.rate = rate;
+            self.demod = Some(match settings.mode {
+                DigiMode::Rtty => Demod::Rtty(RttyDemod::new(
+                    settings.tone_hz,
+                    settings.shift_hz,
+                    settings.baud,
+                    settings.reverse,
+                    rate,
+                )),
+                DigiMode::Bpsk31 | DigiMode::Qpsk31 => Demod::Psk(Box::new(PskDemod::new(
+                    settings.tone_hz,
+                    settings.mode == DigiMode::Qpsk31,
+                    settings.reverse,
+                    rate,
+                ))),
+            });
+            self.settings = settings;
+        }
+
+        let mut text = String::new();
+        let mut quality = None;
+        match self.demod.as_mut() {
+            Some(Demod::Rtty(demod)) => {
+                text.extend(samples.iter().filter_map(|x| demod.process(*x)))
+            }
+            Some(Demod::Psk(demod)) => {
+                for x in samples {
+                    if let Some(ch) = demod.process(*x) {
+                        if demod.quality() > PSK_SQUELCH {
+                            text.push(ch);
+                        }
+                    }
+                }
+                quality = Some(demod.quality());
+            }
+            None => {}
+        }
+        //Line ends are sent as CR LF
+        text.retain(|ch| ch != '\r');
+
+        self.history.extend_from_slice(samples);
+        if self.history.len() > SPECTRUM_LEN {
+            let cut = self.history.len() - SPECTRUM_LEN;
+            self.history.drain(..cut);
+        }
+        self.since_spectrum += samples.len();
+        let spectrum = (self.since_spectrum as f64 >= SPECTRUM_EVERY_SECS * rate).then(|| {
+            self.since_spectrum = 0;
+            self.spectrum()
+        });
+
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.text.push_str(&text);
+        if state.text.len() > TEXT_LEN {
+            let mut cut = state.text.len() - TEXT_LEN;
+            while !state.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            state.text.drain(..cut);
+        }
+        state.quality = quality;
+        if let Some(spectrum) = spectrum {
+            state.spectrum = spectrum;
+        }
+    }
+
+    fn report(&self) -> String {
+        let Ok(state) = self.state.lock() else {
+            return String::new();
+        };
+        state.text.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtty_mark_is_the_upper_tone_unless_reversed() {
+        let mut settings = DigiSettings {
+            tone_hz: 2_000.0,
+            shift_hz: 170.0,
+            ..Default::default()
+        };
+        assert_eq!(settings.tones(), [2_085.0, 1_915.0]);
+        settings.reverse = true;
+        assert_eq!(settings.tones(), [1_915.0, 2_085.0]);
+        settings.mode = DigiMode::Qpsk31;
+        assert_eq!(settings.tones(), [2_000.0]);
+    }
+
+    #[test]
+    fn spectrum_peaks_at_the_tone() {
+        const RATE: f64 = 8_000.0;
+        let mut decoder = DigiDecoder::new();
+        let audio: Vec<f32> = (0..RATE as usize)
+            .map(|idx| (2.0 * std::f64::consts::PI * 1_000.0 * idx as f64 / RATE).sin() as f32)
+            .collect();
+        decoder.process_real(&audio, RATE);
+
+        let state = decoder.state();
+        let state = state.lock().unwrap();
+        assert_eq!(state.spectrum.len(), SPECTRUM_BINS);
+        let peak = (0..SPECTRUM_BINS)
+            .max_by(|a, b| state.spectrum[*a].total_cmp(&state.spectrum[*b]))
+            .unwrap();
+        let peak_hz = peak as f64 * SPECTRUM_MAX_HZ / (SPECTRUM_BINS - 1) as f64;
+        assert!((peak_hz - 1_000.0).abs() < SPECTRUM_MAX_HZ / SPECTRUM_BINS as f64);
+    }
+}
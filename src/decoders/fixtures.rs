@@ -0,0 +1,77 @@
+//! Recordings synthesized for the decoder tests, written the way `BaseBandSink` writes them
+
+use std::path::PathBuf;
+
+/// Writes float samples to a WAV in the temp directory, interleaved I/Q when `channels` is 2
+pub fn write_wav(name: &str, channels: u16, rate: f64, samples: &[f32]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rustic_sdr_{}_{}.wav", std::process::id(), name));
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate: rate as u32,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for sample in samples {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+/// Interleaved I/Q of a carrier frequency modulated by `signal`, 1.0 deviates by `deviation` Hz
+pub fn fm_iq(signal: &[f32], deviation: f64, rate: f64) -> Vec<f32> {
+    let step = 2.0 * std::f64::consts::PI * deviation / rate;
+    let mut phase = 0.0f64;
+    let mut iq = Vec::with_capacity(signal.len() * 2);
+    for x in signal {
+        phase = (phase + step * *x as f64) % (2.0 * std::f64::consts::PI);
+        iq.push(0.5 * phase.cos() as f32);
+        iq.push(0.5 * phase.sin() as f32);
+    }
+    iq
+}
+
+/// Bits of `payload` and its X.25 FCS between two flags, bit stuffed and each byte LSB first
+pub fn hdlc_bits(payload: &[u8]) -> Vec<bool> {
+    let mut crc: u16 = 0xFFFF;
+    for byte in payload {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    let byte_bits = |byte: u8| (0..8).map(move |bit| (byte >> bit) & 1 == 1);
+
+    let mut bits: Vec<bool> = byte_bits(0x7E).collect();
+    let mut ones = 0;
+    for bit in payload
+        .iter()
+        .chain(&(!crc).to_le_bytes())
+        .flat_map(|byte| byte_bits(*byte))
+    {
+        bits.push(bit);
+        ones = if bit { ones + 1 } else { 0 };
+        if ones == 5 {
+            bits.push(false);
+            ones = 0;
+        }
+    }
+    bits.extend(byte_bits(0x7E));
+    bits
+}
+
+/// NRZI levels sending `bits`, a zero is a change of level and a one keeps it
+pub fn nrzi(bits: &[bool]) -> Vec<bool> {
+    let mut level = false;
+    bits.iter()
+        .map(|bit| {
+            level ^= !bit;
+            level
+        })
+        .collect()
+}
@@ -0,0 +1,197 @@
+//! HDLC deframing shared by AIS and AX.25.
+
+//X.25 CRC of a frame followed by its own FCS
+const CRC_GOOD: u16 = 0xF0B8;
+
+/// Flag delimited, bit stuffed frames with an X.25 CRC, fed one bit at a time after NRZI decoding
+pub struct Hdlc {
+    //Last 8 raw bits, newest in the top bit
+    shift: u8,
+    ones: usize,
+    in_frame: bool,
+    bits: Vec<bool>,
+    min_bits: usize,
+    max_bits: usize,
+}
+
+impl Hdlc {
+    /// Frames are only checked if they are `min_bits` to `max_bits` long, FCS included
+    pub fn new(min_bits: usize, max_bits: usize) -> Self {
+        Self {
+            shift: 0,
+            ones: 0,
+            in_frame: false,
+            bits: Vec::new(),
+            min_bits,
+            max_bits,
+        }
+    }
+
+    /// Returns a frame, without its FCS, once its closing flag arrives and the CRC is good
+    pub fn push(&mut self, bit: bool) -> Option<Vec<u8>> {
+        self.shift = (self.shift >> 1) | ((bit as u8) << 7);
+        if self.shift == 0x7E {
+            //The flag's own bits went in before it was recognised
+            let frame_bits = self.bits.len().saturating_sub(7);
+            let frame = (self.in_frame && frame_bits >= self.min_bits)
+                .then(|| frame_bytes(&self.bits[..frame_bits]));
+            self.in_frame = true;
+            self.bits.clear();
+            self.ones = 0;
+            return frame.flatten();
+        }
+        if !self.in_frame {
+            return None;
+        }
+
+        if bit {
+            self.ones += 1;
+            //Seven ones in a row is an abort
+            if self.ones > 6 {
+                self.in_frame = false;
+                return None;
+            }
+            self.bits.push(true);
+        } else {
+            //A zero after five ones is stuffing
+            if self.ones != 5 {
+                self.bits.push(false);
+            }
+            self.ones = 0;
+        }
+
+        if self.bits.len() > self.max_bits {
+            self.in_frame = false;
+        }
+        None
+    }
+}
+
+//Bytes sent least significant bit first, without the FCS, if the CRC is good
+fn frame_bytes(bits: &[bool]) -> Option<Vec<u8>> {
+    if bits.len() % 8 != 0 {
+        return None;
+    }
+    let bytes: Vec<u8> = bits
+        .chunks_exact(8)
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0u8, |acc, (idx, bit)| acc | ((*bit as u8) << idx))
+        })
+        .collect();
+
+    let mut crc: u16 = 0xFFFF;
+    for byte in bytes.iter() {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    (crc == CRC_GOOD).then(|| bytes[..bytes.len() - 2].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAG: u8 = 0x7E;
+
+    //FCS sent after the frame, the complemented CRC low byte first
+    fn fcs(bytes: &[u8]) -> [u8; 2] {
+        let mut crc: u16 = 0xFFFF;
+        for byte in bytes {
+            crc ^= *byte as u16;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x8408
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        (!crc).to_le_bytes()
+    }
+
+    fn byte_bits(byte: u8) -> impl Iterator<Item = bool> {
+        (0..8).map(move |bit| (byte >> bit) & 1 == 1)
+    }
+
+    //Frame and FCS bit stuffed between two flags
+    fn framed(payload: &[u8]) -> Vec<bool> {
+        let mut bits: Vec<bool> = byte_bits(FLAG).collect();
+        let mut ones = 0;
+        for bit in payload
+            .iter()
+            .chain(&fcs(payload))
+            .flat_map(|byte| byte_bits(*byte))
+        {
+            bits.push(bit);
+            ones = if bit { ones + 1 } else { 0 };
+            if ones == 5 {
+                bits.push(false);
+                ones = 0;
+            }
+        }
+        bits.extend(byte_bits(FLAG));
+        bits
+    }
+
+    fn frames(bits: &[bool]) -> Vec<Vec<u8>> {
+        let mut hdlc = Hdlc::new(32, 1024);
+        bits.iter().filter_map(|bit| hdlc.push(*bit)).collect()
+    }
+
+    #[test]
+    fn fcs_matches_the_x25_check_value() {
+        assert_eq!(fcs(b"123456789"), 0x906Eu16.to_le_bytes());
+        let mut frame = b"123456789".to_vec();
+        frame.extend(fcs(&frame));
+        let bits: Vec<bool> = frame.iter().flat_map(|byte| byte_bits(*byte)).collect();
+        assert_eq!(frame_bytes(&bits).as_deref(), Some(&b"123456789"[..]));
+    }
+
+    #[test]
+    fn removes_stuffing() {
+        //Runs of ones and bytes that look like flags all need stuffing
+        let payload = [0xFF, 0x7E, 0x3F, 0xF8, 0x01, 0x7E, 0xFF];
+        let bits = framed(&payload);
+        assert!(bits.len() > 16 + 9 * 8);
+        assert_eq!(frames(&bits), vec![payload.to_vec()]);
+    }
+
+    #[test]
+    fn back_to_back_frames_share_a_flag() {
+        let mut bits = framed(b"first");
+        bits.truncate(bits.len() - 8);
+        bits.extend(framed(b"second"));
+        assert_eq!(frames(&bits), vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn abort_drops_the_frame() {
+        let aborted = framed(b"aborted");
+        let mut bits = aborted[..40].to_vec();
+        bits.extend([true; 7]);
+        bits.extend(framed(b"after"));
+        assert_eq!(frames(&bits), vec![b"after".to_vec()]);
+    }
+
+    #[test]
+    fn bad_crc_is_dropped() {
+        let mut bits = framed(b"corrupted");
+        bits[20] = !bits[20];
+        assert!(frames(&bits).is_empty());
+    }
+
+    #[test]
+    fn frames_outside_the_length_limits_are_dropped() {
+        assert!(frames(&framed(b"")).is_empty());
+        assert!(frames(&framed(&[0x55; 200])).is_empty());
+        assert_eq!(frames(&framed(&[0x55; 100])).len(), 1);
+    }
+}
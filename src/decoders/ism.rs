@@ -0,0 +1,818 @@
+//! 315/433/868 MHz ISM band sensors, in the spirit of rtl_433.
+//!
+//! A pulse detector cuts the raw samples into bursts, each burst is sliced into OOK pulses from the
+//! amplitude and into FSK pulses from the frequency, and every registered [`IsmDevice`] is tried on
+//! the one it wants.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futuresdr::num_complex::Complex32;
+use serde_json::json;
+
+use super::jsonl::JsonLines;
+use super::net::UdpFeed;
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::{FmDiscriminator, OnePole};
+
+/// Events kept in the log
+pub const EVENT_LOG_LEN: usize = 500;
+
+//Carrier has to be this far above the noise floor to count as a pulse, 10 dB
+const OOK_SNR: f32 = 3.16;
+//Silence that ends a burst
+const BURST_GAP_SECS: f64 = 0.01;
+const MAX_PULSES: usize = 1200;
+//Longest carrier kept for FSK slicing
+const MAX_FSK_SECS: f64 = 0.1;
+//Envelope and frequency smoothing
+const SMOOTH_SECS: f64 = 10e-6;
+//The noise floor follows drops quickly and rises slowly so bursts don't raise it
+const NOISE_FALL: f32 = 0.01;
+const NOISE_RISE: f32 = 1e-4;
+const LEVEL_ALPHA: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modulation {
+    Ook,
+    Fsk,
+}
+
+impl std::fmt::Display for Modulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Modulation::Ook => "OOK",
+                Modulation::Fsk => "FSK",
+            }
+        )
+    }
+}
+
+/// Pulses of one burst as (on, off) durations in µs. For FSK "on" is the upper frequency.
+#[derive(Debug, Clone, Default)]
+pub struct PulseTrain {
+    pub pulses: Vec<(f64, f64)>,
+}
+
+impl PulseTrain {
+    /// Bits from pulse widths, a long pulse is a 1. Rows end at gaps longer than `reset_us`.
+    pub fn pwm(&self, short_us: f64, long_us: f64, reset_us: f64) -> Vec<Vec<bool>> {
+        let mut rows = vec![Vec::new()];
+        for (pulse, gap) in self.pulses.iter() {
+            if *pulse < short_us * 0.5 || *pulse > long_us * 1.5 {
+                rows.push(Vec::new());
+                continue;
+            }
+            rows.last_mut()
+                .unwrap()
+                .push(*pulse > (short_us + long_us) / 2.0);
+            if *gap > reset_us {
+                rows.push(Vec::new());
+            }
+        }
+        rows.retain(|row| !row.is_empty());
+        rows
+    }
+
+    /// Bits from gap widths, a long gap is a 1. Rows end at gaps longer than `reset_us`.
+    pub fn ppm(&self, short_us: f64, long_us: f64, reset_us: f64) -> Vec<Vec<bool>> {
+        let mut rows = vec![Vec::new()];
+        for (_, gap) in self.pulses.iter() {
+            if *gap > reset_us || *gap < short_us * 0.5 || *gap > long_us * 1.5 {
+                rows.push(Vec::new());
+            } else {
+                rows.last_mut()
+                    .unwrap()
+                    .push(*gap > (short_us + long_us) / 2.0);
+            }
+        }
+        rows.retain(|row| !row.is_empty());
+        rows
+    }
+
+    /// The level sampled every `bit_us`. Rows end at gaps longer than `reset_us`.
+    pub fn pcm(&self, bit_us: f64, reset_us: f64) -> Vec<Vec<bool>> {
+        let mut rows = vec![Vec::new()];
+        for (pulse, gap) in self.pulses.iter() {
+            let row = rows.last_mut().unwrap();
+            row.resize(row.len() + (pulse / bit_us).round() as usize, true);
+            if *gap > reset_us {
+                rows.push(Vec::new());
+            } else {
+                row.resize(row.len() + (gap / bit_us).round() as usize, false);
+            }
+        }
+        rows.retain(|row| !row.is_empty());
+        rows
+    }
+}
+
+/// Bits packed into bytes, most significant bit first
+pub fn bytes(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (idx, bit)| acc | ((*bit as u8) << (7 - idx)))
+        })
+        .collect()
+}
+
+/// Bits as an unsigned number, most significant bit first
+pub fn bits_value(bits: &[bool]) -> u32 {
+    bits.iter().fold(0, |acc, bit| (acc << 1) | *bit as u32)
+}
+
+/// Manchester decoding with 10 as a 1 and 01 as a 0, stops at the first invalid pair
+pub fn manchester(chips: &[bool]) -> Vec<bool> {
+    chips
+        .chunks_exact(2)
+        .map_while(|pair| (pair[0] != pair[1]).then_some(pair[0]))
+        .collect()
+}
+
+/// The first row of `len` bits that was received at least `min` times
+pub fn repeated_row(rows: &[Vec<bool>], len: usize, min: usize) -> Option<&Vec<bool>> {
+    rows.iter()
+        .filter(|row| row.len() == len)
+        .find(|row| rows.iter().filter(|other| other == row).count() >= min)
+}
+
+/// A sensor protocol, tried on every burst
+pub trait IsmDevice: Send {
+    /// Model name reported with each event
+    fn model(&self) -> &'static str;
+
+    fn modulation(&self) -> Modulation;
+
+    /// Fields of the event as a JSON object, None if the burst isn't from this device
+    fn decode(&self, pulses: &PulseTrain) -> Option<serde_json::Value>;
+}
+
+/// Every device protocol that is built in
+pub fn devices() -> Vec<Box<dyn IsmDevice>> {
+    vec![Box::new(NexusTh), Box::new(Ev1527), Box::new(FordTpms)]
+}
+
+/// One decoded transmission
+#[derive(Debug, Clone)]
+pub struct IsmEvent {
+    pub time: DateTime<Utc>,
+    pub model: &'static str,
+    pub modulation: Modulation,
+    /// How far the burst was above the noise floor
+    pub snr_db: f32,
+    /// What the device sent, a JSON object
+    pub fields: serde_json::Value,
+}
+
+impl IsmEvent {
+    pub fn json(&self) -> serde_json::Value {
+        let mut event = json!({
+            "time": self.time.to_rfc3339(),
+            "model": self.model,
+            "mod": self.modulation.to_string(),
+            "snr": (self.snr_db * 10.0).round() / 10.0,
+        });
+        if let (Some(event), Some(fields)) = (event.as_object_mut(), self.fields.as_object()) {
+            event.extend(fields.clone());
+        }
+        event
+    }
+}
+
+/// Recent events, newest first
+#[derive(Debug, Default)]
+pub struct IsmLog {
+    pub events: VecDeque<IsmEvent>,
+    pub total: u64,
+    /// Bursts seen, whether a device recognised them or not
+    pub bursts: u64,
+    /// Where every event is sent as a JSON datagram
+    pub forward: Option<SocketAddr>,
+    /// JSON-lines file every event is appended to
+    pub json_path: Option<PathBuf>,
+    /// Why the events couldn't be sent or written
+    pub error: Option<String>,
+}
+
+/// OOK/FSK sensor decoder on raw samples
+pub struct IsmDecoder {
+    log: Arc<Mutex<IsmLog>>,
+    devices: Vec<Box<dyn IsmDevice>>,
+    rate: f64,
+    detector: Option<PulseDetector>,
+    udp: UdpFeed,
+    json: JsonLines,
+}
+
+impl IsmDecoder {
+    /// Decoder trying every device in [`devices`]
+    pub fn new() -> Self {
+        Self {
+            log: Arc::new(Mutex::new(IsmLog::default())),
+            devices: devices(),
+            rate: 0.0,
+            detector: None,
+            udp: UdpFeed::default(),
+            json: JsonLines::default(),
+        }
+    }
+
+    /// Adds a protocol that isn't built in
+    pub fn register(&mut self, device: Box<dyn IsmDevice>) {
+        self.devices.push(device);
+    }
+
+    pub fn log(&self) -> Arc<Mutex<IsmLog>> {
+        self.log.clone()
+    }
+
+    fn burst(&mut self, burst: Burst) {
+        let events: Vec<IsmEvent> = self
+            .devices
+            .iter()
+            .filter_map(|device| {
+                let pulses = match device.modulation() {
+                    Modulation::Ook => &burst.ook,
+                    Modulation::Fsk => &burst.fsk,
+                };
+                device.decode(pulses).map(|fields| IsmEvent {
+                    time: Utc::now(),
+                    model: device.model(),
+                    modulation: device.modulation(),
+                    snr_db: burst.snr_db,
+                    fields,
+                })
+            })
+            .collect();
+
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+        log.bursts += 1;
+        for event in events {
+            let json = event.json();
+            if let Some(addr) = log.forward {
+                self.udp.send(addr, json.to_string().as_bytes());
+                log.error = self.udp.error().map(str::to_string);
+            }
+            if let Err(e) = self.json.write(log.json_path.as_deref(), &json) {
+                log.error = Some(e);
+                log.json_path = None;
+            }
+
+            log.total += 1;
+            log.events.push_front(event);
+            log.events.truncate(EVENT_LOG_LEN);
+        }
+    }
+}
+
+impl Default for IsmDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for IsmDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Ism
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Raw
+    }
+
+    fn process_complex(&mut self, samples: &[Complex32], rate: f64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.detector = Some(PulseDetector::new(rate));
+        }
+        let Some(detector) = self.detector.as_mut() else {
+            return;
+        };
+
+        let bursts: Vec<Burst> = samples
+            .iter()
+            .filter_map(|x| detector.process(*x))
+            .collect();
+        for burst in bursts {
+            self.burst(burst);
+        }
+    }
+
+    fn report(&self) -> String {
+        let Ok(log) = self.log.lock() else {
+            return String::new();
+        };
+        let mut report = format!("{} events from {} bursts", log.total, log.bursts);
+        for event in log.events.iter().rev() {
+            report += &format!("\n{}", event.json());
+        }
+        report
+    }
+}
+
+//A burst sliced both ways
+struct Burst {
+    ook: PulseTrain,
+    fsk: PulseTrain,
+    snr_db: f32,
+}
+
+//Finds bursts by comparing the envelope with the noise floor
+struct PulseDetector {
+    us_per_sample: f64,
+    env: OnePole,
+    noise: f32,
+    //Envelope while the carrier is on
+    level: f32,
+    high: bool,
+    in_burst: bool,
+    //Samples since the last edge
+    run: usize,
+    pulse: usize,
+    pulses: Vec<(usize, usize)>,
+    disc: FmDiscriminator,
+    freq: OnePole,
+    //Smoothed frequency of every sample the carrier was on
+    fsk: Vec<f32>,
+    gap_limit: usize,
+    fsk_limit: usize,
+}
+
+impl PulseDetector {
+    fn new(rate: f64) -> Self {
+        Self {
+            us_per_sample: 1e6 / rate,
+            env: OnePole::new(SMOOTH_SECS, rate),
+            noise: 1.0,
+            level: 0.0,
+            high: false,
+            in_burst: false,
+            run: 0,
+            pulse: 0,
+            pulses: Vec::new(),
+            disc: FmDiscriminator::default(),
+            freq: OnePole::new(SMOOTH_SECS, rate),
+            fsk: Vec::new(),
+            gap_limit: (BURST_GAP_SECS * rate) as usize,
+            fsk_limit: (MAX_FSK_SECS * rate) as usize,
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: Complex32) -> Option<Burst> {
+        let env = self.env.process(x.norm());
+        let freq = self.freq.process(self.disc.process(x));
+        self.run += 1;
+
+        if self.high {
+            self.level += LEVEL_ALPHA * (env - self.level);
+            if self.fsk.len() < self.fsk_limit {
+                self.fsk.push(freq);
+            }
+            if env < (self.level * 0.5).max(self.noise * OOK_SNR) {
+                self.high = false;
+                self.pulse = self.run;
+                self.run = 0;
+            }
+            return None;
+        }
+
+        if env > self.noise * OOK_SNR && (!self.in_burst || env > self.level * 0.5) {
+            if self.in_burst {
+                self.pulses.push((self.pulse, self.run));
+            } else {
+                self.level = env;
+            }
+            self.in_burst = true;
+            self.high = true;
+            self.run = 0;
+            return None;
+        }
+
+        if !self.in_burst {
+            let rate = if env < self.noise {
+                NOISE_FALL
+            } else {
+                NOISE_RISE
+            };
+            self.noise += rate * (env - self.noise);
+            return None;
+        }
+        if self.run > self.gap_limit || self.pulses.len() >= MAX_PULSES {
+            self.pulses.push((self.pulse, self.run));
+            return Some(self.finish());
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Burst {
+        self.in_burst = false;
+        let to_us = |samples: usize| samples as f64 * self.us_per_sample;
+        let ook = PulseTrain {
+            pulses: self
+                .pulses
+                .drain(..)
+                .map(|(pulse, gap)| (to_us(pulse), to_us(gap)))
+                .collect(),
+        };
+
+        //Slice halfway between the two tones, the mean works for balanced codes like Manchester
+        let mut fsk = PulseTrain::default();
+        if !self.fsk.is_empty() {
+            let mid = self.fsk.iter().sum::<f32>() / self.fsk.len() as f32;
+            let mut upper = self.fsk[0] > mid;
+            let mut run = 0;
+            let mut pulse = 0;
+            for freq in self.fsk.drain(..) {
+                if (freq > mid) == upper {
+                    run += 1;
+                    continue;
+                }
+                if upper {
+                    pulse = run;
+                } else {
+                    fsk.pulses.push((to_us(pulse), to_us(run)));
+                    pulse = 0;
+                }
+                upper = !upper;
+                run = 1;
+            }
+            if upper {
+                fsk.pulses.push((to_us(run), to_us(self.gap_limit)));
+            } else {
+                fsk.pulses.push((to_us(pulse), to_us(self.gap_limit)));
+            }
+        }
+
+        Burst {
+            ook,
+            fsk,
+            snr_db: 20.0 * (self.level / self.noise.max(1e-9)).log10(),
+        }
+    }
+}
+
+/// Nexus, Rubicson and many other cheap temperature/humidity sensors. 36 bits sent as ~500 µs
+/// pulses followed by a 1 ms gap for 0 and 2 ms for 1, repeated with a 4 ms gap.
+pub struct NexusTh;
+
+impl IsmDevice for NexusTh {
+    fn model(&self) -> &'static str {
+        "Nexus-TH"
+    }
+
+    fn modulation(&self) -> Modulation {
+        Modulation::Ook
+    }
+
+    fn decode(&self, pulses: &PulseTrain) -> Option<serde_json::Value> {
+        let rows = pulses.ppm(1000.0, 2000.0, 3000.0);
+        let row = repeated_row(&rows, 36, 2)?;
+        //A fixed nibble between temperature and humidity
+        if bits_value(&row[24..28]) != 0xF {
+            return None;
+        }
+        let humidity = bits_value(&row[28..36]);
+        if humidity > 100 {
+            return None;
+        }
+        let mut temp = bits_value(&row[12..24]) as i32;
+        if temp & 0x800 != 0 {
+            temp -= 0x1000;
+        }
+
+        Some(json!({
+            "id": bits_value(&row[0..8]),
+            "channel": bits_value(&row[10..12]) + 1,
+            "battery_ok": row[8],
+            "temperature_C": temp as f64 / 10.0,
+            "humidity": humidity,
+        }))
+    }
+}
+
+/// EV1527 and compatible learning code chips used in door/window contacts, PIR sensors and key
+/// fobs. 24 bits, a 1 is a long pulse and short gap, each repeat starts with a sync pulse.
+pub struct Ev1527;
+
+impl IsmDevice for Ev1527 {
+    fn model(&self) -> &'static str {
+        "EV1527"
+    }
+
+    fn modulation(&self) -> Modulation {
+        Modulation::Ook
+    }
+
+    fn decode(&self, pulses: &PulseTrain) -> Option<serde_json::Value> {
+        //The bit period varies from chip to chip, find the short and long pulses from the burst
+        let mut widths: Vec<f64> = pulses.pulses.iter().map(|(pulse, _)| *pulse).collect();
+        if widths.len() < 24 {
+            return None;
+        }
+        widths.sort_by(|a, b| a.total_cmp(b));
+        let short = widths[widths.len() / 5];
+        let long = widths[widths.len() * 4 / 5];
+        if !(2.0..=4.5).contains(&(long / short)) {
+            return None;
+        }
+
+        //The sync pulse ends up as the 25th bit of each row
+        let rows: Vec<Vec<bool>> = pulses
+            .pwm(short, long, long * 4.0)
+            .into_iter()
+            .filter_map(|mut row| match row.len() {
+                24 => Some(row),
+                25 => {
+                    row.truncate(24);
+                    Some(row)
+                }
+                _ => None,
+            })
+            .collect();
+        let row = repeated_row(&rows, 24, 2)?;
+
+        Some(json!({
+            "id": format!("{:05x}", bits_value(&row[0..20])),
+            "cmd": bits_value(&row[20..24]),
+            "short_us": short.round(),
+        }))
+    }
+}
+
+/// Ford tire pressure sensors, FSK with 52 µs Manchester chips after a 0xAAA9 preamble. 8 bytes
+/// of ID, pressure, temperature and flags ending in a sum checksum.
+pub struct FordTpms;
+
+impl IsmDevice for FordTpms {
+    fn model(&self) -> &'static str {
+        "Ford-TPMS"
+    }
+
+    fn modulation(&self) -> Modulation {
+        Modulation::Fsk
+    }
+
+    fn decode(&self, pulses: &PulseTrain) -> Option<serde_json::Value> {
+        const PREAMBLE: [bool; 16] = [
+            true, false, true, false, true, false, true, false, true, false, true, false, true,
+            false, false, true,
+        ];
+        pulses.pcm(52.0, 1000.0).iter().find_map(|row| {
+            let start = row
+                .windows(PREAMBLE.len())
+                .position(|window| window == PREAMBLE)?;
+            let bits = manchester(&row[start + PREAMBLE.len()..]);
+            if bits.len() < 64 {
+                return None;
+            }
+            let b = bytes(&bits[..64]);
+            let sum = b[..7].iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte));
+            if sum != b[7] {
+                return None;
+            }
+
+            let pressure = ((((b[6] & 0x20) as u16) << 3) | b[4] as u16) as f64 * 0.25;
+            Some(json!({
+                "id": format!("{:02x}{:02x}{:02x}{:02x}", b[0], b[1], b[2], b[3]),
+                "pressure_PSI": pressure,
+                "temperature_C": (b[5] & 0x7F) as i32 - 56,
+                "moving": b[6] & 0x44 == 0x44,
+                "learn": b[6] & 0x4C == 0x08,
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::{decode_wav, fixtures};
+    use crate::demod::Vfo;
+
+    const RATE: f64 = 250_000.0;
+    //Quiet before and after the burst, longer than the gap that ends one
+    const QUIET_US: f64 = 20_000.0;
+
+    //Low level noise that is the same on every run
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((self.0 >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.01
+        }
+
+        fn sample(&mut self) -> Complex32 {
+            Complex32::new(self.next(), self.next())
+        }
+    }
+
+    fn samples(us: f64) -> usize {
+        (us * RATE / 1e6).round() as usize
+    }
+
+    //OOK burst of (on, off) durations in µs
+    fn ook(pulses: &[(f64, f64)]) -> Vec<Complex32> {
+        let mut noise = Noise(1);
+        let mut iq: Vec<Complex32> = (0..samples(QUIET_US)).map(|_| noise.sample()).collect();
+        for (on, off) in pulses {
+            iq.extend((0..samples(*on)).map(|_| Complex32::new(0.5, 0.0) + noise.sample()));
+            iq.extend((0..samples(*off)).map(|_| noise.sample()));
+        }
+        iq.extend((0..samples(QUIET_US)).map(|_| noise.sample()));
+        iq
+    }
+
+    //FSK burst with a chip of `chip_us` per entry, true on the upper frequency
+    fn fsk(chips: &[bool], chip_us: f64) -> Vec<Complex32> {
+        let mut noise = Noise(1);
+        let mut iq: Vec<Complex32> = (0..samples(QUIET_US)).map(|_| noise.sample()).collect();
+        let mut phase = 0.0f64;
+        for chip in chips {
+            let freq = if *chip { 40_000.0 } else { -40_000.0 };
+            for _ in 0..samples(chip_us) {
+                phase += 2.0 * std::f64::consts::PI * freq / RATE;
+                iq.push(Complex32::from_polar(0.5, phase as f32) + noise.sample());
+            }
+        }
+        iq.extend((0..samples(QUIET_US)).map(|_| noise.sample()));
+        iq
+    }
+
+    //Events found in a recording of `iq`, oldest first
+    fn decode(name: &str, iq: &[Complex32]) -> Vec<serde_json::Value> {
+        let interleaved: Vec<f32> = iq.iter().flat_map(|x| [x.re, x.im]).collect();
+        let path = fixtures::write_wav(name, 2, RATE, &interleaved);
+        let decoder = IsmDecoder::new();
+        let log = decoder.log();
+        let mut decoders: Vec<Box<dyn Decoder>> = vec![Box::new(decoder)];
+        let decoded = decode_wav(&path, Vfo::default(), &mut decoders);
+        std::fs::remove_file(&path).unwrap();
+        decoded.unwrap();
+
+        let log = log.lock().unwrap();
+        log.events.iter().rev().map(|event| event.json()).collect()
+    }
+
+    fn bits(value: u32, len: usize) -> impl Iterator<Item = bool> {
+        (0..len).rev().map(move |bit| (value >> bit) & 1 == 1)
+    }
+
+    #[test]
+    fn pulse_widths_to_bits() {
+        let train = PulseTrain {
+            pulses: vec![
+                (300.0, 900.0),
+                (900.0, 300.0),
+                (300.0, 5000.0),
+                (900.0, 300.0),
+            ],
+        };
+        assert_eq!(
+            train.pwm(300.0, 900.0, 4000.0),
+            [vec![false, true, false], vec![true]]
+        );
+        //Pulses far off either width break the row too
+        let train = PulseTrain {
+            pulses: vec![(300.0, 300.0), (2000.0, 300.0), (900.0, 300.0)],
+        };
+        assert_eq!(train.pwm(300.0, 900.0, 4000.0), [vec![false], vec![true]]);
+    }
+
+    #[test]
+    fn gap_widths_to_bits() {
+        let train = PulseTrain {
+            pulses: vec![
+                (500.0, 1000.0),
+                (500.0, 2000.0),
+                (500.0, 4000.0),
+                (500.0, 2000.0),
+            ],
+        };
+        assert_eq!(
+            train.ppm(1000.0, 2000.0, 3000.0),
+            [vec![false, true], vec![true]]
+        );
+    }
+
+    #[test]
+    fn levels_sampled_per_bit() {
+        let train = PulseTrain {
+            pulses: vec![(100.0, 50.0), (50.0, 150.0), (50.0, 1000.0), (100.0, 50.0)],
+        };
+        assert_eq!(
+            train.pcm(50.0, 500.0),
+            [
+                vec![true, true, false, true, false, false, false, true],
+                vec![true, true, false]
+            ]
+        );
+    }
+
+    #[test]
+    fn manchester_stops_at_an_invalid_pair() {
+        assert_eq!(
+            manchester(&[true, false, false, true, true, true, false, true]),
+            [true, false]
+        );
+        assert_eq!(manchester(&[false, true, true]), [false]);
+        assert_eq!(bytes(&[true, false, true]), [0xA0]);
+        assert_eq!(bits_value(&[true, false, true, true]), 0xB);
+    }
+
+    #[test]
+    fn decodes_a_nexus_sensor() {
+        //ID 0xA5, battery ok, channel 2, -12.3 °C and 55 %
+        let temp = (0x1000 - 123) as u32;
+        let row: Vec<bool> = bits(0xA5, 8)
+            .chain([true, false])
+            .chain(bits(1, 2))
+            .chain(bits(temp, 12))
+            .chain(bits(0xF, 4))
+            .chain(bits(55, 8))
+            .collect();
+        let mut pulses = Vec::new();
+        for _ in 0..3 {
+            pulses.push((500.0, 4000.0));
+            pulses.extend(
+                row.iter()
+                    .map(|bit| (500.0, if *bit { 2000.0 } else { 1000.0 })),
+            );
+        }
+        pulses.push((500.0, 0.0));
+
+        let events = decode("nexus", &ook(&pulses));
+        assert_eq!(events.len(), 1, "{:?}", events);
+        let event = &events[0];
+        assert_eq!(event["model"], "Nexus-TH");
+        assert_eq!(event["mod"], "OOK");
+        assert_eq!(event["id"], 0xA5);
+        assert_eq!(event["channel"], 2);
+        assert_eq!(event["battery_ok"], true);
+        assert_eq!(event["temperature_C"], -12.3);
+        assert_eq!(event["humidity"], 55);
+    }
+
+    #[test]
+    fn decodes_an_ev1527_remote() {
+        let row: Vec<bool> = bits(0x5A3C1, 20).chain(bits(0x8, 4)).collect();
+        let mut pulses = Vec::new();
+        for _ in 0..4 {
+            pulses.extend(
+                row.iter()
+                    .map(|bit| if *bit { (750.0, 250.0) } else { (250.0, 750.0) }),
+            );
+            //Sync, a short pulse and 31 periods of quiet
+            pulses.push((250.0, 31.0 * 250.0));
+        }
+
+        let events = decode("ev1527", &ook(&pulses));
+        assert_eq!(events.len(), 1, "{:?}", events);
+        let event = &events[0];
+        assert_eq!(event["model"], "EV1527");
+        assert_eq!(event["id"], "5a3c1");
+        assert_eq!(event["cmd"], 8);
+        let short_us = event["short_us"].as_f64().unwrap();
+        assert!(
+            (200.0..300.0).contains(&short_us),
+            "short pulse {}",
+            short_us
+        );
+    }
+
+    #[test]
+    fn decodes_a_ford_tpms_sensor() {
+        //32 PSI, 20 °C and moving
+        let mut packet = vec![0x12, 0x34, 0x56, 0x78, 0x80, 56 + 20, 0x44];
+        packet.push(packet.iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte)));
+
+        let mut chips: Vec<bool> = (0..16).map(|idx| idx % 2 == 0).collect();
+        chips.extend(bits(0xAAA9, 16));
+        for bit in packet.iter().flat_map(|byte| bits(*byte as u32, 8)) {
+            chips.extend([bit, !bit]);
+        }
+
+        let events = decode("ford", &fsk(&chips, 52.0));
+        assert_eq!(events.len(), 1, "{:?}", events);
+        let event = &events[0];
+        assert_eq!(event["model"], "Ford-TPMS");
+        assert_eq!(event["mod"], "FSK");
+        assert_eq!(event["id"], "12345678");
+        assert_eq!(event["pressure_PSI"], 32.0);
+        assert_eq!(event["temperature_C"], 20);
+        assert_eq!(event["moving"], true);
+        assert_eq!(event["learn"], false);
+    }
+}
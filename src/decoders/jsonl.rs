@@ -0,0 +1,39 @@
+//! JSON-lines files decoders append their events to.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Appends one JSON value per line, the file is reopened whenever the path changes
+#[derive(Default)]
+pub struct JsonLines {
+    file: Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl JsonLines {
+    /// Writes `value` to `path`, or closes the file if it is None
+    pub fn write(&mut self, path: Option<&Path>, value: &serde_json::Value) -> Result<(), String> {
+        let Some(path) = path else {
+            self.file = None;
+            return Ok(());
+        };
+        if self.file.as_ref().map(|(open, _)| open.as_path()) != Some(path) {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            self.file = Some((path.to_path_buf(), BufWriter::new(file)));
+        }
+
+        let Some((_, file)) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let res = writeln!(file, "{}", value).and_then(|_| file.flush());
+        if let Err(e) = res {
+            self.file = None;
+            return Err(format!("{}: {}", path.display(), e));
+        }
+        Ok(())
+    }
+}
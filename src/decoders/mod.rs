@@ -0,0 +1,348 @@
+//! Decoders fed from the demodulator.
+//!
+//! Each decoder runs on the flowgraph thread and shares what it decodes through an
+//! `Arc<Mutex<..>>` handed out as a [`DecoderState`] when it is created.
+
+pub mod adsb;
+pub mod ais;
+pub mod aprs;
+pub mod apt;
+pub mod cw;
+pub mod digi;
+#[cfg(test)]
+mod fixtures;
+pub mod hdlc;
+pub mod ism;
+pub mod jsonl;
+pub mod net;
+pub mod pocsag;
+pub mod psk;
+pub mod rds;
+pub mod rtty;
+pub mod selcall;
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futuresdr::num_complex::Complex32;
+
+use crate::demod::{DemodMode, DemodOutput, Demodulator, Vfo};
+use crate::sdr::SdrError;
+
+//Samples fed to the demodulator at a time when decoding a recording
+const OFFLINE_CHUNK: usize = 16_384;
+
+/// Which signal a decoder wants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderInput {
+    /// Mono demodulated audio at `AUDIO_RATE`
+    Audio,
+    /// FM multiplex at `MPX_RATE`, only produced in WFM
+    Mpx,
+    /// Samples straight from the device at its sample rate
+    Raw,
+}
+
+pub trait Decoder: Send {
+    fn kind(&self) -> DecoderKind;
+
+    fn input(&self) -> DecoderInput;
+
+    /// Audio or multiplex samples, `rate` in Hz
+    fn process_real(&mut self, _samples: &[f32], _rate: f64) {}
+
+    /// Raw device samples, `rate` in Hz
+    fn process_complex(&mut self, _samples: &[Complex32], _rate: f64) {}
+
+    /// RF frequency the VFO is listening on in Hz, for decoders that log it
+    fn set_freq(&mut self, _rf_hz: f64) {}
+
+    /// The VFO feeding the decoder, for decoders that follow its settings
+    fn set_vfo(&mut self, _vfo: &Vfo) {}
+
+    /// What has been decoded so far in plain text
+    fn report(&self) -> String;
+}
+
+/// Every decoder the receiver knows about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderKind {
+    Rds,
+    Adsb,
+    Ais,
+    Pocsag,
+    Aprs,
+    Ism,
+    Apt,
+    Cw,
+    Digi,
+    Selcall,
+}
+
+impl DecoderKind {
+    pub const ALL: [DecoderKind; 10] = [
+        DecoderKind::Rds,
+        DecoderKind::Adsb,
+        DecoderKind::Ais,
+        DecoderKind::Pocsag,
+        DecoderKind::Aprs,
+        DecoderKind::Ism,
+        DecoderKind::Apt,
+        DecoderKind::Cw,
+        DecoderKind::Digi,
+        DecoderKind::Selcall,
+    ];
+
+    /// Mode the VFO has to be in, None if the decoder works on raw samples
+    pub fn mode(&self) -> Option<DemodMode> {
+        match self {
+            DecoderKind::Rds => Some(DemodMode::Wfm),
+            DecoderKind::Pocsag | DecoderKind::Aprs | DecoderKind::Apt | DecoderKind::Selcall => {
+                Some(DemodMode::Nfm)
+            }
+            DecoderKind::Cw => Some(DemodMode::Cw),
+            DecoderKind::Digi => Some(DemodMode::Usb),
+            DecoderKind::Adsb | DecoderKind::Ais | DecoderKind::Ism => None,
+        }
+    }
+
+    /// VFO bandwidth when the mode's default is too narrow
+    pub fn bandwidth(&self) -> Option<f64> {
+        match self {
+            DecoderKind::Apt => Some(apt::APT_BANDWIDTH),
+            _ => None,
+        }
+    }
+
+    /// Frequency the capture has to be centered on, None if any will do
+    pub fn center_freq(&self) -> Option<f64> {
+        match self {
+            DecoderKind::Ais => Some(ais::AIS_CENTER),
+            _ => None,
+        }
+    }
+
+    /// Name used on the command line
+    pub fn id(&self) -> &'static str {
+        match self {
+            DecoderKind::Rds => "rds",
+            DecoderKind::Adsb => "adsb",
+            DecoderKind::Ais => "ais",
+            DecoderKind::Pocsag => "pocsag",
+            DecoderKind::Aprs => "aprs",
+            DecoderKind::Ism => "ism",
+            DecoderKind::Apt => "apt",
+            DecoderKind::Cw => "cw",
+            DecoderKind::Digi => "digi",
+            DecoderKind::Selcall => "selcall",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    /// Decoders with TCP feeds serve them on `feed_host`, None opens no ports, as when
+    /// decoding a recording
+    pub fn create(&self, feed_host: Option<IpAddr>) -> (Box<dyn Decoder>, DecoderState) {
+        match self {
+            DecoderKind::Rds => {
+                let decoder = rds::RdsDecoder::new();
+                let state = DecoderState::Rds(decoder.info());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Adsb => {
+                let decoder = adsb::AdsbDecoder::new(feed_host);
+                let state = DecoderState::Adsb(decoder.table());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Ais => {
+                let decoder = ais::AisDecoder::new();
+                let state = DecoderState::Ais(decoder.state());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Pocsag => {
+                let decoder = pocsag::PocsagDecoder::new();
+                let state = DecoderState::Pocsag(decoder.log());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Aprs => {
+                let decoder = aprs::AprsDecoder::new(feed_host);
+                let state = DecoderState::Aprs(decoder.log());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Ism => {
+                let decoder = ism::IsmDecoder::new();
+                let state = DecoderState::Ism(decoder.log());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Apt => {
+                let decoder = apt::AptDecoder::new();
+                let state = DecoderState::Apt(decoder.image());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Cw => {
+                let decoder = cw::CwDecoder::new();
+                let state = DecoderState::Cw(decoder.text());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Digi => {
+                let decoder = digi::DigiDecoder::new();
+                let state = DecoderState::Digi(decoder.state());
+                (Box::new(decoder), state)
+            }
+            DecoderKind::Selcall => {
+                let decoder = selcall::SelcallDecoder::new();
+                let state = DecoderState::Selcall(decoder.log());
+                (Box::new(decoder), state)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DecoderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DecoderKind::Rds => "RDS",
+                DecoderKind::Adsb => "ADS-B",
+                DecoderKind::Ais => "AIS",
+                DecoderKind::Pocsag => "POCSAG",
+                DecoderKind::Aprs => "APRS",
+                DecoderKind::Ism => "ISM",
+                DecoderKind::Apt => "NOAA APT",
+                DecoderKind::Cw => "CW",
+                DecoderKind::Digi => "RTTY/PSK31",
+                DecoderKind::Selcall => "DTMF/Selcall",
+            }
+        )
+    }
+}
+
+/// Handle on what a running decoder has decoded
+#[derive(Clone)]
+pub enum DecoderState {
+    Rds(Arc<Mutex<rds::RdsInfo>>),
+    Adsb(Arc<Mutex<adsb::AircraftTable>>),
+    Ais(Arc<Mutex<ais::AisState>>),
+    Pocsag(Arc<Mutex<pocsag::PagerLog>>),
+    Aprs(Arc<Mutex<aprs::AprsLog>>),
+    Ism(Arc<Mutex<ism::IsmLog>>),
+    Apt(Arc<Mutex<apt::AptImage>>),
+    Cw(Arc<Mutex<cw::CwText>>),
+    Digi(Arc<Mutex<digi::DigiState>>),
+    Selcall(Arc<Mutex<selcall::SelcallLog>>),
+}
+
+/// Hands one block of demodulator output to every decoder
+pub fn feed(
+    decoders: &mut [Box<dyn Decoder>],
+    raw: &[Complex32],
+    raw_rate: f64,
+    vfo: &Vfo,
+    rf_hz: Option<f64>,
+    out: &DemodOutput,
+) {
+    for decoder in decoders.iter_mut() {
+        decoder.set_vfo(vfo);
+        if let Some(rf_hz) = rf_hz {
+            decoder.set_freq(rf_hz);
+        }
+        match decoder.input() {
+            DecoderInput::Audio => decoder.process_real(out.audio, crate::demod::AUDIO_RATE),
+            DecoderInput::Mpx if !out.mpx.is_empty() => {
+                decoder.process_real(out.mpx, crate::demod::MPX_RATE)
+            }
+            DecoderInput::Mpx => {}
+            DecoderInput::Raw => decoder.process_complex(raw, raw_rate),
+        }
+    }
+}
+
+/// Runs `decoders` over a baseband recording made by `BaseBandSink`, the RF frequency is unknown.
+/// Mono recordings are taken to be demodulated audio and only go to decoders that want audio.
+pub fn decode_wav(
+    path: &Path,
+    vfo: Vfo,
+    decoders: &mut [Box<dyn Decoder>],
+) -> Result<(), SdrError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    if spec.channels != 1 && spec.channels != 2 {
+        return Err(SdrError::Recording(
+            "expected a mono audio or stereo I/Q recording".into(),
+        ));
+    }
+
+    let rate = spec.sample_rate as f64;
+    let mut samples: Box<dyn Iterator<Item = hound::Result<f32>>> =
+        match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Float, _) => Box::new(reader.samples::<f32>()),
+            (hound::SampleFormat::Int, 8) => Box::new(
+                reader
+                    .samples::<i8>()
+                    .map(|s| s.map(|s| s as f32 / i8::MAX as f32)),
+            ),
+            (hound::SampleFormat::Int, 16) => Box::new(
+                reader
+                    .samples::<i16>()
+                    .map(|s| s.map(|s| s as f32 / i16::MAX as f32)),
+            ),
+            (_, bits) => {
+                return Err(SdrError::Recording(format!(
+                    "{} bit samples are not supported",
+                    bits
+                )))
+            }
+        };
+
+    let mono = spec.channels == 1;
+    if mono {
+        for decoder in decoders
+            .iter_mut()
+            .filter(|decoder| decoder.input() == DecoderInput::Audio)
+        {
+            decoder.set_vfo(&vfo);
+        }
+    }
+    let mut demod = Demodulator::new(vfo.clone(), rate);
+
+    //Read a chunk at a time so long recordings don't have to fit in memory
+    let mut chunk = Vec::with_capacity(OFFLINE_CHUNK * spec.channels as usize);
+    let mut iq = Vec::with_capacity(OFFLINE_CHUNK);
+    loop {
+        chunk.clear();
+        for sample in samples
+            .by_ref()
+            .take(OFFLINE_CHUNK * spec.channels as usize)
+        {
+            chunk.push(sample?);
+        }
+        if chunk.is_empty() {
+            break;
+        }
+
+        if mono {
+            for decoder in decoders
+                .iter_mut()
+                .filter(|decoder| decoder.input() == DecoderInput::Audio)
+            {
+                decoder.process_real(&chunk, rate);
+            }
+        } else {
+            iq.clear();
+            iq.extend(
+                chunk
+                    .chunks_exact(2)
+                    .map(|pair| Complex32::new(pair[0], pair[1])),
+            );
+            let out = demod.process(&iq);
+            feed(decoders, &iq, rate, &vfo, None, &out);
+        }
+    }
+
+    Ok(())
+}
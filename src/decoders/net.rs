@@ -0,0 +1,129 @@
+//! Network outputs decoders publish to.
+
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Where the decoders' TCP feeds listen unless told otherwise, only this machine can connect
+pub const DEFAULT_FEED_HOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+//How often a port that failed to bind is tried again
+const BIND_RETRY: Duration = Duration::from_secs(5);
+
+/// TCP port every connected client gets a copy of the output on.
+///
+/// Runs on the decoder's thread without blocking it: the port is bound lazily, new clients are
+/// accepted on each send and clients that can't keep up are dropped.
+pub struct TcpFeed {
+    addr: SocketAddr,
+    listener: Option<TcpListener>,
+    clients: Vec<TcpStream>,
+    last_bind: Option<Instant>,
+    error: Option<String>,
+}
+
+impl TcpFeed {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            listener: None,
+            clients: Vec::new(),
+            last_bind: None,
+            error: None,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Why the port couldn't be opened, None once it is listening
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Sends `data` to every client
+    pub fn send(&mut self, data: &[u8]) {
+        self.accept();
+        self.clients
+            .retain_mut(|client| client.write_all(data).is_ok());
+    }
+
+    fn accept(&mut self) {
+        let retry = !matches!(self.last_bind, Some(last) if last.elapsed() < BIND_RETRY);
+        if self.listener.is_none() && retry {
+            self.last_bind = Some(Instant::now());
+            match TcpListener::bind(self.addr).and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            }) {
+                Ok(listener) => {
+                    self.listener = Some(listener);
+                    self.error = None;
+                }
+                Err(e) => self.error = Some(format!("{}: {}", self.addr, e)),
+            }
+        }
+
+        let Some(listener) = self.listener.as_ref() else {
+            return;
+        };
+        //Stops at WouldBlock once every waiting client is in
+        while let Ok((client, _)) = listener.accept() {
+            //A full send buffer means the client fell behind, it is dropped on the next write
+            if client.set_nonblocking(true).is_ok() {
+                let _ = client.set_nodelay(true);
+                self.clients.push(client);
+            }
+        }
+    }
+}
+
+/// UDP socket sending datagrams to a target that can change at any time
+#[derive(Default)]
+pub struct UdpFeed {
+    socket: Option<UdpSocket>,
+    error: Option<String>,
+}
+
+impl UdpFeed {
+    /// Why the last datagram couldn't be sent, None once one goes out
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn send(&mut self, addr: SocketAddr, data: &[u8]) {
+        if self.socket.is_none() {
+            let local = if addr.is_ipv4() {
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+            } else {
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+            };
+            match UdpSocket::bind(local).and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                Ok(socket)
+            }) {
+                Ok(socket) => self.socket = Some(socket),
+                Err(e) => {
+                    self.error = Some(format!("UDP socket: {}", e));
+                    return;
+                }
+            }
+        }
+
+        if let Some(socket) = self.socket.as_ref() {
+            self.error = socket
+                .send_to(data, addr)
+                .err()
+                .map(|e| format!("UDP {}: {}", addr, e));
+            //An IPv4 socket can't reach an IPv6 target, rebind on the next send
+            if self.error.is_some() {
+                self.socket = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,537 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use super::jsonl::JsonLines;
+use super::{Decoder, DecoderInput, DecoderKind};
+
+/// Rates pagers are sent at, all of them are decoded at once
+pub const BAUD_RATES: [u32; 3] = [512, 1200, 2400];
+/// Messages kept in the log
+pub const LOG_LEN: usize = 500;
+
+const SYNC: u32 = 0x7CD2_15D8;
+const IDLE: u32 = 0x7A89_C197;
+//x^10 + x^9 + x^8 + x^6 + x^5 + x^3 + 1
+const BCH_POLY: u32 = 0x769;
+//A batch is the sync codeword followed by 8 frames of 2 codewords
+const BATCH_CODEWORDS: usize = 16;
+//Bit errors allowed when looking for the sync codeword
+const SYNC_ERRORS: u32 = 2;
+//How far the bit clock is pulled towards each transition
+const CLOCK_GAIN: f64 = 0.2;
+const NUMERIC_CHARS: &[u8; 16] = b"0123456789*U -)(";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagerContent {
+    Numeric,
+    Alpha,
+    /// Address only, the pager just beeps
+    Tone,
+}
+
+impl std::fmt::Display for PagerContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PagerContent::Numeric => "numeric",
+                PagerContent::Alpha => "alpha",
+                PagerContent::Tone => "tone",
+            }
+        )
+    }
+}
+
+/// One decoded page
+#[derive(Debug, Clone)]
+pub struct PagerMessage {
+    pub time: DateTime<Utc>,
+    /// RF frequency it was heard on, unknown when decoding a recording
+    pub freq_hz: Option<f64>,
+    pub baud: u32,
+    /// 21 bit capcode
+    pub address: u32,
+    pub function: u8,
+    pub content: PagerContent,
+    pub text: String,
+    /// Codewords that couldn't be corrected
+    pub errors: usize,
+}
+
+impl PagerMessage {
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "time": self.time.to_rfc3339(),
+            "freq_hz": self.freq_hz,
+            "protocol": "POCSAG",
+            "baud": self.baud,
+            "address": self.address,
+            "function": self.function,
+            "type": self.content.to_string(),
+            "message": self.text,
+            "errors": self.errors,
+        })
+    }
+}
+
+/// Recent pages, newest first
+#[derive(Debug, Default)]
+pub struct PagerLog {
+    pub messages: VecDeque<PagerMessage>,
+    pub total: u64,
+    /// JSON-lines file every page is appended to
+    pub json_path: Option<PathBuf>,
+    /// Why the JSON-lines file couldn't be written
+    pub error: Option<String>,
+}
+
+/// POCSAG decoder on NFM audio
+pub struct PocsagDecoder {
+    log: Arc<Mutex<PagerLog>>,
+    receivers: Vec<PocsagRx>,
+    //Error pattern for each syndrome of one or two bit errors
+    corrections: HashMap<u32, u32>,
+    rate: f64,
+    freq_hz: Option<f64>,
+    json: JsonLines,
+}
+
+impl PocsagDecoder {
+    pub fn new() -> Self {
+        let mut corrections = HashMap::new();
+        for first in 0..31 {
+            corrections.insert(bch_syndrome(1 << (first + 1)), 1 << (first + 1));
+            for second in first + 1..31 {
+                let error = (1 << (first + 1)) | (1 << (second + 1));
+                corrections.insert(bch_syndrome(error), error);
+            }
+        }
+
+        Self {
+            log: Arc::new(Mutex::new(PagerLog::default())),
+            receivers: Vec::new(),
+            corrections,
+            rate: 0.0,
+            freq_hz: None,
+            json: JsonLines::default(),
+        }
+    }
+
+    pub fn log(&self) -> Arc<Mutex<PagerLog>> {
+        self.log.clone()
+    }
+
+    fn page(&mut self, page: Page) {
+        let message = page.finish(self.freq_hz);
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+
+        if let Err(e) = self.json.write(log.json_path.as_deref(), &message.json()) {
+            log.error = Some(e);
+            log.json_path = None;
+        }
+
+        log.total += 1;
+        log.messages.push_front(message);
+        log.messages.truncate(LOG_LEN);
+    }
+}
+
+impl Default for PocsagDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for PocsagDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Pocsag
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Audio
+    }
+
+    fn process_real(&mut self, samples: &[f32], rate: f64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.receivers = BAUD_RATES
+                .iter()
+                .map(|baud| PocsagRx::new(*baud, rate))
+                .collect();
+        }
+
+        let mut pages = Vec::new();
+        for rx in self.receivers.iter_mut() {
+            for x in samples {
+                if let Some(bit) = rx.slice(*x) {
+                    rx.bit(bit, &self.corrections, &mut pages);
+                }
+            }
+        }
+        for page in pages {
+            self.page(page);
+        }
+    }
+
+    fn set_freq(&mut self, rf_hz: f64) {
+        self.freq_hz = Some(rf_hz);
+    }
+
+    fn report(&self) -> String {
+        let Ok(log) = self.log.lock() else {
+            return String::new();
+        };
+        let mut report = format!("{} messages", log.total);
+        for message in log.messages.iter().rev() {
+            report += &format!(
+                "\n{} {} {:>7} {} {}: {}",
+                message.time.format("%Y-%m-%d %H:%M:%S"),
+                message.baud,
+                message.address,
+                message.function,
+                message.content,
+                message.text
+            );
+        }
+        report
+    }
+}
+
+//A page being received
+struct Page {
+    baud: u32,
+    address: u32,
+    function: u8,
+    //Message bits in the order they were sent
+    bits: Vec<bool>,
+    errors: usize,
+}
+
+impl Page {
+    fn finish(self, freq_hz: Option<f64>) -> PagerMessage {
+        //Function 0 is the usual numeric page, the rest are treated as alphanumeric
+        let content = if self.bits.is_empty() {
+            PagerContent::Tone
+        } else if self.function == 0 {
+            PagerContent::Numeric
+        } else {
+            PagerContent::Alpha
+        };
+
+        //Characters are sent least significant bit first
+        let chars = |size: usize| {
+            self.bits.chunks_exact(size).map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (idx, bit)| acc | ((*bit as u8) << idx))
+            })
+        };
+        let text: String = match content {
+            PagerContent::Tone => String::new(),
+            PagerContent::Numeric => chars(4)
+                .map(|val| NUMERIC_CHARS[val as usize] as char)
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+            PagerContent::Alpha => chars(7)
+                .filter(|val| *val != 0 && *val != 0x04 && *val != 0x17)
+                .map(|val| {
+                    if val.is_ascii_graphic() || val == b' ' {
+                        val as char
+                    } else {
+                        ' '
+                    }
+                })
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+        };
+
+        PagerMessage {
+            time: Utc::now(),
+            freq_hz,
+            baud: self.baud,
+            address: self.address,
+            function: self.function,
+            content,
+            text,
+            errors: self.errors,
+        }
+    }
+}
+
+//Bit slicer and batch decoder for one baud rate
+struct PocsagRx {
+    baud: u32,
+    //Fraction of a bit per sample
+    step: f64,
+    clock: f64,
+    last_level: bool,
+    shift: u32,
+    synced: bool,
+    inverted: bool,
+    codeword: u32,
+    codeword_bits: usize,
+    //Codewords received since the last sync codeword
+    index: usize,
+    page: Option<Page>,
+}
+
+impl PocsagRx {
+    fn new(baud: u32, rate: f64) -> Self {
+        Self {
+            baud,
+            step: baud as f64 / rate,
+            clock: 0.0,
+            last_level: false,
+            shift: 0,
+            synced: false,
+            inverted: false,
+            codeword: 0,
+            codeword_bits: 0,
+            index: 0,
+            page: None,
+        }
+    }
+
+    #[inline]
+    fn slice(&mut self, x: f32) -> Option<bool> {
+        let level = x > 0.0;
+        //Transitions belong halfway between the points bits are taken at
+        if level != self.last_level {
+            self.clock -= CLOCK_GAIN * (self.clock - 0.5);
+        }
+        self.last_level = level;
+
+        self.clock += self.step;
+        if self.clock < 1.0 {
+            return None;
+        }
+        self.clock -= 1.0;
+        Some(level)
+    }
+
+    fn bit(&mut self, bit: bool, corrections: &HashMap<u32, u32>, pages: &mut Vec<Page>) {
+        self.shift = (self.shift << 1) | bit as u32;
+
+        if !self.synced {
+            if (self.shift ^ SYNC).count_ones() <= SYNC_ERRORS {
+                self.sync(false);
+            } else if (!self.shift ^ SYNC).count_ones() <= SYNC_ERRORS {
+                self.sync(true);
+            }
+            return;
+        }
+
+        self.codeword = (self.codeword << 1) | (bit ^ self.inverted) as u32;
+        self.codeword_bits += 1;
+        if self.codeword_bits < 32 {
+            return;
+        }
+        let codeword = self.codeword;
+        self.codeword_bits = 0;
+
+        if self.index == BATCH_CODEWORDS {
+            //Each batch starts with another sync, without one the transmission is over
+            if (codeword ^ SYNC).count_ones() <= SYNC_ERRORS {
+                self.index = 0;
+            } else {
+                self.synced = false;
+                pages.extend(self.page.take());
+            }
+            return;
+        }
+        let frame = self.index / 2;
+        self.index += 1;
+
+        let corrected = match bch_syndrome(codeword) {
+            0 => Some(codeword),
+            syndrome => corrections.get(&syndrome).map(|error| codeword ^ error),
+        };
+        match corrected {
+            Some(IDLE) => pages.extend(self.page.take()),
+            Some(codeword) if codeword & 0x8000_0000 == 0 => {
+                pages.extend(self.page.take());
+                //The low 3 bits of the address are the frame it was sent in
+                self.page = Some(Page {
+                    baud: self.baud,
+                    address: (((codeword >> 13) & 0x3_FFFF) << 3) | frame as u32,
+                    function: ((codeword >> 11) & 0x3) as u8,
+                    bits: Vec::new(),
+                    errors: 0,
+                });
+            }
+            Some(codeword) => {
+                if let Some(page) = self.page.as_mut() {
+                    page.bits
+                        .extend((11..31).rev().map(|bit| (codeword >> bit) & 1 == 1));
+                }
+            }
+            None => {
+                if let Some(page) = self.page.as_mut() {
+                    page.errors += 1;
+                }
+            }
+        }
+    }
+
+    fn sync(&mut self, inverted: bool) {
+        self.synced = true;
+        self.inverted = inverted;
+        self.index = 0;
+        self.codeword_bits = 0;
+    }
+}
+
+//Remainder of the 31 bit BCH codeword, the even parity bit is ignored
+fn bch_syndrome(codeword: u32) -> u32 {
+    let mut reg = codeword >> 1;
+    for bit in (10..31).rev() {
+        if reg & (1 << bit) != 0 {
+            reg ^= BCH_POLY << (bit - 10);
+        }
+    }
+    reg & 0x3FF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 48_000.0;
+
+    //21 data bits followed by the BCH check bits and even parity
+    fn encode(data: u32) -> u32 {
+        let word = data << 10;
+        let word = word | bch_syndrome(word << 1);
+        (word << 1) | (word.count_ones() & 1)
+    }
+
+    //Message bits from characters of `size` bits sent least significant bit first
+    fn char_bits(chars: &[u8], size: usize) -> Vec<bool> {
+        chars
+            .iter()
+            .flat_map(|ch| (0..size).map(move |bit| (ch >> bit) & 1 == 1))
+            .collect()
+    }
+
+    //Address codeword in its frame then the message, idle to the end of two batches
+    fn codewords(address: u32, function: u8, message: &[bool]) -> Vec<u32> {
+        let mut codewords = vec![IDLE; 2 * BATCH_CODEWORDS];
+        let mut pos = (address & 0x7) as usize * 2;
+        codewords[pos] = encode(((address >> 3) << 2) | function as u32);
+        for chunk in message.chunks(20) {
+            pos += 1;
+            let data = chunk
+                .iter()
+                .chain(std::iter::repeat(&false))
+                .take(20)
+                .fold(1, |acc, bit| (acc << 1) | *bit as u32);
+            codewords[pos] = encode(data);
+        }
+        codewords
+    }
+
+    //Preamble and batches as NRZ audio, a 1 is the positive level
+    fn audio(codewords: &[u32], baud: u32) -> Vec<f32> {
+        let mut bits: Vec<bool> = (0..576).map(|idx| idx % 2 == 0).collect();
+        for batch in codewords.chunks(BATCH_CODEWORDS) {
+            for word in std::iter::once(&SYNC).chain(batch) {
+                bits.extend((0..32).rev().map(|bit| (word >> bit) & 1 == 1));
+            }
+        }
+        let per_bit = RATE / baud as f64;
+        (0..(bits.len() as f64 * per_bit) as usize)
+            .map(|idx| {
+                if bits[(idx as f64 / per_bit) as usize] {
+                    0.5
+                } else {
+                    -0.5
+                }
+            })
+            .collect()
+    }
+
+    fn decode(audio: &[f32]) -> Vec<PagerMessage> {
+        let mut decoder = PocsagDecoder::new();
+        decoder.process_real(audio, RATE);
+        let log = decoder.log();
+        let log = log.lock().unwrap();
+        log.messages.iter().cloned().collect()
+    }
+
+    #[test]
+    fn syndrome_of_valid_codewords_is_zero() {
+        assert_eq!(bch_syndrome(SYNC), 0);
+        assert_eq!(bch_syndrome(IDLE), 0);
+        assert_eq!(encode(IDLE >> 11), IDLE);
+        assert_eq!(encode(SYNC >> 11), SYNC);
+        assert_ne!(bch_syndrome(IDLE ^ 0x10), 0);
+    }
+
+    #[test]
+    fn corrects_every_one_and_two_bit_error() {
+        let decoder = PocsagDecoder::new();
+        //Every error needs its own syndrome for the table to be unambiguous
+        assert_eq!(decoder.corrections.len(), 31 + 31 * 30 / 2);
+        let codeword = encode(0x1A_2B3C);
+        for first in 1..32 {
+            let error = 1 << first;
+            assert_eq!(decoder.corrections[&bch_syndrome(codeword ^ error)], error);
+            for second in first + 1..32 {
+                let error = error | (1 << second);
+                assert_eq!(decoder.corrections[&bch_syndrome(codeword ^ error)], error);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_a_numeric_page() {
+        let mut codewords = codewords(1_234_567, 0, &char_bits(&[1, 2, 3, 4, 5], 4));
+        //Two bit errors in the message codeword are corrected
+        codewords[(1_234_567 & 0x7) * 2 + 1] ^= 0b101 << 20;
+        for baud in BAUD_RATES {
+            let audio = audio(&codewords, baud);
+            //The slicer follows either polarity of the discriminator
+            let inverted: Vec<f32> = audio.iter().map(|x| -x).collect();
+            for audio in [audio, inverted] {
+                let messages = decode(&audio);
+                assert_eq!(messages.len(), 1, "{} baud", baud);
+                let page = &messages[0];
+                assert_eq!(page.baud, baud);
+                assert_eq!(page.address, 1_234_567);
+                assert_eq!(page.function, 0);
+                assert_eq!(page.content, PagerContent::Numeric);
+                assert_eq!(page.text, "12345");
+                assert_eq!(page.errors, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_an_alpha_page_across_batches() {
+        let text = b"Hello from a batch that is long enough to carry on into the next one";
+        let messages = decode(&audio(&codewords(42, 3, &char_bits(text, 7)), 1200));
+        assert_eq!(messages.len(), 1);
+        let page = &messages[0];
+        assert_eq!(page.address, 42);
+        assert_eq!(page.function, 3);
+        assert_eq!(page.content, PagerContent::Alpha);
+        assert_eq!(page.text.as_bytes(), text);
+    }
+
+    #[test]
+    fn address_without_message_is_a_tone_page() {
+        let messages = decode(&audio(&codewords(8, 1, &[]), 512));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, PagerContent::Tone);
+        assert_eq!(messages[0].address, 8);
+    }
+}
@@ -0,0 +1,526 @@
+//! PSK31 keyboard chat, BPSK or QPSK with the rate 1/2 convolutional code, and Varicode.
+
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+
+use futuresdr::num_complex::Complex32;
+
+use crate::dsp::{lowpass_taps, num_taps, Fir, Nco};
+
+/// Symbols per second
+pub const BAUD: f64 = 31.25;
+
+//The audio is brought down in two steps, to about 4 kHz and then to about 16 samples a symbol
+const FIRST_RATE: f64 = 4_000.0;
+const FIRST_CUTOFF: f64 = 300.0;
+const SYMBOL_RATE: f64 = BAUD * 16.0;
+const SYMBOL_CUTOFF: f64 = 40.0;
+//Slots a symbol is split into when looking for where its amplitude peaks
+const TIMING_SLOTS: usize = 16;
+const TIMING_ALPHA: f32 = 0.02;
+//How far each symbol pulls the frequency, and the most it may be corrected by
+const AFC_GAIN: f64 = 0.05;
+const AFC_RANGE: f64 = 15.0;
+const QUALITY_ALPHA: f32 = 0.05;
+//K=5 code used by QPSK31
+const POLY1: u8 = 0x19;
+const POLY2: u8 = 0x17;
+const STATES: usize = 16;
+//Bits the Viterbi decoder waits before deciding
+const VITERBI_DELAY: usize = 20;
+
+//Codes by character, the ones that can't be printed are left out
+const VARICODE: [(char, &str); 98] = [
+    ('\t', "11101111"),
+    ('\n', "11101"),
+    ('\r', "11111"),
+    (' ', "1"),
+    ('!', "111111111"),
+    ('"', "101011111"),
+    ('#', "111110101"),
+    ('$', "111011011"),
+    ('%', "1011010101"),
+    ('&', "1010111011"),
+    ('\'', "101111111"),
+    ('(', "11111011"),
+    (')', "11110111"),
+    ('*', "101101111"),
+    ('+', "111011111"),
+    (',', "1110101"),
+    ('-', "110101"),
+    ('.', "1010111"),
+    ('/', "110101111"),
+    ('0', "10110111"),
+    ('1', "10111101"),
+    ('2', "11101101"),
+    ('3', "11111111"),
+    ('4', "101110111"),
+    ('5', "101011011"),
+    ('6', "101101011"),
+    ('7', "110101101"),
+    ('8', "110101011"),
+    ('9', "110110111"),
+    (':', "11110101"),
+    (';', "110111101"),
+    ('<', "111101101"),
+    ('=', "1010101"),
+    ('>', "111010111"),
+    ('?', "1010101111"),
+    ('@', "1010111101"),
+    ('A', "1111101"),
+    ('B', "11101011"),
+    ('C', "10101101"),
+    ('D', "10110101"),
+    ('E', "1110111"),
+    ('F', "11011011"),
+    ('G', "11111101"),
+    ('H', "101010101"),
+    ('I', "1111111"),
+    ('J', "111111101"),
+    ('K', "101111101"),
+    ('L', "11010111"),
+    ('M', "10111011"),
+    ('N', "11011101"),
+    ('O', "10101011"),
+    ('P', "11010101"),
+    ('Q', "111011101"),
+    ('R', "10101111"),
+    ('S', "1101111"),
+    ('T', "1101101"),
+    ('U', "101010111"),
+    ('V', "110110101"),
+    ('W', "101011101"),
+    ('X', "101110101"),
+    ('Y', "101111011"),
+    ('Z', "1010101101"),
+    ('[', "111110111"),
+    ('\\', "111101111"),
+    (']', "111111011"),
+    ('^', "1010111111"),
+    ('_', "101101101"),
+    ('`', "1011011111"),
+    ('a', "1011"),
+    ('b', "1011111"),
+    ('c', "101111"),
+    ('d', "101101"),
+    ('e', "11"),
+    ('f', "111101"),
+    ('g', "1011011"),
+    ('h', "101011"),
+    ('i', "1101"),
+    ('j', "111101011"),
+    ('k', "10111111"),
+    ('l', "11011"),
+    ('m', "111011"),
+    ('n', "1111"),
+    ('o', "111"),
+    ('p', "111111"),
+    ('q', "110111111"),
+    ('r', "10101"),
+    ('s', "10111"),
+    ('t', "101"),
+    ('u', "110111"),
+    ('v', "1111011"),
+    ('w', "1101011"),
+    ('x', "11011111"),
+    ('y', "1011101"),
+    ('z', "111010101"),
+    ('{', "1010110111"),
+    ('|', "110111011"),
+    ('}', "1010110101"),
+    ('~', "1011010111"),
+];
+
+/// Demodulator and decoder for a PSK31 signal on one audio tone
+pub struct PskDemod {
+    qpsk: bool,
+    reverse: bool,
+    tone: f64,
+    rate: f64,
+    afc: f64,
+    nco: Nco,
+    first: Fir<Complex32>,
+    second: Fir<Complex32>,
+    //Fraction of a symbol, and the average amplitude in each slot of it
+    phase: f64,
+    step: f64,
+    slots: [f32; TIMING_SLOTS],
+    last_slot: usize,
+    best_slot: usize,
+    last_symbol: Complex32,
+    quality: f32,
+    viterbi: Viterbi,
+    //Bits since the last pair of zeros, with a leading 1
+    code: u32,
+    last_bit: bool,
+    varicode: HashMap<u32, char>,
+}
+
+impl PskDemod {
+    /// Receiver for `tone` Hz in audio at `rate`, `reverse` for signals heard on the other sideband
+    pub fn new(tone: f64, qpsk: bool, reverse: bool, rate: f64) -> Self {
+        let first_decim = ((rate / FIRST_RATE).round() as usize).max(1);
+        let first_rate = rate / first_decim as f64;
+        //Rounded down so there are never fewer samples a symbol than timing slots
+        let second_decim = ((first_rate / SYMBOL_RATE) as usize).max(1);
+        let symbol_rate = first_rate / second_decim as f64;
+
+        let varicode = VARICODE
+            .iter()
+            .map(|(ch, code)| {
+                let code = code
+                    .chars()
+                    .fold(0, |acc, bit| (acc << 1) | (bit == '1') as u32);
+                (code, *ch)
+            })
+            .collect();
+
+        Self {
+            qpsk,
+            reverse,
+            tone,
+            rate,
+            afc: 0.0,
+            nco: Nco::new(-tone, rate),
+            first: Fir::new(
+                lowpass_taps(FIRST_CUTOFF, rate, num_taps(rate, FIRST_CUTOFF)),
+                first_decim,
+            ),
+            second: Fir::new(
+                lowpass_taps(
+                    SYMBOL_CUTOFF,
+                    first_rate,
+                    num_taps(first_rate, SYMBOL_CUTOFF),
+                ),
+                second_decim,
+            ),
+            phase: 0.0,
+            step: BAUD / symbol_rate,
+            slots: [0.0; TIMING_SLOTS],
+            last_slot: 0,
+            best_slot: 0,
+            last_symbol: Complex32::default(),
+            quality: 0.0,
+            viterbi: Viterbi::new(),
+            code: 0,
+            last_bit: true,
+            varicode,
+        }
+    }
+
+    /// How cleanly the phase changes land on their expected values, 0 for noise to 1
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+
+    /// Frequency correction the AFC has applied in Hz
+    pub fn afc(&self) -> f64 {
+        self.afc
+    }
+
+    /// Feeds one audio sample, returns a character once one is complete
+    #[inline]
+    pub fn process(&mut self, x: f32) -> Option<char> {
+        let y = self.first.push(self.nco.mix(Complex32::new(x, 0.0)))?;
+        let z = self.second.push(y)?;
+
+        //PSK31 is amplitude shaped, symbols are taken where the amplitude peaks
+        self.phase = (self.phase + self.step).fract();
+        let slot = (self.phase * TIMING_SLOTS as f64) as usize;
+        self.slots[slot] += TIMING_ALPHA * (z.norm() - self.slots[slot]);
+        let at_peak = slot == self.best_slot && slot != self.last_slot;
+        self.last_slot = slot;
+        if !at_peak {
+            return None;
+        }
+        self.best_slot = (0..TIMING_SLOTS)
+            .max_by(|a, b| self.slots[*a].total_cmp(&self.slots[*b]))
+            .unwrap_or(0);
+
+        self.symbol(z)
+    }
+
+    fn symbol(&mut self, z: Complex32) -> Option<char> {
+        let mut change = z * self.last_symbol.conj();
+        self.last_symbol = z;
+        if change.norm() == 0.0 {
+            return None;
+        }
+        change /= change.norm();
+
+        //Raising the change to the number of phases folds every expected one onto 0°
+        let phases = if self.qpsk { 4 } else { 2 };
+        let folded = change.powi(phases);
+        self.quality += QUALITY_ALPHA * (folded.re - self.quality);
+        let error = folded.arg() / phases as f32;
+        self.afc = (self.afc + AFC_GAIN * error as f64 * BAUD / TAU).clamp(-AFC_RANGE, AFC_RANGE);
+        self.nco.set_freq(-(self.tone + self.afc), self.rate);
+
+        //Only the bits are reversed, the frequency error is the same on either sideband
+        if self.reverse {
+            change = change.conj();
+        }
+
+        //A reversal is a 0 and no change a 1
+        let bit = if self.qpsk {
+            self.viterbi.push(change)?
+        } else {
+            change.re > 0.0
+        };
+        self.varicode(bit)
+    }
+
+    //Characters are separated by two zeros, which never appear inside one
+    fn varicode(&mut self, bit: bool) -> Option<char> {
+        let last_bit = std::mem::replace(&mut self.last_bit, bit);
+        if !bit && !last_bit {
+            let code = std::mem::take(&mut self.code) >> 1;
+            return self.varicode.get(&code).copied();
+        }
+        self.code = (self.code << 1) | bit as u32;
+        //Longer than any code, this is noise
+        if self.code >= 1 << 12 {
+            self.code = 0;
+        }
+        None
+    }
+}
+
+//Soft decision Viterbi decoder for the QPSK31 code, keeping each state's bits as it goes
+struct Viterbi {
+    metrics: [f32; STATES],
+    history: [u64; STATES],
+    //Phase change expected for each 2 bit output of the encoder
+    expected: [Complex32; 4],
+    count: usize,
+}
+
+impl Viterbi {
+    fn new() -> Self {
+        Self {
+            metrics: [0.0; STATES],
+            history: [0; STATES],
+            //00 is a reversal, 01 +90°, 10 -90° and 11 no change
+            expected: [
+                Complex32::new(-1.0, 0.0),
+                Complex32::new(0.0, 1.0),
+                Complex32::new(0.0, -1.0),
+                Complex32::new(1.0, 0.0),
+            ],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, change: Complex32) -> Option<bool> {
+        let mut metrics = [f32::MAX; STATES];
+        let mut history = [0; STATES];
+        for (state, metric) in self.metrics.iter().enumerate() {
+            for bit in 0..2 {
+                let reg = ((state << 1) | bit) as u8 & 0x1F;
+                let output = ((((reg & POLY1).count_ones() & 1) << 1)
+                    | ((reg & POLY2).count_ones() & 1)) as usize;
+                let next = (reg & 0x0F) as usize;
+                let candidate = metric + (change - self.expected[output]).norm_sqr();
+                if candidate < metrics[next] {
+                    metrics[next] = candidate;
+                    history[next] = (self.history[state] << 1) | bit as u64;
+                }
+            }
+        }
+
+        let best = metrics.iter().copied().fold(f32::MAX, f32::min);
+        for metric in metrics.iter_mut() {
+            *metric -= best;
+        }
+        self.metrics = metrics;
+        self.history = history;
+
+        self.count += 1;
+        if self.count <= VITERBI_DELAY {
+            return None;
+        }
+        let best = (0..STATES)
+            .min_by(|a, b| self.metrics[*a].total_cmp(&self.metrics[*b]))
+            .unwrap_or(0);
+        Some((self.history[best] >> VITERBI_DELAY) & 1 == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::digi::{DigiDecoder, DigiMode, DigiSettings};
+    use crate::decoders::Decoder;
+
+    const RATE: f64 = 8_000.0;
+
+    fn varicode_bits(text: &str) -> Vec<bool> {
+        text.chars()
+            .flat_map(|ch| {
+                let (_, code) = VARICODE.iter().find(|(code_ch, _)| *code_ch == ch).unwrap();
+                code.chars()
+                    .map(|bit| bit == '1')
+                    .chain([false, false])
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    //QPSK31 phase changes of `bits` through the K=5 encoder
+    fn encode(bits: &[bool]) -> Vec<Complex32> {
+        let expected = Viterbi::new().expected;
+        let mut reg = 0u8;
+        bits.iter()
+            .map(|bit| {
+                reg = ((reg << 1) | *bit as u8) & 0x1F;
+                let output =
+                    (((reg & POLY1).count_ones() & 1) << 1) | ((reg & POLY2).count_ones() & 1);
+                expected[output as usize]
+            })
+            .collect()
+    }
+
+    //Audio of a carrier on `hz` whose phase changes by `changes`, one a symbol with cosine
+    //shaped amplitude between them
+    fn audio(changes: &[Complex32], hz: f64) -> Vec<f32> {
+        let samples = (RATE / BAUD) as usize;
+        let mut symbol = Complex32::new(1.0, 0.0);
+        let mut audio = Vec::new();
+        for change in changes {
+            let next = symbol * change;
+            for idx in 0..samples {
+                let t = idx as f32 / samples as f32;
+                let shape = 0.5 + 0.5 * (std::f32::consts::PI * t).cos();
+                let z = symbol * shape + next * (1.0 - shape);
+                let carrier =
+                    Complex32::from_polar(1.0, (TAU * hz * (audio.len() as f64) / RATE) as f32);
+                audio.push(0.5 * (z * carrier).re);
+            }
+            symbol = next;
+        }
+        audio
+    }
+
+    fn decode(audio: &[f32], mode: DigiMode, reverse: bool) -> String {
+        let mut decoder = DigiDecoder::new();
+        let state = decoder.state();
+        state.lock().unwrap().settings = DigiSettings {
+            mode,
+            reverse,
+            ..Default::default()
+        };
+        for chunk in audio.chunks(1024) {
+            decoder.process_real(chunk, RATE);
+        }
+        let text = state.lock().unwrap().text.clone();
+        text
+    }
+
+    //Idle reversals around the text so the receiver can lock and flush
+    fn framed(text: &str) -> Vec<bool> {
+        let mut bits = vec![false; 64];
+        bits.extend(varicode_bits(text));
+        bits.extend([false; 64]);
+        bits
+    }
+
+    const TEXT: &str = "cq cq de N0CALL pse k\r\n73, {test} 599?";
+
+    #[test]
+    fn varicode_never_has_two_zeros_in_a_row() {
+        let demod = PskDemod::new(1_500.0, false, false, RATE);
+        assert_eq!(demod.varicode.len(), VARICODE.len());
+        for (ch, code) in VARICODE {
+            assert!(code.starts_with('1') && code.ends_with('1'), "{:?}", ch);
+            assert!(!code.contains("00"), "{:?}", ch);
+        }
+    }
+
+    #[test]
+    fn varicode_splits_on_double_zeros() {
+        let mut demod = PskDemod::new(1_500.0, false, false, RATE);
+        let text: String = varicode_bits("e t\n")
+            .iter()
+            .filter_map(|bit| demod.varicode(*bit))
+            .collect();
+        assert_eq!(text, "e t\n");
+    }
+
+    #[test]
+    fn viterbi_corrects_a_bad_symbol() {
+        let bits: Vec<bool> = (0..200u32)
+            .map(|idx| idx.wrapping_mul(2_654_435_761) >> 31 == 1)
+            .collect();
+        let mut changes = encode(&bits);
+        changes[50] = -changes[50];
+        changes[120] *= Complex32::new(0.0, 1.0);
+
+        let mut viterbi = Viterbi::new();
+        let decoded: Vec<bool> = changes
+            .iter()
+            .filter_map(|change| viterbi.push(*change))
+            .collect();
+        assert_eq!(decoded, bits[..bits.len() - VITERBI_DELAY]);
+    }
+
+    #[test]
+    fn bpsk31_round_trip() {
+        let changes: Vec<Complex32> = framed(TEXT)
+            .iter()
+            .map(|bit| Complex32::new(if *bit { 1.0 } else { -1.0 }, 0.0))
+            .collect();
+        assert_eq!(
+            decode(&audio(&changes, 1_500.0), DigiMode::Bpsk31, false),
+            TEXT.replace('\r', "")
+        );
+    }
+
+    #[test]
+    fn afc_follows_a_signal_off_tune() {
+        let changes: Vec<Complex32> = framed(TEXT)
+            .iter()
+            .map(|bit| Complex32::new(if *bit { 1.0 } else { -1.0 }, 0.0))
+            .collect();
+        let audio = audio(&changes, 1_506.0);
+        assert_eq!(
+            decode(&audio, DigiMode::Bpsk31, false),
+            TEXT.replace('\r', "")
+        );
+
+        let mut demod = PskDemod::new(1_500.0, false, false, RATE);
+        audio.iter().for_each(|x| {
+            demod.process(*x);
+        });
+        assert!(
+            (demod.afc() - 6.0).abs() < 1.0,
+            "AFC at {:.1} Hz",
+            demod.afc()
+        );
+    }
+
+    #[test]
+    fn qpsk31_round_trip() {
+        let changes = encode(&framed(TEXT));
+        assert_eq!(
+            decode(&audio(&changes, 1_500.0), DigiMode::Qpsk31, false),
+            TEXT.replace('\r', "")
+        );
+    }
+
+    #[test]
+    fn qpsk31_reversed() {
+        let changes: Vec<Complex32> = encode(&framed(TEXT))
+            .iter()
+            .map(|change| change.conj())
+            .collect();
+        let audio = audio(&changes, 1_500.0);
+        assert_eq!(
+            decode(&audio, DigiMode::Qpsk31, true),
+            TEXT.replace('\r', "")
+        );
+        assert_ne!(
+            decode(&audio, DigiMode::Qpsk31, false),
+            TEXT.replace('\r', "")
+        );
+    }
+}
@@ -0,0 +1,678 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futuresdr::num_complex::Complex32;
+
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::demod::MPX_RATE;
+use crate::dsp::{lowpass_taps, num_taps, Fir, Nco, OnePole, Resampler};
+
+const CARRIER: f64 = 57_000.0;
+const BIT_RATE: f64 = 1_187.5;
+//The 57 kHz baseband is brought down to a whole number of samples per bit
+const SAMPLES_PER_BIT: usize = 16;
+const FIRST_DECIM: usize = 10;
+//Bandwidth of the biphase coded signal
+const RDS_BANDWIDTH: f64 = 2_400.0;
+
+const BLOCK_BITS: u32 = 26;
+//x^10 + x^8 + x^7 + x^5 + x^4 + x^3 + 1
+const POLY: u32 = 0x5B9;
+//Offset words of blocks A, B, C, D and the C' used by version B groups
+const OFFSETS: [u16; 4] = [0x0FC, 0x198, 0x168, 0x1B4];
+const OFFSET_C_PRIME: u16 = 0x350;
+//Longest error burst corrected, longer ones risk turning noise into data
+const MAX_BURST: u32 = 3;
+//Bad blocks in a row before sync is dropped
+const SYNC_LOSS: usize = 10;
+
+const PTY_RDS: [&str; 32] = [
+    "None",
+    "News",
+    "Current Affairs",
+    "Information",
+    "Sport",
+    "Education",
+    "Drama",
+    "Culture",
+    "Science",
+    "Varied",
+    "Pop Music",
+    "Rock Music",
+    "Easy Listening",
+    "Light Classical",
+    "Serious Classical",
+    "Other Music",
+    "Weather",
+    "Finance",
+    "Children's Programmes",
+    "Social Affairs",
+    "Religion",
+    "Phone-in",
+    "Travel",
+    "Leisure",
+    "Jazz Music",
+    "Country Music",
+    "National Music",
+    "Oldies Music",
+    "Folk Music",
+    "Documentary",
+    "Alarm Test",
+    "Alarm",
+];
+
+const PTY_RBDS: [&str; 32] = [
+    "None",
+    "News",
+    "Information",
+    "Sports",
+    "Talk",
+    "Rock",
+    "Classic Rock",
+    "Adult Hits",
+    "Soft Rock",
+    "Top 40",
+    "Country",
+    "Oldies",
+    "Soft",
+    "Nostalgia",
+    "Jazz",
+    "Classical",
+    "Rhythm and Blues",
+    "Soft Rhythm and Blues",
+    "Language",
+    "Religious Music",
+    "Religious Talk",
+    "Personality",
+    "Public",
+    "College",
+    "Spanish Talk",
+    "Spanish Music",
+    "Hip Hop",
+    "Unassigned",
+    "Unassigned",
+    "Weather",
+    "Emergency Test",
+    "Emergency",
+];
+
+/// Name of a programme type, RBDS is the North American variant of the table
+pub fn pty_name(pty: u8, rbds: bool) -> &'static str {
+    let table = if rbds { &PTY_RBDS } else { &PTY_RDS };
+    table.get(pty as usize).copied().unwrap_or("Unknown")
+}
+
+/// Clock time sent in group 4A
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RdsClock {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    /// Local time offset from UTC in half hours
+    pub offset_half_hours: i32,
+}
+
+impl std::fmt::Display for RdsClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02} UTC{:+}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.offset_half_hours as f32 / 2.0
+        )
+    }
+}
+
+/// What the station has sent so far
+#[derive(Debug, Default, Clone)]
+pub struct RdsInfo {
+    pub synced: bool,
+    pub pi: Option<u16>,
+    /// Programme service name, set once all four segments have arrived
+    pub ps: Option<String>,
+    pub radiotext: Option<String>,
+    pub pty: Option<u8>,
+    pub clock: Option<RdsClock>,
+    /// Groups decoded without uncorrectable errors in block B
+    pub groups: u64,
+}
+
+/// RDS decoder on the WFM multiplex
+pub struct RdsDecoder {
+    info: Arc<Mutex<RdsInfo>>,
+    //57 kHz down to baseband
+    nco: Nco,
+    filter: Fir<Complex32>,
+    resampler: Resampler<Complex32>,
+    baseband: Vec<Complex32>,
+    resampled: Vec<Complex32>,
+    costas: Costas,
+    //Last bit period of the in phase signal for the biphase matched filter
+    history: [f32; SAMPLES_PER_BIT],
+    sample: usize,
+    //Average matched filter output at each position within a bit, the peak marks the bit end
+    timing: [f32; SAMPLES_PER_BIT],
+    strobe: usize,
+    last_bit: bool,
+    sync: BlockSync,
+}
+
+impl RdsDecoder {
+    pub fn new() -> Self {
+        let rate = MPX_RATE / FIRST_DECIM as f64;
+        Self {
+            info: Arc::new(Mutex::new(RdsInfo::default())),
+            nco: Nco::new(-CARRIER, MPX_RATE),
+            filter: Fir::new(
+                lowpass_taps(RDS_BANDWIDTH, MPX_RATE, num_taps(MPX_RATE, RDS_BANDWIDTH)),
+                FIRST_DECIM,
+            ),
+            resampler: Resampler::new(rate, BIT_RATE * SAMPLES_PER_BIT as f64),
+            baseband: Vec::new(),
+            resampled: Vec::new(),
+            costas: Costas::new(BIT_RATE * SAMPLES_PER_BIT as f64),
+            history: [0.0; SAMPLES_PER_BIT],
+            sample: 0,
+            timing: [0.0; SAMPLES_PER_BIT],
+            strobe: 0,
+            last_bit: false,
+            sync: BlockSync::new(),
+        }
+    }
+
+    pub fn info(&self) -> Arc<Mutex<RdsInfo>> {
+        self.info.clone()
+    }
+
+    fn symbol(&mut self, x: f32) {
+        let pos = self.sample % SAMPLES_PER_BIT;
+        self.history[pos] = x;
+        self.sample += 1;
+
+        //Biphase symbols are +- in the first half of the bit and -+ in the second
+        let half = SAMPLES_PER_BIT / 2;
+        let matched: f32 = (0..SAMPLES_PER_BIT)
+            .map(|age| {
+                let val = self.history[(pos + SAMPLES_PER_BIT - age) % SAMPLES_PER_BIT];
+                if age < half {
+                    -val
+                } else {
+                    val
+                }
+            })
+            .sum();
+        self.timing[pos] = 0.99 * self.timing[pos] + 0.01 * matched.abs();
+
+        if pos == self.strobe {
+            let bit = matched > 0.0;
+            //Differential coding, a change means 1
+            let data = bit != self.last_bit;
+            self.last_bit = bit;
+            if let Some(block) = self.sync.push(data) {
+                self.block(block);
+            }
+        }
+
+        if pos == SAMPLES_PER_BIT - 1 {
+            let best = (0..SAMPLES_PER_BIT)
+                .max_by(|a, b| self.timing[*a].total_cmp(&self.timing[*b]))
+                .unwrap_or(0);
+            //Only move for a clear winner so noise doesn't make the clock jitter
+            if self.timing[best] > 1.2 * self.timing[self.strobe] {
+                self.strobe = best;
+            }
+        }
+    }
+
+    fn block(&mut self, block: SyncEvent) {
+        let Ok(mut info) = self.info.lock() else {
+            return;
+        };
+        match block {
+            SyncEvent::Lost => info.synced = false,
+            SyncEvent::Group(group) => {
+                info.synced = true;
+                decode_group(&mut info, &mut self.sync.text, group);
+            }
+        }
+    }
+}
+
+impl Default for RdsDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for RdsDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Rds
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Mpx
+    }
+
+    fn process_real(&mut self, samples: &[f32], _rate: f64) {
+        self.baseband.clear();
+        self.resampled.clear();
+        for x in samples {
+            let mixed = self.nco.mix(Complex32::new(*x, 0.0));
+            if let Some(y) = self.filter.push(mixed) {
+                self.baseband.push(y);
+            }
+        }
+        self.resampler.process(&self.baseband, &mut self.resampled);
+
+        for idx in 0..self.resampled.len() {
+            let x = self.costas.process(self.resampled[idx]);
+            self.symbol(x);
+        }
+    }
+
+    fn report(&self) -> String {
+        let Ok(info) = self.info.lock() else {
+            return String::new();
+        };
+        let mut report = String::new();
+        if let Some(pi) = info.pi {
+            report += &format!("PI: {:04X}\n", pi);
+        }
+        if let Some(ps) = info.ps.as_ref() {
+            report += &format!("PS: {}\n", ps);
+        }
+        if let Some(pty) = info.pty {
+            report += &format!("PTY: {} ({})\n", pty, pty_name(pty, false));
+        }
+        if let Some(rt) = info.radiotext.as_ref() {
+            report += &format!("RadioText: {}\n", rt);
+        }
+        if let Some(clock) = info.clock.as_ref() {
+            report += &format!("Clock: {}\n", clock);
+        }
+        report += &format!("Groups: {}", info.groups);
+        report
+    }
+}
+
+//Removes the residual carrier phase and frequency so the bits land on the in phase axis
+struct Costas {
+    phase: f32,
+    freq: f32,
+    alpha: f32,
+    beta: f32,
+    level: OnePole,
+}
+
+impl Costas {
+    fn new(rate: f64) -> Self {
+        let bw = (2.0 * std::f64::consts::PI * 10.0 / rate) as f32;
+        Self {
+            phase: 0.0,
+            freq: 0.0,
+            alpha: 2.0 * bw,
+            beta: bw * bw,
+            level: OnePole::new(0.1, rate),
+        }
+    }
+
+    fn process(&mut self, x: Complex32) -> f32 {
+        let y = x * Complex32::from_polar(1.0, -self.phase);
+        let level = self.level.process(y.norm_sqr()).max(1e-12);
+        let error = (y.re * y.im / level).clamp(-1.0, 1.0);
+        self.freq += self.beta * error;
+        self.phase = (self.phase + self.freq + self.alpha * error) % std::f32::consts::TAU;
+        y.re / level.sqrt()
+    }
+}
+
+enum SyncEvent {
+    Lost,
+    /// Blocks A, B, C (or C'), D, None where uncorrectable
+    Group([Option<u16>; 4]),
+}
+
+//Finds block boundaries and checks each block against its offset word
+struct BlockSync {
+    reg: u32,
+    bits: u64,
+    synced: bool,
+    //Bit count and block index of the last block seen while hunting for sync
+    last_found: Option<(u64, usize)>,
+    block_bits: u32,
+    expected: usize,
+    bad_blocks: usize,
+    group: [Option<u16>; 4],
+    //Error pattern for each syndrome of a short burst
+    corrections: HashMap<u16, u32>,
+    text: TextState,
+}
+
+impl BlockSync {
+    fn new() -> Self {
+        let mut corrections = HashMap::new();
+        for len in 1..=MAX_BURST {
+            //Bursts start and end with an error bit
+            for inner in 0..(1u32 << len.saturating_sub(2)) {
+                let pattern = if len == 1 {
+                    1
+                } else {
+                    (1 << (len - 1)) | (inner << 1) | 1
+                };
+                for shift in 0..=(BLOCK_BITS - len) {
+                    corrections
+                        .entry(syndrome(pattern << shift))
+                        .or_insert(pattern << shift);
+                }
+            }
+        }
+
+        Self {
+            reg: 0,
+            bits: 0,
+            synced: false,
+            last_found: None,
+            block_bits: 0,
+            expected: 0,
+            bad_blocks: 0,
+            group: [None; 4],
+            corrections,
+            text: TextState::default(),
+        }
+    }
+
+    fn push(&mut self, bit: bool) -> Option<SyncEvent> {
+        self.reg = ((self.reg << 1) | bit as u32) & ((1 << BLOCK_BITS) - 1);
+        self.bits += 1;
+
+        if !self.synced {
+            let found = block_index(syndrome(self.reg))?;
+            if let Some((last_bits, last_idx)) = self.last_found {
+                let distance = self.bits - last_bits;
+                let blocks = distance / BLOCK_BITS as u64;
+                if distance % BLOCK_BITS as u64 == 0
+                    && blocks <= 4
+                    && (last_idx + blocks as usize) % 4 == found
+                {
+                    self.synced = true;
+                    self.bad_blocks = 0;
+                    self.group = [None; 4];
+                    self.expected = found;
+                    self.block_bits = 0;
+                    return self.take_block();
+                }
+            }
+            self.last_found = Some((self.bits, found));
+            return None;
+        }
+
+        self.block_bits += 1;
+        if self.block_bits < BLOCK_BITS {
+            return None;
+        }
+        self.block_bits = 0;
+        self.take_block()
+    }
+
+    fn take_block(&mut self) -> Option<SyncEvent> {
+        let idx = self.expected;
+        self.expected = (idx + 1) % 4;
+
+        let data = self.correct(OFFSETS[idx]).or_else(|| {
+            if idx == 2 {
+                self.correct(OFFSET_C_PRIME)
+            } else {
+                None
+            }
+        });
+        self.group[idx] = data;
+        if data.is_some() {
+            self.bad_blocks = 0;
+        } else {
+            self.bad_blocks += 1;
+            if self.bad_blocks >= SYNC_LOSS {
+                self.synced = false;
+                self.last_found = None;
+                return Some(SyncEvent::Lost);
+            }
+        }
+
+        if idx == 3 {
+            let group = self.group;
+            self.group = [None; 4];
+            return Some(SyncEvent::Group(group));
+        }
+        None
+    }
+
+    //The 16 data bits if the block matches `offset` after correcting a short burst
+    fn correct(&self, offset: u16) -> Option<u16> {
+        let error = syndrome(self.reg) ^ offset;
+        let word = if error == 0 {
+            self.reg
+        } else {
+            self.reg ^ self.corrections.get(&error)?
+        };
+        Some((word >> 10) as u16)
+    }
+}
+
+//Remainder of the 26 bit block divided by the generator polynomial
+fn syndrome(block: u32) -> u16 {
+    let mut reg = block;
+    for bit in (10..BLOCK_BITS).rev() {
+        if reg & (1 << bit) != 0 {
+            reg ^= POLY << (bit - 10);
+        }
+    }
+    (reg & 0x3FF) as u16
+}
+
+fn block_index(syndrome: u16) -> Option<usize> {
+    if syndrome == OFFSET_C_PRIME {
+        return Some(2);
+    }
+    OFFSETS.iter().position(|offset| *offset == syndrome)
+}
+
+//Text being assembled across groups
+#[derive(Default)]
+struct TextState {
+    ps: [u8; 8],
+    ps_segments: u8,
+    rt: Vec<u8>,
+    rt_ab: Option<bool>,
+}
+
+fn decode_group(info: &mut RdsInfo, text: &mut TextState, group: [Option<u16>; 4]) {
+    let [a, b, c, d] = group;
+    let Some(b) = b else {
+        return;
+    };
+    info.groups += 1;
+    if let Some(a) = a {
+        info.pi = Some(a);
+    }
+
+    let group_type = b >> 12;
+    let version_b = b & 0x800 != 0;
+    info.pty = Some(((b >> 5) & 0x1F) as u8);
+
+    match (group_type, version_b) {
+        (0, _) => {
+            let Some(d) = d else {
+                return;
+            };
+            let addr = (b & 0x3) as usize;
+            text.ps[addr * 2] = (d >> 8) as u8;
+            text.ps[addr * 2 + 1] = d as u8;
+            text.ps_segments |= 1 << addr;
+            if text.ps_segments == 0xF {
+                info.ps = Some(rds_string(&text.ps));
+            }
+        }
+        (2, _) => {
+            //A change of the A/B flag means a new message
+            let ab = b & 0x10 != 0;
+            if text.rt_ab != Some(ab) {
+                text.rt_ab = Some(ab);
+                text.rt = vec![b' '; 64];
+            }
+            let addr = (b & 0xF) as usize;
+            let chars: Vec<(usize, Option<u16>)> = if version_b {
+                vec![(addr * 2, d)]
+            } else {
+                vec![(addr * 4, c), (addr * 4 + 2, d)]
+            };
+            for (pos, word) in chars {
+                if let Some(word) = word {
+                    text.rt[pos] = (word >> 8) as u8;
+                    text.rt[pos + 1] = word as u8;
+                }
+            }
+            let end = text
+                .rt
+                .iter()
+                .position(|ch| *ch == 0x0D)
+                .unwrap_or(text.rt.len());
+            info.radiotext = Some(rds_string(&text.rt[..end]).trim_end().to_string());
+        }
+        (4, false) => {
+            if let (Some(c), Some(d)) = (c, d) {
+                info.clock = Some(clock_time(b, c, d));
+            }
+        }
+        _ => {}
+    }
+}
+
+//RDS text is ASCII for everything a station is likely to send
+fn rds_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|ch| {
+            if (0x20..0x7F).contains(ch) {
+                *ch as char
+            } else {
+                ' '
+            }
+        })
+        .collect()
+}
+
+fn clock_time(b: u16, c: u16, d: u16) -> RdsClock {
+    let mjd = (((b & 0x3) as u32) << 15) | ((c as u32) >> 1);
+    let hour = (((c & 0x1) as u32) << 4) | ((d >> 12) as u32);
+    let minute = ((d >> 6) & 0x3F) as u32;
+    let offset = (d & 0x1F) as i32;
+    let offset_half_hours = if d & 0x20 != 0 { -offset } else { offset };
+
+    //Modified Julian Date to calendar date, from the RDS standard
+    let mjd = mjd as f64;
+    let yp = ((mjd - 15078.2) / 365.25).floor();
+    let mp = ((mjd - 14956.1 - (yp * 365.25).floor()) / 30.6001).floor();
+    let day = mjd - 14956.0 - (yp * 365.25).floor() - (mp * 30.6001).floor();
+    let k = if mp == 14.0 || mp == 15.0 { 1.0 } else { 0.0 };
+
+    RdsClock {
+        year: (1900.0 + yp + k) as i32,
+        month: (mp - 1.0 - k * 12.0) as u32,
+        day: day as u32,
+        hour,
+        minute,
+        offset_half_hours,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::{decode_wav, fixtures};
+    use crate::demod::{DemodMode, Vfo};
+
+    //Data word followed by its checkword, so the syndrome of the block is `offset`
+    fn block(data: u16, offset: u16) -> u32 {
+        let word = (data as u32) << (BLOCK_BITS - 16);
+        word | (syndrome(word) ^ offset) as u32
+    }
+
+    //Pilot plus RDS sending the four 0A groups that spell out `ps` over and over
+    fn rds_mpx(pi: u16, ps: &[u8; 8], seconds: f64) -> Vec<f32> {
+        let groups = (seconds * BIT_RATE) as usize / (4 * BLOCK_BITS as usize) + 1;
+        let mut bits = Vec::new();
+        for addr in (0..4).cycle().take(groups) {
+            let b = (10 << 5) | addr as u16;
+            let d = u16::from_be_bytes([ps[addr * 2], ps[addr * 2 + 1]]);
+            for (data, offset) in [pi, b, 0xE0CD, d].into_iter().zip(OFFSETS) {
+                let block = block(data, offset);
+                bits.extend((0..BLOCK_BITS).rev().map(|bit| block >> bit & 1 != 0));
+            }
+        }
+
+        //Differentially coded, then one biphase symbol per bit on 57 kHz locked to the pilot
+        let mut level = false;
+        let symbols: Vec<f64> = bits
+            .iter()
+            .map(|bit| {
+                level ^= bit;
+                if level {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+            .collect();
+        (0..(seconds * MPX_RATE) as usize)
+            .map(|n| {
+                let t = n as f64 / MPX_RATE;
+                let pilot = 2.0 * std::f64::consts::PI * 19_000.0 * t;
+                let bit = t * BIT_RATE;
+                let symbol = symbols[bit as usize];
+                let biphase = if bit.fract() < 0.5 { symbol } else { -symbol };
+                (0.1 * pilot.sin() + 0.05 * biphase * (3.0 * pilot).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn corrects_short_error_bursts() {
+        let mut sync = BlockSync::new();
+        let good = block(0x1234, OFFSETS[1]);
+        assert_eq!(block_index(syndrome(good)), Some(1));
+        for error in [0, 0b1, 0b101 << 7, 0b111 << 23] {
+            sync.reg = good ^ error;
+            assert_eq!(sync.correct(OFFSETS[1]), Some(0x1234));
+        }
+    }
+
+    #[test]
+    fn decodes_ps_from_a_recording() {
+        let mpx = rds_mpx(0x1234, b"RUSTIC  ", 3.0);
+        let path = fixtures::write_wav(
+            "rds",
+            2,
+            MPX_RATE,
+            &fixtures::fm_iq(&mpx, 75_000.0, MPX_RATE),
+        );
+        let mut vfo = Vfo::default();
+        vfo.set_mode(DemodMode::Wfm);
+        let decoder = RdsDecoder::new();
+        let info = decoder.info();
+        let mut decoders: Vec<Box<dyn Decoder>> = vec![Box::new(decoder)];
+
+        let decoded = decode_wav(&path, vfo, &mut decoders);
+        std::fs::remove_file(&path).unwrap();
+        decoded.unwrap();
+
+        let info = info.lock().unwrap();
+        assert_eq!(info.pi, Some(0x1234));
+        assert_eq!(info.pty, Some(10));
+        assert_eq!(info.ps.as_deref(), Some("RUSTIC  "));
+    }
+}
@@ -0,0 +1,236 @@
+//! Start-stop FSK teleprinter signals with the 5 bit Baudot/ITA2 code.
+
+use crate::dsp::ToneCorrelator;
+
+/// Speeds in use on the amateur bands
+pub const BAUDS: [f64; 3] = [45.45, 50.0, 75.0];
+/// Mark/space shifts in Hz
+pub const SHIFTS: [f64; 3] = [170.0, 425.0, 850.0];
+
+const DATA_BITS: usize = 5;
+const LTRS: u8 = 31;
+const FIGS: u8 = 27;
+const SPACE: u8 = 4;
+//Characters in letters and figures case by code, the first data bit in the lowest bit.
+//Figures follow the US teleprinter layout used on the air, \0 is a control code.
+const LETTERS: &[u8; 32] = b"\0E\nA SIU\rDRJNFCKTZLWHYPQOBG\0MXV\0";
+const FIGURES: &[u8; 32] = b"\x003\n- \x0087\r$4',!:(5\")2#6019?&\0./;\0";
+
+//Where in a character the demodulator is
+enum Frame {
+    //Mark, waiting for a start bit
+    Idle,
+    //Samples to the middle of the next bit, which bit that is with the start bit as 0
+    Bit(f64, usize),
+}
+
+/// Demodulator and decoder for one speed and shift, mark on the upper tone unless reversed
+pub struct RttyDemod {
+    mark: ToneCorrelator,
+    space: ToneCorrelator,
+    samples_per_bit: f64,
+    frame: Frame,
+    code: u8,
+    figures: bool,
+}
+
+impl RttyDemod {
+    /// `center` is the audio frequency halfway between mark and space
+    pub fn new(center: f64, shift: f64, baud: f64, reverse: bool, rate: f64) -> Self {
+        let (mark, space) = if reverse {
+            (center - shift / 2.0, center + shift / 2.0)
+        } else {
+            (center + shift / 2.0, center - shift / 2.0)
+        };
+        let len = (rate / baud).round() as usize;
+        Self {
+            mark: ToneCorrelator::new(mark, rate, len),
+            space: ToneCorrelator::new(space, rate, len),
+            samples_per_bit: rate / baud,
+            frame: Frame::Idle,
+            code: 0,
+            figures: false,
+        }
+    }
+
+    /// Feeds one audio sample, returns a character once one is complete
+    #[inline]
+    pub fn process(&mut self, x: f32) -> Option<char> {
+        let mark = self.mark.process(x) > self.space.process(x);
+        match self.frame {
+            Frame::Idle => {
+                //The correlators cross halfway into the start bit, it is clearest half a bit later
+                if !mark {
+                    self.frame = Frame::Bit(self.samples_per_bit / 2.0, 0);
+                }
+                None
+            }
+            Frame::Bit(wait, bit) if wait > 1.0 => {
+                self.frame = Frame::Bit(wait - 1.0, bit);
+                None
+            }
+            Frame::Bit(wait, bit) => {
+                let next = Frame::Bit(wait - 1.0 + self.samples_per_bit, bit + 1);
+                match bit {
+                    //A start bit that didn't last was noise
+                    0 if mark => self.frame = Frame::Idle,
+                    0 => {
+                        self.code = 0;
+                        self.frame = next;
+                    }
+                    bit if bit <= DATA_BITS => {
+                        self.code |= (mark as u8) << (bit - 1);
+                        self.frame = next;
+                    }
+                    //Stop bit, a character without one is dropped
+                    _ => {
+                        self.frame = Frame::Idle;
+                        if mark {
+                            return self.character();
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn character(&mut self) -> Option<char> {
+        match self.code {
+            LTRS => self.figures = false,
+            FIGS => self.figures = true,
+            code => {
+                //Unshift on space, most stations expect it
+                if code == SPACE {
+                    self.figures = false;
+                }
+                let table = if self.figures { FIGURES } else { LETTERS };
+                let ch = table[code as usize];
+                return (ch != 0).then_some(ch as char);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::digi::{DigiDecoder, DigiMode, DigiSettings};
+    use crate::decoders::Decoder;
+
+    const RATE: f64 = 8_000.0;
+
+    //ITA2 codes of `text`, shifting to figures when needed and back to letters after a space
+    fn codes(text: &str) -> Vec<u8> {
+        let mut codes = vec![LTRS, LTRS];
+        let mut figures = false;
+        for ch in text.bytes() {
+            match LETTERS.iter().position(|letter| *letter == ch) {
+                Some(code) => {
+                    if figures && !b" \r\n".contains(&ch) {
+                        codes.push(LTRS);
+                        figures = false;
+                    }
+                    codes.push(code as u8);
+                }
+                None => {
+                    if !figures {
+                        codes.push(FIGS);
+                        figures = true;
+                    }
+                    codes.push(FIGURES.iter().position(|fig| *fig == ch).unwrap() as u8);
+                }
+            }
+            if ch == b' ' {
+                figures = false;
+            }
+        }
+        codes
+    }
+
+    //Continuous phase FSK on the settings' mark and space tones with 1.5 stop bits
+    fn audio(codes: &[u8], settings: &DigiSettings) -> Vec<f32> {
+        let tones = settings.tones();
+        let samples_per_bit = RATE / settings.baud;
+        let mut levels = vec![(true, 20.0)];
+        for code in codes {
+            levels.push((false, 1.0));
+            levels.extend((0..DATA_BITS).map(|bit| ((code >> bit) & 1 == 1, 1.0)));
+            levels.push((true, 1.5));
+        }
+        levels.push((true, 20.0));
+
+        let mut phase = 0.0f64;
+        let mut audio = Vec::new();
+        let mut bits = 0.0;
+        for (mark, len) in levels {
+            let hz = if mark { tones[0] } else { tones[1] };
+            let start = (bits * samples_per_bit) as usize;
+            bits += len;
+            for _ in start..(bits * samples_per_bit) as usize {
+                phase += 2.0 * std::f64::consts::PI * hz / RATE;
+                audio.push(0.5 * phase.sin() as f32);
+            }
+        }
+        audio
+    }
+
+    fn decode(audio: &[f32], settings: DigiSettings) -> String {
+        let mut decoder = DigiDecoder::new();
+        let state = decoder.state();
+        state.lock().unwrap().settings = settings;
+        for chunk in audio.chunks(1024) {
+            decoder.process_real(chunk, RATE);
+        }
+        let text = state.lock().unwrap().text.clone();
+        text
+    }
+
+    #[test]
+    fn figures_shift_until_letters_or_a_space() {
+        let mut demod = RttyDemod::new(1_500.0, 170.0, 45.45, false, RATE);
+        let mut text = String::new();
+        for code in codes("N0CALL 599 73") {
+            demod.code = code;
+            text.extend(demod.character());
+        }
+        assert_eq!(text, "N0CALL 599 73");
+        assert_eq!(codes("A1 2")[2..], [3, FIGS, 23, SPACE, FIGS, 19]);
+    }
+
+    #[test]
+    fn round_trip_at_45_baud() {
+        let settings = DigiSettings {
+            mode: DigiMode::Rtty,
+            baud: 45.45,
+            shift_hz: 170.0,
+            ..Default::default()
+        };
+        let text = "CQ CQ DE N0CALL\r\nRST 599 73?";
+        assert_eq!(
+            decode(&audio(&codes(text), &settings), settings),
+            "CQ CQ DE N0CALL\nRST 599 73?"
+        );
+    }
+
+    #[test]
+    fn round_trip_reversed() {
+        let settings = DigiSettings {
+            mode: DigiMode::Rtty,
+            reverse: true,
+            ..Default::default()
+        };
+        let audio = audio(&codes("RYRYRY THE QUICK BROWN FOX"), &settings);
+        assert_eq!(
+            decode(&audio, settings.clone()),
+            "RYRYRY THE QUICK BROWN FOX"
+        );
+        //Without reversing mark and space are swapped and nothing readable comes out
+        let swapped = DigiSettings {
+            reverse: false,
+            ..settings
+        };
+        assert!(!decode(&audio, swapped).contains("QUICK"));
+    }
+}
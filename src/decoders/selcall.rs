@@ -0,0 +1,439 @@
+//! DTMF digits and 5-tone selective calling (ZVEI1, CCIR and EEA) on demodulated audio.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use super::{Decoder, DecoderInput, DecoderKind};
+use crate::dsp::tone_power;
+
+/// Sequences kept in the log
+pub const LOG_LEN: usize = 500;
+
+const DTMF_ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+//Digits 0 to 9 and then the repeat tone, sent instead of a digit that is the same as the last
+const ZVEI1: [f64; 11] = [
+    2_400.0, 1_060.0, 1_160.0, 1_270.0, 1_400.0, 1_530.0, 1_670.0, 1_830.0, 2_000.0, 2_200.0,
+    2_600.0,
+];
+//CCIR tones last 100 ms, EEA uses the same tones for 40 ms
+const CCIR: [f64; 11] = [
+    1_981.0, 1_124.0, 1_197.0, 1_275.0, 1_358.0, 1_446.0, 1_540.0, 1_640.0, 1_747.0, 1_860.0,
+    2_110.0,
+];
+const REPEAT: usize = 10;
+
+//Each measurement covers 20 ms, enough to tell the CCIR tones apart, and they overlap by half
+const WINDOW_SECS: f64 = 0.02;
+const HOP_SECS: f64 = 0.01;
+//Share of the audio power a single tone or both DTMF tones together need, and each DTMF tone
+const TONE_SHARE: f32 = 0.6;
+const DTMF_MIN_SHARE: f32 = 0.2;
+//Measurements a digit has to last, DTMF needs 40 ms and selcall tones 30 ms
+const DTMF_MIN_HOPS: usize = 3;
+const TONE_MIN_HOPS: usize = 2;
+//Selcall tones follow each other without a gap, DTMF digits end a second and a half after the last
+const SELCALL_GAP_HOPS: usize = 5;
+const DTMF_GAP_HOPS: usize = 150;
+const SELCALL_MIN_TONES: usize = 5;
+//How long each standard's tones last, EEA and CCIR share their tones so only this tells them apart
+const TONE_SECS: [(SelcallKind, f64); 3] = [
+    (SelcallKind::Eea, 0.04),
+    (SelcallKind::Zvei1, 0.07),
+    (SelcallKind::Ccir, 0.1),
+];
+//Measurements closer than this are the same tone, ZVEI1 and CCIR have tones only 5 Hz apart
+const SAME_TONE_HZ: f64 = 50.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelcallKind {
+    Dtmf,
+    Zvei1,
+    Ccir,
+    Eea,
+}
+
+impl std::fmt::Display for SelcallKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                SelcallKind::Dtmf => "DTMF",
+                SelcallKind::Zvei1 => "ZVEI1",
+                SelcallKind::Ccir => "CCIR",
+                SelcallKind::Eea => "EEA",
+            }
+        )
+    }
+}
+
+/// One sequence of digits
+#[derive(Debug, Clone)]
+pub struct SelcallEvent {
+    /// When the sequence ended
+    pub time: DateTime<Utc>,
+    /// RF frequency it was heard on, unknown when decoding a recording
+    pub freq_hz: Option<f64>,
+    pub kind: SelcallKind,
+    pub digits: String,
+}
+
+/// Recent sequences, newest first
+#[derive(Debug, Default)]
+pub struct SelcallLog {
+    pub events: VecDeque<SelcallEvent>,
+    pub total: u64,
+}
+
+//What one measurement found
+#[derive(Debug, Clone, PartialEq)]
+enum Tone {
+    Dtmf(char),
+    //Share of the power in each ZVEI1 tone followed by each CCIR tone
+    Single(Vec<f32>),
+}
+
+/// DTMF and selcall decoder on NFM audio
+pub struct SelcallDecoder {
+    log: Arc<Mutex<SelcallLog>>,
+    rate: f64,
+    freq_hz: Option<f64>,
+    singles: Vec<f64>,
+    history: Vec<f32>,
+    window: usize,
+    hop: usize,
+    since: usize,
+    //Tone being heard and for how many measurements, and measurements since the last one
+    current: Option<(Tone, usize)>,
+    gap: usize,
+    dtmf: String,
+    //Selcall tones heard so far, summed over each tone, with how long each lasted
+    tones: Vec<(Vec<f32>, usize)>,
+}
+
+impl SelcallDecoder {
+    pub fn new() -> Self {
+        Self {
+            log: Arc::new(Mutex::new(SelcallLog::default())),
+            rate: 0.0,
+            freq_hz: None,
+            singles: ZVEI1.iter().chain(CCIR.iter()).copied().collect(),
+            history: Vec::new(),
+            window: 0,
+            hop: 0,
+            since: 0,
+            current: None,
+            gap: 0,
+            dtmf: String::new(),
+            tones: Vec::new(),
+        }
+    }
+
+    pub fn log(&self) -> Arc<Mutex<SelcallLog>> {
+        self.log.clone()
+    }
+
+    //DTMF pair or single tone making up most of the last window
+    fn classify(&self) -> Option<Tone> {
+        let samples = &self.history[self.history.len() - self.window..];
+        let total = samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32;
+        if total < 1e-9 {
+            return None;
+        }
+        //A tone's Goertzel power is half its mean square
+        let shares = |freqs: &[f64]| -> Vec<f32> {
+            freqs
+                .iter()
+                .map(|hz| 2.0 * tone_power(samples, *hz, self.rate) / total)
+                .collect()
+        };
+
+        let (row, row_share) = loudest(&shares(&DTMF_ROWS[..]));
+        let (col, col_share) = loudest(&shares(&DTMF_COLS[..]));
+        if row_share > DTMF_MIN_SHARE
+            && col_share > DTMF_MIN_SHARE
+            && row_share + col_share > TONE_SHARE
+        {
+            return Some(Tone::Dtmf(DTMF_KEYS[row][col]));
+        }
+        let shares = shares(&self.singles[..]);
+        (loudest(&shares).1 > TONE_SHARE).then_some(Tone::Single(shares))
+    }
+
+    //Whether a measurement carries on the tone being heard
+    fn same_tone(&self, a: &Tone, b: &Tone) -> bool {
+        match (a, b) {
+            (Tone::Dtmf(a), Tone::Dtmf(b)) => a == b,
+            (Tone::Single(a), Tone::Single(b)) => {
+                (self.singles[loudest(a).0] - self.singles[loudest(b).0]).abs() < SAME_TONE_HZ
+            }
+            _ => false,
+        }
+    }
+
+    fn measurement(&mut self, tone: Option<Tone>) {
+        let heard = tone.is_some();
+        match (self.current.take(), tone) {
+            (Some((current, hops)), Some(tone)) if self.same_tone(&current, &tone) => {
+                let current = match (current, tone) {
+                    (Tone::Single(mut sum), Tone::Single(shares)) => {
+                        sum.iter_mut().zip(shares).for_each(|(sum, x)| *sum += x);
+                        Tone::Single(sum)
+                    }
+                    (current, _) => current,
+                };
+                self.current = Some((current, hops + 1));
+                return;
+            }
+            (Some((current, hops)), tone) => {
+                self.tone_end(current, hops);
+                self.current = tone.map(|tone| (tone, 1));
+            }
+            (None, tone) => self.current = tone.map(|tone| (tone, 1)),
+        }
+
+        if heard {
+            self.gap = 0;
+            return;
+        }
+        self.gap += 1;
+        if self.gap == SELCALL_GAP_HOPS {
+            let tones = std::mem::take(&mut self.tones);
+            if tones.len() >= SELCALL_MIN_TONES {
+                let (kind, digits) = self.selcall(&tones);
+                self.event(kind, digits);
+            }
+        }
+        if self.gap == DTMF_GAP_HOPS && !self.dtmf.is_empty() {
+            let digits = std::mem::take(&mut self.dtmf);
+            self.event(SelcallKind::Dtmf, digits);
+        }
+    }
+
+    fn tone_end(&mut self, tone: Tone, hops: usize) {
+        match tone {
+            Tone::Dtmf(key) if hops >= DTMF_MIN_HOPS => self.dtmf.push(key),
+            Tone::Single(shares) if hops >= TONE_MIN_HOPS => self.tones.push((shares, hops)),
+            _ => {}
+        }
+    }
+
+    //Picks the standard from how long the tones last, each tone is then the loudest of its digits
+    fn selcall(&self, tones: &[(Vec<f32>, usize)]) -> (SelcallKind, String) {
+        let secs = (tones.iter().map(|(_, hops)| hops + 1).sum::<usize>() as f64 * HOP_SECS)
+            / tones.len() as f64;
+        let kind = TONE_SECS
+            .iter()
+            .min_by(|a, b| (a.1 - secs).abs().total_cmp(&(b.1 - secs).abs()))
+            .map(|(kind, _)| *kind)
+            .unwrap_or(SelcallKind::Ccir);
+        let table = match kind {
+            SelcallKind::Zvei1 => 0..ZVEI1.len(),
+            _ => ZVEI1.len()..ZVEI1.len() + CCIR.len(),
+        };
+
+        let mut digits = String::new();
+        for (shares, _) in tones {
+            let digit = loudest(&shares[table.clone()]).0;
+            let ch = if digit == REPEAT {
+                digits.chars().last().unwrap_or('R')
+            } else {
+                char::from_digit(digit as u32, 10).unwrap_or('?')
+            };
+            digits.push(ch);
+        }
+        (kind, digits)
+    }
+
+    fn event(&mut self, kind: SelcallKind, digits: String) {
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+        log.total += 1;
+        log.events.push_front(SelcallEvent {
+            time: Utc::now(),
+            freq_hz: self.freq_hz,
+            kind,
+            digits,
+        });
+        log.events.truncate(LOG_LEN);
+    }
+}
+
+//Index and share of the loudest tone
+fn loudest(shares: &[f32]) -> (usize, f32) {
+    shares
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+impl Default for SelcallDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for SelcallDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Selcall
+    }
+
+    fn input(&self) -> DecoderInput {
+        DecoderInput::Audio
+    }
+
+    fn process_real(&mut self, samples: &[f32], rate: f64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.window = ((WINDOW_SECS * rate) as usize).max(1);
+            self.hop = ((HOP_SECS * rate) as usize).max(1);
+            self.history.clear();
+            self.since = 0;
+        }
+
+        for x in samples {
+            self.history.push(*x);
+            self.since += 1;
+            if self.since >= self.hop && self.history.len() >= self.window {
+                self.since = 0;
+                let tone = self.classify();
+                self.measurement(tone);
+            }
+        }
+        if self.history.len() > self.window {
+            let cut = self.history.len() - self.window;
+            self.history.drain(..cut);
+        }
+    }
+
+    fn set_freq(&mut self, rf_hz: f64) {
+        self.freq_hz = Some(rf_hz);
+    }
+
+    fn report(&self) -> String {
+        let Ok(log) = self.log.lock() else {
+            return String::new();
+        };
+        let mut report = format!("{} sequences", log.total);
+        for event in log.events.iter().rev() {
+            report += &format!(
+                "\n{} {:<5} {}",
+                event.time.format("%Y-%m-%d %H:%M:%S"),
+                event.kind.to_string(),
+                event.digits
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 48_000.0;
+
+    //Each entry is played for `secs` followed by `gap_secs` of silence, all freqs of an entry together
+    fn audio(entries: &[Vec<f64>], secs: f64, gap_secs: f64) -> Vec<f32> {
+        let mut audio = vec![0.0; (0.2 * RATE) as usize];
+        for freqs in entries {
+            let amplitude = 0.5 / freqs.len() as f64;
+            audio.extend((0..(secs * RATE) as usize).map(|idx| {
+                let t = idx as f64 / RATE;
+                freqs
+                    .iter()
+                    .map(|hz| amplitude * (2.0 * std::f64::consts::PI * hz * t).sin())
+                    .sum::<f64>() as f32
+            }));
+            audio.resize(audio.len() + (gap_secs * RATE) as usize, 0.0);
+        }
+        audio.resize(audio.len() + (2.0 * RATE) as usize, 0.0);
+        audio
+    }
+
+    //Tones of a selcall sequence, a digit the same as the last is sent as the repeat tone
+    fn sequence(table: &[f64; 11], digits: &str) -> Vec<Vec<f64>> {
+        let mut last = None;
+        digits
+            .chars()
+            .map(|ch| {
+                let digit = ch.to_digit(10).unwrap() as usize;
+                let idx = if last == Some(digit) { REPEAT } else { digit };
+                last = (idx != REPEAT).then_some(digit);
+                vec![table[idx]]
+            })
+            .collect()
+    }
+
+    fn decode(audio: &[f32]) -> Vec<(SelcallKind, String)> {
+        let mut decoder = SelcallDecoder::new();
+        for chunk in audio.chunks(4096) {
+            decoder.process_real(chunk, RATE);
+        }
+        let log = decoder.log();
+        let log = log.lock().unwrap();
+        log.events
+            .iter()
+            .rev()
+            .map(|event| (event.kind, event.digits.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn zvei1_by_its_70ms_tones() {
+        //3 is 1270 Hz, only 5 Hz from CCIR's
+        let found = decode(&audio(&sequence(&ZVEI1, "12334"), 0.07, 0.0));
+        assert_eq!(found, [(SelcallKind::Zvei1, "12334".to_string())]);
+    }
+
+    #[test]
+    fn ccir_by_its_100ms_tones() {
+        let found = decode(&audio(&sequence(&CCIR, "90553"), 0.1, 0.0));
+        assert_eq!(found, [(SelcallKind::Ccir, "90553".to_string())]);
+    }
+
+    #[test]
+    fn eea_by_its_40ms_tones() {
+        let found = decode(&audio(&sequence(&CCIR, "67780"), 0.04, 0.0));
+        assert_eq!(found, [(SelcallKind::Eea, "67780".to_string())]);
+    }
+
+    #[test]
+    fn every_digit_of_each_standard() {
+        for (table, secs, kind) in [
+            (&ZVEI1, 0.07, SelcallKind::Zvei1),
+            (&CCIR, 0.1, SelcallKind::Ccir),
+            (&CCIR, 0.04, SelcallKind::Eea),
+        ] {
+            let found = decode(&audio(&sequence(table, "0123456789"), secs, 0.0));
+            assert_eq!(found, [(kind, "0123456789".to_string())]);
+        }
+    }
+
+    #[test]
+    fn dtmf_digits() {
+        let keys: Vec<Vec<f64>> = [(0, 0), (0, 1), (3, 2), (3, 1), (2, 3)]
+            .iter()
+            .map(|(row, col)| vec![DTMF_ROWS[*row], DTMF_COLS[*col]])
+            .collect();
+        let found = decode(&audio(&keys, 0.08, 0.08));
+        assert_eq!(found, [(SelcallKind::Dtmf, "12#0C".to_string())]);
+    }
+
+    #[test]
+    fn too_few_tones_are_ignored() {
+        assert!(decode(&audio(&sequence(&ZVEI1, "1234"), 0.07, 0.0)).is_empty());
+    }
+}
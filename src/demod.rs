@@ -0,0 +1,652 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futuresdr::anyhow::{Ok, Result};
+use futuresdr::{
+    macros::async_trait,
+    num_complex::Complex32,
+    runtime::{
+        Block, BlockMeta, BlockMetaBuilder, Kernel, MessageIo, MessageIoBuilder, StreamIo,
+        StreamIoBuilder, WorkIo,
+    },
+};
+
+use crate::decoders::{self, Decoder, DecoderKind};
+use crate::dsp::{
+    bandpass_taps, lowpass_taps, num_taps, Agc, DcRemover, Fir, FmDiscriminator, Nco, OnePole, Pll,
+    Resampler,
+};
+use crate::subtone::{Subtone, SubtoneDetector};
+
+/// Rate of the demodulated audio
+pub const AUDIO_RATE: f64 = 48_000.0;
+/// Rate of the FM multiplex in WFM, wide enough for RDS at 57 kHz
+pub const MPX_RATE: f64 = 240_000.0;
+
+const WFM_DEVIATION: f64 = 75_000.0;
+const NFM_DEVIATION: f64 = 5_000.0;
+//Pilot amplitude, relative to full deviation, needed before L-R is decoded
+const PILOT_LOCK: f32 = 0.02;
+//Audio bandwidth of broadcast FM
+const WFM_AUDIO: f64 = 15_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DemodMode {
+    #[default]
+    Off,
+    Wfm,
+    Nfm,
+    Am,
+    Usb,
+    Lsb,
+    Cw,
+}
+
+impl DemodMode {
+    pub const ALL: [DemodMode; 7] = [
+        DemodMode::Off,
+        DemodMode::Wfm,
+        DemodMode::Nfm,
+        DemodMode::Am,
+        DemodMode::Usb,
+        DemodMode::Lsb,
+        DemodMode::Cw,
+    ];
+
+    /// Channel width a new VFO in this mode starts with
+    pub fn default_bandwidth(&self) -> f64 {
+        match self {
+            DemodMode::Off => 0.0,
+            DemodMode::Wfm => 200_000.0,
+            DemodMode::Nfm => 12_500.0,
+            DemodMode::Am => 10_000.0,
+            DemodMode::Usb | DemodMode::Lsb => 2_800.0,
+            DemodMode::Cw => 500.0,
+        }
+    }
+
+    fn if_rate(&self) -> f64 {
+        match self {
+            DemodMode::Wfm => MPX_RATE,
+            _ => AUDIO_RATE,
+        }
+    }
+}
+
+impl std::fmt::Display for DemodMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DemodMode::Off => "Off",
+                DemodMode::Wfm => "WFM",
+                DemodMode::Nfm => "NFM",
+                DemodMode::Am => "AM",
+                DemodMode::Usb => "USB",
+                DemodMode::Lsb => "LSB",
+                DemodMode::Cw => "CW",
+            }
+        )
+    }
+}
+
+/// The channel being listened to within the captured spectrum
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vfo {
+    /// Distance of the channel from the center frequency in Hz
+    pub offset_hz: f64,
+    pub mode: DemodMode,
+    /// Width of the channel filter in Hz
+    pub bandwidth_hz: f64,
+    /// Audio gain, 1.0 is unity
+    pub volume: f32,
+    /// FM de-emphasis time constant, 50 µs in Europe and 75 µs in the Americas
+    pub deemphasis_us: f64,
+    /// Pitch of the CW tone in Hz
+    pub bfo_hz: f64,
+    /// NFM audio is only heard while this CTCSS tone or DCS code is on the channel
+    pub tone_squelch: Option<Subtone>,
+}
+
+impl Default for Vfo {
+    fn default() -> Self {
+        Self {
+            offset_hz: 0.0,
+            mode: DemodMode::Off,
+            bandwidth_hz: 0.0,
+            volume: 0.5,
+            deemphasis_us: 50.0,
+            bfo_hz: 700.0,
+            tone_squelch: None,
+        }
+    }
+}
+
+impl Vfo {
+    /// Switches mode and resets the channel width to the mode default
+    pub fn set_mode(&mut self, mode: DemodMode) {
+        self.mode = mode;
+        self.bandwidth_hz = mode.default_bandwidth();
+    }
+}
+
+/// One block of demodulator output
+pub struct DemodOutput<'a> {
+    /// FM multiplex at `MPX_RATE`, empty unless in WFM
+    pub mpx: &'a [f32],
+    /// Mono audio at `AUDIO_RATE` before the volume is applied
+    pub audio: &'a [f32],
+    pub left: &'a [f32],
+    pub right: &'a [f32],
+    /// True while the stereo pilot is locked
+    pub stereo: bool,
+    /// CTCSS tone or DCS code found on NFM audio
+    pub subtone: Option<Subtone>,
+    /// False while the tone squelch keeps the audio muted
+    pub open: bool,
+}
+
+//Pilot locked L/R decoding of the FM multiplex
+struct FmStereo {
+    pilot: Fir<f32>,
+    pll: Pll,
+    //Holds the multiplex back by the group delay of the pilot filter, less the one sample
+    //the loop's phase runs ahead, so it lines up with the recovered carrier
+    delay: VecDeque<f32>,
+    sum: Fir<f32>,
+    diff: Fir<f32>,
+    deemphasis: [OnePole; 2],
+    locked: bool,
+}
+
+impl FmStereo {
+    fn new(deemphasis_us: f64) -> Self {
+        let decim = (MPX_RATE / AUDIO_RATE) as usize;
+        let audio_taps = || lowpass_taps(WFM_AUDIO, MPX_RATE, num_taps(MPX_RATE, 4_000.0));
+        let tau = deemphasis_us / 1_000_000.0;
+        let pilot_taps = num_taps(MPX_RATE, 4_000.0);
+        Self {
+            pilot: Fir::new(bandpass_taps(17_000.0, 21_000.0, MPX_RATE, pilot_taps), 1),
+            pll: Pll::new(19_000.0, 50.0, MPX_RATE),
+            delay: VecDeque::from(vec![0.0; (pilot_taps - 1) / 2 - 1]),
+            sum: Fir::new(audio_taps(), decim),
+            diff: Fir::new(audio_taps(), decim),
+            deemphasis: [OnePole::new(tau, AUDIO_RATE), OnePole::new(tau, AUDIO_RATE)],
+            locked: false,
+        }
+    }
+
+    fn process(
+        &mut self,
+        mpx: &[f32],
+        audio: &mut Vec<f32>,
+        left: &mut Vec<f32>,
+        right: &mut Vec<f32>,
+    ) {
+        for x in mpx {
+            let pilot = self.pilot.push(*x).unwrap_or_default();
+            let phase = self.pll.process(pilot);
+            self.delay.push_back(*x);
+            let x = self.delay.pop_front().unwrap_or_default();
+            //L-R is on sin(2θ) for a sin(θ) pilot, the loop locks to its cosine so
+            //phase = θ - π/2 and the carrier is -sin(2 * phase)
+            let diff = self.diff.push(x * -2.0 * (2.0 * phase).sin() as f32);
+            if let (Some(sum), Some(diff)) = (self.sum.push(x), diff) {
+                self.locked = self.pll.lock_level() > PILOT_LOCK;
+                let diff = if self.locked { diff } else { 0.0 };
+                let l = self.deemphasis[0].process(sum + diff);
+                let r = self.deemphasis[1].process(sum - diff);
+                audio.push((l + r) / 2.0);
+                left.push(l);
+                right.push(r);
+            }
+        }
+    }
+}
+
+//Picks one sideband, or the narrow CW filter, out of the channel
+struct Sideband {
+    down: Nco,
+    filter: Fir<Complex32>,
+    up: Nco,
+}
+
+impl Sideband {
+    //Passes `center` +- `half_width` and moves `center` to `out_freq`
+    fn new(center: f64, half_width: f64, out_freq: f64) -> Self {
+        Self {
+            down: Nco::new(-center, AUDIO_RATE),
+            filter: Fir::new(
+                lowpass_taps(
+                    half_width,
+                    AUDIO_RATE,
+                    num_taps(AUDIO_RATE, (half_width / 2.0).max(100.0)),
+                ),
+                1,
+            ),
+            up: Nco::new(out_freq, AUDIO_RATE),
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: Complex32) -> f32 {
+        let filtered = self.filter.push(self.down.mix(x)).unwrap_or_default();
+        self.up.mix(filtered).re
+    }
+}
+
+/// Turns the captured samples into audio for one VFO
+pub struct Demodulator {
+    vfo: Vfo,
+    in_rate: f64,
+    nco: Nco,
+    channel: Fir<Complex32>,
+    resampler: Resampler<Complex32>,
+    discriminator: FmDiscriminator,
+    stereo: Option<FmStereo>,
+    sideband: Option<Sideband>,
+    subtone: Option<SubtoneDetector>,
+    dc: DcRemover,
+    agc: Agc,
+    //Audio samples owed while off, so silence still comes out at the audio rate
+    silence: f64,
+    decimated: Vec<Complex32>,
+    iq: Vec<Complex32>,
+    mpx: Vec<f32>,
+    audio: Vec<f32>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl Demodulator {
+    pub fn new(vfo: Vfo, in_rate: f64) -> Self {
+        let if_rate = vfo.mode.if_rate();
+        let decim = ((in_rate / if_rate) as usize).max(1);
+        //SSB keeps both sides of the carrier until the sideband filter
+        let cutoff = match vfo.mode {
+            DemodMode::Usb | DemodMode::Lsb => vfo.bandwidth_hz,
+            _ => vfo.bandwidth_hz / 2.0,
+        }
+        .clamp(100.0, if_rate / 2.0);
+        let half_bw = vfo.bandwidth_hz / 2.0;
+        let sideband = match vfo.mode {
+            DemodMode::Usb => Some(Sideband::new(half_bw, half_bw, half_bw)),
+            DemodMode::Lsb => Some(Sideband::new(-half_bw, half_bw, -half_bw)),
+            DemodMode::Cw => Some(Sideband::new(0.0, half_bw, vfo.bfo_hz)),
+            _ => None,
+        };
+
+        Self {
+            nco: Nco::new(-vfo.offset_hz, in_rate),
+            channel: Fir::new(
+                lowpass_taps(cutoff, in_rate, num_taps(in_rate, cutoff / 2.0)),
+                decim,
+            ),
+            resampler: Resampler::new(in_rate / decim as f64, if_rate),
+            discriminator: FmDiscriminator::default(),
+            stereo: (vfo.mode == DemodMode::Wfm).then(|| FmStereo::new(vfo.deemphasis_us)),
+            sideband,
+            subtone: (vfo.mode == DemodMode::Nfm).then(|| SubtoneDetector::new(AUDIO_RATE)),
+            dc: DcRemover::new(AUDIO_RATE),
+            agc: Agc::new(AUDIO_RATE),
+            silence: 0.0,
+            decimated: Vec::new(),
+            iq: Vec::new(),
+            mpx: Vec::new(),
+            audio: Vec::new(),
+            left: Vec::new(),
+            right: Vec::new(),
+            vfo,
+            in_rate,
+        }
+    }
+
+    #[inline]
+    pub fn vfo(&self) -> &Vfo {
+        &self.vfo
+    }
+
+    /// Applies a new VFO, only rebuilding the filters if the channel itself changed
+    pub fn set_vfo(&mut self, vfo: Vfo) {
+        let retune_only = Vfo {
+            offset_hz: self.vfo.offset_hz,
+            volume: self.vfo.volume,
+            tone_squelch: self.vfo.tone_squelch,
+            ..vfo.clone()
+        } == self.vfo;
+
+        if retune_only {
+            self.nco.set_freq(-vfo.offset_hz, self.in_rate);
+            self.vfo = vfo;
+        } else {
+            *self = Self::new(vfo, self.in_rate);
+        }
+    }
+
+    pub fn process(&mut self, input: &[Complex32]) -> DemodOutput<'_> {
+        self.decimated.clear();
+        self.iq.clear();
+        self.mpx.clear();
+        self.audio.clear();
+        self.left.clear();
+        self.right.clear();
+
+        if self.vfo.mode == DemodMode::Off {
+            self.silence += input.len() as f64 * AUDIO_RATE / self.in_rate;
+            let count = self.silence as usize;
+            self.silence -= count as f64;
+            self.audio.resize(count, 0.0);
+        } else {
+            for x in input {
+                if let Some(y) = self.channel.push(self.nco.mix(*x)) {
+                    self.decimated.push(y);
+                }
+            }
+            self.resampler.process(&self.decimated, &mut self.iq);
+            self.demodulate();
+        }
+
+        if self.left.is_empty() {
+            self.left.extend_from_slice(&self.audio);
+            self.right.extend_from_slice(&self.audio);
+        }
+
+        let subtone = self
+            .subtone
+            .as_ref()
+            .and_then(|detector| detector.detected());
+        let open = match self.vfo.tone_squelch {
+            Some(tone) if self.vfo.mode == DemodMode::Nfm => subtone == Some(tone),
+            _ => true,
+        };
+
+        DemodOutput {
+            mpx: &self.mpx,
+            audio: &self.audio,
+            left: &self.left,
+            right: &self.right,
+            stereo: self.stereo.as_ref().is_some_and(|stereo| stereo.locked),
+            subtone,
+            open,
+        }
+    }
+
+    fn demodulate(&mut self) {
+        match self.vfo.mode {
+            DemodMode::Off => {}
+            DemodMode::Wfm => {
+                let scale = (2.0 * std::f64::consts::PI * WFM_DEVIATION / MPX_RATE) as f32;
+                for z in self.iq.iter() {
+                    self.mpx.push(self.discriminator.process(*z) / scale);
+                }
+                if let Some(stereo) = self.stereo.as_mut() {
+                    stereo.process(&self.mpx, &mut self.audio, &mut self.left, &mut self.right);
+                }
+            }
+            DemodMode::Nfm => {
+                let scale = (2.0 * std::f64::consts::PI * NFM_DEVIATION / AUDIO_RATE) as f32;
+                for z in self.iq.iter() {
+                    let audio = self.discriminator.process(*z) / scale;
+                    self.audio.push(self.dc.process(audio));
+                }
+                if let Some(subtone) = self.subtone.as_mut() {
+                    subtone.process(&self.audio);
+                }
+            }
+            DemodMode::Am => {
+                for z in self.iq.iter() {
+                    let audio = self.dc.process(z.norm());
+                    self.audio.push(self.agc.process(audio));
+                }
+            }
+            DemodMode::Usb | DemodMode::Lsb | DemodMode::Cw => {
+                if let Some(sideband) = self.sideband.as_mut() {
+                    for z in self.iq.iter() {
+                        let audio = sideband.process(*z);
+                        self.audio.push(self.agc.process(audio));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// State shared between the `Sdr` and its demodulator block
+pub struct DemodShared {
+    vfo: Mutex<Vfo>,
+    vfo_changed: AtomicBool,
+    decoders: Mutex<Vec<Box<dyn Decoder>>>,
+    stereo: AtomicBool,
+    subtone: Mutex<Option<Subtone>>,
+    //Bits of the f64 center frequency in Hz
+    center_freq: AtomicU64,
+    //Bits of the f64 input sample rate, changes with the PPM correction
+    in_rate: AtomicU64,
+}
+
+impl DemodShared {
+    pub fn new(vfo: Vfo) -> Self {
+        Self {
+            vfo: Mutex::new(vfo),
+            vfo_changed: AtomicBool::new(false),
+            decoders: Mutex::new(Vec::new()),
+            stereo: AtomicBool::new(false),
+            subtone: Mutex::new(None),
+            center_freq: AtomicU64::new(0),
+            in_rate: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_vfo(&self, vfo: Vfo) {
+        if let Ok(mut current) = self.vfo.lock() {
+            *current = vfo;
+        }
+        self.vfo_changed.store(true, Ordering::Release);
+    }
+
+    pub fn vfo(&self) -> Vfo {
+        self.vfo.lock().map(|vfo| vfo.clone()).unwrap_or_default()
+    }
+
+    /// Adds a decoder, replacing one of the same kind
+    pub fn add_decoder(&self, decoder: Box<dyn Decoder>) {
+        if let Ok(mut decoders) = self.decoders.lock() {
+            decoders.retain(|d| d.kind() != decoder.kind());
+            decoders.push(decoder);
+        }
+    }
+
+    pub fn remove_decoder(&self, kind: DecoderKind) {
+        if let Ok(mut decoders) = self.decoders.lock() {
+            decoders.retain(|d| d.kind() != kind);
+        }
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        self.stereo.load(Ordering::Relaxed)
+    }
+
+    pub fn subtone(&self) -> Option<Subtone> {
+        self.subtone.lock().ok().and_then(|subtone| *subtone)
+    }
+
+    /// Center frequency of the capture, the VFO offset is added to it for the decoders
+    pub fn set_center_freq(&self, hz: f64) {
+        self.center_freq.store(hz.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Rate the input samples were actually taken at
+    pub fn set_in_rate(&self, hz: f64) {
+        self.in_rate.store(hz.to_bits(), Ordering::Relaxed);
+    }
+
+    fn rf_freq(&self, vfo: &Vfo) -> f64 {
+        f64::from_bits(self.center_freq.load(Ordering::Relaxed)) + vfo.offset_hz
+    }
+}
+
+/// Runs the demodulator and decoders, outputs interleaved stereo audio at `AUDIO_RATE`
+pub struct DemodBlock {
+    shared: Arc<DemodShared>,
+    demod: Demodulator,
+    in_rate: f64,
+}
+
+impl DemodBlock {
+    /// Create Demod block
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(shared: Arc<DemodShared>, in_rate: f64) -> Block {
+        let demod = Demodulator::new(shared.vfo(), in_rate);
+        Block::new(
+            BlockMetaBuilder::new("Demod").build(),
+            StreamIoBuilder::new()
+                .add_input::<Complex32>("in")
+                .add_output::<f32>("out")
+                .build(),
+            MessageIoBuilder::new().build(),
+            DemodBlock {
+                shared,
+                demod,
+                in_rate,
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl Kernel for DemodBlock {
+    async fn work(
+        &mut self,
+        io: &mut WorkIo,
+        sio: &mut StreamIo,
+        _mio: &mut MessageIo<Self>,
+        _meta: &mut BlockMeta,
+    ) -> Result<()> {
+        if self.shared.vfo_changed.swap(false, Ordering::Acquire) {
+            self.demod.set_vfo(self.shared.vfo());
+        }
+        let in_rate = f64::from_bits(self.shared.in_rate.load(Ordering::Relaxed));
+        if in_rate > 0.0 && in_rate != self.in_rate {
+            self.in_rate = in_rate;
+            self.demod = Demodulator::new(self.demod.vfo().clone(), in_rate);
+        }
+
+        let i = sio.input(0).slice::<Complex32>();
+        let o = sio.output(0).slice::<f32>();
+        let items = i.len();
+
+        let vfo = self.demod.vfo().clone();
+        let volume = vfo.volume;
+        let rf_hz = self.shared.rf_freq(&vfo);
+        let out = self.demod.process(i);
+        self.shared.stereo.store(out.stereo, Ordering::Relaxed);
+        if let Ok(mut subtone) = self.shared.subtone.lock() {
+            *subtone = out.subtone;
+        }
+        if let Ok(mut decoders) = self.shared.decoders.lock() {
+            decoders::feed(&mut decoders, i, self.in_rate, &vfo, Some(rf_hz), &out);
+        }
+
+        //Audio that doesn't fit is dropped so a slow sound card never holds up the flowgraph
+        let frames = (o.len() / 2).min(out.left.len());
+        let volume = if out.open { volume } else { 0.0 };
+        for (idx, frame) in o.chunks_exact_mut(2).take(frames).enumerate() {
+            frame[0] = out.left[idx] * volume;
+            frame[1] = out.right[idx] * volume;
+        }
+
+        if sio.input(0).finished() {
+            io.finished = true;
+        }
+
+        sio.input(0).consume(items);
+        sio.output(0).produce(frames * 2);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    //Multiplex built the way a broadcast stereo encoder does it, with audio on the left only
+    #[test]
+    fn stereo_separates_left_from_right() {
+        let mpx: Vec<f32> = (0..MPX_RATE as usize)
+            .map(|n| {
+                let t = n as f64 / MPX_RATE;
+                let pilot = 2.0 * std::f64::consts::PI * 19_000.0 * t;
+                let l = (2.0 * std::f64::consts::PI * 1_000.0 * t).sin();
+                (0.45 * l + 0.45 * l * (2.0 * pilot).sin() + 0.1 * pilot.sin()) as f32
+            })
+            .collect();
+
+        let mut stereo = FmStereo::new(50.0);
+        let (mut audio, mut left, mut right) = (Vec::new(), Vec::new(), Vec::new());
+        stereo.process(&mpx, &mut audio, &mut left, &mut right);
+        assert!(stereo.locked);
+
+        //Skip the first half second while the loop settles
+        let settled = left.len() / 2;
+        let (l, r) = (rms(&left[settled..]), rms(&right[settled..]));
+        assert!(l > 0.2, "left too quiet: {l}");
+        assert!(
+            r < l / 100.0,
+            "only {:.1} dB of separation",
+            20.0 * (l / r).log10()
+        );
+    }
+    //NFM at 250 kHz with a 1 kHz voice tone and an optional CTCSS tone under it
+    fn nfm(ctcss: Option<f64>, secs: f64) -> Vec<Complex32> {
+        let rate = 250_000.0;
+        let mut phase = 0.0f64;
+        (0..(secs * rate) as usize)
+            .map(|n| {
+                let t = n as f64 / rate;
+                let tone =
+                    ctcss.map_or(0.0, |hz| 0.15 * (2.0 * std::f64::consts::PI * hz * t).sin());
+                let dev =
+                    NFM_DEVIATION * (0.5 * (2.0 * std::f64::consts::PI * 1_000.0 * t).sin() + tone);
+                phase += 2.0 * std::f64::consts::PI * dev / rate;
+                Complex32::from_polar(0.5, phase as f32)
+            })
+            .collect()
+    }
+
+    //Open state of every block handed out
+    fn opens(vfo: &Vfo, input: &[Complex32]) -> Vec<bool> {
+        let mut demod = Demodulator::new(vfo.clone(), 250_000.0);
+        input
+            .chunks(25_000)
+            .map(|block| demod.process(block).open)
+            .collect()
+    }
+
+    #[test]
+    fn tone_squelch_stays_muted_until_the_tone_is_there() {
+        let mut vfo = Vfo::default();
+        vfo.set_mode(DemodMode::Nfm);
+        vfo.tone_squelch = Some(Subtone::Ctcss(67.0));
+
+        assert!(opens(&vfo, &nfm(None, 3.0)).iter().all(|open| !open));
+        assert!(opens(&vfo, &nfm(Some(69.3), 3.0)).iter().all(|open| !open));
+
+        //Muted while the detector is still filling its window, then open for good
+        let with_tone = opens(&vfo, &nfm(Some(67.0), 3.0));
+        assert!(!with_tone[0]);
+        let first = with_tone
+            .iter()
+            .position(|open| *open)
+            .expect("never opened");
+        assert!(with_tone[first..].iter().all(|open| *open));
+
+        vfo.tone_squelch = None;
+        assert!(opens(&vfo, &nfm(None, 1.0)).iter().all(|open| *open));
+    }
+}
@@ -0,0 +1,384 @@
+//! Small DSP building blocks shared by the demodulator and the decoders
+
+use std::f64::consts::PI;
+use std::ops::{Add, Mul};
+
+use futuresdr::num_complex::Complex32;
+
+/// Windowed sinc low-pass taps with unity gain at DC, `cutoff` and `rate` in Hz
+pub fn lowpass_taps(cutoff: f64, rate: f64, num_taps: usize) -> Vec<f32> {
+    let fc = cutoff / rate;
+    let mid = (num_taps - 1) as f64 / 2.0;
+    let mut taps: Vec<f64> = (0..num_taps)
+        .map(|idx| {
+            let n = idx as f64 - mid;
+            let sinc = if n == 0.0 {
+                2.0 * fc
+            } else {
+                (2.0 * PI * fc * n).sin() / (PI * n)
+            };
+            //Blackman window
+            let w = 0.42 - 0.5 * (2.0 * PI * idx as f64 / (num_taps - 1) as f64).cos()
+                + 0.08 * (4.0 * PI * idx as f64 / (num_taps - 1) as f64).cos();
+            sinc * w
+        })
+        .collect();
+
+    let sum: f64 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap /= sum;
+    }
+    taps.into_iter().map(|tap| tap as f32).collect()
+}
+
+/// Band-pass taps with unity gain in the middle of the pass band
+pub fn bandpass_taps(low: f64, high: f64, rate: f64, num_taps: usize) -> Vec<f32> {
+    let half_width = (high - low) / 2.0;
+    let center = (high + low) / 2.0;
+    let mid = (num_taps - 1) as f64 / 2.0;
+    //A low-pass moved up to the middle of the band
+    lowpass_taps(half_width, rate, num_taps)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, tap)| 2.0 * tap * (2.0 * PI * center / rate * (idx as f64 - mid)).cos() as f32)
+        .collect()
+}
+
+/// Odd number of taps giving a transition band about `transition` Hz wide
+pub fn num_taps(rate: f64, transition: f64) -> usize {
+    ((4.0 * rate / transition) as usize).clamp(15, 1023) | 1
+}
+
+/// FIR filter that keeps its own history between calls, optionally decimating
+pub struct Fir<T> {
+    //Reversed to line up with the history, which runs oldest to newest
+    taps: Vec<f32>,
+    //History written twice so a full window is always one contiguous slice
+    history: Vec<T>,
+    pos: usize,
+    decim: usize,
+    count: usize,
+}
+
+impl<T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>> Fir<T> {
+    pub fn new(taps: Vec<f32>, decim: usize) -> Self {
+        let len = taps.len();
+        Self {
+            taps: taps.into_iter().rev().collect(),
+            history: vec![T::default(); len * 2],
+            pos: 0,
+            decim: decim.max(1),
+            count: 0,
+        }
+    }
+
+    /// Feeds one sample, returns an output every `decim` inputs
+    #[inline]
+    pub fn push(&mut self, x: T) -> Option<T> {
+        let len = self.taps.len();
+        self.history[self.pos] = x;
+        self.history[self.pos + len] = x;
+        self.pos = (self.pos + 1) % len;
+
+        self.count += 1;
+        if self.count < self.decim {
+            return None;
+        }
+        self.count = 0;
+
+        let window = &self.history[self.pos..self.pos + len];
+        Some(
+            window
+                .iter()
+                .zip(self.taps.iter())
+                .fold(T::default(), |acc, (x, tap)| acc + *x * *tap),
+        )
+    }
+
+    pub fn process(&mut self, input: &[T], out: &mut Vec<T>) {
+        out.extend(input.iter().filter_map(|x| self.push(*x)));
+    }
+}
+
+/// Numerically controlled oscillator, mixing with it moves a signal by `freq`
+pub struct Nco {
+    phase: f64,
+    step: f64,
+}
+
+impl Nco {
+    pub fn new(freq: f64, rate: f64) -> Self {
+        Self {
+            phase: 0.0,
+            step: 2.0 * PI * freq / rate,
+        }
+    }
+
+    pub fn set_freq(&mut self, freq: f64, rate: f64) {
+        self.step = 2.0 * PI * freq / rate;
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> Complex32 {
+        let out = Complex32::new(self.phase.cos() as f32, self.phase.sin() as f32);
+        self.phase = (self.phase + self.step) % (2.0 * PI);
+        out
+    }
+
+    #[inline]
+    pub fn mix(&mut self, x: Complex32) -> Complex32 {
+        x * self.tick()
+    }
+}
+
+/// Linear interpolating resampler for the fractional ratio left after integer decimation
+pub struct Resampler<T> {
+    //Input samples per output sample
+    step: f64,
+    //Position of the next output relative to the current input block
+    pos: f64,
+    last: T,
+}
+
+impl<T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>> Resampler<T> {
+    pub fn new(in_rate: f64, out_rate: f64) -> Self {
+        Self {
+            step: in_rate / out_rate,
+            pos: 0.0,
+            last: T::default(),
+        }
+    }
+
+    pub fn process(&mut self, input: &[T], out: &mut Vec<T>) {
+        for (idx, x) in input.iter().enumerate() {
+            let idx = idx as f64;
+            while self.pos <= idx {
+                let frac = (self.pos - (idx - 1.0)) as f32;
+                out.push(self.last * (1.0 - frac) + *x * frac);
+                self.pos += self.step;
+            }
+            self.last = *x;
+        }
+        self.pos -= input.len() as f64;
+    }
+}
+
+/// Quadrature FM discriminator, outputs the phase change per sample in radians
+#[derive(Default)]
+pub struct FmDiscriminator {
+    last: Complex32,
+}
+
+impl FmDiscriminator {
+    #[inline]
+    pub fn process(&mut self, x: Complex32) -> f32 {
+        let out = (x * self.last.conj()).arg();
+        self.last = x;
+        out
+    }
+}
+
+/// Single pole low-pass, used for de-emphasis and smoothing
+pub struct OnePole {
+    alpha: f32,
+    y: f32,
+}
+
+impl OnePole {
+    /// Filter with time constant `tau` seconds
+    pub fn new(tau: f64, rate: f64) -> Self {
+        Self {
+            alpha: (1.0 - (-1.0 / (tau * rate)).exp()) as f32,
+            y: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.y += self.alpha * (x - self.y);
+        self.y
+    }
+
+    #[inline]
+    pub fn value(&self) -> f32 {
+        self.y
+    }
+}
+
+/// Removes the DC offset of a real signal
+pub struct DcRemover {
+    avg: OnePole,
+}
+
+impl DcRemover {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            avg: OnePole::new(0.05, rate),
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        x - self.avg.process(x)
+    }
+}
+
+/// Peak following gain control for demodulated audio
+pub struct Agc {
+    peak: f32,
+    attack: f32,
+    decay: f32,
+}
+
+impl Agc {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            peak: 1e-3,
+            attack: (1.0 - (-1.0 / (0.002 * rate)).exp()) as f32,
+            decay: (1.0 - (-1.0 / (0.5 * rate)).exp()) as f32,
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let level = x.abs();
+        let coeff = if level > self.peak {
+            self.attack
+        } else {
+            self.decay
+        };
+        self.peak = (self.peak + coeff * (level - self.peak)).max(1e-6);
+        0.5 * x / self.peak
+    }
+}
+
+/// Second order PLL tracking a real tone, e.g. the FM stereo pilot
+pub struct Pll {
+    phase: f64,
+    //Radians per sample
+    freq: f64,
+    min_freq: f64,
+    max_freq: f64,
+    alpha: f64,
+    beta: f64,
+    //In phase amplitude of the tone, high once locked
+    lock: OnePole,
+}
+
+impl Pll {
+    /// Tracks a tone near `freq` Hz, within `range` Hz of it
+    pub fn new(freq: f64, range: f64, rate: f64) -> Self {
+        let to_rad = 2.0 * PI / rate;
+        //Loop bandwidth of about 20 Hz
+        let bw = 2.0 * PI * 20.0 / rate;
+        Self {
+            phase: 0.0,
+            freq: freq * to_rad,
+            min_freq: (freq - range) * to_rad,
+            max_freq: (freq + range) * to_rad,
+            alpha: 2.0 * bw,
+            beta: bw * bw,
+            lock: OnePole::new(0.05, rate),
+        }
+    }
+
+    /// Feeds one sample and returns the phase of the tracked tone
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f64 {
+        let error = -(x as f64) * self.phase.sin();
+        self.lock.process(x * self.phase.cos() as f32);
+        self.freq = (self.freq + self.beta * error).clamp(self.min_freq, self.max_freq);
+        self.phase = (self.phase + self.freq + self.alpha * error) % (2.0 * PI);
+        self.phase
+    }
+
+    /// Amplitude of the tone in phase with the loop
+    #[inline]
+    pub fn lock_level(&self) -> f32 {
+        2.0 * self.lock.value()
+    }
+}
+
+/// Sliding correlation with one tone over the last `len` samples, a matched filter for FSK
+pub struct ToneCorrelator {
+    nco: Nco,
+    //Mixed samples in the window
+    history: Vec<Complex32>,
+    pos: usize,
+    sum: Complex32,
+}
+
+impl ToneCorrelator {
+    pub fn new(freq: f64, rate: f64, len: usize) -> Self {
+        Self {
+            nco: Nco::new(-freq, rate),
+            history: vec![Complex32::default(); len.max(1)],
+            pos: 0,
+            sum: Complex32::default(),
+        }
+    }
+
+    /// Feeds one sample, returns the magnitude of the correlation
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let mixed = self.nco.mix(Complex32::new(x, 0.0));
+        self.sum += mixed - self.history[self.pos];
+        self.history[self.pos] = mixed;
+        self.pos += 1;
+        if self.pos == self.history.len() {
+            self.pos = 0;
+            //Start over from the stored samples so rounding errors can't build up
+            self.sum = self.history.iter().sum();
+        }
+        self.sum.norm()
+    }
+}
+
+/// Power of a single frequency over blocks of `block` samples
+pub struct Goertzel {
+    coeff: f32,
+    s1: f32,
+    s2: f32,
+    block: usize,
+    count: usize,
+}
+
+impl Goertzel {
+    pub fn new(freq: f64, rate: f64, block: usize) -> Self {
+        Self {
+            coeff: (2.0 * (2.0 * PI * freq / rate).cos()) as f32,
+            s1: 0.0,
+            s2: 0.0,
+            block,
+            count: 0,
+        }
+    }
+
+    /// Feeds one sample, returns the normalised power once a block is complete
+    #[inline]
+    pub fn push(&mut self, x: f32) -> Option<f32> {
+        let s0 = x + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+        self.count += 1;
+        if self.count < self.block {
+            return None;
+        }
+
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        self.count = 0;
+        Some(power / (self.block * self.block) as f32)
+    }
+}
+
+/// Goertzel power of `freq` over the whole of `samples`
+pub fn tone_power(samples: &[f32], freq: f64, rate: f64) -> f32 {
+    let mut goertzel = Goertzel::new(freq, rate, samples.len());
+    samples
+        .iter()
+        .filter_map(|x| goertzel.push(*x))
+        .last()
+        .unwrap_or(0.0)
+}
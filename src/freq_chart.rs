@@ -1,12 +1,18 @@
+use std::io::Write;
+use std::path::Path;
+
 use iced::{Element, Length};
 use plotters::{coord::Shift, prelude::*};
 use plotters_backend::DrawingBackend;
 use plotters_iced::{plotters_backend, Chart, ChartBuilder, ChartWidget, DrawingArea};
+use rustic_sdr::FrameMeta;
 
 pub struct FreqChart {
     pub vals: Vec<f32>,
     pub fft_max: f32,
     pub fft_min: f32,
+    //Describes vals, None until the first frame arrives
+    pub meta: Option<FrameMeta>,
 }
 
 impl FreqChart {
@@ -15,7 +21,28 @@ impl FreqChart {
             vals: vec![0.0; super::FFT_AMMOUNT],
             fft_max: 90f32,
             fft_min: 0f32,
+            meta: None,
+        }
+    }
+
+    pub fn set_frame(&mut self, vals: &[f32], meta: FrameMeta) {
+        self.vals.clear();
+        self.vals.extend_from_slice(vals);
+        self.meta = Some(meta);
+    }
+
+    /// Saves the current trace as `frequency_hz,db` rows
+    pub fn save_csv(&self, path: &Path) -> std::io::Result<()> {
+        let Some(meta) = self.meta.as_ref() else {
+            return Err(std::io::Error::other("No spectrum to save yet"));
+        };
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "frequency_hz,db")?;
+        for (idx, val) in self.vals.iter().enumerate() {
+            writeln!(file, "{:.1},{:.2}", meta.bin_freq(idx), val)?;
         }
+        file.flush()
     }
 
     pub fn view(&self) -> Element<super::Message> {
@@ -36,15 +63,42 @@ impl Chart<super::Message> for FreqChart {
         draw_chart(
             ChartBuilder::on(&root),
             &self.vals,
+            self.meta.as_ref(),
             self.fft_max,
             self.fft_min,
         );
     }
 }
 
-fn draw_chart<DB: DrawingBackend>(mut chart: ChartBuilder<DB>, vals: &[f32], max: f32, min: f32) {
+fn draw_chart<DB: DrawingBackend>(
+    mut chart: ChartBuilder<DB>,
+    vals: &[f32],
+    meta: Option<&FrameMeta>,
+    max: f32,
+    min: f32,
+) {
+    //X axis in MHz once the frame says where it was taken, bins until then
+    let bin_x = |idx: usize| match meta {
+        Some(meta) => (meta.bin_freq(idx) / 1_000_000.0) as f32,
+        None => idx as f32,
+    };
     let mut chart = chart
-        .build_cartesian_2d(0f32..super::FFT_AMMOUNT as f32, min..max)
+        .x_label_area_size(20)
+        .build_cartesian_2d(bin_x(0)..bin_x(vals.len().max(1) - 1), min..max)
+        .unwrap();
+
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .disable_y_axis()
+        .x_labels(10)
+        .x_label_formatter(&|x| match meta {
+            Some(_) => format!("{:.3}", x),
+            None => format!("{:.0}", x),
+        })
+        .x_label_style(("sans-serif", 12).into_font().color(&WHITE))
+        .axis_style(WHITE.mix(0.5))
+        .draw()
         .unwrap();
 
     // this looks better but takes alot more time to compute
@@ -63,9 +117,7 @@ fn draw_chart<DB: DrawingBackend>(mut chart: ChartBuilder<DB>, vals: &[f32], max
 
     chart
         .draw_series(LineSeries::new(
-            (0..super::FFT_AMMOUNT)
-                .map(|x| x as f32)
-                .map(|x| (x, vals[x as usize])),
+            vals.iter().enumerate().map(|(idx, val)| (bin_x(idx), *val)),
             &full_palette::ORANGE,
         ))
         .unwrap();
@@ -0,0 +1,124 @@
+use futuresdr::num_complex::Complex32;
+
+//Both trackers follow slow drift, too fast and they start eating real signals
+const DC_ALPHA: f32 = 0.000_1;
+const IQ_ALPHA: f32 = 0.000_01;
+
+/// Removes the DC offset by subtracting a slow running average of the signal
+#[derive(Default)]
+pub struct DcBlocker {
+    dc: Complex32,
+}
+
+impl DcBlocker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, sample: Complex32) -> Complex32 {
+        self.dc += (sample - self.dc) * DC_ALPHA;
+        sample - self.dc
+    }
+}
+
+/// Blind IQ imbalance correction, Q is decorrelated from I to fix the phase
+/// error and then scaled to the power of I to fix the gain error
+#[derive(Default)]
+pub struct IqBalancer {
+    ii: f32,
+    iq: f32,
+    qq: f32,
+}
+
+impl IqBalancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, sample: Complex32) -> Complex32 {
+        self.ii += (sample.re * sample.re - self.ii) * IQ_ALPHA;
+        self.iq += (sample.re * sample.im - self.iq) * IQ_ALPHA;
+
+        let phase = if self.ii > f32::EPSILON {
+            self.iq / self.ii
+        } else {
+            0.0
+        };
+        let q = sample.im - phase * sample.re;
+
+        self.qq += (q * q - self.qq) * IQ_ALPHA;
+        let gain = if self.qq > f32::EPSILON {
+            (self.ii / self.qq).sqrt()
+        } else {
+            1.0
+        };
+
+        Complex32::new(sample.re, q * gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 250_000.0;
+    const TONE_HZ: f64 = 10_000.0;
+
+    //Tone at TONE_HZ with Q off by `gain` and `phase_deg` and a DC offset added
+    fn tone(len: usize, gain: f64, phase_deg: f64, dc: Complex32) -> Vec<Complex32> {
+        (0..len)
+            .map(|n| {
+                let phase = 2.0 * std::f64::consts::PI * TONE_HZ * n as f64 / RATE;
+                let q = gain * (phase + phase_deg.to_radians()).sin();
+                Complex32::new(0.5 * phase.cos() as f32, 0.5 * q as f32) + dc
+            })
+            .collect()
+    }
+
+    //Power of the tone at `hz` over `samples`
+    fn power_at(samples: &[Complex32], hz: f64) -> f32 {
+        let sum: Complex32 = samples
+            .iter()
+            .enumerate()
+            .map(|(n, x)| {
+                let phase = -2.0 * std::f64::consts::PI * hz * n as f64 / RATE;
+                x * Complex32::from_polar(1.0, phase as f32)
+            })
+            .sum();
+        (sum / samples.len() as f32).norm_sqr()
+    }
+
+    #[test]
+    fn dc_offset_is_removed() {
+        let dc = Complex32::new(0.2, -0.1);
+        let mut blocker = DcBlocker::new();
+        let out: Vec<Complex32> = tone(RATE as usize, 1.0, 0.0, dc)
+            .into_iter()
+            .map(|x| blocker.process(x))
+            .collect();
+
+        //Whole cycles of the tone so only the offset is left in the mean
+        let settled = &out[out.len() - 50_000..];
+        let mean = settled.iter().sum::<Complex32>() / settled.len() as f32;
+        assert!(mean.norm() < 0.005, "{} left of the offset", mean);
+        assert!((power_at(settled, TONE_HZ).sqrt() - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn image_is_suppressed() {
+        let input = tone(2_000_000, 1.2, 5.0, Complex32::new(0.0, 0.0));
+        let image_db = |samples: &[Complex32]| {
+            10.0 * (power_at(samples, -TONE_HZ) / power_at(samples, TONE_HZ)).log10()
+        };
+        let before = image_db(&input[..50_000]);
+        assert!(
+            before > -25.0,
+            "image only {before:.1} dB down to start with"
+        );
+
+        let mut balancer = IqBalancer::new();
+        let out: Vec<Complex32> = input.iter().map(|x| balancer.process(*x)).collect();
+        let after = image_db(&out[out.len() - 50_000..]);
+        assert!(after < -50.0, "image still {after:.1} dB down");
+    }
+}
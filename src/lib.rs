@@ -0,0 +1,46 @@
+//! Receiver engine behind RusticSDR.
+//!
+//! Devices are listed with [`sdr_device::get_devices`] and opened with [`Sdr::new`], which
+//! starts a flowgraph feeding the averaged spectrum preview, the baseband recorder and the
+//! demodulator with its [`decoders`].
+//! Tuning, gain, device settings and recording are all controlled through [`Sdr`].
+//!
+//! ```no_run
+//! let devices = rustic_sdr::sdr_device::get_devices()?;
+//! let mut sdr = rustic_sdr::Sdr::new(
+//!     &devices[0],
+//!     rustic_sdr::Freq::new(100_000_000.0),
+//!     rustic_sdr::SampleRates::S2048m.get_freq(),
+//!     20.0,
+//!     10,
+//!     rustic_sdr::Tuning::default(),
+//!     &rustic_sdr::DeviceSettings::default(),
+//! )?;
+//! if let Some(frame) = sdr.get_preview_smaple() {
+//!     println!("{} bins", frame.data.len());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod baseband_sink;
+pub mod decoders;
+pub mod demod;
+pub mod dsp;
+pub mod iq_correction;
+pub mod sdr;
+pub mod sdr_device;
+pub mod spectrum_log;
+pub mod subtone;
+pub mod tail_sink;
+pub mod waterfall_ring;
+
+pub use baseband_sink::{BaseBandFormat, BaseBandSpec};
+pub use decoders::{Decoder, DecoderKind};
+pub use demod::{DemodMode, Vfo};
+pub use sdr::{Freq, FreqUnits, SampleRates, Sdr, SdrError, Tuning};
+pub use sdr_device::DeviceSettings;
+pub use spectrum_log::LogFormat;
+pub use tail_sink::FrameMeta;
+
+/// Number of bins in each spectrum preview frame
+pub const FFT_AMMOUNT: usize = 4096;
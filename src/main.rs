@@ -1,4 +1,6 @@
-use baseband_sink::BaseBandSpec;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
 use iced::theme::Palette;
 use iced::widget::{
     button, column, container, pick_list, radio, row, slider, text, text_input, toggler,
@@ -6,14 +8,24 @@ use iced::widget::{
 use iced::{executor, Background, Color, Padding};
 use iced::{Application, Command, Element, Length, Settings, Subscription, Theme};
 
-mod baseband_sink;
-mod sdr_device;
-mod tail_sink;
+mod api;
 
-mod sdr;
 use iced_aw::menu::{self, Item, Menu, StyleSheet};
 use iced_aw::{menu_bar, menu_items};
-use sdr::*;
+use rustic_sdr::baseband_sink::{self, BaseBandSpec};
+use rustic_sdr::decoders::apt::AptImage;
+use rustic_sdr::decoders::digi::DigiMode;
+use rustic_sdr::decoders::net::DEFAULT_FEED_HOST;
+use rustic_sdr::decoders::{self, rds, DecoderKind, DecoderState};
+use rustic_sdr::demod::{DemodMode, Vfo};
+use rustic_sdr::sdr::*;
+use rustic_sdr::sdr_device;
+use rustic_sdr::spectrum_log::{self, LogFormat};
+use rustic_sdr::subtone::Subtone;
+use rustic_sdr::FFT_AMMOUNT;
+
+mod decoder_view;
+use decoder_view::{AptPreview, DecoderSettings};
 
 mod freq_chart;
 use freq_chart::*;
@@ -21,17 +33,24 @@ use freq_chart::*;
 mod waterfall;
 
 mod utills;
+use api::{ApiCommand, ApiHandle, ApiRequest, ApiStatus};
+use sdr_device::{DeviceSettings, DriverSetting, SettingOption};
 use utills::*;
-use waterfall::{Pallet, WaterFall};
+use waterfall::{Marker, Pallet, WaterFall};
 
-const FFT_AMMOUNT: usize = 4096;
 const STARTING_FREQ_IN_HZ: f64 = 100_000_000.0;
 const UPS: u64 = 60;
+const DEVICE_POLL_SECS: u64 = 2;
+const DEFAULT_API_ADDR: &str = "127.0.0.1:8080";
+//Gain slider min, max and step in dB until a device reports its own
+const DEFAULT_GAIN_RANGE: (f64, f64, f64) = (0.0, 50.0, 0.5);
 
 struct RustcSdrSate {
     sdr_running: ToggleOption,
     selected_sdr: String,
     avalibale_sdrs: Vec<String>,
+    //A device list is being made in the background
+    polling_devices: bool,
     recording: ToggleOption,
     sdr: Option<Sdr>,
 
@@ -41,12 +60,56 @@ struct RustcSdrSate {
     center_freq: String,
     freq_unit: FreqUnits,
     gain: f64,
+    agc: bool,
+    gain_stages: Vec<(String, f64)>,
+    tuning: Tuning,
+    ppm: String,
+    freq_offset: String,
+    device_settings: DeviceSettings,
+    driver_settings: Vec<DriverSetting>,
+    //Driver settings of each device by label, asking a Soapy driver means opening the device
+    driver_settings_found: HashMap<String, Vec<DriverSetting>>,
+    show_device_settings: bool,
+    dc_block: bool,
+    iq_correction: bool,
     sammple_rate_val: Freq,
     sammple_rate: SampleRates,
 
     chart: FreqChart,
 
     waterfall: WaterFall,
+
+    vfo: Vfo,
+    vfo_offset: String,
+    stereo: bool,
+    //CTCSS tone or DCS code heard on the NFM channel
+    subtone: Option<Subtone>,
+    //Enabled decoders, they are recreated whenever the SDR is started
+    decoders: Vec<(DecoderKind, DecoderState)>,
+    decoder_settings: DecoderSettings,
+    apt_preview: AptPreview,
+
+    spectrum_log: bool,
+    spectrum_log_every: usize,
+    spectrum_log_format: LogFormat,
+    //File the last export or log went to, shown in the export menu
+    last_export: Option<String>,
+
+    status: Option<String>,
+
+    api: Option<ApiHandle>,
+    record_duration: f32,
+    //No window so the chart and waterfall don't need updating
+    headless: bool,
+    //Where the ADS-B and APRS feeds listen
+    feed_host: IpAddr,
+}
+
+#[derive(Default)]
+struct AppFlags {
+    api: Option<ApiHandle>,
+    headless: bool,
+    feed_host: Option<IpAddr>,
 }
 
 #[derive(Debug, Clone)]
@@ -58,7 +121,20 @@ pub enum Message {
     ToggleSdr(bool),
     SelectSdr(String),
     RefreshSdrs,
+    PollDevices,
+    DevicesFound(Result<Vec<String>, String>),
     ChangeGain(f64),
+    ChangeGainStage(String, f64),
+    ToggleAgc(bool),
+    PpmChanged(String),
+    FreqOffsetChanged(String),
+    ToggleDeviceSettings,
+    ToggleDcBlock(bool),
+    ToggleIqCorrection(bool),
+    SelectAntenna(String),
+    ChangeBandwidth(f64),
+    DriverSetting(String, SettingOption),
+    DriverSettingsFound(String, Vec<DriverSetting>),
     SammpleRate(SampleRates),
     FftMaxChanged(f32),
     FftMinChanged(f32),
@@ -66,58 +142,393 @@ pub enum Message {
     FftAvgChanged(usize),
     FftRateChanged(usize),
     ColorPallet(Pallet),
+    WaterfallUtc(bool),
+    SaveWaterfall,
+    WaterfallSaved(Result<String, String>),
+    SaveSpectrum,
+    ToggleSpectrumLog(bool),
+    SpectrumLogEvery(usize),
+    SpectrumLogFormat(LogFormat),
+    VfoMode(DemodMode),
+    VfoOffsetChanged(String),
+    VfoVolume(f32),
+    VfoToneSquelch(Option<Subtone>),
+    ToggleDecoder(DecoderKind, bool),
+    AisForward(String),
+    ApplyAisForward,
+    AisForwardResolved(String, Option<SocketAddr>),
+    PagerJsonLog(bool),
+    IsmForward(String),
+    ApplyIsmForward,
+    IsmForwardResolved(String, Option<SocketAddr>),
+    IsmJsonLog(bool),
+    SaveAptImage,
+    AptImageSaved(Result<String, String>),
+    ClearAptImage,
+    DigiMode(DigiMode),
+    DigiBaud(f64),
+    DigiShift(f64),
+    DigiReverse(bool),
+    DigiTune(f64),
+    ClearDigiText,
+    DismissStatus,
 }
 
-fn get_sdr_names() -> Vec<String> {
+fn get_sdr_names() -> Result<Vec<String>, SdrError> {
     let mut avalibale_sdrs: Vec<String> = Vec::new();
-    for (idx, dev) in sdr_device::get_devices().unwrap().iter().enumerate() {
+    for (idx, dev) in sdr_device::get_devices()?.iter().enumerate() {
         avalibale_sdrs.push(idx.to_string() + " | " + &sdr_device::get_name(dev));
     }
 
-    avalibale_sdrs
+    Ok(avalibale_sdrs)
+}
+
+/// Lists the devices on their own thread, Soapy can take seconds to search network drivers
+fn find_sdr_names() -> impl std::future::Future<Output = Result<Vec<String>, String>> {
+    let (tx, rx) = iced::futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(get_sdr_names().map_err(|e| e.to_string()));
+    });
+    async move {
+        rx.await
+            .unwrap_or_else(|_| Err("Listing the devices failed".into()))
+    }
+}
+
+/// Settings the driver of `selected_sdr` accepts, asked on its own thread as opening a Soapy
+/// device to ask can block for seconds
+fn find_driver_settings(
+    selected_sdr: String,
+) -> impl std::future::Future<Output = Vec<DriverSetting>> {
+    let (tx, rx) = iced::futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(
+            selected_device(&selected_sdr)
+                .map(|args| sdr_device::driver_settings(&args))
+                .unwrap_or_default(),
+        );
+    });
+    async move { rx.await.unwrap_or_default() }
+}
+
+fn selected_device(selected_sdr: &str) -> Result<futuresdr::seify::Args, SdrError> {
+    let dev_num = selected_sdr
+        .split(" | ")
+        .next()
+        .and_then(|num| num.parse::<usize>().ok())
+        .ok_or(SdrError::DeviceNotFound)?;
+
+    sdr_device::get_devices()?
+        .into_iter()
+        .nth(dev_num)
+        .ok_or(SdrError::DeviceNotFound)
+}
+
+fn open_sdr(
+    selected_sdr: &str,
+    center_freq: Freq,
+    sample_rate: Freq,
+    gain: f64,
+    fft_avg_num: usize,
+    tuning: Tuning,
+    settings: &DeviceSettings,
+) -> Result<Sdr, SdrError> {
+    let dev = selected_device(selected_sdr)?;
+
+    Sdr::new(
+        &dev,
+        center_freq,
+        sample_rate,
+        gain,
+        fft_avg_num,
+        tuning,
+        settings,
+    )
+}
+
+fn freq_in_unit(freq: &Freq, unit: FreqUnits) -> f64 {
+    match unit {
+        FreqUnits::Hz => freq.get_hz(),
+        FreqUnits::KHz => freq.get_khz(),
+        FreqUnits::MHz => freq.get_mhz(),
+        FreqUnits::GHz => freq.get_ghz(),
+    }
+}
+
+fn device_label(sdr_name: &str) -> &str {
+    sdr_name.split(" | ").nth(1).unwrap_or(sdr_name)
+}
+
+impl RustcSdrSate {
+    fn stop_recording(&mut self) {
+        if let Some(dev) = self.sdr.as_mut() {
+            if self.recording.toggled {
+                if let Err(e) = dev.toggle_recording(BaseBandSpec {
+                    format: baseband_sink::BaseBandFormat::i16,
+                    sample_rate: self.sammple_rate_val.get_hz() as u32,
+                }) {
+                    self.status = Some(e.to_string());
+                }
+                self.waterfall.add_marker(Marker::RecordingStop);
+            }
+        }
+        self.reset_recording();
+    }
+
+    fn reset_recording(&mut self) {
+        self.recording.toggled = false;
+        self.recording.label = Some("Recording".into());
+        self.record_duration = 0.0;
+    }
+
+    fn api_status(&self) -> ApiStatus {
+        ApiStatus {
+            running: self.sdr.is_some(),
+            recording: self.recording.toggled,
+            record_duration: self.record_duration,
+            device: self.selected_sdr.clone(),
+            devices: self.avalibale_sdrs.clone(),
+            center_freq: self.center_freq_val.get_hz(),
+            gain: self.gain,
+            sample_rate: self.sammple_rate_val.get_hz(),
+            sample_rates: SampleRates::ALL
+                .iter()
+                .map(|rate| rate.get_freq().get_hz())
+                .collect(),
+            fft_min: self.chart.fft_min,
+            fft_max: self.chart.fft_max,
+            error: self.status.clone(),
+        }
+    }
+
+    /// Runs an API command through the same messages the GUI sends
+    fn handle_api(&mut self, request: ApiRequest) {
+        self.status = None;
+        let running = self.sdr.is_some();
+        let messages = match &request.command {
+            ApiCommand::Start { device } => {
+                let mut messages = Vec::new();
+                if let Some(device) = device {
+                    let name = self.avalibale_sdrs.iter().find(|name| {
+                        *name == device
+                            || device_label(name) == device
+                            || name.split(" | ").next() == Some(device.as_str())
+                    });
+                    match name {
+                        Some(name) if !running => messages.push(Message::SelectSdr(name.clone())),
+                        Some(_) => (),
+                        None => self.status = Some(SdrError::DeviceNotFound.to_string()),
+                    }
+                }
+                if !running && self.status.is_none() {
+                    messages.push(Message::ToggleSdr(true));
+                }
+                messages
+            }
+            ApiCommand::Stop if running => vec![Message::ToggleSdr(false)],
+            ApiCommand::Stop => Vec::new(),
+            ApiCommand::SetFreq(hz) => vec![Message::FreqChanged(
+                freq_in_unit(&Freq::new(*hz), self.freq_unit).to_string(),
+            )],
+            ApiCommand::SetGain(db) => vec![Message::ChangeGain(*db)],
+            ApiCommand::SetSampleRate(hz) => match SampleRates::from_hz(*hz) {
+                Some(_) if running => {
+                    self.status = Some("Stop the SDR before changing the sample rate".to_string());
+                    Vec::new()
+                }
+                Some(rate) => vec![Message::SammpleRate(rate)],
+                None => {
+                    self.status = Some(SdrError::UnsupportedRate(*hz).to_string());
+                    Vec::new()
+                }
+            },
+            ApiCommand::StartRecording => vec![Message::ToggleRecord(true)],
+            ApiCommand::StopRecording => vec![Message::ToggleRecord(false)],
+        };
+
+        for message in messages {
+            let _ = self.update(message);
+        }
+        request.reply(self.api_status());
+    }
+
+    fn set_vfo(&mut self, vfo: Vfo) {
+        if let Some(dev) = self.sdr.as_ref() {
+            dev.set_vfo(vfo.clone());
+        }
+        self.vfo = vfo;
+    }
+
+    /// Creates a fresh decoder for `kind` and hands it to the running SDR
+    fn start_decoder(&mut self, kind: DecoderKind) {
+        let (decoder, state) = kind.create(Some(self.feed_host));
+        self.decoder_settings.apply(&state);
+        if let Some(dev) = self.sdr.as_ref() {
+            dev.add_decoder(decoder);
+        }
+        self.decoders.retain(|(enabled, _)| *enabled != kind);
+        self.decoders.push((kind, state));
+    }
+
+    /// Hands changed decoder settings to every running decoder
+    fn apply_decoder_settings(&self) {
+        for (_, state) in self.decoders.iter() {
+            self.decoder_settings.apply(state);
+        }
+    }
+
+    /// One line summary of the station's RDS, shown next to the frequency entry
+    fn rds_summary(&self) -> Option<String> {
+        let info = self.decoders.iter().find_map(|(_, state)| match state {
+            DecoderState::Rds(info) => info.lock().ok().map(|info| info.clone()),
+            _ => None,
+        })?;
+        if !info.synced && info.pi.is_none() {
+            return Some("RDS: no signal".into());
+        }
+
+        let mut parts = Vec::new();
+        if let Some(ps) = info.ps {
+            parts.push(ps.trim().to_string());
+        }
+        if let Some(pi) = info.pi {
+            parts.push(format!("PI {:04X}", pi));
+        }
+        if let Some(pty) = info.pty {
+            parts.push(rds::pty_name(pty, false).to_string());
+        }
+        if let Some(clock) = info.clock {
+            parts.push(clock.to_string());
+        }
+        if let Some(rt) = info.radiotext.filter(|rt| !rt.is_empty()) {
+            parts.push(rt);
+        }
+        Some(parts.join(" | "))
+    }
+
+    fn set_tuning(&mut self, tuning: Tuning) {
+        if let Some(dev) = self.sdr.as_mut() {
+            if let Err(e) = dev.set_tuning(tuning) {
+                self.status = Some(e.to_string());
+                return;
+            }
+        }
+        self.tuning = tuning;
+    }
+
+    /// Resets the settings that only make sense for the previously selected device, the driver
+    /// is only asked for its settings the first time a device is selected
+    fn device_changed(&mut self) -> Command<Message> {
+        self.device_settings = DeviceSettings::default();
+        let label = device_label(&self.selected_sdr).to_string();
+        if let Some(settings) = self.driver_settings_found.get(&label) {
+            self.driver_settings = settings.clone();
+            return Command::none();
+        }
+
+        self.driver_settings = Vec::new();
+        if self.selected_sdr.is_empty() {
+            return Command::none();
+        }
+        Command::perform(
+            find_driver_settings(self.selected_sdr.clone()),
+            move |settings| Message::DriverSettingsFound(label.clone(), settings),
+        )
+    }
+
+    fn device_lost(&mut self) {
+        //No callbacks into a flowgraph that may be dead or wedged, the baseband sink finishes
+        //the WAV header itself when the flowgraph is torn down
+        if self.recording.toggled {
+            self.waterfall.add_marker(Marker::RecordingStop);
+        }
+        self.reset_recording();
+        if let Some(dev) = self.sdr.take() {
+            //A wedged source can hold up the flowgraph shutdown so keep it off the GUI thread
+            std::thread::spawn(move || drop(dev));
+        }
+        self.sdr_running.toggled = false;
+        self.spectrum_log = false;
+        self.status = Some(format!("Device lost: {}", self.selected_sdr));
+    }
 }
 
 impl Application for RustcSdrSate {
     type Executor = executor::Default;
-    type Flags = ();
+    type Flags = AppFlags;
     type Message = Message;
     type Theme = Theme;
 
-    fn new(_flags: ()) -> (RustcSdrSate, Command<Self::Message>) {
-        let avalibale_sdrs = get_sdr_names();
+    fn new(flags: AppFlags) -> (RustcSdrSate, Command<Self::Message>) {
+        let (avalibale_sdrs, status) = match get_sdr_names() {
+            Ok(names) => (names, None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
 
-        (
-            RustcSdrSate {
-                sdr_running: ToggleOption {
-                    label: Some("SDR Running".into()),
-                    toggled: false,
-                },
-                selected_sdr: avalibale_sdrs
-                    .first()
-                    .unwrap_or(&"".to_string())
-                    .to_string(),
-                avalibale_sdrs,
-                recording: ToggleOption {
-                    label: Some("Recording".into()),
-                    toggled: false,
-                },
-                sdr: None,
-
-                fft_update_rate: UPS,
-                fft_avg_num: 10,
-                center_freq_val: Freq::new(STARTING_FREQ_IN_HZ),
-                center_freq: STARTING_FREQ_IN_HZ.to_string(),
-                freq_unit: FreqUnits::Hz,
-                gain: 0.0,
-                sammple_rate_val: Freq::new(250_000f64),
-                sammple_rate: SampleRates::S250k,
-
-                chart: FreqChart::new(),
-
-                waterfall: WaterFall::new(),
+        let mut state = RustcSdrSate {
+            sdr_running: ToggleOption {
+                label: Some("SDR Running".into()),
+                toggled: false,
             },
-            Command::none(),
-        )
+            selected_sdr: avalibale_sdrs
+                .first()
+                .unwrap_or(&"".to_string())
+                .to_string(),
+            avalibale_sdrs,
+            polling_devices: false,
+            recording: ToggleOption {
+                label: Some("Recording".into()),
+                toggled: false,
+            },
+            sdr: None,
+
+            fft_update_rate: UPS,
+            fft_avg_num: 10,
+            center_freq_val: Freq::new(STARTING_FREQ_IN_HZ),
+            center_freq: STARTING_FREQ_IN_HZ.to_string(),
+            freq_unit: FreqUnits::Hz,
+            gain: 0.0,
+            agc: false,
+            gain_stages: Vec::new(),
+            tuning: Tuning::default(),
+            ppm: "0".into(),
+            freq_offset: "0".into(),
+            device_settings: DeviceSettings::default(),
+            driver_settings: Vec::new(),
+            driver_settings_found: HashMap::new(),
+            show_device_settings: false,
+            dc_block: false,
+            iq_correction: false,
+            sammple_rate_val: Freq::new(250_000f64),
+            sammple_rate: SampleRates::S250k,
+
+            chart: FreqChart::new(),
+
+            waterfall: WaterFall::new(),
+
+            vfo: Vfo::default(),
+            vfo_offset: "0".into(),
+            stereo: false,
+            subtone: None,
+            decoders: Vec::new(),
+            decoder_settings: DecoderSettings::default(),
+            apt_preview: AptPreview::default(),
+
+            spectrum_log: false,
+            spectrum_log_every: 10,
+            spectrum_log_format: LogFormat::Csv,
+            last_export: None,
+
+            status,
+
+            api: flags.api,
+            record_duration: 0.0,
+            headless: flags.headless,
+            feed_host: flags.feed_host.unwrap_or(DEFAULT_FEED_HOST),
+        };
+        let command = state.device_changed();
+
+        (state, command)
     }
 
     fn title(&self) -> String {
@@ -127,6 +538,48 @@ impl Application for RustcSdrSate {
     fn view(&self) -> Element<Message> {
         let menu_tpl_1 = |items| Menu::new(items).max_width(180.0).offset(10.0).spacing(5.0);
         let menu_sub = |items| Menu::new(items).max_width(180.0).offset(0.0).spacing(5.0);
+        let mut gain_items = Vec::new();
+        if let Some(dev) = self.sdr.as_ref() {
+            if dev.supports_agc() {
+                gain_items.push(Item::new(toggler(
+                    Some("AGC".into()),
+                    self.agc,
+                    Message::ToggleAgc,
+                )));
+            }
+            for (stage, (name, val)) in dev.gain_stages().iter().zip(self.gain_stages.iter()) {
+                let (min, max, step) = sdr_device::range_bounds(&stage.range);
+                let stage_name = name.clone();
+                gain_items.push(Item::new(column![
+                    text(format!("{}: {:.1} dB", name, val)),
+                    slider(std::ops::RangeInclusive::new(min, max), *val, move |val| {
+                        Message::ChangeGainStage(stage_name.clone(), val)
+                    })
+                    .step(step)
+                ]));
+            }
+        }
+        if gain_items.is_empty() {
+            gain_items.push(Item::new(text("No gain stages")));
+        }
+        let (gain_min, gain_max, gain_step) = self
+            .sdr
+            .as_ref()
+            .map(|dev| sdr_device::range_bounds(dev.gain_range()))
+            .unwrap_or(DEFAULT_GAIN_RANGE);
+
+        let decoder_items = DecoderKind::ALL
+            .iter()
+            .map(|kind| {
+                let kind = *kind;
+                Item::new(toggler(
+                    Some(kind.to_string()),
+                    self.decoders.iter().any(|(enabled, _)| *enabled == kind),
+                    move |toggle| Message::ToggleDecoder(kind, toggle),
+                ))
+            })
+            .collect();
+
         let mb = menu_bar!((
             text("FFT Settings"),
             menu_tpl_1(menu_items!((row!(
@@ -157,6 +610,10 @@ impl Application for RustcSdrSate {
                     self.chart.fft_min,
                     Message::FftMinChanged
                 )
+            ))(toggler(
+                Some("UTC Time Scale".into()),
+                self.waterfall.utc,
+                Message::WaterfallUtc
             ))(
                 row!(
                     text("Pallet")
@@ -170,35 +627,35 @@ impl Application for RustcSdrSate {
                         radio(
                             "Turbo",
                             Pallet::Turbo,
-                            Some(self.waterfall.pallet),
+                            Some(self.waterfall.pallet()),
                             Message::ColorPallet
                         )
                         .size(15),
                         radio(
                             "Magma",
                             Pallet::Magma,
-                            Some(self.waterfall.pallet),
+                            Some(self.waterfall.pallet()),
                             Message::ColorPallet
                         )
                         .size(15),
                         radio(
                             "Plasma",
                             Pallet::Plasma,
-                            Some(self.waterfall.pallet),
+                            Some(self.waterfall.pallet()),
                             Message::ColorPallet
                         )
                         .size(15),
                         radio(
                             "Spectral",
                             Pallet::Spectral,
-                            Some(self.waterfall.pallet),
+                            Some(self.waterfall.pallet()),
                             Message::ColorPallet
                         )
                         .size(15),
                         radio(
                             "Rainbow",
                             Pallet::Rainbow,
-                            Some(self.waterfall.pallet),
+                            Some(self.waterfall.pallet()),
                             Message::ColorPallet
                         )
                         .size(15),
@@ -207,6 +664,42 @@ impl Application for RustcSdrSate {
                     .padding(2))
                 ))
             )))
+        )(text("Gain"), menu_tpl_1(gain_items))(
+            text("Decoders"),
+            menu_tpl_1(decoder_items)
+        )(
+            text("Export"),
+            menu_tpl_1(menu_items!((button(text("Save Waterfall PNG"))
+                .on_press(Message::SaveWaterfall)
+                .width(Length::Fill))(
+                button(text("Save Spectrum CSV"))
+                    .on_press(Message::SaveSpectrum)
+                    .width(Length::Fill)
+            )(toggler(
+                Some("Spectrum Log".into()),
+                self.spectrum_log,
+                Message::ToggleSpectrumLog
+            ))(
+                row!(
+                    text("Log Every "),
+                    button(text("<")).on_press(Message::SpectrumLogEvery(0)),
+                    container(text(self.spectrum_log_every.to_string())).padding(2),
+                    button(text(">")).on_press(Message::SpectrumLogEvery(1))
+                )
+                .align_items(iced::Alignment::Center)
+            )(pick_list(
+                &LogFormat::ALL[..],
+                Some(self.spectrum_log_format),
+                Message::SpectrumLogFormat
+            ))(
+                text(
+                    self.last_export
+                        .as_ref()
+                        .map(|file| format!("Saved {}", file))
+                        .unwrap_or_default()
+                )
+                .size(12)
+            )))
         ))
         .draw_path(menu::DrawPath::Backdrop)
         .style(|theme: &iced::Theme| {
@@ -247,13 +740,15 @@ impl Application for RustcSdrSate {
                     Message::SelectSdr
                 ),
                 button("Refresh").on_press(Message::RefreshSdrs),
+                button("Device Settings").on_press(Message::ToggleDeviceSettings),
                 row!(
-                    text("Gain: "),
+                    text(format!("Gain: {:.1} dB ", self.gain)),
                     slider(
-                        std::ops::RangeInclusive::new(0.0, 1000.0),
+                        std::ops::RangeInclusive::new(gain_min, gain_max),
                         self.gain,
                         Message::ChangeGain
                     )
+                    .step(gain_step)
                 )
                 .padding(5),
                 pick_list(
@@ -272,13 +767,127 @@ impl Application for RustcSdrSate {
                     &FreqUnits::ALL[..],
                     Some(self.freq_unit),
                     Message::Unit
-                )]
+                )],
+                column![text(self.rds_summary().unwrap_or_default())]
+                    .padding(5)
+                    .width(Length::FillPortion(2))
             )
+            .align_items(iced::Alignment::Center),
+            row!(
+                text("Mode "),
+                pick_list(&DemodMode::ALL[..], Some(self.vfo.mode), Message::VfoMode),
+                text("VFO Offset (kHz) "),
+                text_input("0", &self.vfo_offset)
+                    .on_input(Message::VfoOffsetChanged)
+                    .width(100),
+                text(format!("Volume: {:.0}% ", self.vfo.volume * 100.0)),
+                slider(
+                    std::ops::RangeInclusive::new(0.0, 2.0),
+                    self.vfo.volume,
+                    Message::VfoVolume
+                )
+                .step(0.05)
+                .width(150),
+                text(if self.stereo { "Stereo" } else { "Mono" }).style(if self.stereo {
+                    Color::from_rgb8(80, 250, 123)
+                } else {
+                    Color::from_rgb8(150, 150, 150)
+                }),
+                pick_list(Subtone::all(), self.vfo.tone_squelch, |tone| {
+                    Message::VfoToneSquelch(Some(tone))
+                })
+                .placeholder("Tone squelch off"),
+                button("Off")
+                    .on_press_maybe(self.vfo.tone_squelch.map(|_| Message::VfoToneSquelch(None))),
+                text(
+                    self.subtone
+                        .map(|tone| tone.to_string())
+                        .unwrap_or_default()
+                ),
+            )
+            .spacing(5)
+            .padding(5)
+            .align_items(iced::Alignment::Center)
         ],));
 
         let chart_elements = container(column![self.chart.view(),]);
 
-        column![
+        let mut device_elements = row!(
+            text("PPM "),
+            text_input("0", &self.ppm)
+                .on_input(Message::PpmChanged)
+                .width(80),
+            text("Offset (MHz) "),
+            text_input("0", &self.freq_offset)
+                .on_input(Message::FreqOffsetChanged)
+                .width(120),
+        )
+        .spacing(5)
+        .padding(5)
+        .align_items(iced::Alignment::Center);
+        device_elements = device_elements
+            .push(
+                toggler(
+                    Some("DC Block".into()),
+                    self.dc_block,
+                    Message::ToggleDcBlock,
+                )
+                .width(Length::Shrink),
+            )
+            .push(
+                toggler(
+                    Some("IQ Correction".into()),
+                    self.iq_correction,
+                    Message::ToggleIqCorrection,
+                )
+                .width(Length::Shrink),
+            );
+        if let Some(dev) = self.sdr.as_ref() {
+            if !dev.antennas().is_empty() {
+                device_elements = device_elements.push(text("Antenna ")).push(pick_list(
+                    dev.antennas().to_vec(),
+                    self.device_settings.antenna.clone(),
+                    Message::SelectAntenna,
+                ));
+            }
+            if let Some(range) = dev.bandwidth_range() {
+                let (min, max, step) = sdr_device::range_bounds(range);
+                let bandwidth = self.device_settings.bandwidth.unwrap_or(max);
+                device_elements = device_elements
+                    .push(text(format!("Bandwidth: {:.0} kHz ", bandwidth / 1_000.0)))
+                    .push(
+                        slider(
+                            std::ops::RangeInclusive::new(min, max),
+                            bandwidth,
+                            Message::ChangeBandwidth,
+                        )
+                        .step(step)
+                        .width(150),
+                    );
+            }
+        }
+        for setting in self.driver_settings.iter() {
+            let selected = self
+                .device_settings
+                .driver_args
+                .iter()
+                .find(|(key, _)| *key == setting.key)
+                .and_then(|(_, val)| setting.options.iter().find(|opt| opt.value == *val))
+                .unwrap_or(&setting.options[0]);
+            let key = setting.key.clone();
+            device_elements = device_elements
+                .push(text(format!("{} ", setting.label)))
+                .push(pick_list(
+                    &setting.options[..],
+                    Some(selected.clone()),
+                    move |opt| Message::DriverSetting(key.clone(), opt),
+                ));
+        }
+        if self.sdr.is_some() && !self.driver_settings.is_empty() {
+            device_elements = device_elements.push(text("(driver settings apply on restart)"));
+        }
+
+        let mut content = column![
             menus,
             freq_elements.padding(Padding {
                 top: 10.0,
@@ -286,27 +895,103 @@ impl Application for RustcSdrSate {
                 left: 0.0,
                 right: 0.0
             }),
-            chart_elements,
-            self.waterfall.view()
-        ]
-        .into()
+        ];
+        if self.show_device_settings {
+            content = content.push(device_elements);
+        }
+        for panel in self.decoders.iter().filter_map(|(_, state)| {
+            decoder_view::view(state, &self.decoder_settings, &self.apt_preview)
+        }) {
+            content = content.push(panel);
+        }
+        content = content.push(chart_elements);
+        match decoder_view::side_view(self.decoders.iter().map(|(_, state)| state)) {
+            Some(side) => content = content.push(row![self.waterfall.view(), side]),
+            None => content = content.push(self.waterfall.view()),
+        }
+
+        if let Some(status) = self.status.as_ref() {
+            content = content.push(
+                container(
+                    row!(
+                        text(status)
+                            .style(Color::from_rgb8(255, 85, 85))
+                            .width(Length::Fill),
+                        button("Dismiss").on_press(Message::DismissStatus)
+                    )
+                    .align_items(iced::Alignment::Center),
+                )
+                .padding(5),
+            );
+        }
+
+        content.into()
     }
 
     fn update(&mut self, message: Message) -> Command<Message> {
         match message {
             Message::Tick => {
+                let requests = self
+                    .api
+                    .as_ref()
+                    .map(|api| api.pending())
+                    .unwrap_or_default();
+                for request in requests {
+                    self.handle_api(request);
+                }
+
+                if self.sdr.as_mut().is_some_and(|dev| !dev.is_streaming()) {
+                    self.device_lost();
+                }
+
+                if !self.headless {
+                    for (_, state) in self.decoders.iter() {
+                        if let DecoderState::Apt(image) = state {
+                            if let Ok(image) = image.lock() {
+                                self.apt_preview.update(&image);
+                            }
+                        }
+                    }
+                }
+
                 if let Some(dev) = self.sdr.as_mut() {
-                    if let Ok(sample) = dev.get_preview_smaple() {
-                        for (idx, val) in sample.iter().enumerate() {
-                            self.chart.vals[idx] = *val;
+                    if let Some(e) = dev.take_spectrum_log_error() {
+                        self.status = Some(e);
+                    }
+                    self.spectrum_log = dev.is_spectrum_logging();
+                    self.stereo = dev.is_stereo();
+                    self.subtone = dev.subtone();
+
+                    if let Some(frame) = dev.get_preview_smaple().filter(|frame| frame.new) {
+                        if !self.headless {
+                            self.chart.set_frame(frame.data, frame.meta);
+
+                            self.waterfall.add_line(
+                                frame.data,
+                                &frame.meta,
+                                self.chart.fft_max,
+                                self.chart.fft_min,
+                            );
                         }
 
-                        self.waterfall
-                            .add_line(&sample, self.chart.fft_max, self.chart.fft_min);
+                        if let Some(api) = self.api.as_ref() {
+                            api.publish_spectrum(frame.data);
+                        }
                     }
 
                     if self.recording.toggled {
-                        let secs = dev.get_record_duration().unwrap() as u32;
+                        let secs = match dev.get_record_duration() {
+                            Ok(secs) => {
+                                self.record_duration = secs;
+                                secs as u32
+                            }
+                            Err(e) => {
+                                self.status = Some(e.to_string());
+                                self.recording.toggled = false;
+                                self.recording.label = Some("Recording".into());
+                                return Command::none();
+                            }
+                        };
                         let hours = secs / (60 * 60);
                         let sec_left = secs - (hours * 60 * 60);
                         let time = format!(
@@ -318,37 +1003,39 @@ impl Application for RustcSdrSate {
                         self.recording.label = Some(time);
                     }
                 }
+
+                if let Some(api) = self.api.as_ref() {
+                    api.publish_status(self.api_status());
+                }
             }
             Message::Unit(new_unit) => {
-                let new = match new_unit {
-                    FreqUnits::Hz => self.center_freq_val.get_hz(),
-                    FreqUnits::KHz => self.center_freq_val.get_khz(),
-                    FreqUnits::MHz => self.center_freq_val.get_mhz(),
-                    FreqUnits::GHz => self.center_freq_val.get_ghz(),
-                };
+                let new = freq_in_unit(&self.center_freq_val, new_unit);
                 self.center_freq = new.to_string();
                 self.freq_unit = new_unit;
             }
             Message::ToggleRecord(toggle) => {
                 if let Some(dev) = self.sdr.as_mut() {
-                    if toggle {
-                        dev.toggle_recording(
-                            BaseBandSpec {
-                                format: baseband_sink::BaseBandFormat::i16,
-                                sample_rate: self.sammple_rate_val.get_hz() as u32,
-                            },
-                            &self.center_freq_val,
-                        );
-                        self.recording.toggled = true;
-                    } else {
-                        dev.toggle_recording(
-                            BaseBandSpec {
-                                format: baseband_sink::BaseBandFormat::i16,
-                                sample_rate: self.sammple_rate_val.get_hz() as u32,
-                            },
-                            &self.center_freq_val,
-                        );
-                        self.recording.toggled = false;
+                    if toggle != self.recording.toggled {
+                        match dev.toggle_recording(BaseBandSpec {
+                            format: baseband_sink::BaseBandFormat::i16,
+                            sample_rate: self.sammple_rate_val.get_hz() as u32,
+                        }) {
+                            Ok(recording) => {
+                                self.recording.toggled = recording;
+                                self.waterfall.add_marker(if recording {
+                                    Marker::RecordingStart
+                                } else {
+                                    Marker::RecordingStop
+                                });
+                            }
+                            Err(e) => {
+                                self.status = Some(e.to_string());
+                                self.recording.toggled = false;
+                            }
+                        }
+                        if !self.recording.toggled {
+                            self.recording.label = Some("Recording".into());
+                        }
                     }
                 }
             }
@@ -361,43 +1048,73 @@ impl Application for RustcSdrSate {
                         FreqUnits::GHz => self.center_freq_val.set_ghz(new_freq),
                     }
                     if let Some(dev) = self.sdr.as_mut() {
-                        let _ = dev.set_freq(self.center_freq_val.clone());
+                        self.status = dev
+                            .set_freq(self.center_freq_val.clone())
+                            .err()
+                            .map(|e| e.to_string());
                     }
                 }
                 self.center_freq = new_freq_str;
             }
             Message::ToggleSdr(toggle) => {
-                if let Some(dev) = self.sdr.as_mut() {
-                    if self.recording.toggled {
-                        dev.toggle_recording(
-                            BaseBandSpec {
-                                format: baseband_sink::BaseBandFormat::i16,
-                                sample_rate: self.sammple_rate_val.get_hz() as u32,
-                            },
-                            &self.center_freq_val,
-                        );
-                        self.recording.toggled = false;
-                    }
+                if self.sdr.is_some() {
+                    self.stop_recording();
 
                     self.sdr = None;
                     self.sdr_running.toggled = toggle;
+                    self.spectrum_log = false;
+                    self.stereo = false;
+                    self.subtone = None;
                 } else {
                     if !self.selected_sdr.is_empty() {
-                        let dev_num: usize = self
-                            .selected_sdr
-                            .split(" | ")
-                            .next()
-                            .unwrap()
-                            .parse::<usize>()
-                            .unwrap();
-
-                        self.sdr = Some(Sdr::new(
-                            &sdr_device::get_devices().unwrap()[dev_num],
+                        match open_sdr(
+                            &self.selected_sdr,
                             self.center_freq_val.clone(),
                             self.sammple_rate_val.clone(),
                             self.gain,
                             self.fft_avg_num,
-                        ));
+                            self.tuning,
+                            &DeviceSettings {
+                                audio_output: !self.headless,
+                                ..self.device_settings.clone()
+                            },
+                        ) {
+                            Ok(mut sdr) => {
+                                sdr.set_dc_block(self.dc_block);
+                                sdr.set_iq_correction(self.iq_correction);
+                                sdr.set_vfo(self.vfo.clone());
+                                if sdr.supports_agc() {
+                                    let _ = sdr.set_agc(self.agc);
+                                }
+                                self.gain_stages = sdr
+                                    .gain_stages()
+                                    .iter()
+                                    .map(|stage| {
+                                        let val = sdr
+                                            .get_gain_stage(&stage.name)
+                                            .unwrap_or(sdr_device::range_bounds(&stage.range).0);
+                                        (stage.name.clone(), val)
+                                    })
+                                    .collect();
+                                if self.device_settings.antenna.is_none() {
+                                    self.device_settings.antenna = sdr.get_antenna();
+                                }
+                                if self.device_settings.bandwidth.is_none() {
+                                    self.device_settings.bandwidth = sdr.get_bandwidth();
+                                }
+                                self.sdr = Some(sdr);
+                                self.status = None;
+                                let enabled: Vec<DecoderKind> =
+                                    self.decoders.iter().map(|(kind, _)| *kind).collect();
+                                for kind in enabled {
+                                    self.start_decoder(kind);
+                                }
+                            }
+                            Err(e) => {
+                                self.status = Some(e.to_string());
+                                return Command::none();
+                            }
+                        }
                     } else {
                         return Command::none();
                     }
@@ -405,43 +1122,155 @@ impl Application for RustcSdrSate {
                     self.sdr_running.toggled = toggle;
                 }
             }
+            Message::PollDevices => {
+                if !self.polling_devices {
+                    self.polling_devices = true;
+                    return Command::perform(find_sdr_names(), Message::DevicesFound);
+                }
+            }
+            Message::DevicesFound(found) => {
+                self.polling_devices = false;
+                //A running device is only lost once it stops streaming, some drivers leave open
+                //devices out of the list
+                if let Some(names) = found.ok().filter(|names| *names != self.avalibale_sdrs) {
+                    self.avalibale_sdrs = names;
+                    if self.sdr.is_none() && !self.avalibale_sdrs.contains(&self.selected_sdr) {
+                        self.selected_sdr = self
+                            .avalibale_sdrs
+                            .first()
+                            .unwrap_or(&"".to_string())
+                            .to_string();
+                        return self.device_changed();
+                    }
+                }
+            }
             Message::SelectSdr(sdr_name) => {
                 if !self.sdr_running.toggled {
                     self.selected_sdr = sdr_name;
+                    return self.device_changed();
                 }
             }
             Message::RefreshSdrs => {
-                self.avalibale_sdrs = get_sdr_names();
+                //Asked again in case a device was busy the first time
+                self.driver_settings_found.clear();
+                self.avalibale_sdrs = match get_sdr_names() {
+                    Ok(names) => names,
+                    Err(e) => {
+                        self.status = Some(e.to_string());
+                        Vec::new()
+                    }
+                };
                 self.selected_sdr = self
                     .avalibale_sdrs
                     .first()
                     .unwrap_or(&"".to_string())
                     .to_string();
+                return self.device_changed();
             }
             Message::ChangeGain(new_gain) => {
                 self.gain = new_gain;
 
                 if let Some(dev) = self.sdr.as_mut() {
-                    dev.set_gain(self.gain);
+                    match dev.set_gain(self.gain) {
+                        Ok(applied) => {
+                            self.gain = applied;
+                            self.status = None;
+                            self.waterfall.add_marker(Marker::Gain(applied));
+                        }
+                        Err(e) => self.status = Some(e.to_string()),
+                    }
+                }
+            }
+            Message::ChangeGainStage(name, new_gain) => {
+                if let Some(dev) = self.sdr.as_mut() {
+                    match dev.set_gain_stage(&name, new_gain) {
+                        Ok(applied) => {
+                            if let Some(stage) = self
+                                .gain_stages
+                                .iter_mut()
+                                .find(|(stage, _)| *stage == name)
+                            {
+                                stage.1 = applied;
+                            }
+                            self.status = None;
+                            self.waterfall
+                                .add_marker(Marker::GainStage(name.clone(), applied));
+                        }
+                        Err(e) => self.status = Some(e.to_string()),
+                    }
+                }
+            }
+            Message::PpmChanged(new_ppm) => {
+                if let Ok(ppm) = new_ppm.parse::<f64>() {
+                    self.set_tuning(Tuning { ppm, ..self.tuning });
+                }
+                self.ppm = new_ppm;
+            }
+            Message::FreqOffsetChanged(new_offset) => {
+                if let Ok(offset) = new_offset.parse::<f64>() {
+                    self.set_tuning(Tuning {
+                        offset_hz: offset * 1_000_000.0,
+                        ..self.tuning
+                    });
+                }
+                self.freq_offset = new_offset;
+            }
+            Message::ToggleDeviceSettings => {
+                self.show_device_settings = !self.show_device_settings;
+            }
+            Message::ToggleDcBlock(toggle) => {
+                self.dc_block = toggle;
+                if let Some(dev) = self.sdr.as_ref() {
+                    dev.set_dc_block(toggle);
+                }
+            }
+            Message::ToggleIqCorrection(toggle) => {
+                self.iq_correction = toggle;
+                if let Some(dev) = self.sdr.as_ref() {
+                    dev.set_iq_correction(toggle);
+                }
+            }
+            Message::SelectAntenna(antenna) => {
+                if let Some(dev) = self.sdr.as_mut() {
+                    match dev.set_antenna(&antenna) {
+                        Ok(()) => self.device_settings.antenna = Some(antenna),
+                        Err(e) => self.status = Some(e.to_string()),
+                    }
+                }
+            }
+            Message::ChangeBandwidth(bandwidth) => {
+                if let Some(dev) = self.sdr.as_mut() {
+                    match dev.set_bandwidth(bandwidth) {
+                        Ok(applied) => self.device_settings.bandwidth = Some(applied),
+                        Err(e) => self.status = Some(e.to_string()),
+                    }
+                }
+            }
+            Message::DriverSetting(key, opt) => {
+                let driver_args = &mut self.device_settings.driver_args;
+                driver_args.retain(|(arg_key, _)| *arg_key != key);
+                driver_args.push((key, opt.value));
+            }
+            Message::DriverSettingsFound(label, settings) => {
+                if device_label(&self.selected_sdr) == label {
+                    self.driver_settings = settings.clone();
+                }
+                self.driver_settings_found.insert(label, settings);
+            }
+            Message::ToggleAgc(toggle) => {
+                if let Some(dev) = self.sdr.as_mut() {
+                    match dev.set_agc(toggle) {
+                        Ok(()) => {
+                            self.agc = toggle;
+                            self.waterfall.add_marker(Marker::Agc(toggle));
+                        }
+                        Err(e) => self.status = Some(e.to_string()),
+                    }
                 }
             }
             Message::SammpleRate(new_rate) => {
                 if !self.sdr_running.toggled {
-                    let mut new_freq = Freq::new(0f64);
-                    match new_rate {
-                        SampleRates::S250k => new_freq.set_khz(250f64),
-                        SampleRates::S1024m => new_freq.set_mhz(1.024),
-                        SampleRates::S1536m => new_freq.set_mhz(1.536),
-                        SampleRates::S1792m => new_freq.set_mhz(1.792),
-                        SampleRates::S192m => new_freq.set_mhz(1.192),
-                        SampleRates::S2048m => new_freq.set_mhz(2.048),
-                        SampleRates::S216m => new_freq.set_mhz(2.16),
-                        SampleRates::S24m => new_freq.set_mhz(2.4),
-                        SampleRates::S256m => new_freq.set_mhz(2.56),
-                        SampleRates::S288m => new_freq.set_mhz(2.88),
-                        SampleRates::S32m => new_freq.set_mhz(3.2),
-                    }
-                    self.sammple_rate_val = new_freq;
+                    self.sammple_rate_val = new_rate.get_freq();
                     self.sammple_rate = new_rate;
                 }
             }
@@ -452,7 +1281,7 @@ impl Application for RustcSdrSate {
                 self.chart.fft_min = new_min;
             }
             Message::WindowResize((_width, height)) => {
-                self.waterfall.height = height as usize;
+                self.waterfall.set_height(height as usize);
             }
             Message::FftAvgChanged(ammount) => {
                 match ammount {
@@ -493,7 +1322,200 @@ impl Application for RustcSdrSate {
                 }
             }
             Message::ColorPallet(pallet) => {
-                self.waterfall.pallet = pallet;
+                self.waterfall.set_pallet(pallet);
+            }
+            Message::WaterfallUtc(utc) => {
+                self.waterfall.utc = utc;
+            }
+            Message::SaveWaterfall => {
+                let file_name =
+                    spectrum_log::file_name("waterfall", self.center_freq_val.get_hz(), "png");
+                return Command::perform(
+                    self.waterfall.save_png(file_name),
+                    Message::WaterfallSaved,
+                );
+            }
+            Message::SaveSpectrum => {
+                let file_name =
+                    spectrum_log::file_name("spectrum", self.center_freq_val.get_hz(), "csv");
+                match self.chart.save_csv(std::path::Path::new(&file_name)) {
+                    Ok(()) => self.last_export = Some(file_name),
+                    Err(e) => self.status = Some(e.to_string()),
+                }
+            }
+            Message::ToggleSpectrumLog(toggle) => {
+                if let Some(dev) = self.sdr.as_ref() {
+                    let res = if toggle {
+                        dev.start_spectrum_log(self.spectrum_log_format, self.spectrum_log_every)
+                            .map(|file_name| self.last_export = Some(file_name))
+                    } else {
+                        dev.stop_spectrum_log()
+                    };
+                    match res {
+                        Ok(()) => self.spectrum_log = toggle,
+                        Err(e) => self.status = Some(e.to_string()),
+                    }
+                }
+            }
+            Message::SpectrumLogEvery(ammount) => match ammount {
+                //sub
+                0 => {
+                    if self.spectrum_log_every > 1 {
+                        self.spectrum_log_every -= 1;
+                    }
+                }
+                //add
+                1 => {
+                    self.spectrum_log_every += 1;
+                }
+                _ => {
+                    panic!("Invalid SpectrumLogEvery message {:?}", ammount);
+                }
+            },
+            Message::SpectrumLogFormat(format) => {
+                self.spectrum_log_format = format;
+            }
+            Message::VfoMode(mode) => {
+                let mut vfo = self.vfo.clone();
+                vfo.set_mode(mode);
+                self.set_vfo(vfo);
+            }
+            Message::VfoOffsetChanged(new_offset) => {
+                if let Ok(offset) = new_offset.parse::<f64>() {
+                    self.set_vfo(Vfo {
+                        offset_hz: offset * 1_000.0,
+                        ..self.vfo.clone()
+                    });
+                }
+                self.vfo_offset = new_offset;
+            }
+            Message::VfoVolume(volume) => {
+                self.set_vfo(Vfo {
+                    volume,
+                    ..self.vfo.clone()
+                });
+            }
+            Message::VfoToneSquelch(tone_squelch) => {
+                self.set_vfo(Vfo {
+                    tone_squelch,
+                    ..self.vfo.clone()
+                });
+            }
+            Message::ToggleDecoder(kind, toggle) => {
+                if toggle {
+                    if let Some(mode) = kind.mode().filter(|mode| *mode != self.vfo.mode) {
+                        let _ = self.update(Message::VfoMode(mode));
+                    }
+                    if let Some(bandwidth_hz) = kind.bandwidth() {
+                        self.set_vfo(Vfo {
+                            bandwidth_hz,
+                            ..self.vfo.clone()
+                        });
+                    }
+                    if let Some(hz) = kind.center_freq() {
+                        let freq = freq_in_unit(&Freq::new(hz), self.freq_unit);
+                        let _ = self.update(Message::FreqChanged(freq.to_string()));
+                    }
+                    self.start_decoder(kind);
+                } else {
+                    if let Some(dev) = self.sdr.as_ref() {
+                        dev.remove_decoder(kind);
+                    }
+                    self.decoders.retain(|(enabled, _)| *enabled != kind);
+                }
+            }
+            Message::AisForward(addr) => self.decoder_settings.ais_forward.input = addr,
+            Message::ApplyAisForward => {
+                let addr = self.decoder_settings.ais_forward.input.clone();
+                return Command::perform(decoder_view::resolve(addr.clone()), move |resolved| {
+                    Message::AisForwardResolved(addr.clone(), resolved)
+                });
+            }
+            Message::AisForwardResolved(addr, resolved) => {
+                self.decoder_settings.ais_forward.applied = Some((addr, resolved));
+                self.apply_decoder_settings();
+            }
+            Message::PagerJsonLog(toggle) => {
+                let rf_hz = self.center_freq_val.get_hz() + self.vfo.offset_hz;
+                self.decoder_settings.pager_json =
+                    toggle.then(|| spectrum_log::file_name("pocsag", rf_hz, "jsonl"));
+                self.apply_decoder_settings();
+            }
+            Message::IsmForward(addr) => self.decoder_settings.ism_forward.input = addr,
+            Message::ApplyIsmForward => {
+                let addr = self.decoder_settings.ism_forward.input.clone();
+                return Command::perform(decoder_view::resolve(addr.clone()), move |resolved| {
+                    Message::IsmForwardResolved(addr.clone(), resolved)
+                });
+            }
+            Message::IsmForwardResolved(addr, resolved) => {
+                self.decoder_settings.ism_forward.applied = Some((addr, resolved));
+                self.apply_decoder_settings();
+            }
+            Message::IsmJsonLog(toggle) => {
+                let hz = self.center_freq_val.get_hz();
+                self.decoder_settings.ism_json =
+                    toggle.then(|| spectrum_log::file_name("ism", hz, "jsonl"));
+                self.apply_decoder_settings();
+            }
+            Message::SaveAptImage => {
+                //Copied out so the decoder isn't held up while the PNG is encoded
+                let images: Vec<AptImage> = self
+                    .decoders
+                    .iter()
+                    .filter_map(|(_, state)| match state {
+                        DecoderState::Apt(image) => image.lock().ok().map(|image| image.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                return Command::batch(images.into_iter().map(|image| {
+                    Command::perform(decoder_view::save_apt_png(image), Message::AptImageSaved)
+                }));
+            }
+            Message::WaterfallSaved(saved) | Message::AptImageSaved(saved) => match saved {
+                Ok(file_name) => self.last_export = Some(file_name),
+                Err(e) => self.status = Some(e),
+            },
+            Message::ClearAptImage => {
+                for (_, state) in self.decoders.iter() {
+                    if let DecoderState::Apt(image) = state {
+                        if let Ok(mut image) = image.lock() {
+                            image.clear();
+                        }
+                    }
+                }
+            }
+            Message::DigiMode(mode) => {
+                self.decoder_settings.digi.mode = mode;
+                self.apply_decoder_settings();
+            }
+            Message::DigiBaud(baud) => {
+                self.decoder_settings.digi.baud = baud;
+                self.apply_decoder_settings();
+            }
+            Message::DigiShift(shift) => {
+                self.decoder_settings.digi.shift_hz = shift;
+                self.apply_decoder_settings();
+            }
+            Message::DigiReverse(toggle) => {
+                self.decoder_settings.digi.reverse = toggle;
+                self.apply_decoder_settings();
+            }
+            Message::DigiTune(hz) => {
+                self.decoder_settings.digi.tone_hz = hz.round();
+                self.apply_decoder_settings();
+            }
+            Message::ClearDigiText => {
+                for (_, state) in self.decoders.iter() {
+                    if let DecoderState::Digi(digi) = state {
+                        if let Ok(mut digi) = digi.lock() {
+                            digi.text.clear();
+                        }
+                    }
+                }
+            }
+            Message::DismissStatus => {
+                self.status = None;
             }
         }
 
@@ -505,6 +1527,8 @@ impl Application for RustcSdrSate {
             1000 / self.fft_update_rate,
         ))
         .map(|_| Message::Tick);
+        let poll = iced::time::every(iced::time::Duration::from_secs(DEVICE_POLL_SECS))
+            .map(|_| Message::PollDevices);
         let event = iced::event::listen_with(|event, _| match event {
             iced::Event::Window(_, iced::window::Event::Resized { width, height }) => {
                 Some(Message::WindowResize((width, height)))
@@ -512,7 +1536,7 @@ impl Application for RustcSdrSate {
             _ => None,
         });
 
-        Subscription::batch(vec![tick, event])
+        Subscription::batch(vec![tick, poll, event])
     }
 
     fn theme(&self) -> Self::Theme {
@@ -523,6 +1547,135 @@ impl Application for RustcSdrSate {
     }
 }
 
+/// Drives the app without a window, the web UI and API use the same messages as the GUI
+fn run_headless(flags: AppFlags) {
+    let (mut state, _) = RustcSdrSate::new(flags);
+    if let Some(status) = state.status.as_ref() {
+        eprintln!("{}", status);
+    }
+
+    let mut last_poll = std::time::Instant::now();
+    loop {
+        let _ = state.update(Message::Tick);
+        if last_poll.elapsed().as_secs() >= DEVICE_POLL_SECS {
+            //No window to keep responsive so the list is made in line
+            let found = get_sdr_names().map_err(|e| e.to_string());
+            let _ = state.update(Message::DevicesFound(found));
+            last_poll = std::time::Instant::now();
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(
+            1000 / state.fft_update_rate,
+        ));
+    }
+}
+
+/// Runs one decoder over a baseband recording and prints what it found
+fn decode_recording(id: &str, file: &str, offset_hz: f64) {
+    let Some(kind) = DecoderKind::from_id(id) else {
+        let ids: Vec<&str> = DecoderKind::ALL.iter().map(|kind| kind.id()).collect();
+        eprintln!("Unknown decoder {}, expected one of {}", id, ids.join(", "));
+        return;
+    };
+
+    let mut vfo = Vfo {
+        offset_hz,
+        ..Vfo::default()
+    };
+    vfo.set_mode(kind.mode().unwrap_or_default());
+    if let Some(bandwidth_hz) = kind.bandwidth() {
+        vfo.bandwidth_hz = bandwidth_hz;
+    }
+    let (decoder, state) = kind.create(None);
+    let mut decoders = vec![decoder];
+    if let Err(e) = decoders::decode_wav(std::path::Path::new(file), vfo, &mut decoders) {
+        eprintln!("Failed to decode {}: {}", file, e);
+        return;
+    }
+    println!("{}", decoders[0].report());
+
+    //Images are written next to the recording's name
+    if let DecoderState::Apt(image) = state {
+        let Ok(image) = image.lock() else {
+            return;
+        };
+        let png = std::path::Path::new(file).with_extension("png");
+        match image.save_png(&png) {
+            Ok(()) => println!("Saved {}", png.display()),
+            Err(e) => eprintln!("Failed to save {}: {}", png.display(), e),
+        }
+    }
+}
+
 fn main() {
-    let _ = RustcSdrSate::run(Settings::default());
+    let mut flags = AppFlags::default();
+    let mut args = std::env::args().skip(1).peekable();
+    let mut api_addr = None;
+    let mut api_token = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => flags.headless = true,
+            "--decode" => {
+                let (Some(id), Some(file)) = (args.next(), args.next()) else {
+                    eprintln!("Usage: --decode <decoder> <recording.wav> [offset_hz]");
+                    return;
+                };
+                let offset_hz = args
+                    .next_if(|next| !next.starts_with("--"))
+                    .and_then(|offset| offset.parse().ok())
+                    .unwrap_or(0.0);
+                decode_recording(&id, &file, offset_hz);
+                return;
+            }
+            "--api" => {
+                api_addr = Some(
+                    args.next_if(|next| !next.starts_with("--"))
+                        .unwrap_or(DEFAULT_API_ADDR.to_string()),
+                );
+            }
+            "--feed-host" => {
+                let Some(host) = args.next().and_then(|host| host.parse().ok()) else {
+                    eprintln!("Usage: --feed-host <ip>");
+                    return;
+                };
+                flags.feed_host = Some(host);
+            }
+            "--api-token" => {
+                let Some(token) = args.next() else {
+                    eprintln!("Usage: --api-token <token>");
+                    return;
+                };
+                api_token = Some(token);
+            }
+            _ => eprintln!("Unknown argument {}", arg),
+        }
+    }
+
+    if let Some(addr) = api_addr {
+        match addr
+            .parse()
+            .map_err(|e: std::net::AddrParseError| e.into())
+            .and_then(|addr| ApiHandle::start(addr, api_token))
+        {
+            Ok(api) => {
+                println!(
+                    "API on http://{}, spectrum on ws://{}/api/spectrum",
+                    api.addr(),
+                    api.spectrum_addr()
+                );
+                flags.api = Some(api);
+            }
+            Err(e) => eprintln!("Failed to start the API on {}: {}", addr, e),
+        }
+    }
+
+    if flags.headless {
+        if flags.api.is_none() {
+            eprintln!("Headless mode needs the API, start it with --api [addr]");
+            return;
+        }
+        run_headless(flags);
+    } else {
+        let _ = RustcSdrSate::run(Settings::with_flags(flags));
+    }
 }
@@ -1,25 +1,104 @@
-use futuresdr::anyhow::Ok;
+use futuresdr::blocks::audio::AudioSink;
 use futuresdr::blocks::seify::SourceBuilder;
-use futuresdr::blocks::{Apply, ApplyNM, Fft};
+use futuresdr::blocks::{Apply, ApplyNM, Fft, NullSink};
 use futuresdr::macros::connect;
 use futuresdr::num_complex::{Complex32, ComplexFloat};
 use futuresdr::runtime::scheduler::SmolScheduler;
 use futuresdr::runtime::{Flowgraph, FlowgraphHandle, Runtime};
 use std::collections::VecDeque;
-use std::sync::atomic::AtomicUsize;
-use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::baseband_sink::{BaseBandSink, BaseBandSpec};
-use crate::sdr_device::SdrLimits;
-use crate::tail_sink::{TailRing, TailSink};
+use crate::decoders::{Decoder, DecoderKind};
+use crate::demod::{DemodBlock, DemodShared, Vfo, AUDIO_RATE};
+use crate::iq_correction::{DcBlocker, IqBalancer};
+use crate::sdr_device::{
+    range_bounds, snap_to_range, DeviceSettings, GainStage, SdrLimits, SdrType,
+};
+use crate::spectrum_log::{self, LogFormat, LogWriter, SpectrumLog};
+use crate::subtone::Subtone;
+use crate::tail_sink::{FrameInfo, TailFrame, TailReader, TailRing, TailSink};
 use crate::FFT_AMMOUNT;
 
 static RT: LazyLock<Runtime<SmolScheduler>> = LazyLock::new(Runtime::new);
 
+//Frequency component drivers use for PPM correction
+const PPM_COMPONENT: &str = "CORR";
+
+//How long the preview can go without a new frame before the device is considered lost
+const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Everything that can go wrong while opening or controlling a device
+#[derive(Debug)]
 pub enum SdrError {
     FreqNotInRange,
+    DeviceNotFound,
+    DeviceOpen(futuresdr::seify::Error),
+    Device(futuresdr::seify::Error),
+    UnsupportedRate(f64),
+    GainNotInRange(f64),
+    UnknownGainStage(String),
+    UnsupportedBandwidth(f64),
+    Io(std::io::Error),
+    Flowgraph(futuresdr::anyhow::Error),
+    Recording(String),
 }
 
+impl std::fmt::Display for SdrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SdrError::FreqNotInRange => write!(f, "Frequency is out of the device range"),
+            SdrError::DeviceNotFound => write!(f, "Selected device is no longer available"),
+            SdrError::DeviceOpen(e) => write!(f, "Failed to open device: {}", e),
+            SdrError::Device(e) => write!(f, "Device error: {}", e),
+            SdrError::UnsupportedRate(rate) => {
+                write!(f, "Sample rate {} is not supported by the device", rate)
+            }
+            SdrError::GainNotInRange(gain) => {
+                write!(f, "Gain {} dB is out of the device range", gain)
+            }
+            SdrError::UnknownGainStage(name) => {
+                write!(f, "The device has no gain stage named {}", name)
+            }
+            SdrError::UnsupportedBandwidth(bandwidth) => {
+                write!(f, "Bandwidth {} is not supported by the device", bandwidth)
+            }
+            SdrError::Io(e) => write!(f, "I/O error: {}", e),
+            SdrError::Flowgraph(e) => write!(f, "Flowgraph error: {}", e),
+            SdrError::Recording(e) => write!(f, "Unreadable recording: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SdrError {}
+
+impl From<futuresdr::seify::Error> for SdrError {
+    fn from(e: futuresdr::seify::Error) -> Self {
+        SdrError::DeviceOpen(e)
+    }
+}
+
+impl From<std::io::Error> for SdrError {
+    fn from(e: std::io::Error) -> Self {
+        SdrError::Io(e)
+    }
+}
+
+impl From<hound::Error> for SdrError {
+    fn from(e: hound::Error) -> Self {
+        SdrError::Recording(e.to_string())
+    }
+}
+
+impl From<futuresdr::anyhow::Error> for SdrError {
+    fn from(e: futuresdr::anyhow::Error) -> Self {
+        SdrError::Flowgraph(e)
+    }
+}
+
+/// A frequency stored in Hz
 #[derive(Debug, Default, Clone)]
 pub struct Freq(f64);
 
@@ -69,6 +148,38 @@ impl Freq {
     }
 }
 
+/// Maps the RF frequency the user works with to the frequency the hardware is tuned to
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    /// Oscillator error of the device in parts per million
+    pub ppm: f64,
+    /// Added to the RF frequency to get the hardware frequency,
+    /// positive for upconverters and negative for LNBs
+    pub offset_hz: f64,
+}
+
+impl Tuning {
+    /// Hardware frequency for `rf`, applying the PPM correction in software when asked to
+    pub fn hw_freq(&self, rf: &Freq, software_ppm: bool) -> f64 {
+        let freq = rf.get_hz() + self.offset_hz;
+        if software_ppm {
+            freq / (1.0 + self.ppm / 1_000_000.0)
+        } else {
+            freq
+        }
+    }
+
+    /// Rate the device actually samples at when asked for `rate`, the same oscillator clocks
+    /// both so without driver correction the rate is off by the same PPM as the frequency
+    pub fn true_rate(&self, rate: &Freq, software_ppm: bool) -> f64 {
+        if software_ppm {
+            rate.get_hz() * (1.0 + self.ppm / 1_000_000.0)
+        } else {
+            rate.get_hz()
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FreqUnits {
     Hz,
@@ -101,6 +212,7 @@ impl std::fmt::Display for FreqUnits {
     }
 }
 
+/// Sample rates offered for the source
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SampleRates {
     S250k,
@@ -108,6 +220,7 @@ pub enum SampleRates {
     S1536m,
     S1792m,
     S192m,
+    S2m,
     S2048m,
     S216m,
     S24m,
@@ -117,12 +230,13 @@ pub enum SampleRates {
 }
 
 impl SampleRates {
-    pub const ALL: [SampleRates; 11] = [
+    pub const ALL: [SampleRates; 12] = [
         SampleRates::S250k,
         SampleRates::S1024m,
         SampleRates::S1536m,
         SampleRates::S1792m,
         SampleRates::S192m,
+        SampleRates::S2m,
         SampleRates::S2048m,
         SampleRates::S216m,
         SampleRates::S24m,
@@ -130,6 +244,33 @@ impl SampleRates {
         SampleRates::S288m,
         SampleRates::S32m,
     ];
+
+    pub fn get_freq(&self) -> Freq {
+        let mut freq = Freq::new(0f64);
+        match self {
+            SampleRates::S250k => freq.set_khz(250f64),
+            SampleRates::S1024m => freq.set_mhz(1.024),
+            SampleRates::S1536m => freq.set_mhz(1.536),
+            SampleRates::S1792m => freq.set_mhz(1.792),
+            SampleRates::S192m => freq.set_mhz(1.92),
+            SampleRates::S2m => freq.set_mhz(2.0),
+            SampleRates::S2048m => freq.set_mhz(2.048),
+            SampleRates::S216m => freq.set_mhz(2.16),
+            SampleRates::S24m => freq.set_mhz(2.4),
+            SampleRates::S256m => freq.set_mhz(2.56),
+            SampleRates::S288m => freq.set_mhz(2.88),
+            SampleRates::S32m => freq.set_mhz(3.2),
+        }
+
+        freq
+    }
+
+    /// Finds the listed rate matching `hz` to within 1 Hz
+    pub fn from_hz(hz: f64) -> Option<SampleRates> {
+        SampleRates::ALL
+            .into_iter()
+            .find(|rate| (rate.get_freq().get_hz() - hz).abs() < 1.0)
+    }
 }
 
 impl std::fmt::Display for SampleRates {
@@ -143,6 +284,7 @@ impl std::fmt::Display for SampleRates {
                 SampleRates::S1536m => "1.536 msps",
                 SampleRates::S1792m => "1.792 msps",
                 SampleRates::S192m => "1.92 msps",
+                SampleRates::S2m => "2.0 msps",
                 SampleRates::S2048m => "2.048 msps",
                 SampleRates::S216m => "2.16 mmsps",
                 SampleRates::S24m => "2.4 msps",
@@ -154,11 +296,23 @@ impl std::fmt::Display for SampleRates {
     }
 }
 
+/// A running receiver, the flowgraph is stopped when it is dropped
 pub struct Sdr {
     limits: SdrLimits,
-    tail_ring: Arc<TailRing<f32>>,
+    //Kept for controls the source block has no message port for
+    device: SdrType,
+    tuning: Tuning,
+    //False when the driver has no PPM correction and it is folded into the tuned frequency
+    hw_ppm: bool,
+    center_freq: Freq,
+    sample_rate: Freq,
+    tail: TailReader<f32>,
     handle: FlowgraphHandle,
-    fft_avg: Arc<AtomicUsize>,
+    frame_info: Arc<FrameInfo>,
+    dc_block: Arc<AtomicBool>,
+    iq_correction: Arc<AtomicBool>,
+    demod: Arc<DemodShared>,
+    last_frame: (u64, Instant),
     sdr_id: usize,
     freq_port_id: usize,
     gain_port_id: usize,
@@ -170,28 +324,39 @@ pub struct Sdr {
 }
 
 impl Sdr {
+    /// Opens the device described by `sdr_args` and starts streaming from it,
+    /// `center_freq` is the RF frequency and `gain_db` is clamped to the device range
     pub fn new(
         sdr_args: &futuresdr::seify::Args,
         center_freq: Freq,
         sample_rate: Freq,
-        gain_percent: f64,
+        gain_db: f64,
         fft_avg_num: usize,
-    ) -> Self {
+        tuning: Tuning,
+        settings: &DeviceSettings,
+    ) -> Result<Self, SdrError> {
         let mut fg = Flowgraph::new();
 
         //SDR Soruce
-        let (device, limits) = crate::sdr_device::new_sdr(sdr_args).unwrap();
-        let gain = limits
-            .gain_range
-            .closest((gain_percent / 1000.0) * get_max_gain(&limits))
-            .unwrap();
+        let (device, limits) = crate::sdr_device::new_sdr(sdr_args, settings)?;
+        let hw_ppm = apply_ppm(&device, tuning.ppm);
+        let hw_freq = tuning.hw_freq(&center_freq, !hw_ppm);
+        let true_rate = tuning.true_rate(&sample_rate, !hw_ppm);
+        if !limits.freq_range.contains(hw_freq) {
+            return Err(SdrError::FreqNotInRange);
+        }
+        if !limits.sample_rate_range.contains(sample_rate.get_hz()) {
+            return Err(SdrError::UnsupportedRate(sample_rate.get_hz()));
+        }
+        //The GUI can't know the device range before it is opened so clamp instead of failing
+        let gain =
+            snap_to_range(&limits.gain_range, gain_db).ok_or(SdrError::GainNotInRange(gain_db))?;
         let src = SourceBuilder::new()
-            .device(device)
-            .frequency(center_freq.get_hz())
+            .device(device.clone())
+            .frequency(hw_freq)
             .sample_rate(sample_rate.get_hz())
             .gain(gain)
-            .build()
-            .unwrap();
+            .build()?;
         let freq_port_id = src
             .message_input_name_to_id("freq")
             .expect("No freq port found!");
@@ -211,6 +376,30 @@ impl Sdr {
             .message_input_name_to_id("duration")
             .expect("No duration port found!");
 
+        //DC and IQ correction, shared by the preview and the baseband recording
+        let dc_block = Arc::new(AtomicBool::new(false));
+        let iq_correction = Arc::new(AtomicBool::new(false));
+        let dc_block_ref = dc_block.clone();
+        let iq_correction_ref = iq_correction.clone();
+        let mut dc_blocker = DcBlocker::new();
+        let mut iq_balancer = IqBalancer::new();
+        //Retunes are stamped here, the first block after the source, and found again by index
+        let frame_info = Arc::new(FrameInfo::new(center_freq.get_hz(), true_rate, fft_avg_num));
+        let frame_info_ref = frame_info.clone();
+        let mut sample_idx: u64 = 0;
+        let correction = Apply::new(move |x: &Complex32| {
+            frame_info_ref.stamp(sample_idx);
+            sample_idx += 1;
+            let mut sample = *x;
+            if dc_block_ref.load(std::sync::atomic::Ordering::Relaxed) {
+                sample = dc_blocker.process(sample);
+            }
+            if iq_correction_ref.load(std::sync::atomic::Ordering::Relaxed) {
+                sample = iq_balancer.process(sample);
+            }
+            sample
+        });
+
         //Preview window
         let mut window: [f32; FFT_AMMOUNT] = [0.0; FFT_AMMOUNT];
         for (idx, val) in window.iter_mut().enumerate() {
@@ -240,14 +429,22 @@ impl Sdr {
             10.0 * f32::log10(x.powi(2).abs() / (FFT_AMMOUNT as f32 / sample_rate_hz) + 1.0)
         });
 
-        let fft_avg = Arc::new(AtomicUsize::new(fft_avg_num));
-        let fft_avg_ref = fft_avg.clone();
+        let frame_info_ref = frame_info.clone();
         let window: Arc<Mutex<VecDeque<Vec<f32>>>> = Mutex::new(VecDeque::new()).into();
         let window_ref = window.clone();
+        let mut frame: u64 = 0;
+        let mut frame_tuning = frame_info.tuning_at(0);
         let avg_window = ApplyNM::<_, _, _, FFT_AMMOUNT, FFT_AMMOUNT>::new(
             move |in_samples: &[f32], out_samples: &mut [f32]| {
                 let mut window = window_ref.lock().unwrap();
-                let window_size = fft_avg_ref.load(std::sync::atomic::Ordering::Relaxed);
+                //Don't blend the old frequency into frames labeled with the new one
+                let tuning = frame_info_ref.tuning_at(frame * FFT_AMMOUNT as u64);
+                frame += 1;
+                if tuning != frame_tuning {
+                    frame_tuning = tuning;
+                    window.clear();
+                }
+                let window_size = frame_info_ref.fft_avg().max(1);
                 while window.len() >= window_size {
                     let old_val: Option<Vec<f32>> = window.pop_back();
                     drop(old_val);
@@ -257,35 +454,58 @@ impl Sdr {
                 for (idx, sample) in out_samples.iter_mut().enumerate() {
                     *sample = 0.0;
                     for section in window.iter() {
-                        *sample += section[idx] / window_size as f32;
+                        *sample += section[idx] / window.len() as f32;
                     }
                 }
+                frame_info_ref.set_averaged(window.len());
             },
         );
 
-        let tail_ring = Arc::new(TailRing::<f32>::new(FFT_AMMOUNT));
-        let tail_sink = TailSink::new(tail_ring.clone());
+        let (tail_writer, tail) = TailRing::<f32>::new(FFT_AMMOUNT);
+        let tail_sink = TailSink::new(tail_writer, frame_info.clone());
+
+        //Demodulator, the VFO starts off and is set once the device is open
+        let demod = Arc::new(DemodShared::new(Vfo::default()));
+        demod.set_center_freq(center_freq.get_hz());
+        demod.set_in_rate(true_rate);
+        let demod_block = DemodBlock::new(demod.clone(), true_rate);
 
         let mut sdr_id = 0;
         let mut bb_id = 0;
         let con = || -> futuresdr::anyhow::Result<()> {
-            connect!(fg, src > bb_sink);
-            connect!(fg, src > hanning_window > fft > psd > avg_window > tail_sink);
+            connect!(fg, src > correction > bb_sink);
+            connect!(fg, correction > hanning_window > fft > psd > avg_window > tail_sink);
+            if settings.audio_output {
+                let audio_out = AudioSink::new(AUDIO_RATE as u32, 2);
+                connect!(fg, correction > demod_block > audio_out);
+            } else {
+                let audio_out = NullSink::<f32>::new();
+                connect!(fg, correction > demod_block > audio_out);
+            }
 
             sdr_id = src;
             bb_id = bb_sink;
 
             futuresdr::anyhow::Result::Ok(())
         };
-        con().unwrap();
+        con()?;
 
         let (_res, handle) = RT.start_sync(fg);
 
-        Sdr {
+        Ok(Sdr {
             limits,
-            tail_ring,
+            device,
+            tuning,
+            hw_ppm,
+            center_freq,
+            sample_rate,
+            tail,
             handle,
-            fft_avg,
+            frame_info,
+            dc_block,
+            iq_correction,
+            demod,
+            last_frame: (0, Instant::now()),
             sdr_id,
             freq_port_id,
             gain_port_id,
@@ -294,15 +514,32 @@ impl Sdr {
             toggle_port_id,
             spec_port_id,
             duration_port_id,
-        }
+        })
     }
 
+    /// Latest averaged spectrum frame in dB, `FFT_AMMOUNT` bins with DC in the middle,
+    /// check `new` to skip frames that were already returned
     #[inline]
-    pub fn get_preview_smaple(&mut self) -> Result<MutexGuard<Vec<f32>>, ()> {
-        self.tail_ring.get()
+    pub fn get_preview_smaple(&mut self) -> Option<TailFrame<'_, f32>> {
+        self.tail.read()
+    }
+
+    /// Returns false once the source has stopped producing samples, e.g. the device was unplugged
+    pub fn is_streaming(&mut self) -> bool {
+        if self.tail.is_finished() {
+            return false;
+        }
+
+        let frames = self.tail.frames();
+        if frames != self.last_frame.0 {
+            self.last_frame = (frames, Instant::now());
+        }
+
+        self.last_frame.1.elapsed() < STALL_TIMEOUT
     }
 
-    pub fn get_record_duration(&mut self) -> Result<f32, futuresdr::anyhow::Error> {
+    /// Length of the current recording in seconds, 0 when not recording
+    pub fn get_record_duration(&mut self) -> Result<f32, SdrError> {
         let res = futuresdr::async_io::block_on(self.handle.callback(
             self.bb_id,
             self.duration_port_id,
@@ -311,55 +548,249 @@ impl Sdr {
 
         match res {
             futuresdr::runtime::Pmt::F32(val) => Ok(val),
+            futuresdr::runtime::Pmt::String(err) => Err(SdrError::Io(std::io::Error::other(err))),
             _ => Ok(0.0),
         }
     }
 
+    /// Tunes to the RF frequency `freq`, the range is checked against the hardware frequency
     pub fn set_freq(&mut self, freq: Freq) -> Result<(), SdrError> {
-        if !self.limits.freq_range.contains(freq.get_hz()) {
-            return core::result::Result::Err(SdrError::FreqNotInRange);
+        let hw_freq = self.tuning.hw_freq(&freq, !self.hw_ppm);
+        if !self.limits.freq_range.contains(hw_freq) {
+            return Err(SdrError::FreqNotInRange);
         }
 
-        let _ = futuresdr::async_io::block_on(self.handle.callback(
+        futuresdr::async_io::block_on(self.handle.callback(
             self.sdr_id,
             self.freq_port_id,
-            futuresdr::runtime::Pmt::F64(freq.get_hz()),
-        ));
+            futuresdr::runtime::Pmt::F64(hw_freq),
+        ))?;
+        let true_rate = self.tuning.true_rate(&self.sample_rate, !self.hw_ppm);
+        self.frame_info.retune(freq.get_hz(), true_rate);
+        self.demod.set_in_rate(true_rate);
+        self.demod.set_center_freq(freq.get_hz());
+        self.center_freq = freq;
 
-        core::result::Result::Ok(())
+        Ok(())
     }
 
-    pub fn set_gain(&mut self, gain_percent: f64) {
-        let gain = self
-            .limits
-            .gain_range
-            .closest((gain_percent / 1000.0) * get_max_gain(&self.limits))
-            .unwrap();
+    /// Changes the PPM correction and frequency offset and retunes to keep the same RF frequency
+    pub fn set_tuning(&mut self, tuning: Tuning) -> Result<(), SdrError> {
+        let old_tuning = self.tuning;
+        if tuning.ppm != old_tuning.ppm {
+            self.hw_ppm = apply_ppm(&self.device, tuning.ppm);
+        }
+        self.tuning = tuning;
+
+        let res = self.set_freq(self.center_freq.clone());
+        if res.is_err() {
+            self.tuning = old_tuning;
+            self.hw_ppm = apply_ppm(&self.device, old_tuning.ppm);
+        }
+
+        res
+    }
+
+    #[inline]
+    pub fn get_tuning(&self) -> Tuning {
+        self.tuning
+    }
+
+    /// Sets the overall gain in dB and returns the value actually applied
+    pub fn set_gain(&mut self, gain_db: f64) -> Result<f64, SdrError> {
+        let gain = get_gain(&self.limits.gain_range, gain_db)?;
 
-        let _ = futuresdr::async_io::block_on(self.handle.callback(
+        futuresdr::async_io::block_on(self.handle.callback(
             self.sdr_id,
             self.gain_port_id,
             futuresdr::runtime::Pmt::F64(gain),
-        ));
+        ))?;
+
+        Ok(gain)
     }
 
-    pub fn toggle_recording(&mut self, spec: BaseBandSpec, freq: &Freq) {
-        let _ = futuresdr::async_io::block_on(self.handle.callback(
+    #[inline]
+    pub fn gain_range(&self) -> &futuresdr::seify::Range {
+        &self.limits.gain_range
+    }
+
+    #[inline]
+    pub fn gain_stages(&self) -> &[GainStage] {
+        &self.limits.gain_stages
+    }
+
+    /// Current value of a gain stage in dB, None while AGC is in control
+    pub fn get_gain_stage(&self, name: &str) -> Option<f64> {
+        self.device
+            .gain_element(futuresdr::seify::Direction::Rx, 0, name)
+            .ok()
+            .flatten()
+    }
+
+    /// Sets a single gain stage in dB and returns the value actually applied
+    pub fn set_gain_stage(&mut self, name: &str, gain_db: f64) -> Result<f64, SdrError> {
+        let stage = self
+            .limits
+            .gain_stages
+            .iter()
+            .find(|stage| stage.name == name)
+            .ok_or_else(|| SdrError::UnknownGainStage(name.to_owned()))?;
+        let gain = get_gain(&stage.range, gain_db)?;
+
+        self.device
+            .set_gain_element(futuresdr::seify::Direction::Rx, 0, name, gain)
+            .map_err(SdrError::Device)?;
+
+        Ok(gain)
+    }
+
+    #[inline]
+    pub fn supports_agc(&self) -> bool {
+        self.limits.supports_agc
+    }
+
+    pub fn set_agc(&mut self, enabled: bool) -> Result<(), SdrError> {
+        self.device
+            .enable_agc(futuresdr::seify::Direction::Rx, 0, enabled)
+            .map_err(SdrError::Device)
+    }
+
+    #[inline]
+    pub fn antennas(&self) -> &[String] {
+        &self.limits.antennas
+    }
+
+    pub fn get_antenna(&self) -> Option<String> {
+        self.device.antenna(futuresdr::seify::Direction::Rx, 0).ok()
+    }
+
+    pub fn set_antenna(&mut self, name: &str) -> Result<(), SdrError> {
+        self.device
+            .set_antenna(futuresdr::seify::Direction::Rx, 0, name)
+            .map_err(SdrError::Device)
+    }
+
+    #[inline]
+    pub fn bandwidth_range(&self) -> Option<&futuresdr::seify::Range> {
+        self.limits.bandwidth_range.as_ref()
+    }
+
+    pub fn get_bandwidth(&self) -> Option<f64> {
+        self.device
+            .bandwidth(futuresdr::seify::Direction::Rx, 0)
+            .ok()
+    }
+
+    /// Sets the analog filter bandwidth in Hz and returns the value actually applied
+    pub fn set_bandwidth(&mut self, bandwidth: f64) -> Result<f64, SdrError> {
+        let bandwidth = self
+            .limits
+            .bandwidth_range
+            .as_ref()
+            .and_then(|range| snap_to_range(range, bandwidth))
+            .ok_or(SdrError::UnsupportedBandwidth(bandwidth))?;
+
+        self.device
+            .set_bandwidth(futuresdr::seify::Direction::Rx, 0, bandwidth)
+            .map_err(SdrError::Device)?;
+
+        Ok(bandwidth)
+    }
+
+    /// Returns true if a recording was started and false if one was stopped,
+    /// the file is named after the RF frequency rather than the hardware one
+    pub fn toggle_recording(&mut self, spec: BaseBandSpec) -> Result<bool, SdrError> {
+        futuresdr::async_io::block_on(self.handle.callback(
             self.bb_id,
             self.spec_port_id,
             futuresdr::runtime::Pmt::Any(Box::new(spec)),
-        ));
+        ))?;
 
-        let _ = futuresdr::async_io::block_on(self.handle.callback(
+        let res = futuresdr::async_io::block_on(self.handle.callback(
             self.bb_id,
             self.toggle_port_id,
-            futuresdr::runtime::Pmt::F64(freq.get_hz()),
-        ));
+            futuresdr::runtime::Pmt::F64(self.center_freq.get_hz()),
+        ))?;
+
+        match res {
+            futuresdr::runtime::Pmt::Bool(recording) => Ok(recording),
+            futuresdr::runtime::Pmt::String(err) => Err(SdrError::Io(std::io::Error::other(err))),
+            _ => Ok(false),
+        }
     }
 
+    /// Turns the software DC offset removal on or off
+    pub fn set_dc_block(&self, enabled: bool) {
+        self.dc_block
+            .store(enabled, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Turns the automatic IQ gain and phase correction on or off
+    pub fn set_iq_correction(&self, enabled: bool) {
+        self.iq_correction
+            .store(enabled, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Number of FFT frames averaged into each preview frame
     pub fn set_fft_avg(&self, num: usize) {
-        self.fft_avg
-            .store(num, std::sync::atomic::Ordering::Relaxed);
+        self.frame_info.set_fft_avg(num);
+    }
+
+    /// Starts appending every `every`th preview frame to a new file, returns its name
+    pub fn start_spectrum_log(&self, format: LogFormat, every: usize) -> Result<String, SdrError> {
+        let file_name = spectrum_log::file_name(
+            "spectrum_log",
+            self.center_freq.get_hz(),
+            format.extension(),
+        );
+        let log = SpectrumLog::create(std::path::Path::new(&file_name), format)?;
+        self.frame_info.start_log(LogWriter::spawn(log, every)?)?;
+
+        Ok(file_name)
+    }
+
+    pub fn stop_spectrum_log(&self) -> Result<(), SdrError> {
+        Ok(self.frame_info.stop_log()?)
+    }
+
+    #[inline]
+    pub fn is_spectrum_logging(&self) -> bool {
+        self.frame_info.is_logging()
+    }
+
+    /// Why the spectrum log stopped by itself, reported once
+    pub fn take_spectrum_log_error(&self) -> Option<String> {
+        self.frame_info.take_log_error()
+    }
+
+    /// Sets the demodulator, the offset is relative to the center frequency
+    pub fn set_vfo(&self, vfo: Vfo) {
+        self.demod.set_vfo(vfo);
+    }
+
+    pub fn vfo(&self) -> Vfo {
+        self.demod.vfo()
+    }
+
+    /// Starts running `decoder` on the demodulator output, replacing one of the same kind
+    pub fn add_decoder(&self, decoder: Box<dyn Decoder>) {
+        self.demod.add_decoder(decoder);
+    }
+
+    pub fn remove_decoder(&self, kind: DecoderKind) {
+        self.demod.remove_decoder(kind);
+    }
+
+    /// True while a WFM station's stereo pilot is locked
+    #[inline]
+    pub fn is_stereo(&self) -> bool {
+        self.demod.is_stereo()
+    }
+
+    /// CTCSS tone or DCS code found on the NFM channel
+    #[inline]
+    pub fn subtone(&self) -> Option<Subtone> {
+        self.demod.subtone()
     }
 }
 
@@ -369,19 +800,23 @@ impl Drop for Sdr {
     }
 }
 
-fn get_max_gain(limits: &SdrLimits) -> f64 {
-    let mut max_gain: f64 = 0.0;
-    limits.gain_range.items.iter().for_each(|x| match x {
-        futuresdr::seify::RangeItem::Interval(_start, stop) => {
-            max_gain = max_gain.max(*stop);
-        }
-        futuresdr::seify::RangeItem::Value(val) => {
-            max_gain = max_gain.max(*val);
-        }
-        futuresdr::seify::RangeItem::Step(_start, stop, _step) => {
-            max_gain = max_gain.max(*stop);
-        }
-    });
+/// Hands the PPM correction to the driver, returns false if it has to be done in software
+fn apply_ppm(device: &SdrType, ppm: f64) -> bool {
+    let supported = device
+        .frequency_components(futuresdr::seify::Direction::Rx, 0)
+        .is_ok_and(|components| components.iter().any(|c| c == PPM_COMPONENT));
+
+    supported
+        && device
+            .set_component_frequency(futuresdr::seify::Direction::Rx, 0, PPM_COMPONENT, ppm)
+            .is_ok()
+}
+
+fn get_gain(range: &futuresdr::seify::Range, gain_db: f64) -> Result<f64, SdrError> {
+    let (min, max, _step) = range_bounds(range);
+    if gain_db < min || gain_db > max {
+        return Err(SdrError::GainNotInRange(gain_db));
+    }
 
-    max_gain
+    snap_to_range(range, gain_db).ok_or(SdrError::GainNotInRange(gain_db))
 }
@@ -1,15 +1,84 @@
 use std::sync::Arc;
 
-use futuresdr::seify::{Args, Range};
+use futuresdr::seify::{Args, Direction, Range, RangeItem};
 
+use crate::sdr::SdrError;
+
+/// What an opened device reports it can do
 #[derive(Debug)]
 pub struct SdrLimits {
     pub freq_range: Range,
     pub gain_range: Range,
     pub sample_rate_range: Range,
+    pub gain_stages: Vec<GainStage>,
+    pub supports_agc: bool,
+    pub antennas: Vec<String>,
+    //None when the driver has no adjustable analog filter
+    pub bandwidth_range: Option<Range>,
+}
+
+/// Settings applied when the device is opened
+#[derive(Debug, Default, Clone)]
+pub struct DeviceSettings {
+    pub antenna: Option<String>,
+    pub bandwidth: Option<f64>,
+    /// Driver specific (key, value) pairs passed along with the device args
+    pub driver_args: Vec<(String, String)>,
+    /// Play the demodulated audio on the default output device
+    pub audio_output: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingOption {
+    pub value: String,
+    pub label: String,
+}
+
+impl std::fmt::Display for SettingOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// A driver specific option such as a bias-tee, the first option is the driver default
+#[derive(Debug, Clone)]
+pub struct DriverSetting {
+    pub key: String,
+    pub label: String,
+    pub options: Vec<SettingOption>,
+}
+
+//(key, label, options as (value, label)), used when the driver can't be asked
+type SettingTable = &'static [(
+    &'static str,
+    &'static str,
+    &'static [(&'static str, &'static str)],
+)];
+
+const OFF_ON: &[(&str, &str)] = &[("false", "Off"), ("true", "On")];
+
+const RTLSDR_SETTINGS: SettingTable = &[
+    ("biastee", "Bias-T", OFF_ON),
+    (
+        "direct_samp",
+        "Direct Sampling",
+        &[("0", "Off"), ("1", "I Branch"), ("2", "Q Branch")],
+    ),
+    ("offset_tune", "Offset Tuning", OFF_ON),
+];
+
+const AIRSPY_SETTINGS: SettingTable = &[("biastee", "Bias-T", OFF_ON)];
+
+const HACKRF_SETTINGS: SettingTable = &[("bias_tx", "Antenna Power", OFF_ON)];
+
+/// A named gain element of the rx chain (LNA, VGA, MIX, IF, TUNER...)
+#[derive(Debug, Clone)]
+pub struct GainStage {
+    pub name: String,
+    pub range: Range,
 }
 
-type SdrType = futuresdr::seify::Device<
+pub type SdrType = futuresdr::seify::Device<
     Arc<
         dyn futuresdr::seify::DeviceTrait<
                 RxStreamer = Box<(dyn futuresdr::seify::RxStreamer + 'static)>,
@@ -18,42 +87,271 @@ type SdrType = futuresdr::seify::Device<
     >,
 >;
 
-pub fn new_sdr(args: &Args) -> Result<(SdrType, SdrLimits), Box<dyn std::error::Error>> {
-    let device = futuresdr::seify::Device::from_args(args)?;
+/// Opens a device with `settings` applied and queries its limits
+pub fn new_sdr(args: &Args, settings: &DeviceSettings) -> Result<(SdrType, SdrLimits), SdrError> {
+    let mut args = args.clone();
+    for (key, val) in settings.driver_args.iter() {
+        args.set(key.as_str(), val.as_str());
+    }
+    let device = futuresdr::seify::Device::from_args(&args)?;
+
+    if let Some(antenna) = settings.antenna.as_ref() {
+        device
+            .set_antenna(Direction::Rx, 0, antenna)
+            .map_err(SdrError::Device)?;
+    }
+    if let Some(bandwidth) = settings.bandwidth {
+        device
+            .set_bandwidth(Direction::Rx, 0, bandwidth)
+            .map_err(SdrError::Device)?;
+    }
+
+    //Not every driver exposes its stages so treat failures as none
+    let mut gain_stages = Vec::new();
+    for name in device.gain_elements(Direction::Rx, 0).unwrap_or_default() {
+        if let Ok(range) = device.gain_element_range(Direction::Rx, 0, &name) {
+            gain_stages.push(GainStage { name, range });
+        }
+    }
 
     let limits = SdrLimits {
-        freq_range: device
-            .frequency_range(futuresdr::seify::Direction::Rx, 0)
-            .unwrap(),
-        gain_range: device
-            .gain_range(futuresdr::seify::Direction::Rx, 0)
-            .unwrap(),
-        sample_rate_range: device
-            .get_sample_rate_range(futuresdr::seify::Direction::Rx, 0)
-            .unwrap(),
+        freq_range: device.frequency_range(Direction::Rx, 0)?,
+        gain_range: device.gain_range(Direction::Rx, 0)?,
+        sample_rate_range: device.get_sample_rate_range(Direction::Rx, 0)?,
+        gain_stages,
+        supports_agc: device.supports_agc(Direction::Rx, 0).unwrap_or(false),
+        antennas: device.antennas(Direction::Rx, 0).unwrap_or_default(),
+        bandwidth_range: device
+            .get_bandwidth_range(Direction::Rx, 0)
+            .ok()
+            .filter(|range| !range.items.is_empty()),
     };
 
     Ok((device, limits))
 }
 
+/// Lists the devices seify can find
 #[inline]
 pub fn get_devices() -> Result<Vec<Args>, futuresdr::seify::Error> {
     futuresdr::seify::enumerate()
 }
 
+/// Settings the driver of `args` accepts when the device is opened, asked from the driver
+/// where it can tell and otherwise taken from a table of known drivers. Soapy devices are
+/// opened to ask, which can block for seconds.
+pub fn driver_settings(args: &Args) -> Vec<DriverSetting> {
+    let arg = |key: &str| args.get::<String>(key).unwrap_or_default();
+
+    match arg("driver").as_str() {
+        "soapy" => {
+            let driver = arg("soapy_driver");
+            soapy_settings(&driver, &arg("serial"))
+                .unwrap_or_else(|| from_table(known_settings(&driver)))
+        }
+        //The native driver takes the same keys as the Soapy module
+        "rtlsdr" => from_table(RTLSDR_SETTINGS),
+        _ => Vec::new(),
+    }
+}
+
+fn from_table(table: SettingTable) -> Vec<DriverSetting> {
+    table
+        .iter()
+        .map(|(key, label, options)| DriverSetting {
+            key: key.to_string(),
+            label: label.to_string(),
+            options: options_from_table(options),
+        })
+        .collect()
+}
+
+fn options_from_table(options: &[(&str, &str)]) -> Vec<SettingOption> {
+    options
+        .iter()
+        .map(|(value, label)| SettingOption {
+            value: value.to_string(),
+            label: label.to_string(),
+        })
+        .collect()
+}
+
+fn known_settings(driver: &str) -> SettingTable {
+    match driver {
+        "rtlsdr" => RTLSDR_SETTINGS,
+        "airspy" => AIRSPY_SETTINGS,
+        "hackrf" => HACKRF_SETTINGS,
+        _ => &[],
+    }
+}
+
+//Opens the device to read its setting info, None when it can't be opened (e.g. it is already
+//streaming) or reports nothing that fits in a pick list
+fn soapy_settings(driver: &str, serial: &str) -> Option<Vec<DriverSetting>> {
+    let mut soapy_args = format!("driver={}", driver);
+    if !serial.is_empty() {
+        soapy_args += &format!(",serial={}", serial);
+    }
+    let device = soapysdr::Device::new(soapy_args.as_str()).ok()?;
+    let settings: Vec<DriverSetting> = device
+        .setting_info()
+        .ok()?
+        .into_iter()
+        .filter_map(|info| {
+            let label = info.name.clone().unwrap_or_else(|| info.key.clone());
+            let options = match info.data_type {
+                soapysdr::ArgType::Bool => options_from_table(OFF_ON),
+                _ if !info.options.is_empty() => info
+                    .options
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, value)| SettingOption {
+                        value: value.clone(),
+                        label: info.option_names.get(idx).unwrap_or(value).clone(),
+                    })
+                    .collect(),
+                //Free form values have no sensible pick list
+                _ => return None,
+            };
+            let mut setting = DriverSetting {
+                key: info.key,
+                label,
+                options,
+            };
+            //The first option is taken as the default
+            if let Some(idx) = setting
+                .options
+                .iter()
+                .position(|opt| opt.value == info.value)
+            {
+                setting.options[..=idx].rotate_right(1);
+            }
+            Some(setting)
+        })
+        .collect();
+
+    (!settings.is_empty()).then_some(settings)
+}
+
+/// Human readable name for a device from `get_devices`
 pub fn get_name(args: &Args) -> String {
-    let mut name = args.get::<String>("driver").unwrap();
+    let arg = |key: &str| args.get::<String>(key).unwrap_or_default();
+    let mut name = arg("driver");
+    if name.is_empty() {
+        name = "unknown".to_owned();
+    }
 
     match name.as_str() {
-        "rtlsdr" => name += &(" ".to_owned() + &args.get::<String>("index").unwrap()),
+        "rtlsdr" => name += &(" ".to_owned() + &arg("index")),
         "soapy" => {
-            name += &(" ".to_owned()
-                + &args.get::<String>("soapy_driver").unwrap()
-                + " "
-                + &args.get::<String>("label").unwrap())
+            for key in ["soapy_driver", "label"] {
+                let val = arg(key);
+                if !val.is_empty() {
+                    name += &(" ".to_owned() + &val);
+                }
+            }
         }
         _ => (),
     }
 
     name
 }
+
+/// Returns the lowest value, highest value and smallest step of a range
+pub fn range_bounds(range: &Range) -> (f64, f64, f64) {
+    let mut min = f64::MAX;
+    let mut max = f64::MIN;
+    let mut step = f64::MAX;
+    for item in range.items.iter() {
+        let (start, stop) = match item {
+            RangeItem::Interval(start, stop) => (*start, *stop),
+            RangeItem::Value(val) => (*val, *val),
+            RangeItem::Step(start, stop, item_step) => {
+                step = step.min(*item_step);
+                (*start, *stop)
+            }
+        };
+        min = min.min(start);
+        max = max.max(stop);
+    }
+
+    if range.items.is_empty() {
+        return (0.0, 0.0, 1.0);
+    }
+    if step == f64::MAX {
+        //Continuous ranges still need something sensible for a slider
+        step = ((max - min) / 100.0).max(0.1);
+    }
+
+    (min, max, step)
+}
+
+/// Returns the allowed value closest to `val`, snapped to the increments of `RangeItem::Step`
+pub fn snap_to_range(range: &Range, val: f64) -> Option<f64> {
+    range
+        .items
+        .iter()
+        .map(|item| match item {
+            RangeItem::Interval(start, stop) => val.clamp(*start, *stop),
+            RangeItem::Value(item_val) => *item_val,
+            RangeItem::Step(start, stop, step) => {
+                //The last grid point at or below stop, with a little slack for rounding error
+                let last = ((stop - start) / step + 1e-9).floor();
+                let steps = ((val.clamp(*start, *stop) - start) / step).round();
+                start + steps.min(last) * step
+            }
+        })
+        .min_by(|a, b| (a - val).abs().total_cmp(&(b - val).abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_cover_every_item() {
+        let range = Range::new(vec![
+            RangeItem::Value(-5.0),
+            RangeItem::Step(0.0, 49.6, 0.5),
+            RangeItem::Interval(10.0, 60.0),
+        ]);
+        assert_eq!(range_bounds(&range), (-5.0, 60.0, 0.5));
+    }
+
+    #[test]
+    fn bounds_of_continuous_and_empty_ranges() {
+        let range = Range::new(vec![RangeItem::Interval(0.0, 50.0)]);
+        assert_eq!(range_bounds(&range), (0.0, 50.0, 0.5));
+        let range = Range::new(vec![RangeItem::Interval(0.0, 1.0)]);
+        assert_eq!(range_bounds(&range), (0.0, 1.0, 0.1));
+        assert_eq!(range_bounds(&Range::new(Vec::new())), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn snaps_to_steps_and_values() {
+        let range = Range::new(vec![RangeItem::Step(0.0, 49.6, 0.5)]);
+        assert_eq!(snap_to_range(&range, 20.2), Some(20.0));
+        assert_eq!(snap_to_range(&range, 20.3), Some(20.5));
+        assert_eq!(snap_to_range(&range, 100.0), Some(49.5));
+        assert_eq!(snap_to_range(&range, -3.0), Some(0.0));
+
+        let range = Range::new(vec![RangeItem::Step(0.0, 10.0, 4.0)]);
+        assert_eq!(snap_to_range(&range, 10.0), Some(8.0));
+        assert_eq!(snap_to_range(&range, 9.9), Some(8.0));
+
+        let range = Range::new(vec![RangeItem::Value(1.0), RangeItem::Value(7.0)]);
+        assert_eq!(snap_to_range(&range, 3.0), Some(1.0));
+        assert_eq!(snap_to_range(&range, 5.0), Some(7.0));
+    }
+
+    #[test]
+    fn snaps_into_the_nearest_interval() {
+        let range = Range::new(vec![
+            RangeItem::Interval(0.0, 10.0),
+            RangeItem::Interval(20.0, 30.0),
+        ]);
+        assert_eq!(snap_to_range(&range, 5.0), Some(5.0));
+        assert_eq!(snap_to_range(&range, 14.0), Some(10.0));
+        assert_eq!(snap_to_range(&range, 16.0), Some(20.0));
+        assert_eq!(snap_to_range(&Range::new(Vec::new()), 5.0), None);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use chrono::Utc;
+
+use crate::tail_sink::FrameMeta;
+
+/// How logged spectrum frames are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One row per frame: unix time, center freq, sample rate, then the dB values
+    #[default]
+    Csv,
+    /// One record per frame, all little endian: u64 unix time in µs, f64 center freq,
+    /// f64 sample rate, u32 bin count, then that many f32 dB values
+    Binary,
+}
+
+impl LogFormat {
+    pub const ALL: [LogFormat; 2] = [LogFormat::Csv, LogFormat::Binary];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LogFormat::Csv => "csv",
+            LogFormat::Binary => "bin",
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                LogFormat::Csv => "CSV",
+                LogFormat::Binary => "Binary",
+            }
+        )
+    }
+}
+
+//Frames waiting for the writer thread before new ones are dropped
+const QUEUE_FRAMES: usize = 64;
+
+/// Appends spectrum frames to a file
+pub struct SpectrumLog {
+    writer: BufWriter<File>,
+    format: LogFormat,
+}
+
+impl SpectrumLog {
+    pub fn create(path: &Path, format: LogFormat) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if format == LogFormat::Csv {
+            writeln!(writer, "timestamp,center_freq_hz,sample_rate_hz,db")?;
+        }
+
+        Ok(Self { writer, format })
+    }
+
+    pub fn log(&mut self, meta: &FrameMeta, data: &[f32]) -> std::io::Result<()> {
+        let time = meta
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        match self.format {
+            LogFormat::Csv => {
+                write!(
+                    self.writer,
+                    "{:.6},{},{}",
+                    time.as_secs_f64(),
+                    meta.center_freq,
+                    meta.sample_rate
+                )?;
+                for val in data {
+                    write!(self.writer, ",{:.2}", val)?;
+                }
+                writeln!(self.writer)?;
+            }
+            LogFormat::Binary => {
+                self.writer
+                    .write_all(&(time.as_micros() as u64).to_le_bytes())?;
+                self.writer.write_all(&meta.center_freq.to_le_bytes())?;
+                self.writer.write_all(&meta.sample_rate.to_le_bytes())?;
+                self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+                for val in data {
+                    self.writer.write_all(&val.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Hands every Nth frame to a thread that writes the log, so the flowgraph never waits on the disk
+pub struct LogWriter {
+    tx: SyncSender<(FrameMeta, Vec<f32>)>,
+    thread: JoinHandle<std::io::Result<()>>,
+    every: usize,
+    count: usize,
+    //Frames dropped because the writer fell behind
+    dropped: usize,
+}
+
+impl LogWriter {
+    pub fn spawn(mut log: SpectrumLog, every: usize) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel::<(FrameMeta, Vec<f32>)>(QUEUE_FRAMES);
+        let thread = std::thread::Builder::new()
+            .name("spectrum log".into())
+            .spawn(move || {
+                for (meta, data) in rx {
+                    log.log(&meta, &data)?;
+                }
+                log.flush()
+            })?;
+
+        Ok(Self {
+            tx,
+            thread,
+            every: every.max(1),
+            count: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Queues the frame if it is the Nth one since the last queued, false once the writer
+    /// has stopped on an error, which `finish` then returns
+    pub fn push(&mut self, meta: &FrameMeta, data: &[f32]) -> bool {
+        self.count += 1;
+        if self.count < self.every {
+            return true;
+        }
+        self.count = 0;
+
+        match self.tx.try_send((*meta, data.to_vec())) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Writes out what is queued and closes the file
+    pub fn finish(self) -> std::io::Result<()> {
+        drop(self.tx);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(std::io::Error::other("spectrum log writer panicked")))?;
+        if self.dropped > 0 {
+            return Err(std::io::Error::other(format!(
+                "{} frames dropped, the disk could not keep up",
+                self.dropped
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// File name for an export, in the same style as the baseband recordings
+pub fn file_name(kind: &str, freq_hz: f64, extension: &str) -> String {
+    format!(
+        "{}_{}Hz_{}.{}",
+        kind,
+        freq_hz,
+        Utc::now().format("%-H-%-M-%-S_%-m-%-d-%Y"),
+        extension
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_logs_every_nth_frame() {
+        let path = std::env::temp_dir().join(format!("spectrum_log_{}.csv", std::process::id()));
+        let log = SpectrumLog::create(&path, LogFormat::Csv).unwrap();
+        let mut writer = LogWriter::spawn(log, 3).unwrap();
+        for seq in 1..=9 {
+            let meta = FrameMeta {
+                seq,
+                ..Default::default()
+            };
+            assert!(writer.push(&meta, &[seq as f32; 4]));
+        }
+        writer.finish().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<&str> = text.lines().skip(1).collect();
+        assert_eq!(rows.len(), 3);
+        for (row, seq) in rows.iter().zip([3, 6, 9]) {
+            assert!(row.ends_with(&format!(",{seq}.00,{seq}.00,{seq}.00,{seq}.00")));
+        }
+    }
+}
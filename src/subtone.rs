@@ -0,0 +1,294 @@
+//! Sub-audible CTCSS tones and DCS codes sent under the voice on NFM channels.
+
+use std::collections::HashMap;
+
+use crate::dsp::{lowpass_taps, num_taps, tone_power, Fir};
+
+/// The 50 standard CTCSS tones in Hz
+pub const CTCSS_TONES: [f64; 50] = [
+    67.0, 69.3, 71.9, 74.4, 77.0, 79.7, 82.5, 85.4, 88.5, 91.5, 94.8, 97.4, 100.0, 103.5, 107.2,
+    110.9, 114.8, 118.8, 123.0, 127.3, 131.8, 136.5, 141.3, 146.2, 151.4, 156.7, 159.8, 162.2,
+    165.5, 167.9, 171.3, 173.8, 177.3, 179.9, 183.5, 186.2, 189.9, 192.8, 196.6, 199.5, 203.5,
+    206.5, 210.7, 218.1, 225.7, 229.1, 233.6, 241.8, 250.3, 254.1,
+];
+/// The standard DCS codes, written in octal like on the radios
+pub const DCS_CODES: [u16; 104] = [
+    0o023, 0o025, 0o026, 0o031, 0o032, 0o036, 0o043, 0o047, 0o051, 0o053, 0o054, 0o065, 0o071,
+    0o072, 0o073, 0o074, 0o114, 0o115, 0o116, 0o122, 0o125, 0o131, 0o132, 0o134, 0o143, 0o145,
+    0o152, 0o155, 0o156, 0o162, 0o165, 0o172, 0o174, 0o205, 0o212, 0o223, 0o225, 0o226, 0o243,
+    0o244, 0o245, 0o246, 0o251, 0o252, 0o255, 0o261, 0o263, 0o265, 0o266, 0o271, 0o274, 0o306,
+    0o311, 0o315, 0o325, 0o331, 0o332, 0o343, 0o346, 0o351, 0o356, 0o364, 0o365, 0o371, 0o411,
+    0o412, 0o413, 0o423, 0o431, 0o432, 0o445, 0o446, 0o452, 0o454, 0o455, 0o462, 0o464, 0o465,
+    0o466, 0o503, 0o506, 0o516, 0o523, 0o526, 0o532, 0o546, 0o565, 0o606, 0o612, 0o624, 0o627,
+    0o631, 0o632, 0o654, 0o662, 0o664, 0o703, 0o712, 0o723, 0o731, 0o732, 0o734, 0o743, 0o754,
+];
+
+//Everything above the tones is filtered off and the rest brought down to this rate
+const RATE: f64 = 2_400.0;
+const CUTOFF: f64 = 260.0;
+//Audio each CTCSS measurement is over, long enough to tell 67.0 from 69.3 Hz, and how often
+const CTCSS_WINDOW_SECS: f64 = 1.0;
+const CTCSS_EVERY_SECS: f64 = 0.25;
+//Share of the sub-audible power a tone needs to be found, and to be kept once it has been
+const CTCSS_FIND: f32 = 0.3;
+const CTCSS_KEEP: f32 = 0.15;
+const DCS_BAUD: f64 = 134.4;
+const DCS_BITS: usize = 23;
+const DCS_CLOCK_GAIN: f64 = 0.1;
+//Words a code can be missed for before it is dropped
+const DCS_LOSE: usize = 3;
+
+/// A CTCSS tone or DCS code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subtone {
+    /// Tone in Hz, one of [`CTCSS_TONES`]
+    Ctcss(f64),
+    /// One of [`DCS_CODES`], inverted codes are sent with the polarity flipped
+    Dcs { code: u16, inverted: bool },
+}
+
+impl Subtone {
+    /// Every tone and code, normal DCS codes before inverted ones
+    pub fn all() -> Vec<Subtone> {
+        let ctcss = CTCSS_TONES.iter().map(|hz| Subtone::Ctcss(*hz));
+        let dcs = [false, true].into_iter().flat_map(|inverted| {
+            DCS_CODES.iter().map(move |code| Subtone::Dcs {
+                code: *code,
+                inverted,
+            })
+        });
+        ctcss.chain(dcs).collect()
+    }
+}
+
+impl std::fmt::Display for Subtone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subtone::Ctcss(hz) => write!(f, "CTCSS {:.1} Hz", hz),
+            Subtone::Dcs { code, inverted } => {
+                write!(f, "DCS {:03o}{}", code, if *inverted { "I" } else { "N" })
+            }
+        }
+    }
+}
+
+//The 23 bit Golay word for a code, code bits first, then 100 and the 11 check bits
+fn dcs_word(code: u16) -> u32 {
+    let data = code as u32 | 0x800;
+    let mut check = data;
+    for _ in 0..12 {
+        check <<= 1;
+        if check & 0x1000 != 0 {
+            check ^= 0x08EA;
+        }
+    }
+    data | ((check & 0x0FFE) << 11)
+}
+
+/// Finds the CTCSS tone or DCS code on NFM audio
+pub struct SubtoneDetector {
+    filter: Fir<f32>,
+    rate: f64,
+    history: Vec<f32>,
+    window: usize,
+    every: usize,
+    since: usize,
+    ctcss: Option<f64>,
+    words: HashMap<u32, Subtone>,
+    step: f64,
+    clock: f64,
+    last_level: bool,
+    //The last two words worth of bits, the newest at the top
+    bits: u64,
+    //Bits since the code was last seen
+    dcs_since: usize,
+    dcs: Option<Subtone>,
+}
+
+impl SubtoneDetector {
+    pub fn new(rate: f64) -> Self {
+        let decim = ((rate / RATE) as usize).max(1);
+        let out_rate = rate / decim as f64;
+
+        let mut words = HashMap::new();
+        for code in DCS_CODES {
+            let word = dcs_word(code);
+            words.insert(
+                word,
+                Subtone::Dcs {
+                    code,
+                    inverted: false,
+                },
+            );
+            words.insert(
+                !word & 0x7F_FFFF,
+                Subtone::Dcs {
+                    code,
+                    inverted: true,
+                },
+            );
+        }
+
+        Self {
+            filter: Fir::new(lowpass_taps(CUTOFF, rate, num_taps(rate, 100.0)), decim),
+            rate: out_rate,
+            history: Vec::new(),
+            window: (CTCSS_WINDOW_SECS * out_rate) as usize,
+            every: (CTCSS_EVERY_SECS * out_rate) as usize,
+            since: 0,
+            ctcss: None,
+            words,
+            step: DCS_BAUD / out_rate,
+            clock: 0.0,
+            last_level: false,
+            bits: 0,
+            dcs_since: 0,
+            dcs: None,
+        }
+    }
+
+    /// Tone or code currently on the channel, DCS first as its wide spectrum can look like a tone
+    pub fn detected(&self) -> Option<Subtone> {
+        self.dcs.or(self.ctcss.map(Subtone::Ctcss))
+    }
+
+    pub fn process(&mut self, audio: &[f32]) {
+        for x in audio {
+            if let Some(y) = self.filter.push(*x) {
+                self.dcs_sample(y);
+                self.history.push(y);
+                self.since += 1;
+            }
+        }
+        if self.history.len() > self.window {
+            let cut = self.history.len() - self.window;
+            self.history.drain(..cut);
+        }
+        if self.since >= self.every && self.history.len() == self.window {
+            self.since = 0;
+            self.ctcss_window();
+        }
+    }
+
+    //Strongest standard tone in the window, if enough of the power is in it
+    fn ctcss_window(&mut self) {
+        let total = self.history.iter().map(|x| x * x).sum::<f32>() / self.history.len() as f32;
+        //A tone's Goertzel power is half its mean square
+        let share = |hz: f64| 2.0 * tone_power(&self.history, hz, self.rate) / total.max(1e-12);
+        let (best, best_share) = CTCSS_TONES
+            .iter()
+            .map(|hz| (*hz, share(*hz)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0.0, 0.0));
+
+        let keep = self.ctcss == Some(best) && best_share > CTCSS_KEEP;
+        self.ctcss = (keep || best_share > CTCSS_FIND).then_some(best);
+    }
+
+    //NRZ bits with clock recovery, each new bit is checked against every code's word
+    fn dcs_sample(&mut self, x: f32) {
+        let level = x > 0.0;
+        if level != self.last_level {
+            self.clock -= DCS_CLOCK_GAIN * (self.clock - 0.5);
+        }
+        self.last_level = level;
+        self.clock += self.step;
+        if self.clock < 1.0 {
+            return;
+        }
+        self.clock -= 1.0;
+
+        //Sent first bit first, so the oldest bit of each word ends up lowest
+        self.bits = (self.bits >> 1) | ((level as u64) << (2 * DCS_BITS - 1));
+        let word = (self.bits >> DCS_BITS) as u32;
+        let previous = (self.bits & ((1 << DCS_BITS) - 1)) as u32;
+        self.dcs_since += 1;
+        //The word is sent over and over, one seen twice in a row is taken to be real
+        if let Some(found) = self.words.get(&word).filter(|_| word == previous) {
+            //Codes can be rotations of each other, the first one found is kept
+            self.dcs.get_or_insert(*found);
+            self.dcs_since = 0;
+        } else if self.dcs_since > DCS_LOSE * DCS_BITS {
+            self.dcs = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDIO_RATE: f64 = 48_000.0;
+
+    //A second of voice at 1 kHz with the subtone under it, as it comes out of the discriminator
+    fn with_voice(subtone: impl Fn(f64) -> f64, secs: f64) -> Vec<f32> {
+        (0..(secs * AUDIO_RATE) as usize)
+            .map(|n| {
+                let t = n as f64 / AUDIO_RATE;
+                (0.5 * (2.0 * std::f64::consts::PI * 1_000.0 * t).sin() + subtone(t)) as f32
+            })
+            .collect()
+    }
+
+    fn ctcss(hz: f64) -> impl Fn(f64) -> f64 {
+        move |t| 0.15 * (2.0 * std::f64::consts::PI * hz * t).sin()
+    }
+
+    //NRZ at 134.4 baud, the word sent over and over with its oldest bit first
+    fn dcs(code: u16, inverted: bool) -> impl Fn(f64) -> f64 {
+        let word = dcs_word(code);
+        move |t| {
+            let bit = (t * DCS_BAUD) as usize % DCS_BITS;
+            if (word >> bit & 1 == 1) != inverted {
+                0.15
+            } else {
+                -0.15
+            }
+        }
+    }
+
+    fn detect(audio: &[f32]) -> Option<Subtone> {
+        let mut detector = SubtoneDetector::new(AUDIO_RATE);
+        //Blocks the size the demodulator hands over
+        for block in audio.chunks(1024) {
+            detector.process(block);
+        }
+        detector.detected()
+    }
+
+    #[test]
+    fn dcs_word_matches_the_standard() {
+        assert_eq!(dcs_word(0o023), 0x76_3813);
+    }
+
+    #[test]
+    fn neighbouring_ctcss_tones_are_told_apart() {
+        assert_eq!(
+            detect(&with_voice(ctcss(67.0), 2.0)),
+            Some(Subtone::Ctcss(67.0))
+        );
+        assert_eq!(
+            detect(&with_voice(ctcss(69.3), 2.0)),
+            Some(Subtone::Ctcss(69.3))
+        );
+        assert_eq!(detect(&with_voice(|_| 0.0, 2.0)), None);
+    }
+
+    #[test]
+    fn dcs_is_found_in_both_polarities() {
+        assert_eq!(
+            detect(&with_voice(dcs(0o023, false), 2.0)),
+            Some(Subtone::Dcs {
+                code: 0o023,
+                inverted: false
+            })
+        );
+        assert_eq!(
+            detect(&with_voice(dcs(0o023, true), 2.0)),
+            Some(Subtone::Dcs {
+                code: 0o023,
+                inverted: true
+            })
+        );
+    }
+}
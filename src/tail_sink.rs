@@ -1,6 +1,9 @@
-use std::sync::atomic::AtomicUsize;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
+use crate::spectrum_log::LogWriter;
 use futuresdr::anyhow::{Ok, Result};
 use futuresdr::runtime::MessageIo;
 use futuresdr::{
@@ -11,113 +14,443 @@ use futuresdr::{
     },
 };
 
-//There should be one thread reading and one writing
-pub struct TailRing<T>
-where
-    T: Send + 'static + Copy + std::fmt::Display + Default,
-{
-    //tail = 0 none is current
-    //tail = 1 a is current
-    //tail = 2 b is current
-    //tail = 3 c is current
-    tail_state: AtomicUsize,
-    a: Mutex<Vec<T>>,
-    b: Mutex<Vec<T>>,
-    c: Mutex<Vec<T>>,
-    buffer_size: usize,
-}
-
-impl<T: Send + 'static + Copy + std::fmt::Display + Default> TailRing<T> {
-    pub fn new(buffer_size: usize) -> Self {
+//The ring is model checked with loom, run with `RUSTFLAGS="--cfg loom" cargo test --release --lib tail_sink`
+mod sync {
+    #[cfg(loom)]
+    pub use loom::{
+        cell::UnsafeCell,
+        sync::atomic::{AtomicBool, AtomicU64, AtomicUsize},
+        sync::Arc,
+    };
+    #[cfg(not(loom))]
+    pub use std::sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize},
+        Arc,
+    };
+
+    //Same access API as loom's cell so the ring code is the same under both
+    #[cfg(not(loom))]
+    pub struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+    #[cfg(not(loom))]
+    impl<T> UnsafeCell<T> {
+        pub fn new(val: T) -> Self {
+            Self(std::cell::UnsafeCell::new(val))
+        }
+
+        pub fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+            f(self.0.get())
+        }
+
+        pub fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+            f(self.0.get())
+        }
+    }
+}
+
+//Low bits of the back state hold the index of the back buffer
+const INDEX_MASK: usize = 0b011;
+//Set when the back buffer holds a frame the reader has not taken yet
+const DIRTY: usize = 0b100;
+
+/// Describes what a spectrum frame was taken from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameMeta {
+    /// Counts up from 1 with every published frame
+    pub seq: u64,
+    /// RF frequency of the middle bin in Hz
+    pub center_freq: f64,
+    pub sample_rate: f64,
+    pub fft_size: usize,
+    /// Number of FFT frames averaged into this one
+    pub avg_count: usize,
+    /// When the frame was published
+    pub timestamp: SystemTime,
+}
+
+impl Default for FrameMeta {
+    fn default() -> Self {
         Self {
-            tail_state: AtomicUsize::new(0),
-            a: vec![T::default(); buffer_size].into(),
-            b: vec![T::default(); buffer_size].into(),
-            c: vec![T::default(); buffer_size].into(),
-            buffer_size,
-        }
-    }
-
-    pub fn get_lease(&self) -> (MutexGuard<Vec<T>>, usize) {
-        let tail_state = self.tail_state.load(std::sync::atomic::Ordering::Relaxed);
-        if tail_state == 0 {
-            return (self.a.lock().unwrap(), 1);
-        } else if tail_state == 2 {
-            if let core::result::Result::Ok(guard) = self.c.try_lock() {
-                return (guard, 3);
-            } else {
-                return (self.a.lock().unwrap(), 1);
-            }
-        } else if tail_state == 1 {
-            if let core::result::Result::Ok(guard) = self.b.try_lock() {
-                return (guard, 2);
-            } else {
-                return (self.c.lock().unwrap(), 3);
-            }
-        } else if let core::result::Result::Ok(guard) = self.a.try_lock() {
-            return (guard, 1);
-        } else {
-            return (self.b.lock().unwrap(), 2);
+            seq: 0,
+            center_freq: 0.0,
+            sample_rate: 0.0,
+            fft_size: 0,
+            avg_count: 0,
+            timestamp: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+impl FrameMeta {
+    /// Frequency in Hz at the middle of bin `idx`
+    pub fn bin_freq(&self, idx: usize) -> f64 {
+        self.center_freq - self.sample_rate / 2.0
+            + (idx as f64 + 0.5) * self.sample_rate / self.fft_size as f64
+    }
+
+    /// True if the two frames cover different spectrum, i.e. there was a retune between them
+    pub fn is_retune(&self, other: &FrameMeta) -> bool {
+        self.center_freq != other.center_freq || self.sample_rate != other.sample_rate
+    }
+}
+
+//A tuning and the first sample out of the source taken at it
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tuned {
+    sample: u64,
+    center_freq: f64,
+    sample_rate: f64,
+}
+
+/// What the flowgraph is tuned to, retunes are written by `Sdr` and stamped on the sample
+/// stream right after the source so every frame is labeled with what its samples were taken at
+pub struct FrameInfo {
+    //Retune waiting for the next sample out of the source
+    pending: Mutex<Option<(f64, f64)>>,
+    has_pending: AtomicBool,
+    //Tunings in sample order, the first one covers the oldest frame not published yet
+    tunings: Mutex<VecDeque<Tuned>>,
+    fft_avg: AtomicUsize,
+    //Frames that went into the last averaged frame
+    averaged: AtomicUsize,
+    //Fed from the flowgraph so logging keeps up even when the GUI doesn't, the file is
+    //written on the log's own thread
+    log: Mutex<Option<LogWriter>>,
+    //Why the log stopped on its own
+    log_error: Mutex<Option<String>>,
+}
+
+impl FrameInfo {
+    pub fn new(center_freq: f64, sample_rate: f64, fft_avg: usize) -> Self {
+        Self {
+            pending: Mutex::new(None),
+            has_pending: AtomicBool::new(false),
+            tunings: Mutex::new(VecDeque::from([Tuned {
+                sample: 0,
+                center_freq,
+                sample_rate,
+            }])),
+            fft_avg: AtomicUsize::new(fft_avg),
+            averaged: AtomicUsize::new(0),
+            log: Mutex::new(None),
+            log_error: Mutex::new(None),
+        }
+    }
+
+    /// Call once the source has been retuned, `sample_rate` is the corrected rate
+    pub fn retune(&self, center_freq: f64, sample_rate: f64) {
+        if let Ok(mut pending) = self.pending.lock() {
+            *pending = Some((center_freq, sample_rate));
+        }
+        self.has_pending.store(true, Ordering::Release);
+    }
+
+    /// Called with the index of every sample coming out of the source,
+    /// a retune that is waiting starts at it
+    #[inline]
+    pub fn stamp(&self, sample: u64) {
+        if !self.has_pending.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let Some((center_freq, sample_rate)) = self.pending.lock().ok().and_then(|mut p| p.take())
+        else {
+            return;
         };
+        if let Ok(mut tunings) = self.tunings.lock() {
+            tunings.push_back(Tuned {
+                sample,
+                center_freq,
+                sample_rate,
+            });
+        }
     }
 
-    pub fn get(&self) -> Result<MutexGuard<Vec<T>>, ()> {
-        let tail_state = self.tail_state.load(std::sync::atomic::Ordering::Relaxed);
-        if tail_state == 1 {
-            let guard = self.a.try_lock();
-            if let core::result::Result::Ok(buff) = guard {
-                core::result::Result::Ok(buff)
-            } else {
-                Err(())
-            }
-        } else if tail_state == 2 {
-            let guard = self.b.try_lock();
-            if let core::result::Result::Ok(buff) = guard {
-                core::result::Result::Ok(buff)
-            } else {
-                Err(())
+    /// Center frequency and sample rate the source was at for `sample`
+    pub fn tuning_at(&self, sample: u64) -> (f64, f64) {
+        let Ok(tunings) = self.tunings.lock() else {
+            return (0.0, 0.0);
+        };
+        tunings
+            .iter()
+            .rev()
+            .find(|tuned| tuned.sample <= sample)
+            .or(tunings.front())
+            .map(|tuned| (tuned.center_freq, tuned.sample_rate))
+            .unwrap_or((0.0, 0.0))
+    }
+
+    pub fn set_fft_avg(&self, num: usize) {
+        self.fft_avg.store(num, Ordering::Relaxed);
+    }
+
+    pub fn fft_avg(&self) -> usize {
+        self.fft_avg.load(Ordering::Relaxed)
+    }
+
+    pub fn set_averaged(&self, num: usize) {
+        self.averaged.store(num, Ordering::Relaxed);
+    }
+
+    /// Starts logging frames, replacing any log already running
+    pub fn start_log(&self, log: LogWriter) -> std::io::Result<()> {
+        let old = self
+            .log
+            .lock()
+            .ok()
+            .and_then(|mut current| current.replace(log));
+        old.map_or(Ok(()), LogWriter::finish)
+    }
+
+    pub fn stop_log(&self) -> std::io::Result<()> {
+        match self.log.lock().ok().and_then(|mut log| log.take()) {
+            Some(log) => log.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_logging(&self) -> bool {
+        self.log.lock().is_ok_and(|log| log.is_some())
+    }
+
+    /// The error that stopped the log, if any, cleared by reading it
+    pub fn take_log_error(&self) -> Option<String> {
+        self.log_error.lock().ok().and_then(|mut e| e.take())
+    }
+
+    fn log(&self, meta: &FrameMeta, data: &[f32]) {
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+        if log.as_mut().is_some_and(|log| !log.push(meta, data)) {
+            //The writer already stopped so joining it here doesn't block
+            if let Some(Err(e)) = log.take().map(LogWriter::finish) {
+                if let Ok(mut log_error) = self.log_error.lock() {
+                    *log_error = Some(e.to_string());
+                }
             }
-        } else if tail_state == 3 {
-            let guard = self.c.try_lock();
-            if let core::result::Result::Ok(buff) = guard {
-                core::result::Result::Ok(buff)
-            } else {
-                Err(())
+        }
+    }
+
+    //seq is filled in by the writer. A frame that straddles a retune is labeled with what its
+    //first sample was taken at, tunings before it are no longer needed once it is published
+    fn meta(&self, frame: u64, fft_size: usize) -> FrameMeta {
+        let first = frame * fft_size as u64;
+        let (center_freq, sample_rate) = match self.tunings.lock() {
+            Ok(mut tunings) => {
+                while tunings.get(1).is_some_and(|next| next.sample <= first) {
+                    tunings.pop_front();
+                }
+                tunings
+                    .front()
+                    .map(|tuned| (tuned.center_freq, tuned.sample_rate))
+                    .unwrap_or((0.0, 0.0))
             }
-        } else {
-            Err(())
+            Err(_) => (0.0, 0.0),
+        };
+
+        FrameMeta {
+            seq: 0,
+            center_freq,
+            sample_rate,
+            fft_size,
+            avg_count: self.averaged.load(Ordering::Relaxed),
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+struct Slot<T> {
+    //seq is 0 until the first frame lands in this slot
+    meta: FrameMeta,
+    data: Vec<T>,
+}
+
+/// Triple buffer shared by one `TailWriter` and one `TailReader`.
+///
+/// Each side owns one buffer and the third sits in the back, the writer fills its own
+/// buffer and swaps it into the back, the reader swaps its buffer for the back one when
+/// a newer frame is there. Neither side ever waits on the other.
+pub struct TailRing<T> {
+    slots: [sync::UnsafeCell<Slot<T>>; 3],
+    back: sync::AtomicUsize,
+    //Number of frames published so far, used to spot a stalled source
+    frames: sync::AtomicU64,
+    //Set once the writer has stopped
+    finished: sync::AtomicBool,
+}
+
+//Safety: a slot is only touched by the side whose index points at it, indices are only
+//handed over through `back` with AcqRel so the data written before a swap is visible after it
+unsafe impl<T: Send> Sync for TailRing<T> {}
+
+impl<T: Copy + Default> TailRing<T> {
+    /// Creates the ring with frames of `buffer_size` items and splits it into its two ends
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(buffer_size: usize) -> (TailWriter<T>, TailReader<T>) {
+        let slot = || {
+            sync::UnsafeCell::new(Slot {
+                meta: FrameMeta::default(),
+                data: vec![T::default(); buffer_size],
+            })
+        };
+        let ring = sync::Arc::new(Self {
+            slots: [slot(), slot(), slot()],
+            back: sync::AtomicUsize::new(1),
+            frames: sync::AtomicU64::new(0),
+            finished: sync::AtomicBool::new(false),
+        });
+
+        (
+            TailWriter {
+                ring: ring.clone(),
+                idx: 0,
+                seq: 0,
+            },
+            TailReader {
+                ring,
+                idx: 2,
+                last_seq: 0,
+            },
+        )
+    }
+}
+
+/// The producing end of a `TailRing`
+pub struct TailWriter<T> {
+    ring: sync::Arc<TailRing<T>>,
+    idx: usize,
+    seq: u64,
+}
+
+impl<T> TailWriter<T> {
+    /// The buffer for the next frame, its contents are whatever the reader last gave back
+    pub fn buffer(&mut self) -> &mut [T] {
+        //Safety: the writer is the only side holding `idx`
+        self.ring.slots[self.idx].with_mut(|slot| unsafe { &mut (*slot).data })
+    }
+
+    /// Runs `f` on the buffer for the next frame, loom checks every access made in it
+    pub fn with_buffer<R>(&mut self, f: impl FnOnce(&mut [T]) -> R) -> R {
+        //Safety: the writer is the only side holding `idx`
+        self.ring.slots[self.idx].with_mut(|slot| f(unsafe { &mut (*slot).data }))
+    }
+
+    /// Hands the filled buffer to the reader and takes the back one to fill next
+    pub fn publish(&mut self, meta: FrameMeta) {
+        self.seq += 1;
+        //Safety: the writer is the only side holding `idx`
+        let seq = self.seq;
+        self.ring.slots[self.idx]
+            .with_mut(|slot| unsafe { (*slot).meta = FrameMeta { seq, ..meta } });
+
+        //Release so the reader sees the frame, Acquire so the reader is done with what comes back
+        let old = self.ring.back.swap(self.idx | DIRTY, Ordering::AcqRel);
+        self.idx = old & INDEX_MASK;
+        self.ring.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks the stream as over, the reader keeps the last frame
+    pub fn finish(&self) {
+        self.ring.finished.store(true, Ordering::Release);
+    }
+}
+
+/// A frame taken from a `TailRing`
+pub struct TailFrame<'a, T> {
+    pub meta: FrameMeta,
+    /// False when the reader already returned this frame before
+    pub new: bool,
+    pub data: &'a [T],
+}
+
+//The frame in `slot`, None if nothing has been published to it yet
+fn frame<'a, T>(slot: &'a Slot<T>, last_seq: &mut u64) -> Option<TailFrame<'a, T>> {
+    if slot.meta.seq == 0 {
+        return None;
+    }
+
+    let new = slot.meta.seq != *last_seq;
+    *last_seq = slot.meta.seq;
+    Some(TailFrame {
+        meta: slot.meta,
+        new,
+        data: &slot.data,
+    })
+}
+
+/// The consuming end of a `TailRing`
+pub struct TailReader<T> {
+    ring: sync::Arc<TailRing<T>>,
+    idx: usize,
+    last_seq: u64,
+}
+
+impl<T> TailReader<T> {
+    /// The latest published frame, None until the first one arrives
+    pub fn read(&mut self) -> Option<TailFrame<'_, T>> {
+        self.take_back();
+        //Safety: the reader is the only side holding `idx`
+        let slot = self.ring.slots[self.idx].with(|slot| unsafe { &*slot });
+        frame(slot, &mut self.last_seq)
+    }
+
+    /// Runs `f` on the latest published frame, loom checks every access made in it
+    pub fn read_with<R>(&mut self, f: impl FnOnce(TailFrame<'_, T>) -> R) -> Option<R> {
+        self.take_back();
+        let last_seq = &mut self.last_seq;
+        //Safety: the reader is the only side holding `idx`
+        self.ring.slots[self.idx].with(|slot| frame(unsafe { &*slot }, last_seq).map(f))
+    }
+
+    //Swaps in the back buffer when it holds a frame not taken yet
+    fn take_back(&mut self) {
+        if self.ring.back.load(Ordering::Relaxed) & DIRTY != 0 {
+            //Release hands our old buffer back, Acquire makes the new frame visible
+            let old = self.ring.back.swap(self.idx, Ordering::AcqRel);
+            self.idx = old & INDEX_MASK;
         }
     }
+
+    /// Number of frames published so far
+    pub fn frames(&self) -> u64 {
+        self.ring.frames.load(Ordering::Relaxed)
+    }
+
+    /// True once the sink has stopped, e.g. because the source ended
+    pub fn is_finished(&self) -> bool {
+        self.ring.finished.load(Ordering::Acquire)
+    }
 }
 
-pub struct TailSink<T>
-where
-    T: Send + 'static + Copy + std::fmt::Display + Default,
-{
-    ring: Arc<TailRing<T>>,
+/// Publishes every `fft_size` spectrum values as one frame, logging it if a log is running
+pub struct TailSink {
+    writer: TailWriter<f32>,
+    info: Arc<FrameInfo>,
     filled: usize,
-    _type: std::marker::PhantomData<T>,
+    //Frames taken in so far, frame n holds samples n * fft_size onwards
+    frame: u64,
 }
 
-impl<T: Send + 'static + Copy + std::fmt::Display + Default> TailSink<T> {
+impl TailSink {
     /// Create Tail Sink block
     #[allow(clippy::new_ret_no_self)]
-    pub fn new(ring: Arc<TailRing<T>>) -> Block {
+    pub fn new(writer: TailWriter<f32>, info: Arc<FrameInfo>) -> Block {
         Block::new(
             BlockMetaBuilder::new("TailSink").build(),
-            StreamIoBuilder::new().add_input::<T>("in").build(),
+            StreamIoBuilder::new().add_input::<f32>("in").build(),
             MessageIoBuilder::new().build(),
-            TailSink::<T> {
-                ring,
+            TailSink {
+                writer,
+                info,
                 filled: 0,
-                _type: std::marker::PhantomData,
+                frame: 0,
             },
         )
     }
 }
 
 #[async_trait]
-impl<T: Send + 'static + Copy + std::fmt::Display + Default> Kernel for TailSink<T> {
+impl Kernel for TailSink {
     async fn work(
         &mut self,
         io: &mut WorkIo,
@@ -125,40 +458,28 @@ impl<T: Send + 'static + Copy + std::fmt::Display + Default> Kernel for TailSink
         _mio: &mut MessageIo<Self>,
         _meta: &mut BlockMeta,
     ) -> Result<()> {
-        let i = sio.input(0).slice::<T>();
-        let mut items = i.len();
-        let total_items = items;
+        let i = sio.input(0).slice::<f32>();
+        let total_items = i.len();
         let mut offset = 0;
-        if items > 0 {
-            while items != 0 {
-                //items > buffer - filled
-                //items == buffer - filled
-                //items < buffer - filled
-                let mut lease = self.ring.get_lease();
-                let to_fill: usize = if self.filled == self.ring.buffer_size {
-                    self.ring
-                        .tail_state
-                        .store(lease.1, std::sync::atomic::Ordering::Relaxed);
-                    drop(lease); // This is needed to unlock the mutex
-                    lease = self.ring.get_lease();
-
-                    self.filled = 0;
-                    self.ring.buffer_size
-                } else {
-                    self.ring.buffer_size - self.filled
-                }
-                .min(items);
+        while offset < total_items {
+            let buffer = self.writer.buffer();
+            let to_fill = (buffer.len() - self.filled).min(total_items - offset);
+            buffer[self.filled..self.filled + to_fill]
+                .copy_from_slice(&i[offset..offset + to_fill]);
+            offset += to_fill;
+            self.filled += to_fill;
 
-                for (idx, t) in i[offset..offset + to_fill].iter().enumerate() {
-                    lease.0[self.filled + idx] = *t;
-                }
-                offset += to_fill;
-                self.filled += to_fill;
-                items -= to_fill;
+            if self.filled == buffer.len() {
+                let meta = self.info.meta(self.frame, buffer.len());
+                self.frame += 1;
+                self.info.log(&meta, buffer);
+                self.writer.publish(meta);
+                self.filled = 0;
             }
         }
 
         if sio.input(0).finished() {
+            self.writer.finish();
             io.finished = true;
         }
 
@@ -166,3 +487,133 @@ impl<T: Send + 'static + Copy + std::fmt::Display + Default> Kernel for TailSink
         Ok(())
     }
 }
+
+impl Drop for TailSink {
+    fn drop(&mut self) {
+        //The flowgraph drops its blocks when it stops for any reason
+        self.writer.finish();
+        let _ = self.info.stop_log();
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    //Every frame is filled with its own seq so a torn one has mixed values
+    #[test]
+    fn readers_never_see_torn_frames() {
+        const FRAMES: u64 = 200_000;
+        let (mut writer, mut reader) = TailRing::<u64>::new(256);
+
+        let producer = std::thread::spawn(move || {
+            for seq in 1..=FRAMES {
+                writer.buffer().fill(seq);
+                writer.publish(FrameMeta::default());
+            }
+            writer.finish();
+        });
+
+        let mut last_seq = 0;
+        let mut seen = 0;
+        loop {
+            let finished = reader.is_finished();
+            if let Some(frame) = reader.read() {
+                assert!(frame.meta.seq >= last_seq, "frames went backwards");
+                assert_eq!(frame.new, frame.meta.seq != last_seq);
+                assert!(
+                    frame.data.iter().all(|val| *val == frame.meta.seq),
+                    "torn frame {}",
+                    frame.meta.seq
+                );
+                if frame.new {
+                    seen += 1;
+                }
+                last_seq = frame.meta.seq;
+            }
+            if finished {
+                break;
+            }
+        }
+        producer.join().unwrap();
+
+        //The last frame is never lost even if most in between are skipped
+        assert_eq!(last_seq, FRAMES);
+        assert!(seen > 0);
+        assert_eq!(reader.frames(), FRAMES);
+    }
+
+    #[test]
+    fn frames_are_labeled_by_their_first_sample() {
+        let info = FrameInfo::new(100e6, 2e6, 1);
+        for sample in 0..10 {
+            info.stamp(sample);
+        }
+        info.retune(101e6, 2e6);
+        //Frames already in flight keep the old frequency until the stamped sample
+        assert_eq!(info.tuning_at(9), (100e6, 2e6));
+        info.stamp(10);
+        assert_eq!(info.tuning_at(10), (101e6, 2e6));
+        assert_eq!(info.tuning_at(3), (100e6, 2e6));
+
+        assert_eq!(info.meta(0, 8).center_freq, 100e6);
+        //Samples 8 to 15 straddle the retune
+        assert_eq!(info.meta(1, 8).center_freq, 100e6);
+        assert_eq!(info.meta(2, 8).center_freq, 101e6);
+        assert_eq!(info.tunings.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reader_keeps_the_last_frame() {
+        let (mut writer, mut reader) = TailRing::<u64>::new(4);
+        assert!(reader.read().is_none());
+
+        writer.buffer().fill(7);
+        writer.publish(FrameMeta::default());
+        let frame = reader.read().unwrap();
+        assert!(frame.new);
+        assert_eq!(frame.data, &[7; 4]);
+
+        let frame = reader.read().unwrap();
+        assert!(!frame.new);
+        assert_eq!(frame.meta.seq, 1);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+
+    //Slot accesses are made inside loom's cell, so a reader and writer ever holding the same
+    //index at the same time fails the model, on top of the data checks
+    #[test]
+    fn publish_read_swap() {
+        loom::model(|| {
+            let (mut writer, mut reader) = TailRing::<u64>::new(2);
+
+            let producer = loom::thread::spawn(move || {
+                for seq in 1..=3 {
+                    writer.with_buffer(|buffer| buffer.fill(seq));
+                    writer.publish(FrameMeta::default());
+                }
+            });
+
+            let mut last_seq = 0;
+            for _ in 0..3 {
+                reader.read_with(|frame| {
+                    assert!(frame.meta.seq >= last_seq);
+                    assert!(frame.data.iter().all(|val| *val == frame.meta.seq));
+                    last_seq = frame.meta.seq;
+                });
+            }
+            producer.join().unwrap();
+
+            reader
+                .read_with(|frame| {
+                    assert_eq!(frame.meta.seq, 3);
+                    assert_eq!(frame.data, &[3, 3]);
+                })
+                .unwrap();
+        });
+    }
+}
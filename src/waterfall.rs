@@ -1,11 +1,78 @@
 use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
+use chrono::{DateTime, Utc};
+
+use iced::widget::shader::{self, wgpu};
 use iced::{
-    widget::{column, container, image, image::Handle, Column},
-    Element, Length,
+    mouse,
+    widget::{container, row, Space},
+    Element, Length, Rectangle, Size,
 };
+use plotters::{coord::Shift, prelude::*};
+use plotters_backend::DrawingBackend;
+use plotters_iced::{plotters_backend, Chart, ChartBuilder, ChartWidget, DrawingArea};
 
 use crate::FFT_AMMOUNT;
+use rustic_sdr::waterfall_ring::{self, LineRing, Upload, RGBA};
+use rustic_sdr::FrameMeta;
+
+//Width of the time scale left of the waterfall
+const AXIS_WIDTH: f32 = 120.0;
+//Closest two time labels may be in lines
+const MIN_TICK_SPACING: f64 = 40.0;
+//Room for the axes around the waterfall in an exported PNG, and for the markers left of them
+const PNG_AXIS_WIDTH: u32 = 110;
+const PNG_MARKER_WIDTH: u32 = 160;
+const PNG_AXIS_HEIGHT: u32 = 50;
+//Tick intervals to pick from in seconds
+const TICK_INTERVALS: [u64; 12] = [1, 2, 5, 10, 15, 30, 60, 120, 300, 600, 1800, 3600];
+
+/// Events drawn as a line across the waterfall and labeled on the time scale
+#[derive(Debug, Clone, PartialEq)]
+pub enum Marker {
+    /// New center frequency in Hz
+    Retune(f64),
+    RecordingStart,
+    RecordingStop,
+    /// New overall gain in dB
+    Gain(f64),
+    /// A single gain stage and its new value in dB
+    GainStage(String, f64),
+    Agc(bool),
+}
+
+impl Marker {
+    fn color(&self) -> [u8; RGBA] {
+        match self {
+            Marker::Retune(_) => [255, 255, 255, 255],
+            Marker::RecordingStart => [255, 85, 85, 255],
+            Marker::RecordingStop => [160, 160, 160, 255],
+            Marker::Gain(_) | Marker::GainStage(_, _) | Marker::Agc(_) => [255, 215, 0, 255],
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Marker::Retune(hz) => format!("{:.3} MHz", hz / 1_000_000.0),
+            Marker::RecordingStart => "Rec start".into(),
+            Marker::RecordingStop => "Rec stop".into(),
+            Marker::Gain(db) => format!("Gain {:.1} dB", db),
+            Marker::GainStage(name, db) => format!("{} {:.1} dB", name, db),
+            Marker::Agc(on) => format!("AGC {}", if *on { "on" } else { "off" }),
+        }
+    }
+}
+
+//What the time scale knows about each line of the waterfall, markers are shown beside the
+//line they happened before rather than taking up lines of their own
+#[derive(Clone)]
+struct LineInfo {
+    timestamp: SystemTime,
+    markers: Vec<Marker>,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Pallet {
@@ -16,6 +83,20 @@ pub enum Pallet {
     Rainbow,
 }
 
+impl Pallet {
+    /// Colour table for this pallet
+    fn lut(&self) -> [[u8; RGBA]; 256] {
+        let grad = match self {
+            Pallet::Turbo => colorgrad::turbo(),
+            Pallet::Magma => colorgrad::magma(),
+            Pallet::Plasma => colorgrad::plasma(),
+            Pallet::Spectral => colorgrad::spectral(),
+            Pallet::Rainbow => colorgrad::rainbow(),
+        };
+        waterfall_ring::lut(&grad)
+    }
+}
+
 impl std::fmt::Display for Pallet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -32,10 +113,21 @@ impl std::fmt::Display for Pallet {
     }
 }
 
+/// Scrolling spectrogram kept as a ring of RGBA lines, newest line at the top
 pub struct WaterFall {
-    pub height: usize,
-    pub handels: VecDeque<Handle>,
-    pub pallet: Pallet,
+    height: usize,
+    pallet: Pallet,
+    lut: [[u8; RGBA]; 256],
+    //Shared with the shader that draws it, which uploads only the lines it hasn't seen
+    ring: Arc<Mutex<LineRing>>,
+    //Meta of the newest line, used to spot retunes
+    last_meta: Option<FrameMeta>,
+    //Newest first, one per line of the ring
+    lines: VecDeque<LineInfo>,
+    //Drawn before the next line
+    pending_markers: Vec<Marker>,
+    /// Label the time scale with UTC wall clock instead of seconds before the newest line
+    pub utc: bool,
 }
 
 impl WaterFall {
@@ -43,49 +135,595 @@ impl WaterFall {
         Self {
             height: 0,
             pallet: Pallet::Turbo,
-            handels: VecDeque::new(),
+            lut: Pallet::Turbo.lut(),
+            ring: Arc::new(Mutex::new(LineRing::new(FFT_AMMOUNT))),
+            last_meta: None,
+            lines: VecDeque::new(),
+            pending_markers: Vec::new(),
+            utc: false,
         }
     }
 
-    pub fn add_line(&mut self, sample: &[f32], max: f32, min: f32) {
-        let mut new_data: Vec<u8> = Vec::with_capacity(FFT_AMMOUNT * 4);
-        for val in sample {
-            let adj_val = val.clamp(min, max) / max;
-            let pallet = match self.pallet {
-                Pallet::Turbo => colorgrad::turbo(),
-                Pallet::Magma => colorgrad::magma(),
-                Pallet::Plasma => colorgrad::plasma(),
-                Pallet::Spectral => colorgrad::spectral(),
-                Pallet::Rainbow => colorgrad::rainbow(),
-            };
-            let color = pallet.at(adj_val.into()).to_rgba8();
-            let pix: Vec<u8> = vec![color[0], color[1], color[2], color[3]];
+    /// Marks the next line with `marker`
+    pub fn add_marker(&mut self, marker: Marker) {
+        self.pending_markers.push(marker);
+    }
+
+    #[inline]
+    pub fn pallet(&self) -> Pallet {
+        self.pallet
+    }
+
+    /// Changes the colours of lines added from now on
+    pub fn set_pallet(&mut self, pallet: Pallet) {
+        if pallet != self.pallet {
+            self.pallet = pallet;
+            self.lut = pallet.lut();
+        }
+    }
+
+    /// Number of lines kept, the newest ones survive a resize
+    pub fn set_height(&mut self, height: usize) {
+        if height == self.height {
+            return;
+        }
+
+        if let Ok(mut ring) = self.ring.lock() {
+            ring.resize(height);
+        }
+        self.height = height;
+        self.lines.truncate(height);
+    }
 
-            new_data.extend(pix);
+    pub fn add_line(&mut self, sample: &[f32], meta: &FrameMeta, max: f32, min: f32) {
+        if self.height == 0 {
+            return;
         }
 
-        while self.handels.len() >= self.height {
-            let old_handle = self.handels.pop_back();
-            drop(old_handle);
+        if self.last_meta.is_some_and(|last| last.is_retune(meta)) {
+            self.pending_markers.push(Marker::Retune(meta.center_freq));
         }
+        self.last_meta = Some(*meta);
 
-        self.handels
-            .push_front(Handle::from_pixels(FFT_AMMOUNT as u32, 1, new_data));
+        let Ok(mut ring) = self.ring.lock() else {
+            return;
+        };
+        waterfall_ring::color_line(ring.next_line(), sample, &self.lut, max, min);
+        self.lines.push_front(LineInfo {
+            timestamp: meta.timestamp,
+            markers: std::mem::take(&mut self.pending_markers),
+        });
+        self.lines.truncate(self.height);
     }
 
     pub fn view(&self) -> Element<super::Message> {
-        let mut waterfall_display = Column::new();
-        for i in self.handels.iter() {
-            waterfall_display = waterfall_display.push(
-                image(i.clone())
-                    .content_fit(iced::ContentFit::Fill)
-                    .width(Length::Fill),
-            );
-        }
-        let water_fall_elements = container(column![waterfall_display
+        let waterfall_display: Element<super::Message> = if self.height > 0 {
+            shader::Shader::new(RingProgram {
+                ring: self.ring.clone(),
+            })
+            .width(Length::Fill)
+            .height(Length::Fixed(self.height as f32))
+            .into()
+        } else {
+            Space::new(Length::Fill, Length::Fill).into()
+        };
+
+        let time_axis = ChartWidget::new(self)
+            .width(Length::Fixed(AXIS_WIDTH))
+            .height(Length::Fixed(self.height as f32));
+
+        container(row![time_axis, waterfall_display])
             .height(Length::Fill)
-            .width(Length::Fill)]);
+            .width(Length::Fill)
+            .into()
+    }
+
+    /// Copies the lines kept and saves them on their own thread, as drawing the PNG a pixel at
+    /// a time takes a while. Gives the file name it was saved as.
+    pub fn save_png(
+        &self,
+        file_name: String,
+    ) -> impl std::future::Future<Output = Result<String, String>> {
+        let png = self.last_meta.map(|meta| WaterfallPng {
+            meta,
+            lines: self.lines.iter().cloned().collect(),
+            pixels: self
+                .ring
+                .lock()
+                .map(|ring| ring.unrolled())
+                .unwrap_or_default(),
+            utc: self.utc,
+        });
+        let (tx, rx) = iced::futures::channel::oneshot::channel();
+        std::thread::spawn(move || {
+            let saved = png
+                .ok_or_else(|| "The waterfall is empty".to_string())
+                .and_then(|png| png.save(Path::new(&file_name)));
+            let _ = tx.send(saved.map(|()| file_name));
+        });
+        async move {
+            rx.await
+                .unwrap_or_else(|_| Err("Saving the waterfall failed".into()))
+        }
+    }
+
+    /// Seconds between lines averaged over everything on screen
+    fn line_period(&self) -> Option<f64> {
+        let newest = self.lines.front()?.timestamp;
+        let oldest = self.lines.back()?.timestamp;
+        let span = newest.duration_since(oldest).ok()?.as_secs_f64();
+        (self.lines.len() > 1 && span > 0.0).then(|| span / (self.lines.len() - 1) as f64)
+    }
+}
+
+fn time_label(utc: bool, time: SystemTime, newest: SystemTime) -> String {
+    if utc {
+        DateTime::<Utc>::from(time).format("%H:%M:%S").to_string()
+    } else {
+        let ago = newest.duration_since(time).unwrap_or_default();
+        format!("-{}s", ago.as_secs_f64().round())
+    }
+}
+
+//What an exported PNG is drawn from, copied out so it can be drawn off the GUI thread
+struct WaterfallPng {
+    meta: FrameMeta,
+    //Newest first
+    lines: Vec<LineInfo>,
+    pixels: Vec<u8>,
+    utc: bool,
+}
+
+impl WaterfallPng {
+    //Every line at one pixel per bin with the frequency and time axes drawn in, the frequency
+    //axis is that of the newest line
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let meta = self.meta;
+        let (Some(newest), Some(oldest)) = (self.lines.first(), self.lines.last()) else {
+            return Err("The waterfall is empty".into());
+        };
+        let newest = newest.timestamp;
+        let span = newest
+            .duration_since(oldest.timestamp)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let rows = self.lines.len();
+
+        let root = BitMapBackend::new(
+            path,
+            (
+                FFT_AMMOUNT as u32 + PNG_AXIS_WIDTH + PNG_MARKER_WIDTH,
+                rows as u32 + PNG_AXIS_HEIGHT,
+            ),
+        )
+        .into_drawing_area();
+        root.fill(&BLACK).map_err(|e| e.to_string())?;
+        let (marker_area, chart_area) = root.split_horizontally(PNG_MARKER_WIDTH);
+
+        //Time runs down the image so the y axis is negative seconds from the newest line
+        let start = meta.bin_freq(0) / 1_000_000.0;
+        let end = meta.bin_freq(FFT_AMMOUNT - 1) / 1_000_000.0;
+        let mut chart = ChartBuilder::on(&chart_area)
+            .y_label_area_size(PNG_AXIS_WIDTH)
+            .x_label_area_size(PNG_AXIS_HEIGHT)
+            .build_cartesian_2d(start..end, -span..0.0)
+            .map_err(|e| e.to_string())?;
+        let y_label = |y: &f64| {
+            let ago = Duration::from_secs_f64(-y.min(0.0));
+            time_label(self.utc, newest - ago, newest)
+        };
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .x_labels(10)
+            .x_label_formatter(&|x| format!("{:.3}", x))
+            .x_desc("MHz")
+            .y_labels(10)
+            .y_label_formatter(&y_label)
+            .label_style(("sans-serif", 14).into_font().color(&WHITE))
+            .axis_style(WHITE)
+            .draw()
+            .map_err(|e| e.to_string())?;
 
-        water_fall_elements.into()
+        let (x_px, y_px) = chart.plotting_area().get_pixel_range();
+        let line_len = FFT_AMMOUNT * RGBA;
+        for (y, line) in self.pixels.chunks_exact(line_len).take(rows).enumerate() {
+            for (x, pix) in line.chunks_exact(RGBA).enumerate() {
+                root.draw_pixel(
+                    (x_px.start + x as i32, y_px.start + y as i32),
+                    &RGBColor(pix[0], pix[1], pix[2]),
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+        for (y, line) in self.lines.iter().enumerate() {
+            let Some((label, color)) = marker_label(&line.markers) else {
+                continue;
+            };
+            let y = y_px.start + y as i32;
+            marker_area
+                .draw(&PathElement::new(
+                    vec![(0, y), (PNG_MARKER_WIDTH as i32 - 4, y)],
+                    color,
+                ))
+                .map_err(|e| e.to_string())?;
+            marker_area
+                .draw(&Text::new(
+                    label,
+                    (4, y - 16),
+                    ("sans-serif", 14).into_font().color(&color),
+                ))
+                .map_err(|e| e.to_string())?;
+        }
+
+        root.present().map_err(|e| e.to_string())
     }
 }
+
+//Everything that happened before a line as one label, in the colour of the first event
+fn marker_label(markers: &[Marker]) -> Option<(String, RGBColor)> {
+    let [r, g, b, _] = markers.first()?.color();
+    let label = markers
+        .iter()
+        .map(|marker| marker.label())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some((label, RGBColor(r, g, b)))
+}
+
+impl Chart<super::Message> for WaterFall {
+    type State = ();
+    // leave it empty
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, _builder: ChartBuilder<DB>) {}
+
+    fn draw_chart<DB: DrawingBackend>(&self, _state: &Self::State, root: DrawingArea<DB, Shift>) {
+        let (Some(newest), Some(period)) = (self.lines.front(), self.line_period()) else {
+            return;
+        };
+        let newest = newest.timestamp;
+        let tick_x = AXIS_WIDTH as i32 - 6;
+        let interval = TICK_INTERVALS
+            .iter()
+            .copied()
+            .find(|secs| *secs as f64 / period >= MIN_TICK_SPACING)
+            .unwrap_or(TICK_INTERVALS[TICK_INTERVALS.len() - 1]);
+
+        //A tick goes where a line falls in an older interval than the line above it
+        let bucket = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                / interval
+        };
+        for (y, line) in self.lines.iter().enumerate() {
+            if let Some((label, color)) = marker_label(&line.markers) {
+                let _ = root.draw(&PathElement::new(
+                    vec![(0, y as i32), (AXIS_WIDTH as i32, y as i32)],
+                    color,
+                ));
+                let _ = root.draw(&Text::new(
+                    label,
+                    (2, y as i32 - 12),
+                    ("sans-serif", 11).into_font().color(&color),
+                ));
+            }
+        }
+
+        let mut last_tick: Option<usize> = None;
+        for (y, (newer, line)) in self.lines.iter().zip(self.lines.iter().skip(1)).enumerate() {
+            let y = y + 1;
+            if !line.markers.is_empty() {
+                continue;
+            }
+
+            let boundary = bucket(newer.timestamp);
+            if boundary == bucket(line.timestamp)
+                || last_tick.is_some_and(|last| ((y - last) as f64) < MIN_TICK_SPACING)
+            {
+                continue;
+            }
+            last_tick = Some(y);
+
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(boundary * interval);
+            let _ = root.draw(&PathElement::new(
+                vec![(tick_x, y as i32), (AXIS_WIDTH as i32, y as i32)],
+                WHITE.mix(0.5),
+            ));
+            let _ = root.draw(&Text::new(
+                time_label(self.utc, time, newest),
+                (tick_x - 60, y as i32 - 6),
+                ("sans-serif", 12).into_font().color(&WHITE),
+            ));
+        }
+    }
+}
+
+//Hands the ring to the renderer, which keeps the texture between frames
+struct RingProgram {
+    ring: Arc<Mutex<LineRing>>,
+}
+
+impl shader::Program<super::Message> for RingProgram {
+    type State = ();
+    type Primitive = RingPrimitive;
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        _cursor: mouse::Cursor,
+        _bounds: Rectangle,
+    ) -> Self::Primitive {
+        RingPrimitive {
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RingPrimitive {
+    ring: Arc<Mutex<LineRing>>,
+}
+
+impl shader::Primitive for RingPrimitive {
+    fn prepare(
+        &self,
+        format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _bounds: Rectangle,
+        _target_size: Size<u32>,
+        _scale_factor: f32,
+        storage: &mut shader::Storage,
+    ) {
+        if !storage.has::<RingPipeline>() {
+            storage.store(RingPipeline::new(device, format));
+        }
+        let (Some(pipeline), Ok(mut ring)) = (storage.get_mut::<RingPipeline>(), self.ring.lock())
+        else {
+            return;
+        };
+        pipeline.upload(device, queue, &mut ring);
+    }
+
+    fn render(
+        &self,
+        storage: &shader::Storage,
+        target: &wgpu::TextureView,
+        _target_size: Size<u32>,
+        viewport: Rectangle<u32>,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if let Some(pipeline) = storage.get::<RingPipeline>() {
+            pipeline.render(target, viewport, encoder);
+        }
+    }
+}
+
+//GPU copy of the ring, drawn with the head offset applied in the shader
+struct RingTexture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    generation: u64,
+}
+
+struct RingPipeline {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    //head, height and width as u32
+    uniforms: wgpu::Buffer,
+    texture: Option<RingTexture>,
+}
+
+impl RingPipeline {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("waterfall"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "waterfall.wgsl"
+            ))),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("waterfall"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("waterfall"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("waterfall"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("waterfall"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            layout,
+            uniforms,
+            texture: None,
+        }
+    }
+
+    //Sends what changed in the ring since the last frame, normally one or two lines
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, ring: &mut LineRing) {
+        let generation = ring.generation();
+        let uniforms = [
+            ring.head() as u32,
+            ring.height() as u32,
+            FFT_AMMOUNT as u32,
+            0,
+        ];
+
+        match ring.take_upload(self.texture.as_ref().map(|t| t.generation)) {
+            Upload::Full {
+                width,
+                height,
+                pixels,
+            } => {
+                self.texture = None;
+                if height == 0 {
+                    return;
+                }
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("waterfall"),
+                    size: wgpu::Extent3d {
+                        width: width as u32,
+                        height: height as u32,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                write_lines(queue, &texture, width, 0, height, pixels);
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("waterfall"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: self.uniforms.as_entire_binding(),
+                        },
+                    ],
+                });
+                self.texture = Some(RingTexture {
+                    texture,
+                    bind_group,
+                    generation,
+                });
+            }
+            Upload::Lines(lines) => {
+                if let Some(texture) = self.texture.as_ref() {
+                    for (line, pixels) in lines {
+                        write_lines(queue, &texture.texture, FFT_AMMOUNT, line, 1, pixels);
+                    }
+                }
+            }
+        }
+
+        let bytes: Vec<u8> = uniforms.iter().flat_map(|val| val.to_le_bytes()).collect();
+        queue.write_buffer(&self.uniforms, 0, &bytes);
+    }
+
+    fn render(
+        &self,
+        target: &wgpu::TextureView,
+        viewport: Rectangle<u32>,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let Some(texture) = self.texture.as_ref() else {
+            return;
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("waterfall"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &texture.bind_group, &[]);
+        pass.set_viewport(
+            viewport.x as f32,
+            viewport.y as f32,
+            viewport.width as f32,
+            viewport.height as f32,
+            0.0,
+            1.0,
+        );
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn write_lines(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: usize,
+    first: usize,
+    count: usize,
+    pixels: &[u8],
+) {
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: 0,
+                y: first as u32,
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        pixels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some((width * RGBA) as u32),
+            rows_per_image: None,
+        },
+        wgpu::Extent3d {
+            width: width as u32,
+            height: count as u32,
+            depth_or_array_layers: 1,
+        },
+    );
+}
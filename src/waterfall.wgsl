@@ -0,0 +1,32 @@
+//Draws the waterfall line ring newest line at the top, `head` is where the ring starts
+struct Ring {
+    head: u32,
+    height: u32,
+    width: u32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var lines: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> ring: Ring;
+
+struct VertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+//One triangle big enough to cover the viewport
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOut {
+    let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    var out: VertexOut;
+    out.pos = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let x = min(u32(in.uv.x * f32(ring.width)), ring.width - 1u);
+    let line = min(u32(in.uv.y * f32(ring.height)), ring.height - 1u);
+    return textureLoad(lines, vec2<u32>(x, (ring.head + line) % ring.height), 0);
+}
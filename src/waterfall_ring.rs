@@ -0,0 +1,143 @@
+//! Pixel ring behind the waterfall and the colouring of its lines, kept apart from the widget
+//! so they can be benchmarked.
+
+//Bytes per pixel
+pub const RGBA: usize = 4;
+
+/// A gradient sampled at 256 points so a line is coloured with table lookups
+pub fn lut(grad: &colorgrad::Gradient) -> [[u8; RGBA]; 256] {
+    let mut lut = [[0; RGBA]; 256];
+    for (idx, color) in lut.iter_mut().enumerate() {
+        *color = grad.at(idx as f64 / 255.0).to_rgba8();
+    }
+    lut
+}
+
+/// Colours `line` from the dB values in `sample`, scaled so `max` is the top of the table and
+/// anything under `min` the bottom
+pub fn color_line(line: &mut [u8], sample: &[f32], lut: &[[u8; RGBA]; 256], max: f32, min: f32) {
+    for (pix, val) in line.chunks_exact_mut(RGBA).zip(sample) {
+        let adj_val = (val.clamp(min, max) / max).clamp(0.0, 1.0);
+        pix.copy_from_slice(&lut[(adj_val * 255.0) as usize]);
+    }
+}
+
+/// `height` RGBA lines of `width` pixels used as a ring, `head` is the newest line.
+///
+/// The GPU keeps its own copy of the ring and only the lines written since its last upload
+/// are sent, the ring is never reordered or copied as a whole while streaming.
+pub struct LineRing {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    head: usize,
+    //Lines written since the last upload, oldest first
+    dirty: Vec<usize>,
+    //Bumped whenever the GPU copy has to be rebuilt from the whole ring
+    generation: u64,
+}
+
+impl std::fmt::Debug for LineRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LineRing({}x{})", self.width, self.height)
+    }
+}
+
+/// What the GPU copy of the ring needs to catch up
+pub enum Upload<'a> {
+    /// The whole ring, with the texture recreated at this size first
+    Full {
+        width: usize,
+        height: usize,
+        pixels: &'a [u8],
+    },
+    /// Single lines as (index, pixels)
+    Lines(Vec<(usize, &'a [u8])>),
+}
+
+impl LineRing {
+    pub fn new(width: usize) -> Self {
+        Self {
+            width,
+            height: 0,
+            pixels: Vec::new(),
+            head: 0,
+            dirty: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Changes the number of lines kept, the newest ones survive
+    pub fn resize(&mut self, height: usize) {
+        let mut pixels = self.unrolled();
+        pixels.resize(height * self.line_len(), 0);
+        self.pixels = pixels;
+        self.height = height;
+        self.head = 0;
+        self.dirty.clear();
+        self.generation += 1;
+    }
+
+    /// Moves the head up one line and returns it to be overwritten
+    pub fn next_line(&mut self) -> &mut [u8] {
+        let line_len = self.line_len();
+        self.head = (self.head + self.height - 1) % self.height;
+        //Once every line is dirty sending the whole ring is cheaper than going line by line
+        if self.dirty.len() < self.height {
+            self.dirty.push(self.head);
+        } else {
+            self.dirty.clear();
+            self.generation += 1;
+        }
+        &mut self.pixels[self.head * line_len..(self.head + 1) * line_len]
+    }
+
+    /// What has to be uploaded for a GPU copy made at `generation` to match the ring
+    pub fn take_upload(&mut self, generation: Option<u64>) -> Upload<'_> {
+        if generation != Some(self.generation) {
+            self.dirty.clear();
+            return Upload::Full {
+                width: self.width,
+                height: self.height,
+                pixels: &self.pixels,
+            };
+        }
+
+        let line_len = self.line_len();
+        let pixels = &self.pixels;
+        Upload::Lines(
+            self.dirty
+                .drain(..)
+                .map(|line| (line, &pixels[line * line_len..(line + 1) * line_len]))
+                .collect(),
+        )
+    }
+
+    /// The ring copied out newest line first
+    pub fn unrolled(&self) -> Vec<u8> {
+        let split = self.head * self.line_len();
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        pixels.extend_from_slice(&self.pixels[split..]);
+        pixels.extend_from_slice(&self.pixels[..split]);
+        pixels
+    }
+
+    fn line_len(&self) -> usize {
+        self.width * RGBA
+    }
+}
@@ -0,0 +1,128 @@
+//! Decodes recordings through the library the way `rusticSDR --decode` does
+
+use std::path::PathBuf;
+
+use rustic_sdr::decoders::{self, DecoderState};
+use rustic_sdr::{DecoderKind, SdrError, Vfo};
+
+const RATE: f64 = 48_000.0;
+//Where the signal sits in the recording
+const OFFSET_HZ: f64 = 6_000.0;
+const WPM: f64 = 20.0;
+const MORSE: [(char, &str); 11] = [
+    ('A', ".-"),
+    ('G', "--."),
+    ('I', ".."),
+    ('K', "-.-"),
+    ('M', "--"),
+    ('O', "---"),
+    ('P', ".--."),
+    ('R', ".-."),
+    ('S', "..."),
+    ('V', "...-"),
+    ('Y', "-.--"),
+];
+
+//Key down or up for each sample of `text` sent at `WPM`
+fn keying(text: &str) -> Vec<bool> {
+    let dit = (1_200.0 / WPM / 1_000.0 * RATE) as usize;
+    let mut keying = vec![false; 10 * dit];
+    for ch in text.chars() {
+        if ch == ' ' {
+            keying.extend(vec![false; 4 * dit]);
+            continue;
+        }
+        let (_, code) = MORSE.iter().find(|(letter, _)| *letter == ch).unwrap();
+        for element in code.chars() {
+            let len = if element == '.' { dit } else { 3 * dit };
+            keying.extend(vec![true; len]);
+            keying.extend(vec![false; dit]);
+        }
+        keying.extend(vec![false; 2 * dit]);
+    }
+    keying.extend(vec![false; 10 * dit]);
+    keying
+}
+
+//A float I/Q recording the way the baseband recorder writes one
+fn write_iq(name: &str, iq: impl Iterator<Item = (f32, f32)>) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("rustic_sdr_it_{}_{}.wav", std::process::id(), name));
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: RATE as u32,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for (i, q) in iq {
+        writer.write_sample(i).unwrap();
+        writer.write_sample(q).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+#[test]
+fn decodes_cw_from_an_iq_recording() {
+    //A carrier keyed on and off at the offset, with a little noise the same on every run
+    let mut seed = 1u32;
+    let mut noise = move || {
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        ((seed >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.01
+    };
+    let iq = keying("VVV PARIS MY GM OK")
+        .into_iter()
+        .enumerate()
+        .map(|(idx, key)| {
+            let phase = 2.0 * std::f64::consts::PI * OFFSET_HZ * idx as f64 / RATE;
+            let level = if key { 0.5 } else { 0.0 };
+            (
+                level * phase.cos() as f32 + noise(),
+                level * phase.sin() as f32 + noise(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let path = write_iq("cw", iq.into_iter());
+
+    let kind = DecoderKind::Cw;
+    let mut vfo = Vfo {
+        offset_hz: OFFSET_HZ,
+        ..Vfo::default()
+    };
+    vfo.set_mode(kind.mode().unwrap());
+    let (decoder, state) = kind.create(None);
+    let mut decoders = vec![decoder];
+    let decoded = decoders::decode_wav(&path, vfo, &mut decoders);
+    std::fs::remove_file(&path).unwrap();
+    decoded.unwrap();
+
+    assert!(decoders[0].report().contains("MY GM OK"));
+    let DecoderState::Cw(text) = state else {
+        panic!("not a CW decoder");
+    };
+    let text = text.lock().unwrap();
+    assert!(text.text.ends_with(" MY GM OK "), "{:?}", text.text);
+    assert!((text.wpm - WPM).abs() < 3.0, "read as {:.1} WPM", text.wpm);
+}
+
+#[test]
+fn rejects_recordings_that_are_not_audio_or_iq() {
+    let path = std::env::temp_dir().join(format!("rustic_sdr_it_{}_quad.wav", std::process::id()));
+    let spec = hound::WavSpec {
+        channels: 4,
+        sample_rate: RATE as u32,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for _ in 0..16 {
+        writer.write_sample(0i16).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let (decoder, _) = DecoderKind::Cw.create(None);
+    let decoded = decoders::decode_wav(&path, Vfo::default(), &mut [decoder]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(decoded, Err(SdrError::Recording(_))));
+}